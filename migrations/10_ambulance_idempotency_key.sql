@@ -0,0 +1,4 @@
+-- Migration: Allow clients to retry adding an ambulance without creating a duplicate
+
+ALTER TABLE ambulances
+    ADD COLUMN idempotency_key TEXT UNIQUE;
\ No newline at end of file
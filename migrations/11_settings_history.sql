@@ -0,0 +1,13 @@
+-- Migration: Record every change to a user's settings for auditing
+
+CREATE TABLE settings_history (
+                                  history_id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+                                  user_id UUID NOT NULL REFERENCES accounts(user_id) ON DELETE CASCADE,
+                                  actor_id UUID REFERENCES accounts(user_id) ON DELETE SET NULL,
+                                  old_hospital GEOMETRY(POINT, 4326),
+                                  new_hospital GEOMETRY(POINT, 4326),
+                                  old_pref_eta INTERVAL NOT NULL,
+                                  new_pref_eta INTERVAL NOT NULL,
+                                  changed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX idx_settings_history_user_id ON settings_history(user_id, history_id);
\ No newline at end of file
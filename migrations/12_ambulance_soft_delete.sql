@@ -0,0 +1,4 @@
+-- Migration: Allow ambulances to be soft deleted and later restored
+
+ALTER TABLE ambulances
+    ADD COLUMN deleted_at TIMESTAMPTZ;
\ No newline at end of file
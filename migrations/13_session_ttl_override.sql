@@ -0,0 +1,4 @@
+-- Migration: Allow individual accounts to override the session TTL
+
+ALTER TABLE accounts
+    ADD COLUMN session_ttl_override INTERVAL CHECK (session_ttl_override > INTERVAL '0');
\ No newline at end of file
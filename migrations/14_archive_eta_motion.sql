@@ -0,0 +1,5 @@
+-- Migration: Archive the ambulance's reported speed and heading with each ETA
+
+ALTER TABLE archive_etas
+    ADD COLUMN speed DOUBLE PRECISION,
+    ADD COLUMN heading DOUBLE PRECISION;
\ No newline at end of file
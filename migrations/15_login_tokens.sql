@@ -0,0 +1,8 @@
+-- Migration: Single use login tokens, such as for links sent by SMS
+
+CREATE TABLE login_tokens (
+                              token_hash BYTEA PRIMARY KEY CHECK (octet_length(token_hash) = 32),
+                              user_id UUID NOT NULL REFERENCES accounts(user_id) ON DELETE CASCADE,
+                              expires_at TIMESTAMPTZ NOT NULL
+);
+CREATE INDEX idx_login_tokens_user_id ON login_tokens(user_id);
\ No newline at end of file
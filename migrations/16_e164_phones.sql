@@ -0,0 +1,9 @@
+-- Migration: Store phone numbers in E.164 form, such as +15551234567, rather than as 10 digit US numbers
+
+ALTER TABLE phone_numbers
+    ALTER COLUMN phone TYPE VARCHAR(16);
+
+UPDATE phone_numbers SET phone = '+1' || phone WHERE phone NOT LIKE '+%';
+
+ALTER TABLE phone_numbers
+    ADD CONSTRAINT phone_numbers_e164 CHECK (phone ~ '^\+[1-9][0-9]{6,14}$');
\ No newline at end of file
@@ -0,0 +1,4 @@
+-- Migration: Mark sessions created from login tokens
+
+ALTER TABLE sessions
+    ADD COLUMN from_login_token BOOLEAN NOT NULL DEFAULT false;
\ No newline at end of file
@@ -1,16 +1,17 @@
 -- Migration: Create schema for ambulance tracking app
 
-DROP TABLE IF EXISTS accounts CASCADE;
-DROP TABLE IF EXISTS ambulance_locations CASCADE;
-DROP TABLE IF EXISTS ambulances CASCADE;
-DROP TABLE IF EXISTS eta_notifications CASCADE;
-DROP TABLE IF EXISTS etas CASCADE;
-DROP TABLE IF EXISTS live_tracking_sessions CASCADE;
-DROP TABLE IF EXISTS phone_numbers CASCADE;
-DROP TABLE IF EXISTS sessions CASCADE;
-DROP TABLE IF EXISTS archive_ambulance_locations CASCADE;
-DROP TABLE IF EXISTS archive_etas CASCADE;
-DROP TYPE IF EXISTS account_role;
+-- the drops name the current schema so that migrating a tenant's schema leaves the tables of the
+-- schemas behind it on the search path alone
+DO $$
+DECLARE
+    dropped TEXT;
+BEGIN
+    FOREACH dropped IN ARRAY ARRAY['accounts', 'ambulance_locations', 'ambulances', 'eta_notifications', 'etas', 'live_tracking_sessions', 'phone_numbers', 'sessions', 'archive_ambulance_locations', 'archive_etas'] LOOP
+        EXECUTE format('DROP TABLE IF EXISTS %I.%I CASCADE', current_schema(), dropped);
+    END LOOP;
+    EXECUTE format('DROP TYPE IF EXISTS %I.account_role', current_schema());
+END;
+$$;
 
 CREATE EXTENSION IF NOT EXISTS postgis;
 
@@ -19,7 +20,7 @@ CREATE EXTENSION IF NOT EXISTS postgis;
 -- ----------------------------------------
 DO $$
 BEGIN
-    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'account_role') THEN
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'account_role' AND typnamespace = current_schema()::regnamespace) THEN
 CREATE TYPE account_role AS ENUM ('admin','user','site_admin');
 END IF;
 END;
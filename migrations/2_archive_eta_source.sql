@@ -0,0 +1,4 @@
+-- Migration: Record which ETA provider produced an archived ETA
+
+ALTER TABLE archive_etas ADD COLUMN source TEXT;
+CREATE INDEX idx_archive_etas_ambulance ON archive_etas(ambulance_id, calculated_at);
@@ -0,0 +1,4 @@
+-- Migration: Store the destination of a tracked ambulance and whether its eta alert was dismissed
+
+ALTER TABLE live_tracking_sessions ADD COLUMN destination GEOMETRY(POINT, 4326);
+ALTER TABLE live_tracking_sessions ADD COLUMN self_alert_dismissed BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,4 @@
+-- Migration: Allow failed ETA calculations to be archived
+
+ALTER TABLE archive_etas ALTER COLUMN eta DROP NOT NULL;
+ALTER TABLE archive_etas ADD COLUMN error TEXT;
\ No newline at end of file
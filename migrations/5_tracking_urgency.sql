@@ -0,0 +1,15 @@
+-- Migration: Restrict tracking urgency to a known set of levels
+
+CREATE TYPE urgency AS ENUM ('low','normal','high','critical');
+
+ALTER TABLE live_tracking_sessions
+    ALTER COLUMN urgency TYPE urgency USING (
+        CASE lower(trim(urgency))
+            WHEN 'low' THEN 'low'
+            WHEN 'high' THEN 'high'
+            WHEN 'critical' THEN 'critical'
+            ELSE 'normal'
+        END
+    )::urgency,
+    ALTER COLUMN urgency SET DEFAULT 'normal',
+    ALTER COLUMN urgency SET NOT NULL;
\ No newline at end of file
@@ -0,0 +1,10 @@
+-- Migration: Remember previous passwords so that they cannot be reused
+
+CREATE TABLE password_history (
+                                  history_id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+                                  user_id UUID NOT NULL REFERENCES accounts(user_id) ON DELETE CASCADE,
+                                  password_hash BYTEA NOT NULL CHECK (octet_length(password_hash) = 32),
+                                  password_salt BYTEA NOT NULL CHECK (octet_length(password_salt) = 16),
+                                  replaced_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+CREATE INDEX idx_password_history_user_id ON password_history(user_id, history_id);
\ No newline at end of file
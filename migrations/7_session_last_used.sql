@@ -0,0 +1,4 @@
+-- Migration: Track when sessions were last used so that idle sessions can expire
+
+ALTER TABLE sessions
+    ADD COLUMN last_used_at TIMESTAMPTZ NOT NULL DEFAULT now();
\ No newline at end of file
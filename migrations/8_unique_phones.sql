@@ -0,0 +1,6 @@
+-- Migration: Allow phone numbers to be kept unique per user when the server is configured to
+
+ALTER TABLE phone_numbers
+    ADD COLUMN unique_number BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE UNIQUE INDEX idx_phone_numbers_unique_number ON phone_numbers(user_id, phone) WHERE unique_number;
\ No newline at end of file
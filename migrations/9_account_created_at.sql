@@ -0,0 +1,6 @@
+-- Migration: Record when accounts were created so that they can be listed in a stable order
+
+ALTER TABLE accounts
+    ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+CREATE INDEX idx_accounts_created_at ON accounts(created_at, user_id);
\ No newline at end of file
@@ -1,5 +1,11 @@
 mod ambulance_tracker;
+mod cursor;
 mod account_manager;
+mod clock;
+mod request_context;
 
 pub use account_manager::*;
-pub use ambulance_tracker::*;
\ No newline at end of file
+pub use ambulance_tracker::*;
+pub use clock::*;
+pub use cursor::*;
+pub use request_context::*;
\ No newline at end of file
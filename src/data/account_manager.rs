@@ -1,14 +1,23 @@
+mod authorization;
+mod phone_format;
 mod settings_manager;
 mod tracking_manager;
 
+pub use authorization::*;
+pub use phone_format::*;
 pub use settings_manager::*;
 pub use tracking_manager::*;
 
+use crate::data::Cursor;
 use serde::{Deserialize, Serialize};
+use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::types::Uuid;
+use std::fmt;
+use std::ops::Deref;
+use std::time::Duration;
 use thiserror::Error;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct AccountId(pub Uuid);
 impl AccountId {
 	pub fn new(uuid: Uuid) -> Self {
@@ -16,12 +25,90 @@ impl AccountId {
 	}
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub struct SessionToken(pub [u8; 32]);
 impl SessionToken {
 	pub fn new(bytes: [u8; 32]) -> Self {
 		Self(bytes)
 	}
+
+	/// Encodes the token as 64 lowercase hex characters, suitable for headers and cookies.
+	pub fn to_hex(&self) -> String {
+		const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
+
+		let mut hex = String::with_capacity(64);
+		for byte in self.0 {
+			hex.push(HEX_CHARS[(byte >> 4) as usize] as char);
+			hex.push(HEX_CHARS[(byte & 0x0f) as usize] as char);
+		}
+		hex
+	}
+
+	/// Decodes a token from exactly 64 hex characters. Both upper and lower case are accepted.
+	pub fn from_hex(hex: &str) -> Result<Self, TokenParseError> {
+		fn nibble(c: u8) -> Result<u8, TokenParseError> {
+			match c {
+				b'0'..=b'9' => Ok(c - b'0'),
+				b'a'..=b'f' => Ok(c - b'a' + 10),
+				b'A'..=b'F' => Ok(c - b'A' + 10),
+				_ => Err(TokenParseError::InvalidCharacter)
+			}
+		}
+
+		let hex = hex.as_bytes();
+		if hex.len() != 64 {
+			return Err(TokenParseError::InvalidLength(hex.len()));
+		}
+
+		let mut bytes = [0u8; 32];
+		for (i, pair) in hex.chunks_exact(2).enumerate() {
+			bytes[i] = (nibble(pair[0])? << 4) | nibble(pair[1])?;
+		}
+		Ok(Self(bytes))
+	}
+}
+
+/// The token is a secret, so it is never printed
+impl fmt::Debug for SessionToken {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("SessionToken(redacted)")
+	}
+}
+
+/// A secret, such as a temporary password, which is hidden from [Debug] output so that it cannot
+/// accidentally be logged. The value is still reachable through [Deref] or [Redacted::into_inner].
+#[derive(Clone, PartialEq, Eq)]
+pub struct Redacted<T>(T);
+impl<T> Redacted<T> {
+	pub fn new(value: T) -> Self {
+		Self(value)
+	}
+
+	pub fn into_inner(self) -> T {
+		self.0
+	}
+}
+
+impl<T> Deref for Redacted<T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&self.0
+	}
+}
+
+impl<T> fmt::Debug for Redacted<T> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str("Redacted")
+	}
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TokenParseError {
+	#[error("A session token must be exactly 64 hex characters, found {0}.")]
+	InvalidLength(usize),
+	#[error("A session token may only contain hex characters.")]
+	InvalidCharacter
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
@@ -44,6 +131,40 @@ impl AccountRole {
 			_ => false
 		}
 	}
+
+	/// Returns whether self is a higher level than target, and so may manage it regardless of
+	/// ownership.
+	///
+	/// A SiteAdmin can manage Admins and users, an Admin can manage users, and a user can manage
+	/// none.
+	pub fn can_manage(self, target: AccountRole) -> bool {
+		self.level() > target.level()
+	}
+
+	fn level(self) -> u8 {
+		match self {
+			AccountRole::User => 0,
+			AccountRole::Admin => 1,
+			AccountRole::SiteAdmin => 2
+		}
+	}
+}
+
+/// An account as listed to administrators
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccountInfo {
+	pub id: AccountId,
+	pub username: String,
+	pub role: AccountRole,
+	pub created_at: DateTime<Utc>
+}
+
+/// Selects a page of a keyset paginated listing. The next page is found by passing the [Cursor] of
+/// the final entry of the previous page as `after`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PageParams {
+	pub after: Option<Cursor>,
+	pub limit: i64
 }
 
 #[derive(Debug, Error)]
@@ -52,14 +173,20 @@ pub enum AccountCreationError {
 	InvalidOwnerRole,
 	#[error("Specified owner account id not found.")]
 	OwnerNotFound,
+	#[error("The password must be at least {0} characters long.")]
+	PasswordTooShort(usize),
 	#[error("Other error: {0}")]
 	Other(Box<dyn std::error::Error>)
 }
 
 #[derive(Debug, Error)]
 pub enum AccountOwnerManageError {
-	#[error("The targeted user is not found, or the account specified as the owner does not own the account for which management is requested.")]
+	#[error("The targeted user is not found.")]
 	UserNotFound,
+	#[error("The account specified as the owner does not own the account for which management is requested.")]
+	NotOwner,
+	#[error("The acting account's role is not high enough to manage the targeted account.")]
+	InsufficientRole,
 	#[error("Other error: {0}")]
 	Other(Box<dyn std::error::Error>)
 }
@@ -70,30 +197,55 @@ pub enum AccountChangePasswordError {
 	UserNotFound,
 	#[error("Incorrect Password")]
 	IncorrectPassword,
+	#[error("The new password was used too recently.")]
+	PasswordReused,
+	#[error("The new password must be at least {0} characters long.")]
+	PasswordTooShort(usize),
 	#[error("Other error: {0}")]
 	Other(Box<dyn std::error::Error>)
 }
 
+/// Why a login was refused. Implementations only return the variants for the account states they
+/// support.
 #[derive(Debug, Error)]
 pub enum AccountLoginError {
 	#[error("The targeted user is not found.")]
 	UserNotFound,
 	#[error("Incorrect password")]
 	IncorrectPassword,
+	/// Returned in place of [AccountLoginError::UserNotFound] and
+	/// [AccountLoginError::IncorrectPassword] by implementations configured not to reveal which
+	/// usernames exist
+	#[error("Incorrect username or password")]
+	InvalidCredentials,
+	#[error("The login token is invalid, expired or already used.")]
+	InvalidLoginToken,
 	#[error("Other error: {0}")]
 	Other(Box<dyn std::error::Error>)
 }
 
+/// A successful login
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginSuccess {
+	pub account_id: AccountId,
+	pub token: SessionToken,
+	/// Whether the session can only be used to change the password until it is changed
+	pub password_reset_needed: bool
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum SessionRetrievalPurpose {
 	/// The action for which a session token is necessary is changing a password
 	ChangePassword,
 	/// The action for which a session token is necessary is not changing a password
-	Other
+	Other,
+	/// As [SessionRetrievalPurpose::Other], but the action also requires an
+	/// [AccountRole::Admin] or [AccountRole::SiteAdmin] account
+	AdminAction
 }
 #[derive(Debug, Error)]
 pub enum SessionRetrievalError {
-	#[error("The user must change the password")]
+	#[error("The session cannot be used for this action, either because the user must change the password or because the user's role is not high enough")]
 	InvalidPurpose,
 	#[error("Session token is not valid or does not exist.")]
 	InvalidToken,
@@ -111,37 +263,229 @@ pub trait AccountManager {
 	/// [AccountRole::Admin] can only create [AccountRole::User] accounts, and an [AccountRole::User]
 	/// cannot create accounts.
 	async fn create_account(&self, owner_id: &AccountId, account_role: AccountRole, username: &str)
-		-> Result<(AccountId, String), AccountCreationError>;
+		-> Result<(AccountId, Redacted<String>), AccountCreationError>;
+
+	/// Creates an [AccountRole::Admin] account owned by the site admin along with a first
+	/// [AccountRole::User] account owned by the new admin, such as when onboarding an organisation.
+	/// Returns each account's id and temporary password, admin first. Neither account is created
+	/// if either cannot be, such as when a username is taken.
+	async fn create_admin_with_user(&self, site_admin_id: &AccountId, admin_username: &str, user_username: &str)
+		-> Result<(AccountId, Redacted<String>, AccountId, Redacted<String>), AccountCreationError>;
+
+	/// Creates an account as [AccountManager::create_account] does, but with a known initial
+	/// password rather than a generated one, such as when migrating accounts. The account must
+	/// change it before performing any other action if `reset_needed` is set.
+	///
+	/// Returns [AccountCreationError::PasswordTooShort] if the password is shorter than the
+	/// implementation's minimum password length.
+	async fn create_account_with_password(&self, owner_id: &AccountId, account_role: AccountRole, username: &str, password: &str, reset_needed: bool)
+		-> Result<AccountId, AccountCreationError>;
 
 	/// Resets the password of an account, returning a new temporary password which must be changed
 	/// prior to performing any other action.
 	///
-	/// The specified owner must be the owner of this account, regardless of the owner role.
+	/// The specified owner must be the owner of this account, otherwise
+	/// [AccountOwnerManageError::NotOwner] is returned. This reveals to the caller that the account
+	/// exists, so callers exposing it to untrusted users may wish to report it as
+	/// [AccountOwnerManageError::UserNotFound] instead. An owner whose role can no longer own the
+	/// account's role gets [AccountOwnerManageError::InsufficientRole].
 	async fn reset_password(&self, owner_id: &AccountId, account_id: &AccountId)
-		-> Result<String, AccountOwnerManageError>;
+		-> Result<Redacted<String>, AccountOwnerManageError>;
 
-	/// Deletes the specified account and all owned resources.
+	/// Deletes the specified account and all owned resources, including its sessions, phones,
+	/// tracking sessions, and any accounts it owns.
 	///
-	/// The specified owner must be the owner of this account, regardless of the owner role.
+	/// The specified owner must be the owner of this account, otherwise
+	/// [AccountOwnerManageError::NotOwner] is returned, revealing that the account exists as with
+	/// [AccountManager::reset_password]. The owner's role is checked as it is there.
 	async fn delete_account(&self, owner_id: &AccountId, account_id: &AccountId)
 		-> Result<(), AccountOwnerManageError>;
 
+	/// Deletes an admin as [AccountManager::delete_account] does, but first moves the accounts it
+	/// owns to another admin so that they are kept. Both happen in one transaction.
+	///
+	/// The site admin must be the owner of the deleted admin, otherwise
+	/// [AccountOwnerManageError::NotOwner] is returned. Both accounts must be distinct admins,
+	/// otherwise [AccountOwnerManageError::InsufficientRole] is returned.
+	async fn delete_admin_reassigning(&self, site_admin_id: &AccountId, admin_id: &AccountId, new_admin_id: &AccountId)
+		-> Result<(), AccountOwnerManageError>;
+
+	/// Returns the owner of an account, or `None` if the account has no owner (a
+	/// [AccountRole::SiteAdmin]).
+	async fn get_owner(&self, account_id: &AccountId)
+		-> Result<Option<AccountId>, AccountOwnerManageError>;
+
+	/// Returns whether the account must change its password before performing any other action,
+	/// or [AccountOwnerManageError::UserNotFound] if it does not exist
+	async fn password_reset_required(&self, account_id: &AccountId)
+		-> Result<bool, AccountOwnerManageError>;
+
 	/// Changes a user's password if the provided current password is correct. Note that no password
-	/// requirements should be enforced at this level.
+	/// requirements should be enforced at this level, other than optionally rejecting recently used
+	/// passwords with [AccountChangePasswordError::PasswordReused] and short passwords with
+	/// [AccountChangePasswordError::PasswordTooShort].
 	async fn change_password(&self, account_id: &AccountId, current_password: &str, new_password: &str)
 		-> Result<(), AccountChangePasswordError>;
 
-	/// Invalidates the provided session token. If the session token does not exist, no action is taken.
+	/// Behaves as [AccountManager::change_password], but identifies the session the change is made
+	/// from, so that implementations which invalidate sessions after a password change can keep it
+	async fn change_password_in_session(&self, account_id: &AccountId, session_token: &SessionToken, current_password: &str, new_password: &str)
+		-> Result<(), AccountChangePasswordError>;
+
+	/// Lists every account, oldest first, for a [AccountRole::SiteAdmin] managing the whole
+	/// organization rather than only the accounts it owns. Returns
+	/// [AccountOwnerManageError::InsufficientRole] if the actor is not a site admin, and
+	/// [AccountOwnerManageError::UserNotFound] if the actor does not exist.
+	async fn list_all_accounts(&self, actor_id: &AccountId, page: PageParams)
+		-> Result<Vec<AccountInfo>, AccountOwnerManageError>;
+
+	/// Invalidates every session of the target, returning how many there were.
+	///
+	/// Unlike the ownership based operations, the actor only needs a higher role than the target
+	/// (see [AccountRole::can_manage]), otherwise [AccountOwnerManageError::InsufficientRole] is
+	/// returned. [AccountOwnerManageError::UserNotFound] is returned if either account is missing.
+	async fn force_logout(&self, actor_id: &AccountId, target_id: &AccountId)
+		-> Result<u64, AccountOwnerManageError>;
+
+	/// Sets how long the target's sessions may go unused before they expire, replacing the
+	/// manager's default, or restores the default if `None`. Existing sessions are judged by the
+	/// new TTL immediately.
+	///
+	/// As with [AccountManager::force_logout], the actor only needs a higher role than the target,
+	/// otherwise [AccountOwnerManageError::InsufficientRole] is returned.
+	async fn set_session_ttl(&self, actor_id: &AccountId, target_id: &AccountId, ttl: Option<Duration>)
+		-> Result<(), AccountOwnerManageError>;
+
+	/// Invalidates the provided session token, returning the number of sessions removed. If the
+	/// session token does not exist, no action is taken and `0` is returned.
 	async fn destroy_session(&self, token: &SessionToken)
-		-> Result<(), Box<dyn std::error::Error>>;
+		-> Result<u64, Box<dyn std::error::Error>>;
 
 	/// Attempts to log in the specified user
 	async fn login(&self, username: &str, password: &str)
 		-> Result<SessionToken, AccountLoginError>;
 
+	/// Behaves as [AccountManager::login], but also returns the account and whether it must change
+	/// its password, so that a client can be directed accordingly
+	async fn login_detailed(&self, username: &str, password: &str)
+		-> Result<LoginSuccess, AccountLoginError>;
+
+	/// Issues a single use token which [AccountManager::login_with_token] exchanges for a session,
+	/// such as to send in a login link, returning it encoded as hex. Tokens are only issued for
+	/// [AccountRole::User] accounts and expire after a configured time.
+	///
+	/// The specified owner must be the owner of the account, otherwise
+	/// [AccountOwnerManageError::NotOwner] is returned as with [AccountManager::reset_password].
+	/// Returns [AccountOwnerManageError::InsufficientRole] for accounts of any other role.
+	async fn issue_login_token(&self, owner_id: &AccountId, account_id: &AccountId)
+		-> Result<Redacted<String>, AccountOwnerManageError>;
+
+	/// Logs in with a token from [AccountManager::issue_login_token], consuming it. Returns
+	/// [AccountLoginError::InvalidLoginToken] if the token was never issued, has expired, or has
+	/// already been used.
+	///
+	/// The session may be used for any purpose even while the account needs a password reset, as
+	/// the user may never have been given a password.
+	async fn login_with_token(&self, token: &str)
+		-> Result<SessionToken, AccountLoginError>;
+
+	/// Checks whether the password is correct for the specified user without creating a session,
+	/// such as to confirm a user's identity before a sensitive action
+	async fn verify_password(&self, account_id: &AccountId, password: &str)
+		-> Result<bool, AccountLoginError>;
+
 	/// Attempts to look up a user using the authenticated session token.
 	///
-	/// If a password reset is necessary, the token is not valid for any purpose but a password reset.
+	/// If a password reset is necessary, the token is not valid for any purpose but a password reset,
+	/// and [SessionRetrievalPurpose::AdminAction] additionally requires an admin account.
 	async fn retrieve_account(&self, session_token: &SessionToken, purpose: SessionRetrievalPurpose)
 		-> Result<AccountId, SessionRetrievalError>;
+
+	/// Behaves as [AccountManager::retrieve_account], but also returns the account's role as read
+	/// alongside the session, so that authorization decisions agree with the purpose check.
+	async fn retrieve_session(&self, session_token: &SessionToken, purpose: SessionRetrievalPurpose)
+		-> Result<(AccountId, AccountRole), SessionRetrievalError>;
+
+	/// Marks a session as used now, so that activity keeps it from expiring. Returns
+	/// [SessionRetrievalError::InvalidToken] if the session does not exist or has already expired.
+	async fn touch_session(&self, session_token: &SessionToken)
+		-> Result<(), SessionRetrievalError>;
+
+	/// Replaces a session's token with a new one for the same account, such as after a privilege
+	/// sensitive change, so that the old token stops working without logging the user out. The
+	/// session is marked as used. Returns [SessionRetrievalError::InvalidToken] if the session does
+	/// not exist or has already expired.
+	async fn rotate_session(&self, session_token: &SessionToken)
+		-> Result<SessionToken, SessionRetrievalError>;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn can_manage_lower_levels_only() {
+		assert!(AccountRole::SiteAdmin.can_manage(AccountRole::Admin));
+		assert!(AccountRole::SiteAdmin.can_manage(AccountRole::User));
+		assert!(AccountRole::Admin.can_manage(AccountRole::User));
+
+		assert!(!AccountRole::SiteAdmin.can_manage(AccountRole::SiteAdmin));
+		assert!(!AccountRole::Admin.can_manage(AccountRole::Admin));
+		assert!(!AccountRole::Admin.can_manage(AccountRole::SiteAdmin));
+		assert!(!AccountRole::User.can_manage(AccountRole::User));
+		assert!(!AccountRole::User.can_manage(AccountRole::Admin));
+	}
+
+	#[test]
+	fn session_token_hex_round_trip() {
+		let mut bytes = [0u8; 32];
+		for (i, b) in bytes.iter_mut().enumerate() {
+			*b = (i as u8).wrapping_mul(37);
+		}
+		let token = SessionToken::new(bytes);
+
+		let hex = token.to_hex();
+		assert_eq!(hex.len(), 64);
+		assert_eq!(SessionToken::from_hex(&hex), Ok(token));
+		assert_eq!(SessionToken::from_hex(&hex.to_uppercase()), Ok(token));
+	}
+
+	#[test]
+	fn secrets_are_redacted_from_debug() {
+		let token = SessionToken::new([0xab; 32]);
+		let debug = format!("{:?}", token);
+		assert_eq!(debug, "SessionToken(redacted)");
+		assert!(!debug.contains("ab"));
+		assert!(!format!("{:?}", Some(token)).contains("171"));
+
+		let password = Redacted::new("hunter2hunter2".to_string());
+		assert!(!format!("{:?}", password).contains("hunter2"));
+		assert!(!format!("{:#?}", (1, &password)).contains("hunter2"));
+		assert_eq!(password.as_str(), "hunter2hunter2");
+		assert_eq!(password.into_inner(), "hunter2hunter2");
+	}
+
+	#[test]
+	fn session_token_hex_known_value() {
+		let token = SessionToken::new([0xab; 32]);
+		assert_eq!(token.to_hex(), "ab".repeat(32));
+	}
+
+	#[test]
+	fn session_token_hex_rejects_wrong_length() {
+		assert_eq!(SessionToken::from_hex(""), Err(TokenParseError::InvalidLength(0)));
+		assert_eq!(SessionToken::from_hex(&"a".repeat(63)), Err(TokenParseError::InvalidLength(63)));
+		assert_eq!(SessionToken::from_hex(&"a".repeat(66)), Err(TokenParseError::InvalidLength(66)));
+	}
+
+	#[test]
+	fn session_token_hex_rejects_non_hex() {
+		let mut hex = "0".repeat(63);
+		hex.push('g');
+		assert_eq!(SessionToken::from_hex(&hex), Err(TokenParseError::InvalidCharacter));
+
+		// multibyte characters must not be mistaken for two hex digits
+		let hex = format!("{}é", "0".repeat(62));
+		assert_eq!(SessionToken::from_hex(&hex), Err(TokenParseError::InvalidCharacter));
+	}
 }
\ No newline at end of file
@@ -0,0 +1,136 @@
+use thiserror::Error;
+use crate::data::account_manager::{AccountId, AccountRole};
+
+/// An operation one account may attempt to perform on another
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AccountAction {
+	Create,
+	ResetPassword,
+	Delete,
+	Suspend
+}
+
+impl AccountAction {
+	pub const ALL: [AccountAction; 4] = [AccountAction::Create, AccountAction::ResetPassword, AccountAction::Delete, AccountAction::Suspend];
+}
+
+/// The account an [AccountAction] is performed on.
+///
+/// For [AccountAction::Create] this describes the account to be created, whose owner is the
+/// account creating it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AccountTarget {
+	pub role: AccountRole,
+	pub owner: Option<AccountId>
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AuthzError {
+	#[error("A site_admin can only manage admins, an admin can only manage users, a user cannot manage accounts.")]
+	InvalidRole,
+	#[error("The acting account does not own the targeted account.")]
+	NotOwner
+}
+
+/// Checks whether the actor may perform the action on the target.
+///
+/// - [AccountAction::Create] requires that the actor's role can own the new account's role (see
+///   [AccountRole::can_own]), and that the actor is given as its owner.
+/// - [AccountAction::ResetPassword] and [AccountAction::Delete] require that the actor is the
+///   target's direct owner, and then that its role can still own the target's.
+/// - [AccountAction::Suspend] only requires a higher role than the target (see
+///   [AccountRole::can_manage]), regardless of ownership.
+pub fn authorize(actor_role: AccountRole, actor_id: &AccountId, action: AccountAction, target: &AccountTarget) -> Result<(), AuthzError> {
+	let owned = target.owner == Some(*actor_id);
+	match action {
+		AccountAction::Create => {
+			if !actor_role.can_own(target.role) {
+				return Err(AuthzError::InvalidRole);
+			}
+			if !owned {
+				return Err(AuthzError::NotOwner);
+			}
+			Ok(())
+		}
+		AccountAction::ResetPassword => {
+			if !owned {
+				return Err(AuthzError::NotOwner);
+			}
+			if !actor_role.can_own(target.role) {
+				return Err(AuthzError::InvalidRole);
+			}
+			Ok(())
+		}
+		AccountAction::Delete => {
+			if !owned {
+				return Err(AuthzError::NotOwner);
+			}
+			if !actor_role.can_own(target.role) {
+				return Err(AuthzError::InvalidRole);
+			}
+			Ok(())
+		}
+		AccountAction::Suspend => {
+			if !actor_role.can_manage(target.role) {
+				return Err(AuthzError::InvalidRole);
+			}
+			Ok(())
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sqlx::types::Uuid;
+
+	const ROLES: [AccountRole; 3] = [AccountRole::User, AccountRole::Admin, AccountRole::SiteAdmin];
+
+	#[test]
+	fn owner_with_valid_role_is_allowed() {
+		let actor = AccountId::new(Uuid::from_u128(1));
+		for action in AccountAction::ALL {
+			assert_eq!(authorize(AccountRole::SiteAdmin, &actor, action, &AccountTarget { role: AccountRole::Admin, owner: Some(actor) }), Ok(()));
+			assert_eq!(authorize(AccountRole::Admin, &actor, action, &AccountTarget { role: AccountRole::User, owner: Some(actor) }), Ok(()));
+		}
+	}
+
+	#[test]
+	fn every_combination_follows_policy() {
+		let actor = AccountId::new(Uuid::from_u128(1));
+		let other = AccountId::new(Uuid::from_u128(2));
+
+		for action in AccountAction::ALL {
+			for actor_role in ROLES {
+				for target_role in ROLES {
+					for owner in [Some(actor), Some(other), None] {
+						let target = AccountTarget { role: target_role, owner };
+						let can_own = matches!((actor_role, target_role), (AccountRole::SiteAdmin, AccountRole::Admin) | (AccountRole::Admin, AccountRole::User));
+						let owned = owner == Some(actor);
+						let expected = match action {
+							AccountAction::Create if !can_own => Err(AuthzError::InvalidRole),
+							AccountAction::Create if !owned => Err(AuthzError::NotOwner),
+							AccountAction::ResetPassword | AccountAction::Delete if !owned => Err(AuthzError::NotOwner),
+							AccountAction::ResetPassword | AccountAction::Delete if !can_own => Err(AuthzError::InvalidRole),
+							AccountAction::Suspend if !matches!((actor_role, target_role), (AccountRole::SiteAdmin, AccountRole::Admin | AccountRole::User) | (AccountRole::Admin, AccountRole::User)) => Err(AuthzError::InvalidRole),
+							_ => Ok(())
+						};
+						assert_eq!(authorize(actor_role, &actor, action, &target), expected,
+							"{actor_role:?} {action:?} {target_role:?} owned by {owner:?}");
+					}
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn user_cannot_act_on_itself() {
+		let actor = AccountId::new(Uuid::from_u128(1));
+		for action in AccountAction::ALL {
+			for role in ROLES {
+				let target = AccountTarget { role, owner: Some(actor) };
+				assert_eq!(authorize(AccountRole::User, &actor, action, &target), Err(AuthzError::InvalidRole));
+			}
+		}
+	}
+}
\ No newline at end of file
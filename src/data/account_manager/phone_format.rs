@@ -0,0 +1,51 @@
+/// Formats a phone number for display, such as a phone's label when none was given.
+///
+/// Stored 10 digit US numbers and `+1` E.164 numbers are written as `(555) 123-4567`, and `+44`
+/// UK numbers in their usual groups, such as `+44 20 7946 0958` or `+44 7700 900123`. Any other
+/// number, including malformed input, is returned unchanged.
+pub fn format_phone(phone: &str) -> String {
+	let is_digits = |digits: &str| digits.bytes().all(|c| c.is_ascii_digit());
+
+	if let Some(national) = phone.strip_prefix("+1").or(Some(phone).filter(|phone| !phone.starts_with('+'))) {
+		if national.len() == 10 && is_digits(national) {
+			return format!("({}) {}-{}", &national[0..3], &national[3..6], &national[6..10]);
+		}
+	}
+
+	if let Some(national) = phone.strip_prefix("+44") {
+		if national.len() == 10 && is_digits(national) {
+			// London numbers use a 2 digit area code, and most others, including mobiles, 4 digits
+			return if national.starts_with('2') {
+				format!("+44 {} {} {}", &national[0..2], &national[2..6], &national[6..10])
+			} else {
+				format!("+44 {} {}", &national[0..4], &national[4..10])
+			};
+		}
+	}
+
+	phone.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn formats_us_numbers() {
+		assert_eq!(format_phone("5551234567"), "(555) 123-4567");
+		assert_eq!(format_phone("+15551234567"), "(555) 123-4567");
+	}
+
+	#[test]
+	fn formats_uk_numbers() {
+		assert_eq!(format_phone("+442079460958"), "+44 20 7946 0958");
+		assert_eq!(format_phone("+447700900123"), "+44 7700 900123");
+	}
+
+	#[test]
+	fn leaves_malformed_numbers_unchanged() {
+		for phone in ["", "555", "55512345678", "555123456x", "+1555123456", "+4420794609", "+33123456789", "é5551234567", "(555) 123-4567"] {
+			assert_eq!(format_phone(phone), phone, "{}", phone);
+		}
+	}
+}
\ No newline at end of file
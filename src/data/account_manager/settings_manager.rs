@@ -1,4 +1,6 @@
+use std::collections::HashMap;
 use std::time::Duration;
+use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::types::Uuid;
 use thiserror::Error;
 use crate::data::account_manager::AccountId;
@@ -16,14 +18,141 @@ pub struct UserSettings {
 	pub default_eta_alert: Duration
 }
 
+impl UserSettings {
+	/// Starts building settings with no hospital location and [DEFAULT_ETA_ALERT]
+	pub fn builder() -> UserSettingsBuilder {
+		UserSettingsBuilder::default()
+	}
+}
+
+/// Builds [UserSettings], checking up front the values [SettingsManager::set_settings] would
+/// otherwise reject later
+#[derive(Debug, Clone)]
+pub struct UserSettingsBuilder {
+	hospital_location: Option<geo_types::Point>,
+	default_eta_alert: Duration
+}
+
+impl Default for UserSettingsBuilder {
+	fn default() -> Self {
+		Self { hospital_location: None, default_eta_alert: DEFAULT_ETA_ALERT }
+	}
+}
+
+impl UserSettingsBuilder {
+	pub fn hospital_location(mut self, hospital_location: Option<geo_types::Point>) -> Self {
+		self.hospital_location = hospital_location;
+		self
+	}
+
+	pub fn default_eta_alert(mut self, default_eta_alert: Duration) -> Self {
+		self.default_eta_alert = default_eta_alert;
+		self
+	}
+
+	/// Returns the settings, or why they are invalid: the hospital location must be a longitude and
+	/// latitude within the WGS84 ranges, and the default ETA alert shorter than [MAX_ETA_ALERT]
+	pub fn build(self) -> Result<UserSettings, SettingsValidationError> {
+		if let Some(location) = self.hospital_location {
+			if !(-180.0..=180.0).contains(&location.x()) || !(-90.0..=90.0).contains(&location.y()) {
+				return Err(SettingsValidationError::InvalidHospitalLocation);
+			}
+		}
+		if self.default_eta_alert >= MAX_ETA_ALERT {
+			return Err(SettingsValidationError::EtaAlertTooLong);
+		}
+		Ok(UserSettings { hospital_location: self.hospital_location, default_eta_alert: self.default_eta_alert })
+	}
+}
+
+/// A recorded change to a user's settings
+#[derive(Debug, Clone)]
+pub struct SettingsChange {
+	/// The account which made the change, or `None` if it has since been deleted
+	pub actor_id: Option<AccountId>,
+	pub before: UserSettings,
+	pub after: UserSettings,
+	pub changed_at: DateTime<Utc>
+}
+
+/// Default ETA alerts must be shorter than this, matching the constraint on `accounts.pref_eta`
+pub const MAX_ETA_ALERT: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// The default ETA alert given to new accounts, unless the deployment configures another
+pub const DEFAULT_ETA_ALERT: Duration = Duration::from_secs(15 * 60);
+
+/// The most phones a user may have
+pub const MAX_PHONES_PER_USER: i64 = 20;
+
+/// Returns whether a phone number is in E.164 form: a `+` followed by the country code and number,
+/// 7 to 15 digits in all, not starting with `0`
+pub fn is_valid_phone_number(number: &str) -> bool {
+	match number.strip_prefix('+') {
+		Some(digits) => (7..=15).contains(&digits.len()) && !digits.starts_with('0') && digits.bytes().all(|c| c.is_ascii_digit()),
+		None => false
+	}
+}
+
+/// Converts a phone number written in a common format, such as `+44 20 7946 0958`,
+/// `+1 (555) 123-4567` or `555.123.4567`, into the E.164 form accepted by [is_valid_phone_number].
+/// Numbers without a `+` are taken to be US numbers, with or without the leading `1`. Returns
+/// `None` if the number contains any other characters or is not a valid number.
+pub fn normalize_phone_number(number: &str) -> Option<String> {
+	let number = number.trim();
+	let (international, number) = match number.strip_prefix('+') {
+		Some(number) => (true, number),
+		None => (false, number)
+	};
+	if !number.chars().all(|c| c.is_ascii_digit() || matches!(c, ' ' | '(' | ')' | '-' | '.')) {
+		return None;
+	}
+
+	let digits: String = number.chars().filter(char::is_ascii_digit).collect();
+	let normalized = match digits.len() {
+		_ if international => format!("+{}", digits),
+		10 => format!("+1{}", digits),
+		11 if digits.starts_with('1') => format!("+{}", digits),
+		_ => return None
+	};
+	is_valid_phone_number(&normalized).then_some(normalized)
+}
+
 #[derive(Debug, Error)]
 pub enum SettingsError {
 	#[error("The specified user cannot be found")]
 	UserNotFound,
+	#[error("The ETA alert must be shorter than 6 hours")]
+	EtaAlertTooLong,
+	#[error("The hospital location must be a longitude and latitude within range")]
+	InvalidHospitalLocation,
+	#[error("Invalid phone number: {0}")]
+	InvalidPhone(String),
+	#[error("A user cannot have more than {} phones", MAX_PHONES_PER_USER)]
+	TooManyPhones,
+	#[error("The user already has the phone number {0}")]
+	DuplicatePhone(String),
 	#[error("Other error: {0}")]
 	Other(Box<dyn std::error::Error>),
 }
 
+/// Why [UserSettingsBuilder::build] rejected the settings
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SettingsValidationError {
+	#[error("The hospital location must be a longitude and latitude within range")]
+	InvalidHospitalLocation,
+	#[error("The ETA alert must be shorter than 6 hours")]
+	EtaAlertTooLong
+}
+
+impl From<SettingsValidationError> for SettingsError {
+	fn from(e: SettingsValidationError) -> Self {
+		match e {
+			SettingsValidationError::InvalidHospitalLocation => SettingsError::InvalidHospitalLocation,
+			SettingsValidationError::EtaAlertTooLong => SettingsError::EtaAlertTooLong
+		}
+	}
+}
+
 #[derive(Debug, Error)]
 pub enum DeletePhoneError {
 	#[error("The specified user cannot be found")]
@@ -40,16 +169,114 @@ pub trait SettingsManager {
 	/// Retrieves a user's settings
 	async fn get_settings(&self, user_id: AccountId) -> Result<UserSettings, SettingsError>;
 
-	/// Updates a user's settings, replacing it entirely
+	/// Retrieves the settings of several users at once. Users which cannot be found are omitted
+	/// from the result rather than causing an error.
+	async fn get_settings_many(&self, user_ids: &[AccountId]) -> Result<HashMap<AccountId, UserSettings>, SettingsError>;
+
+	/// Updates a user's settings, replacing it entirely. Returns [SettingsError::EtaAlertTooLong] if
+	/// the default ETA alert is not shorter than [MAX_ETA_ALERT].
 	async fn set_settings(&self, user_id: AccountId, settings: UserSettings) -> Result<(), SettingsError>;
 
+	/// Updates only a user's default ETA alert, leaving the rest of their settings unchanged.
+	/// Returns [SettingsError::EtaAlertTooLong] if it is not shorter than [MAX_ETA_ALERT].
+	async fn set_default_eta_alert(&self, user_id: AccountId, default_eta_alert: Duration) -> Result<(), SettingsError>;
+
+	/// Returns every recorded change to a user's settings, oldest first. Changes made through
+	/// [SettingsManager::set_settings] and [SettingsManager::set_default_eta_alert] are recorded
+	/// with the user as the actor.
+	async fn get_settings_history(&self, user_id: AccountId) -> Result<Vec<SettingsChange>, SettingsError>;
+
 	/// Returns a list of a user's phones
 	async fn get_phones(&self, user_id: AccountId) -> Result<Vec<PhoneNumber>, SettingsError>;
 
-	/// Creates a new phone for a user. Duplicates are allowed. Phone should be 10 chars long
-	/// representing a standard 10 digit US phone number as digits only.
+	/// Returns the number of phones a user has, without retrieving them
+	async fn count_phones(&self, user_id: AccountId) -> Result<i64, SettingsError>;
+
+	/// Returns the user's phones whose label or number contains the query, ignoring case. An empty
+	/// query returns all of the user's phones, as [SettingsManager::get_phones] does.
+	async fn search_phones(&self, user_id: AccountId, query: &str) -> Result<Vec<PhoneNumber>, SettingsError>;
+
+	/// Creates a new phone for a user. The number is normalized with [normalize_phone_number]
+	/// before being stored, returning [SettingsError::InvalidPhone] if that fails, and
+	/// [SettingsError::TooManyPhones] is returned if the user already has [MAX_PHONES_PER_USER].
+	///
+	/// Duplicates are allowed unless the implementation has been configured to keep numbers unique
+	/// per user, in which case [SettingsError::DuplicatePhone] is returned if the user already has
+	/// the normalized number.
 	async fn new_phone(&self, user_id: AccountId, phone: &str, label: &str) -> Result<PhoneNumber, SettingsError>;
 
-	/// Deletes a phone
+	/// Creates several (number, label) phones for a user at once, in the order given. Either all
+	/// are created or none are.
+	///
+	/// Numbers are normalized as in [SettingsManager::new_phone]. Returns
+	/// [SettingsError::InvalidPhone] if any number cannot be normalized,
+	/// [SettingsError::TooManyPhones] if the user would have more than [MAX_PHONES_PER_USER] phones
+	/// afterwards, and [SettingsError::DuplicatePhone] if uniqueness is enforced and a number is
+	/// repeated or already belongs to the user.
+	async fn import_phones(&self, user_id: AccountId, phones: &[(String, String)]) -> Result<Vec<PhoneNumber>, SettingsError>;
+
+	/// Deletes a phone, returning [DeletePhoneError::UserNotFound] if the user does not exist and
+	/// [DeletePhoneError::PhoneNotFound] if the user exists but has no such phone
 	async fn delete_phone(&self, user_id: AccountId, phone_id: Uuid) -> Result<(), DeletePhoneError>;
+
+	/// Deletes all of a user's phones, returning how many were removed. Returns
+	/// [SettingsError::UserNotFound] if the user does not exist.
+	async fn delete_all_phones(&self, user_id: AccountId) -> Result<u64, SettingsError>;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn normalize_phone_number_accepts_common_formats() {
+		for number in ["5551234567", "+15551234567", "15551234567", "(555) 123-4567", "+1 (555) 123-4567", "555.123.4567", " 555-123-4567 "] {
+			assert_eq!(normalize_phone_number(number).as_deref(), Some("+15551234567"), "{}", number);
+		}
+		for number in ["+442079460958", "+44 20 7946 0958", "+44 (20) 7946-0958"] {
+			assert_eq!(normalize_phone_number(number).as_deref(), Some("+442079460958"), "{}", number);
+		}
+	}
+
+	#[test]
+	fn normalize_phone_number_rejects_invalid() {
+		for number in ["", "+", "555-1234", "25551234567", "+0442079460958", "+123456", "+1234567890123456", "555123456x", "555/123/4567", "555 123 4567 ext 1", "1+5551234567", "++15551234567"] {
+			assert_eq!(normalize_phone_number(number), None, "{}", number);
+		}
+	}
+
+	#[test]
+	fn valid_phone_numbers_are_e164() {
+		assert!(is_valid_phone_number("+15551234567"));
+		assert!(is_valid_phone_number("+442079460958"));
+		for number in ["5551234567", "+0123456789", "+1555 1234567", "+1234567890123456"] {
+			assert!(!is_valid_phone_number(number), "{}", number);
+		}
+	}
+
+	#[test]
+	fn too_many_phones_names_the_limit() {
+		assert_eq!(SettingsError::TooManyPhones.to_string(), format!("A user cannot have more than {} phones", MAX_PHONES_PER_USER));
+	}
+
+	#[test]
+	fn settings_builder_validates() {
+		let settings = UserSettings::builder().build().unwrap();
+		assert_eq!((settings.hospital_location, settings.default_eta_alert), (None, DEFAULT_ETA_ALERT));
+
+		let settings = UserSettings::builder()
+			.hospital_location(Some(geo_types::Point::new(-0.1276, 51.5072)))
+			.default_eta_alert(Duration::from_secs(30 * 60))
+			.build()
+			.unwrap();
+		assert_eq!(settings.hospital_location, Some(geo_types::Point::new(-0.1276, 51.5072)));
+		assert_eq!(settings.default_eta_alert, Duration::from_secs(30 * 60));
+
+		let result = UserSettings::builder().default_eta_alert(Duration::from_secs(7 * 60 * 60)).build();
+		assert_eq!(result.unwrap_err(), SettingsValidationError::EtaAlertTooLong);
+		for location in [geo_types::Point::new(181.0, 0.0), geo_types::Point::new(0.0, -91.0), geo_types::Point::new(f64::NAN, 0.0)] {
+			let result = UserSettings::builder().hospital_location(Some(location)).build();
+			assert_eq!(result.unwrap_err(), SettingsValidationError::InvalidHospitalLocation, "{:?}", location);
+		}
+	}
 }
\ No newline at end of file
@@ -1,14 +1,61 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 use std::time::Duration;
+use serde::{Deserialize, Serialize};
 use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::types::Uuid;
 use thiserror::Error;
 use crate::data::account_manager::{AccountId, PhoneNumber};
-use crate::data::ambulance_tracker::Ambulance;
+use crate::data::ambulance_tracker::{Ambulance, AmbulanceId};
 
+/// How urgently a user needs a tracked ambulance
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "urgency", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum Urgency {
+	Low,
+	Normal,
+	High,
+	Critical
+}
+
+impl Urgency {
+	pub const ALL: [Urgency; 4] = [Urgency::Low, Urgency::Normal, Urgency::High, Urgency::Critical];
+
+	pub fn as_str(self) -> &'static str {
+		match self {
+			Urgency::Low => "low",
+			Urgency::Normal => "normal",
+			Urgency::High => "high",
+			Urgency::Critical => "critical"
+		}
+	}
+}
+
+impl Display for Urgency {
+	fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.as_str())
+	}
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("urgency must be one of low, normal, high or critical")]
+pub struct UrgencyParseError;
+
+/// Parses the lowercase names used in serialization, as received from clients
+impl FromStr for Urgency {
+	type Err = UrgencyParseError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Urgency::ALL.into_iter().find(|urgency| urgency.as_str() == s).ok_or(UrgencyParseError)
+	}
+}
+
+#[derive(Debug, Clone)]
 pub struct TrackedAmbulance {
 	pub ambulance: Ambulance,
 	pub user_label: String,
-	pub urgency: String,
+	pub urgency: Urgency,
 	pub phones_tracking: (PhoneNumber, Duration),
 	pub eta: DateTime<Utc>,
 	pub user_eta_notify: Option<Duration>,
@@ -18,6 +65,8 @@ pub struct TrackedAmbulance {
 pub enum UserLookupError {
 	#[error("user not found")]
 	UserNotFound,
+	#[error("the user is not tracking an ambulance")]
+	NotTracking,
 	#[error("other error")]
 	OtherError(Box<dyn std::error::Error>),
 }
@@ -27,21 +76,82 @@ pub enum AmbulanceLookupError {
 	AmbulanceNotFound,
 	#[error("user not found")]
 	UserNotFound,
+	#[error("no destination was given and the user has no hospital location")]
+	NoDestination,
+	#[error("phone not found")]
+	PhoneNotFound,
+	#[error("the query took longer than the statement timeout")]
+	Timeout,
 	#[error("other error")]
 	OtherError(Box<dyn std::error::Error>),
 }
 
+#[async_trait::async_trait]
 pub trait TrackingManager {
 
-	/// Returns a list of which ambulances a user is currently tracking
+	/// Returns the ambulance the user most recently began tracking, or
+	/// [UserLookupError::NotTracking] if the user is not tracking any
 	async fn get_user_tracking(&self, id: AccountId) -> Result<TrackedAmbulance, UserLookupError>;
 
-	/// Begins tracking an ambulance
-	async fn track_ambulance(&self, id: AccountId, ambulance_id: Uuid, user_label: &str, urgency: &str, phones: (Uuid, Duration)) -> Result<(), AmbulanceLookupError>;
+	/// Begins tracking an ambulance, notifying the phone once the ETA drops below the duration.
+	/// Returns [AmbulanceLookupError::PhoneNotFound] if the phone does not belong to the user.
+	///
+	/// Without an explicit destination, an already set destination is kept, otherwise the ambulance
+	/// is assumed to be headed to the user's hospital. Returns
+	/// [AmbulanceLookupError::NoDestination] if there is neither. Returns the stored tracking,
+	/// including the ETA to the destination. A soft deleted ambulance cannot be tracked and gives
+	/// [AmbulanceLookupError::AmbulanceNotFound].
+	async fn track_ambulance(&self, id: AccountId, ambulance_id: AmbulanceId, user_label: &str, urgency: Urgency, destination: Option<geo_types::Point>, phones: (Uuid, Duration)) -> Result<TrackedAmbulance, AmbulanceLookupError>;
 	
 	/// Dismisses the user eta alert
-	async fn dismiss_eta_alert(&self, id: AccountId, ambulance_id: Uuid) -> Result<(), AmbulanceLookupError>;
+	async fn dismiss_eta_alert(&self, id: AccountId, ambulance_id: AmbulanceId) -> Result<(), AmbulanceLookupError>;
+
+	/// Changes where a tracked ambulance is headed, recalculating its ETA and restoring a dismissed
+	/// eta alert
+	async fn set_destination(&self, id: AccountId, ambulance_id: AmbulanceId, destination: geo_types::Point) -> Result<(), AmbulanceLookupError>;
 	
-	/// Stops tracking the ambulance for the user
-	async fn stop_tracking_ambulance(&self, id: AccountId, ambulance_id: Uuid) -> Result<(), AmbulanceLookupError>;
+	/// Stops tracking the ambulance for the user, removing the tracking and its notifications.
+	///
+	/// This is not idempotent: returns [AmbulanceLookupError::AmbulanceNotFound] if the user is not
+	/// tracking the ambulance, including when it was already stopped, and
+	/// [AmbulanceLookupError::UserNotFound] if the user does not exist.
+	async fn stop_tracking_ambulance(&self, id: AccountId, ambulance_id: AmbulanceId) -> Result<(), AmbulanceLookupError>;
+
+	/// Returns how many users are actively tracking the ambulance, not counting trackings where
+	/// the ambulance has arrived. Returns [AmbulanceLookupError::AmbulanceNotFound] if the ambulance
+	/// does not exist or is soft deleted.
+	async fn count_trackers(&self, ambulance_id: AmbulanceId) -> Result<i64, AmbulanceLookupError>;
+
+	/// Returns each user actively tracking the ambulance along with the phones they attached to the
+	/// tracking, such as to notify them when it arrives. Users without phones are included with
+	/// none. Returns [AmbulanceLookupError::AmbulanceNotFound] if the ambulance does not exist or is
+	/// soft deleted, and [AmbulanceLookupError::Timeout] if the lookup exceeds the implementation's
+	/// read timeout.
+	async fn get_trackers(&self, ambulance_id: AmbulanceId) -> Result<Vec<(AccountId, Vec<PhoneNumber>)>, AmbulanceLookupError>;
+
+	/// Recalculates the ETA of every active tracking session with a destination using the
+	/// ambulance's latest location, returning how many were refreshed. Intended to be called
+	/// periodically by the host application. Fails with [AmbulanceLookupError::Timeout] if listing
+	/// the sessions exceeds the implementation's read timeout.
+	async fn refresh_tracked_etas(&self) -> Result<usize, Box<dyn std::error::Error>>;
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn urgency_round_trips_through_str() {
+		for urgency in Urgency::ALL {
+			assert_eq!(urgency.to_string().parse::<Urgency>(), Ok(urgency));
+		}
+		assert_eq!("critical".parse::<Urgency>(), Ok(Urgency::Critical));
+	}
+
+	#[test]
+	fn urgency_rejects_unknown_values() {
+		assert_eq!("urgent".parse::<Urgency>(), Err(UrgencyParseError));
+		assert_eq!("High".parse::<Urgency>(), Err(UrgencyParseError));
+		assert_eq!("".parse::<Urgency>(), Err(UrgencyParseError));
+	}
+}
\ No newline at end of file
@@ -2,40 +2,354 @@ use std::time::Duration;
 use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::types::Uuid;
 use thiserror::Error;
+use crate::data::Cursor;
+
+/// Identifies an ambulance. Kept distinct from [crate::data::AccountId] and from a bare [Uuid] so
+/// that ids cannot be swapped at a call site.
+///
+/// ```
+/// use ambulancetrackerbackend::data::AmbulanceId;
+/// use sqlx::types::Uuid;
+///
+/// fn locate(_id: AmbulanceId) {}
+/// locate(AmbulanceId::new(Uuid::nil()));
+/// ```
+///
+/// ```compile_fail
+/// use ambulancetrackerbackend::data::AmbulanceId;
+/// use sqlx::types::Uuid;
+///
+/// fn locate(_id: AmbulanceId) {}
+/// locate(Uuid::nil());
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct AmbulanceId(pub Uuid);
+impl AmbulanceId {
+	pub fn new(uuid: Uuid) -> Self {
+		Self(uuid)
+	}
+}
 
 #[derive(Clone, Debug)]
 pub struct Ambulance {
-	pub id: Uuid,
+	pub id: AmbulanceId,
 	pub name: String,
 	pub location: geo_types::Point,
 	pub last_updated: DateTime<Utc>
 }
 
+/// The maximum length of an ambulance name, in characters
+pub const MAX_AMBULANCE_NAME_LENGTH: usize = 255;
+
+/// Trims surrounding whitespace from an ambulance name, returning `None` if the result is empty or
+/// longer than [MAX_AMBULANCE_NAME_LENGTH] characters.
+pub fn normalize_ambulance_name(name: &str) -> Option<&str> {
+	let name = name.trim();
+	if name.is_empty() || name.chars().count() > MAX_AMBULANCE_NAME_LENGTH {
+		None
+	} else {
+		Some(name)
+	}
+}
+
+/// What happened to a single update of [AmbulanceTracker::bulk_update_locations]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UpdateOutcome {
+	Applied,
+	/// The fetched time was not after the ambulance's last update
+	Skipped,
+	NotFound
+}
+
 #[derive(Debug, Error)]
 pub enum AmbulanceTrackerError {
 	#[error("ambulance not found")]
 	AmbulanceNotFound,
+	#[error("invalid location geometry")]
+	InvalidGeometry,
+	#[error("location is implausible for the ambulance")]
+	ImplausibleLocation,
+	#[error("ambulance name must not be empty or longer than 255 characters")]
+	InvalidName,
+	#[error("fetched timestamp is too far in the future")]
+	TimestampInFuture,
+	#[error("the start of the time range is after its end")]
+	InvalidTimeRange,
+	#[error("the requested update window is too large")]
+	WindowTooLarge,
+	#[error("the query took longer than the statement timeout")]
+	Timeout,
 	#[error("other error: {0}")]
 	Other(Box<dyn std::error::Error>),
 }
 
+/// The read only half of an [AmbulanceTracker], suitable for services which must never modify
+/// ambulances. Soft deleted ambulances are never listed, counted or found by any of its methods.
 #[async_trait::async_trait]
-pub trait AmbulanceTracker {
+pub trait AmbulanceReader {
+
+	/// Returns a list of ambulances which have had location updates within the specified duration.
+	/// The window includes its start, and ends as far ahead of the current time as update
+	/// timestamps are allowed to be, so updates timestamped further in the future are not listed.
+	///
+	/// Implementations may limit how large the duration can be, returning
+	/// [AmbulanceTrackerError::WindowTooLarge] rather than reading an unbounded number of entries.
+	async fn get_recently_updated(&self, last_updated: Duration)
+		-> Result<Vec<Ambulance>, AmbulanceTrackerError>;
+
+	/// Returns up to `limit` of the entries of [AmbulanceReader::get_recently_updated], most
+	/// recently updated first. The next page is found by passing the [Cursor] of the final entry as
+	/// `after`.
+	async fn get_recently_updated_page(&self, last_updated: Duration, after: Option<Cursor>, limit: i64)
+		-> Result<Vec<Ambulance>, AmbulanceTrackerError>;
+
+	/// Returns a list of ambulances whose last location update was between the two times,
+	/// inclusive. Returns [AmbulanceTrackerError::InvalidTimeRange] if `from` is after `to`.
+	async fn get_updated_between(&self, from: DateTime<Utc>, to: DateTime<Utc>)
+		-> Result<Vec<Ambulance>, AmbulanceTrackerError>;
+
+	/// Returns the number of ambulances which have had location updates within the specified
+	/// duration, matching the entries returned by [AmbulanceReader::get_recently_updated]
+	async fn count_recently_updated(&self, last_updated: Duration)
+		-> Result<i64, AmbulanceTrackerError>;
+
+	/// Returns the total number of ambulances being tracked
+	async fn count_ambulances(&self) -> Result<i64, AmbulanceTrackerError>;
+
+	/// Returns the ambulance, or `None` if it does not exist or is soft deleted
+	async fn get_ambulance(&self, id: AmbulanceId) -> Result<Option<Ambulance>, AmbulanceTrackerError>;
+
+	/// Returns whether the ambulance exists and is not soft deleted, without reading its location,
+	/// such as to validate an id before tracking it
+	async fn ambulance_exists(&self, id: AmbulanceId) -> Result<bool, AmbulanceTrackerError>;
+
+	/// Returns up to `limit` ambulances whose name contains the query, ignoring case, ordered by
+	/// name. An empty query matches nothing.
+	async fn search_by_name(&self, query: &str, limit: i64) -> Result<Vec<Ambulance>, AmbulanceTrackerError>;
 
-	/// Adds a new ambulance to be tracked, returning the new entry's information
+	/// Returns up to `limit` ambulances ordered by their last update, oldest first, such as to spot
+	/// ambulances which have stopped reporting
+	async fn get_ambulances_by_staleness(&self, limit: i64) -> Result<Vec<Ambulance>, AmbulanceTrackerError>;
+
+}
+
+#[async_trait::async_trait]
+pub trait AmbulanceTracker: AmbulanceReader {
+
+	/// Adds a new ambulance to be tracked, returning the new entry's information.
+	///
+	/// The name is trimmed of surrounding whitespace before being stored. Returns
+	/// [AmbulanceTrackerError::InvalidName] if it is then empty or too long, and
+	/// [AmbulanceTrackerError::InvalidGeometry] if the location is not a finite point.
 	async fn add_ambulance(&self, name: &str, location: geo_types::Point, fetched: DateTime<Utc>)
-		-> Result<Ambulance, Box<dyn std::error::Error>>;
+		-> Result<Ambulance, AmbulanceTrackerError>;
+
+	/// Behaves as [AmbulanceTracker::add_ambulance], but if an ambulance was already added with the
+	/// same idempotency key it is returned as currently stored instead of adding another, so that a
+	/// client can safely retry a request whose response was lost. If that ambulance has since been
+	/// deleted, [AmbulanceTrackerError::AmbulanceNotFound] is returned and the key stays used.
+	async fn add_ambulance_idempotent(&self, name: &str, location: geo_types::Point, fetched: DateTime<Utc>, key: &str)
+		-> Result<Ambulance, AmbulanceTrackerError>;
 
 	/// Updates an ambulances current location if and only if the fetched time is after the previous
 	/// fetched time.
-	async fn update_ambulance(&self, id: Uuid, location: geo_types::Point, fetched: DateTime<Utc>)
+	///
+	/// Returns [AmbulanceTrackerError::TimestampInFuture] if the fetched time is further ahead of
+	/// the current time than the allowed clock skew, since accepting it would block all later
+	/// updates.
+	///
+	/// Implementations may also reject locations which are likely GPS glitches with
+	/// [AmbulanceTrackerError::ImplausibleLocation], leaving the stored location unchanged.
+	///
+	/// A soft deleted ambulance is not updated and gives [AmbulanceTrackerError::AmbulanceNotFound].
+	async fn update_ambulance(&self, id: AmbulanceId, location: geo_types::Point, fetched: DateTime<Utc>)
 		-> Result<(), AmbulanceTrackerError>;
 
-	/// Returns a list of ambulances which have had location updates within the specified duration
-	async fn get_recently_updated(&self, last_updated: Duration)
-		-> Result<Vec<Ambulance>, Box<dyn std::error::Error>>;
+	/// Behaves as [AmbulanceTracker::update_ambulance], but also updates a soft deleted ambulance,
+	/// which stays deleted
+	async fn update_ambulance_include_deleted(&self, id: AmbulanceId, location: geo_types::Point, fetched: DateTime<Utc>)
+		-> Result<(), AmbulanceTrackerError>;
+
+	/// Behaves as [AmbulanceTracker::update_ambulance], but returns `true` if the update was applied
+	/// and `false` if it was skipped because the fetched time was not after the previous one.
+	async fn try_update_ambulance(&self, id: AmbulanceId, location: geo_types::Point, fetched: DateTime<Utc>)
+		-> Result<bool, AmbulanceTrackerError>;
+
+	/// Applies many location updates at once, returning the outcome of each in the order given.
+	/// Each update follows the rules of [AmbulanceTracker::try_update_ambulance], except that when
+	/// an ambulance appears more than once only its latest update can be applied and the rest are
+	/// skipped.
+	///
+	/// The whole batch is rejected with [AmbulanceTrackerError::InvalidGeometry] or
+	/// [AmbulanceTrackerError::TimestampInFuture] if any update would be.
+	async fn bulk_update_locations(&self, updates: &[(AmbulanceId, geo_types::Point, DateTime<Utc>)])
+		-> Result<Vec<UpdateOutcome>, AmbulanceTrackerError>;
+
+	/// Marks an ambulance as deleted, so that it is no longer found or updated, while keeping its
+	/// history. Returns [AmbulanceTrackerError::AmbulanceNotFound] if it does not exist or is
+	/// already deleted.
+	async fn soft_delete_ambulance(&self, id: AmbulanceId) -> Result<(), AmbulanceTrackerError>;
+
+	/// Undoes [AmbulanceTracker::soft_delete_ambulance]. Restoring an ambulance which is not
+	/// deleted does nothing, and [AmbulanceTrackerError::AmbulanceNotFound] is returned if it does
+	/// not exist.
+	async fn restore_ambulance(&self, id: AmbulanceId) -> Result<(), AmbulanceTrackerError>;
+
+}
+
+/// Exposes only the [AmbulanceReader] half of a tracker, so that holders cannot modify ambulances
+/// even if the underlying tracker could.
+pub struct ReadOnlyAmbulanceTracker(Box<dyn AmbulanceReader + 'static + Sync + Send>);
 
-	/// Returns the ambulance
-	async fn get_ambulance(&self, id: Uuid) -> Result<Option<Ambulance>, Box<dyn std::error::Error>>;
+#[async_trait::async_trait]
+impl AmbulanceReader for ReadOnlyAmbulanceTracker {
+	async fn get_recently_updated(&self, last_updated: Duration) -> Result<Vec<Ambulance>, AmbulanceTrackerError> {
+		self.0.get_recently_updated(last_updated).await
+	}
+
+	async fn get_recently_updated_page(&self, last_updated: Duration, after: Option<Cursor>, limit: i64) -> Result<Vec<Ambulance>, AmbulanceTrackerError> {
+		self.0.get_recently_updated_page(last_updated, after, limit).await
+	}
+
+	async fn get_updated_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Ambulance>, AmbulanceTrackerError> {
+		self.0.get_updated_between(from, to).await
+	}
+
+	async fn count_recently_updated(&self, last_updated: Duration) -> Result<i64, AmbulanceTrackerError> {
+		self.0.count_recently_updated(last_updated).await
+	}
+
+	async fn count_ambulances(&self) -> Result<i64, AmbulanceTrackerError> {
+		self.0.count_ambulances().await
+	}
+
+	async fn get_ambulance(&self, id: AmbulanceId) -> Result<Option<Ambulance>, AmbulanceTrackerError> {
+		self.0.get_ambulance(id).await
+	}
+
+	async fn ambulance_exists(&self, id: AmbulanceId) -> Result<bool, AmbulanceTrackerError> {
+		self.0.ambulance_exists(id).await
+	}
+
+	async fn search_by_name(&self, query: &str, limit: i64) -> Result<Vec<Ambulance>, AmbulanceTrackerError> {
+		self.0.search_by_name(query, limit).await
+	}
 
+	async fn get_ambulances_by_staleness(&self, limit: i64) -> Result<Vec<Ambulance>, AmbulanceTrackerError> {
+		self.0.get_ambulances_by_staleness(limit).await
+	}
 }
+
+impl ReadOnlyAmbulanceTracker {
+	pub fn new(reader: Box<dyn AmbulanceReader + 'static + Sync + Send>) -> Self {
+		Self(reader)
+	}
+}
+
+/// Serves a fixed set of ambulances from memory, for testing code which only reads ambulances
+/// without a database. Every ambulance is treated as not deleted, and the recently updated window
+/// has no limit.
+#[cfg(test)]
+pub(crate) struct InMemoryAmbulanceReader(Vec<Ambulance>);
+
+#[cfg(test)]
+impl InMemoryAmbulanceReader {
+	pub(crate) fn new(ambulances: Vec<Ambulance>) -> Self {
+		Self(ambulances)
+	}
+
+	fn updated_within(&self, last_updated: Duration) -> impl Iterator<Item = &Ambulance> {
+		let from = Utc::now() - last_updated;
+		self.0.iter().filter(move |ambulance| ambulance.last_updated >= from)
+	}
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl AmbulanceReader for InMemoryAmbulanceReader {
+	async fn get_recently_updated(&self, last_updated: Duration) -> Result<Vec<Ambulance>, AmbulanceTrackerError> {
+		Ok(self.updated_within(last_updated).cloned().collect())
+	}
+
+	async fn get_recently_updated_page(&self, last_updated: Duration, after: Option<Cursor>, limit: i64) -> Result<Vec<Ambulance>, AmbulanceTrackerError> {
+		let mut page: Vec<Ambulance> = self.updated_within(last_updated)
+			.filter(|ambulance| after.map_or(true, |after| (ambulance.last_updated, ambulance.id.0) < (after.time, after.id)))
+			.cloned()
+			.collect();
+		page.sort_by(|a, b| (b.last_updated, b.id.0).cmp(&(a.last_updated, a.id.0)));
+		page.truncate(limit.max(0) as usize);
+		Ok(page)
+	}
+
+	async fn get_updated_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Ambulance>, AmbulanceTrackerError> {
+		if from > to {
+			return Err(AmbulanceTrackerError::InvalidTimeRange);
+		}
+		Ok(self.0.iter().filter(|ambulance| (from..=to).contains(&ambulance.last_updated)).cloned().collect())
+	}
+
+	async fn count_recently_updated(&self, last_updated: Duration) -> Result<i64, AmbulanceTrackerError> {
+		Ok(self.updated_within(last_updated).count() as i64)
+	}
+
+	async fn count_ambulances(&self) -> Result<i64, AmbulanceTrackerError> {
+		Ok(self.0.len() as i64)
+	}
+
+	async fn get_ambulance(&self, id: AmbulanceId) -> Result<Option<Ambulance>, AmbulanceTrackerError> {
+		Ok(self.0.iter().find(|ambulance| ambulance.id == id).cloned())
+	}
+
+	async fn ambulance_exists(&self, id: AmbulanceId) -> Result<bool, AmbulanceTrackerError> {
+		Ok(self.0.iter().any(|ambulance| ambulance.id == id))
+	}
+
+	async fn search_by_name(&self, query: &str, limit: i64) -> Result<Vec<Ambulance>, AmbulanceTrackerError> {
+		if query.is_empty() {
+			return Ok(Vec::new());
+		}
+		let query = query.to_lowercase();
+		let mut found: Vec<Ambulance> = self.0.iter().filter(|ambulance| ambulance.name.to_lowercase().contains(&query)).cloned().collect();
+		found.sort_by(|a, b| a.name.cmp(&b.name));
+		found.truncate(limit.max(0) as usize);
+		Ok(found)
+	}
+
+	async fn get_ambulances_by_staleness(&self, limit: i64) -> Result<Vec<Ambulance>, AmbulanceTrackerError> {
+		let mut ambulances = self.0.clone();
+		ambulances.sort_by_key(|ambulance| ambulance.last_updated);
+		ambulances.truncate(limit.max(0) as usize);
+		Ok(ambulances)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::sql::sql_ambulance_tracker::SQLAmbulanceTracker;
+	use std::marker::PhantomData;
+
+	/// Tells at compile time whether `T` is an [AmbulanceTracker], as the inherent method is only
+	/// picked over the trait's when the bound holds
+	struct Probe<T>(PhantomData<T>);
+
+	trait NotATracker {
+		fn is_tracker(&self) -> bool {
+			false
+		}
+	}
+
+	impl<T> NotATracker for Probe<T> {}
+
+	impl<T: AmbulanceTracker> Probe<T> {
+		fn is_tracker(&self) -> bool {
+			true
+		}
+	}
+
+	#[test]
+	fn read_only_tracker_is_not_a_tracker() {
+		assert!(!Probe::<ReadOnlyAmbulanceTracker>(PhantomData).is_tracker());
+		assert!(Probe::<SQLAmbulanceTracker>(PhantomData).is_tracker());
+	}
+}
\ No newline at end of file
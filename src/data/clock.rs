@@ -0,0 +1,62 @@
+use std::sync::Mutex;
+use std::time::Duration;
+use sqlx::types::chrono::{DateTime, Utc};
+
+/// The source of the current time for time dependent logic, such as session expiry and the
+/// recently updated window, so that tests can control it
+pub trait Clock: Send + Sync {
+	fn now(&self) -> DateTime<Utc>;
+}
+
+/// Reads the system clock, used by every manager unless configured otherwise
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+	fn now(&self) -> DateTime<Utc> {
+		Utc::now()
+	}
+}
+
+/// A clock which only moves when told to
+#[derive(Debug)]
+pub struct ManualClock(Mutex<DateTime<Utc>>);
+
+impl ManualClock {
+	pub fn new(now: DateTime<Utc>) -> Self {
+		Self(Mutex::new(now))
+	}
+
+	pub fn set(&self, now: DateTime<Utc>) {
+		*self.0.lock().unwrap() = now;
+	}
+
+	pub fn advance(&self, by: Duration) {
+		*self.0.lock().unwrap() += by;
+	}
+}
+
+impl Clock for ManualClock {
+	fn now(&self) -> DateTime<Utc> {
+		*self.0.lock().unwrap()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn manual_clock_only_moves_when_told() {
+		let start = DateTime::UNIX_EPOCH;
+		let clock = ManualClock::new(start);
+		assert_eq!(clock.now(), start);
+		assert_eq!(clock.now(), start);
+
+		clock.advance(Duration::from_secs(90));
+		assert_eq!(clock.now(), start + Duration::from_secs(90));
+
+		clock.set(start);
+		assert_eq!(clock.now(), start);
+	}
+}
\ No newline at end of file
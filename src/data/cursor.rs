@@ -0,0 +1,182 @@
+use std::fmt;
+use std::str::FromStr;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::types::Uuid;
+use thiserror::Error;
+use crate::data::{AccountInfo, Ambulance};
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+const VERSION: u8 = 1;
+/// Version, timestamp in microseconds, id and checksum
+const ENCODED_BYTES: usize = 1 + 8 + 16 + 2;
+/// Every three bytes become four characters, so no padding is needed
+const ENCODED_LENGTH: usize = ENCODED_BYTES / 3 * 4;
+
+/// The position of the last entry of a page, handed back to the caller as an opaque URL-safe
+/// base64 string so that the next page can be requested with a single value.
+///
+/// Entries are ordered by `time` and then `id`, matching the keyset used by the paginated reads.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Cursor {
+	pub time: DateTime<Utc>,
+	pub id: Uuid,
+}
+
+impl Cursor {
+	pub fn new(time: DateTime<Utc>, id: Uuid) -> Self {
+		Self { time, id }
+	}
+
+	/// Encodes the cursor. Timestamps are kept to microsecond precision, matching the database.
+	pub fn encode(&self) -> String {
+		let mut bytes = [0u8; ENCODED_BYTES];
+		bytes[0] = VERSION;
+		bytes[1..9].copy_from_slice(&self.time.timestamp_micros().to_be_bytes());
+		bytes[9..25].copy_from_slice(self.id.as_bytes());
+		let checksum = checksum(&bytes[..25]);
+		bytes[25..].copy_from_slice(&checksum.to_be_bytes());
+
+		let mut encoded = String::with_capacity(ENCODED_LENGTH);
+		for chunk in bytes.chunks_exact(3) {
+			let n = (chunk[0] as u32) << 16 | (chunk[1] as u32) << 8 | chunk[2] as u32;
+			for shift in [18, 12, 6, 0] {
+				encoded.push(ALPHABET[(n >> shift & 0x3f) as usize] as char);
+			}
+		}
+		encoded
+	}
+
+	pub fn decode(encoded: &str) -> Result<Self, CursorError> {
+		fn sextet(c: u8) -> Result<u32, CursorError> {
+			match c {
+				b'A'..=b'Z' => Ok((c - b'A') as u32),
+				b'a'..=b'z' => Ok((c - b'a' + 26) as u32),
+				b'0'..=b'9' => Ok((c - b'0' + 52) as u32),
+				b'-' => Ok(62),
+				b'_' => Ok(63),
+				_ => Err(CursorError::InvalidCharacter)
+			}
+		}
+
+		let encoded = encoded.as_bytes();
+		if encoded.len() != ENCODED_LENGTH {
+			return Err(CursorError::InvalidLength(encoded.len()));
+		}
+
+		let mut bytes = [0u8; ENCODED_BYTES];
+		for (i, chunk) in encoded.chunks_exact(4).enumerate() {
+			let mut n = 0;
+			for &c in chunk {
+				n = n << 6 | sextet(c)?;
+			}
+			bytes[i * 3..i * 3 + 3].copy_from_slice(&n.to_be_bytes()[1..]);
+		}
+
+		if u16::from_be_bytes([bytes[25], bytes[26]]) != checksum(&bytes[..25]) {
+			return Err(CursorError::ChecksumMismatch);
+		}
+		if bytes[0] != VERSION {
+			return Err(CursorError::UnsupportedVersion(bytes[0]));
+		}
+
+		let micros = i64::from_be_bytes(bytes[1..9].try_into().unwrap());
+		let time = DateTime::from_timestamp_micros(micros).ok_or(CursorError::InvalidTimestamp)?;
+		let id = Uuid::from_bytes(bytes[9..25].try_into().unwrap());
+		Ok(Self { time, id })
+	}
+}
+
+/// Fletcher-16, enough to catch a cursor which was edited or truncated by hand
+fn checksum(bytes: &[u8]) -> u16 {
+	let (mut a, mut b) = (0u16, 0u16);
+	for &byte in bytes {
+		a = (a + byte as u16) % 255;
+		b = (b + a) % 255;
+	}
+	b << 8 | a
+}
+
+impl From<&Ambulance> for Cursor {
+	fn from(ambulance: &Ambulance) -> Self {
+		Self::new(ambulance.last_updated, ambulance.id.0)
+	}
+}
+
+impl From<&AccountInfo> for Cursor {
+	fn from(account: &AccountInfo) -> Self {
+		Self::new(account.created_at, account.id.0)
+	}
+}
+
+impl fmt::Display for Cursor {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.encode())
+	}
+}
+
+impl FromStr for Cursor {
+	type Err = CursorError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Self::decode(s)
+	}
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum CursorError {
+	#[error("A cursor must be exactly 36 characters, found {0}.")]
+	InvalidLength(usize),
+	#[error("A cursor may only contain URL-safe base64 characters.")]
+	InvalidCharacter,
+	#[error("The cursor has been modified.")]
+	ChecksumMismatch,
+	#[error("Unsupported cursor version {0}.")]
+	UnsupportedVersion(u8),
+	#[error("The cursor's timestamp is out of range.")]
+	InvalidTimestamp
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sqlx::types::chrono::SubsecRound;
+
+	fn cursor() -> Cursor {
+		Cursor::new(Utc::now().trunc_subsecs(6), Uuid::new_v4())
+	}
+
+	#[test]
+	fn cursor_round_trips() {
+		let cursor = cursor();
+		let encoded = cursor.encode();
+		assert_eq!(encoded.len(), ENCODED_LENGTH);
+		assert!(encoded.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_'));
+		assert_eq!(Cursor::decode(&encoded), Ok(cursor));
+		assert_eq!(encoded.parse::<Cursor>(), Ok(cursor));
+		assert_eq!(cursor.to_string(), encoded);
+
+		let epoch = Cursor::new(DateTime::UNIX_EPOCH, Uuid::nil());
+		assert_eq!(Cursor::decode(&epoch.encode()), Ok(epoch));
+	}
+
+	#[test]
+	fn cursor_rejects_tampering() {
+		let encoded = cursor().encode();
+		for i in 0..encoded.len() {
+			let mut tampered = encoded.clone().into_bytes();
+			tampered[i] = if tampered[i] == b'A' { b'B' } else { b'A' };
+			let tampered = String::from_utf8(tampered).unwrap();
+			assert_eq!(Cursor::decode(&tampered), Err(CursorError::ChecksumMismatch), "{}", tampered);
+		}
+	}
+
+	#[test]
+	fn cursor_rejects_malformed() {
+		let encoded = cursor().encode();
+		assert_eq!(Cursor::decode(""), Err(CursorError::InvalidLength(0)));
+		assert_eq!(Cursor::decode(&encoded[1..]), Err(CursorError::InvalidLength(35)));
+		assert_eq!(Cursor::decode(&format!("{}=", &encoded[1..])), Err(CursorError::InvalidCharacter));
+		assert_eq!(Cursor::decode(&format!("{}+", &encoded[1..])), Err(CursorError::InvalidCharacter));
+	}
+}
\ No newline at end of file
@@ -0,0 +1,137 @@
+use rand::TryRngCore;
+use sqlx::types::Uuid;
+
+/// Identifies a single user action across every manager it touches, so that log lines emitted
+/// while handling it can be correlated.
+///
+/// The convention is to create one context per incoming request and run each manager call inside
+/// its span, after which every event emitted by the managers carries a `request_id` field:
+///
+/// ```ignore
+/// use tracing::Instrument;
+///
+/// let ctx = RequestContext::new()?;
+/// let token = accounts.login(username, password).instrument(ctx.span()).await?;
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RequestContext {
+	pub request_id: Uuid
+}
+
+impl RequestContext {
+	/// Creates a context with a random v4 request id
+	pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+		let mut bytes = [0u8; 16];
+		rand::rngs::OsRng.try_fill_bytes(&mut bytes)?;
+		Ok(Self::with_id(sqlx::types::uuid::Builder::from_random_bytes(bytes).into_uuid()))
+	}
+
+	/// Creates a context for an existing request id, such as one received from an upstream proxy
+	pub fn with_id(request_id: Uuid) -> Self {
+		Self { request_id }
+	}
+
+	/// Returns a span carrying the request id, within which manager calls should be run
+	pub fn span(&self) -> tracing::Span {
+		tracing::info_span!("request", request_id = %self.request_id)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::eta::fixed_eta::{FixedEta, NoRouteEta};
+	use crate::data::{AccountManager, AmbulanceTracker, SettingsManager, TrackingManager, Urgency};
+	use crate::sql::archive_eta::ArchiveEta;
+	use crate::sql::sql_account_manager::SqlAccountManager;
+	use crate::sql::sql_ambulance_tracker::SQLAmbulanceTracker;
+	use crate::sql::sql_settings_manager::SQLSettingsManager;
+	use crate::sql::sql_tracking_manager::SQLTrackingManager;
+	use geo_types::Point;
+	use sqlx::types::chrono::Utc;
+	use sqlx::PgPool;
+	use std::fmt::Debug;
+	use std::sync::{Arc, Mutex};
+	use std::time::Duration;
+	use tracing::field::{Field, Visit};
+	use tracing::span::{Attributes, Id};
+	use tracing::{Event, Instrument, Subscriber};
+	use tracing_subscriber::layer::{Context, SubscriberExt};
+	use tracing_subscriber::registry::LookupSpan;
+	use tracing_subscriber::Layer;
+
+	struct RequestIdField(String);
+
+	struct RequestIdVisitor(Option<String>);
+	impl Visit for RequestIdVisitor {
+		fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+			if field.name() == "request_id" {
+				self.0 = Some(format!("{:?}", value));
+			}
+		}
+	}
+
+	/// Records the request id of the innermost span carrying one for every event emitted by this
+	/// crate, ignoring events from dependencies
+	struct CaptureRequestIds(Arc<Mutex<Vec<Option<String>>>>);
+	impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for CaptureRequestIds {
+		fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+			let mut visitor = RequestIdVisitor(None);
+			attrs.record(&mut visitor);
+			if let (Some(request_id), Some(span)) = (visitor.0, ctx.span(id)) {
+				span.extensions_mut().insert(RequestIdField(request_id));
+			}
+		}
+
+		fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+			if !event.metadata().target().starts_with(env!("CARGO_CRATE_NAME")) {
+				return;
+			}
+
+			let request_id = ctx.event_scope(event).and_then(|scope| scope
+				.filter_map(|span| span.extensions().get::<RequestIdField>().map(|field| field.0.clone()))
+				.next());
+			self.0.lock().unwrap().push(request_id);
+		}
+	}
+
+	#[test]
+	fn new_contexts_are_unique() {
+		let ctx = RequestContext::new().unwrap();
+		assert_ne!(ctx, RequestContext::new().unwrap());
+		assert_eq!(ctx.request_id.get_version_num(), 4);
+	}
+
+	#[sqlx::test]
+	async fn events_share_request_id(pool: PgPool) {
+		let events = Arc::new(Mutex::new(Vec::new()));
+		let _guard = tracing::subscriber::set_default(
+			tracing_subscriber::registry().with(CaptureRequestIds(events.clone()))
+		);
+
+		let accounts = SqlAccountManager::new(pool.clone());
+		let (site_admin, _) = accounts.create_site_admin("root").await.unwrap();
+		let ambulances = SQLAmbulanceTracker::new(pool.clone());
+		let ambulance = ambulances.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), Utc::now()).await.unwrap();
+		let archive = ArchiveEta::new(pool.clone(), Box::new(FixedEta::new(Duration::from_secs(60)))).with_best_effort(true);
+		let phone = SQLSettingsManager::new(pool.clone()).new_phone(site_admin, "0123456789", "Home").await.unwrap();
+		let setup_tracking = SQLTrackingManager::new(pool.clone(), Box::new(FixedEta::new(Duration::from_secs(60))));
+		setup_tracking.track_ambulance(site_admin, ambulance.id, "patient", Urgency::High, Some(Point::new(1.0, 1.0)), (phone.phone_id, Duration::from_secs(300))).await.unwrap();
+		setup_tracking.set_destination(site_admin, ambulance.id, Point::new(1.0, 1.0)).await.unwrap();
+		let tracking = SQLTrackingManager::new(pool.clone(), Box::new(NoRouteEta));
+		sqlx::query("DROP TABLE archive_etas").execute(&pool).await.unwrap();
+
+		let ctx = RequestContext::new().unwrap();
+		async {
+			// both managers emit a warning, one for the failed archive and one for the failed refresh
+			archive.calculate_eta_for_ambulance(&ambulances, ambulance.id, Point::new(1.0, 1.0)).await.unwrap();
+			tracking.refresh_tracked_etas().await.unwrap();
+		}.instrument(ctx.span()).await;
+
+		let events = events.lock().unwrap();
+		assert!(events.len() >= 2, "expected events from both managers, found {:?}", events);
+		for request_id in events.iter() {
+			assert_eq!(request_id.as_deref(), Some(&*ctx.request_id.to_string()));
+		}
+	}
+}
\ No newline at end of file
@@ -1,2 +1,5 @@
 pub mod eta_finder;
-pub mod mapbox_eta;
\ No newline at end of file
+pub mod fixed_eta;
+pub mod mapbox_eta;
+pub mod timed_eta;
+pub mod speed_profile_eta;
\ No newline at end of file
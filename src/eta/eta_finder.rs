@@ -1,10 +1,235 @@
 use std::time::Duration;
 use geo_types::Point;
+use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::types::Uuid;
+use thiserror::Error;
+use crate::data::{Ambulance, AmbulanceId, AmbulanceReader, AmbulanceTrackerError};
+
+/// Routing preferences for an ETA calculation. Finders ignore any options they do not support.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EtaOptions {
+	pub exclude_tolls: bool,
+	pub exclude_ferries: bool,
+	pub exclude_motorways: bool,
+	/// When the route should start, rather than now
+	pub depart_at: Option<DateTime<Utc>>
+}
+
+/// Why an ETA could not be calculated, allowing composed finders to decide whether to retry or
+/// fall back without inspecting error messages
+#[derive(Debug, Error)]
+pub enum EtaError {
+	/// The provider could not be reached
+	#[error("network error: {0}")]
+	Network(#[source] Box<dyn std::error::Error + Send + Sync>),
+	/// The provider was reached but reported an error or returned an unusable response
+	#[error("provider error: {0}")]
+	Provider(String),
+	/// The provider rejected the configured credentials
+	#[error("provider rejected the credentials")]
+	Unauthorized,
+	/// The provider could not find a route between the points
+	#[error("no route found")]
+	NoRoute,
+	/// A point is outside the WGS84 longitude and latitude ranges, such as when the two are swapped
+	#[error("coordinates are out of range")]
+	InvalidCoordinates,
+	#[error("eta calculation timed out")]
+	Timeout,
+	#[error("other error: {0}")]
+	Other(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl EtaError {
+	/// A short label for the kind of error, suitable for storing or reporting
+	pub fn category(&self) -> &'static str {
+		match self {
+			EtaError::Network(_) => "network",
+			EtaError::Provider(_) => "provider",
+			EtaError::Unauthorized => "unauthorized",
+			EtaError::NoRoute => "no_route",
+			EtaError::InvalidCoordinates => "invalid_coordinates",
+			EtaError::Timeout => "timeout",
+			EtaError::Other(_) => "other",
+		}
+	}
+}
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for EtaError {
+	fn from(e: Box<dyn std::error::Error + Send + Sync>) -> Self {
+		EtaError::Other(e)
+	}
+}
+
+/// Why the ETA of a tracked ambulance could not be calculated
+#[derive(Debug, Error)]
+pub enum AmbulanceEtaError {
+	#[error("ambulance not found")]
+	AmbulanceNotFound,
+	#[error("ambulance lookup failed: {0}")]
+	Tracker(AmbulanceTrackerError),
+	#[error("{0}")]
+	Eta(EtaError),
+}
 
 #[async_trait::async_trait]
 pub trait EtaFinder {
 
-	async fn calculate_eta(&self, ambulance_id: Uuid, from: Point, to: Point) -> Result<Duration, Box<dyn std::error::Error>>;
+	async fn calculate_eta(&self, ambulance_id: Uuid, from: Point, to: Point) -> Result<Duration, EtaError>;
 
+	/// A label identifying the provider of this finder's ETAs, such as `mapbox`
+	fn source(&self) -> &str {
+		"unknown"
+	}
+
+	/// Calculates an ETA along with the label of the provider which produced it.
+	///
+	/// Finders which delegate to other finders should override this to report the provider which
+	/// was actually used.
+	async fn calculate_eta_with_source(&self, ambulance_id: Uuid, from: Point, to: Point) -> Result<(Duration, String), EtaError> {
+		let eta = self.calculate_eta(ambulance_id, from, to).await?;
+		Ok((eta, self.source().to_string()))
+	}
+
+	/// Calculates an optimistic and a pessimistic ETA, in that order.
+	///
+	/// By default this applies a spread of [DEFAULT_ETA_SPREAD] in either direction around
+	/// [EtaFinder::calculate_eta]. Finders with better knowledge of uncertainty should override it.
+	async fn calculate_eta_range(&self, ambulance_id: Uuid, from: Point, to: Point) -> Result<(Duration, Duration), EtaError> {
+		let eta = self.calculate_eta(ambulance_id, from, to).await?;
+		Ok(spread_eta(eta, DEFAULT_ETA_SPREAD))
+	}
+
+	/// Calculates an ETA using the specified routing options.
+	///
+	/// By default the options are ignored and this is the same as [EtaFinder::calculate_eta].
+	async fn calculate_eta_with_options(&self, ambulance_id: Uuid, from: Point, to: Point, _options: &EtaOptions) -> Result<Duration, EtaError> {
+		self.calculate_eta(ambulance_id, from, to).await
+	}
+
+	/// Calculates an ETA using the specified routing options, along with the label of the provider
+	/// which produced it.
+	///
+	/// Finders which delegate to other finders should override this to report the provider which
+	/// was actually used.
+	async fn calculate_eta_with_options_and_source(&self, ambulance_id: Uuid, from: Point, to: Point, options: &EtaOptions) -> Result<(Duration, String), EtaError> {
+		let eta = self.calculate_eta_with_options(ambulance_id, from, to, options).await?;
+		Ok((eta, self.source().to_string()))
+	}
+
+	/// Calculates the ETA of an ambulance from its current location, as known to the tracker, to
+	/// the specified point.
+	async fn calculate_eta_for_ambulance(&self, tracker: &(dyn AmbulanceReader + Send + Sync), ambulance_id: AmbulanceId, to: Point) -> Result<Duration, AmbulanceEtaError> {
+		let ambulance = find_ambulance(tracker, ambulance_id).await?;
+		self.calculate_eta(ambulance_id.0, ambulance.location, to).await.map_err(AmbulanceEtaError::Eta)
+	}
+
+}
+
+async fn find_ambulance(tracker: &(dyn AmbulanceReader + Send + Sync), id: AmbulanceId) -> Result<Ambulance, AmbulanceEtaError> {
+	match tracker.get_ambulance(id).await {
+		Ok(Some(ambulance)) => Ok(ambulance),
+		Ok(None) => Err(AmbulanceEtaError::AmbulanceNotFound),
+		Err(e) => Err(AmbulanceEtaError::Tracker(e))
+	}
 }
+
+/// Fetches an ambulance and calculates its ETA from its current location to the specified point,
+/// returning both so that callers see the location the ETA was calculated from.
+pub async fn get_ambulance_with_eta(tracker: &(dyn AmbulanceReader + Send + Sync), finder: &(dyn EtaFinder + Send + Sync), id: AmbulanceId, to: Point) -> Result<(Ambulance, Duration), AmbulanceEtaError> {
+	let ambulance = find_ambulance(tracker, id).await?;
+	let eta = finder.calculate_eta(id.0, ambulance.location, to).await.map_err(AmbulanceEtaError::Eta)?;
+	Ok((ambulance, eta))
+}
+
+/// The fraction of an ETA by which the default range extends in either direction
+pub const DEFAULT_ETA_SPREAD: f64 = 0.15;
+
+/// Widens a point estimate into an (optimistic, pessimistic) range by the given fraction in either
+/// direction. The fraction is clamped to `[0, 1]`.
+pub fn spread_eta(eta: Duration, spread: f64) -> (Duration, Duration) {
+	let spread = spread.clamp(0.0, 1.0);
+	(eta.mul_f64(1.0 - spread), eta.mul_f64(1.0 + spread))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::data::InMemoryAmbulanceReader;
+
+	/// Knows a single ambulance, located at the origin
+	fn tracker(id: Uuid) -> InMemoryAmbulanceReader {
+		InMemoryAmbulanceReader::new(vec![Ambulance {
+			id: AmbulanceId::new(id),
+			name: "Ambulance 1".to_string(),
+			location: Point::new(0.0, 0.0),
+			last_updated: Utc::now()
+		}])
+	}
+
+	/// Takes one minute per unit of distance along the x axis
+	struct DistanceEta;
+
+	#[async_trait::async_trait]
+	impl EtaFinder for DistanceEta {
+		async fn calculate_eta(&self, _ambulance_id: Uuid, from: Point, to: Point) -> Result<Duration, EtaError> {
+			Ok(Duration::from_secs(((to.x() - from.x()).abs() * 60.0) as u64))
+		}
+	}
+
+	#[tokio::test]
+	async fn eta_for_ambulance_uses_current_location() {
+		let id = AmbulanceId::new(Uuid::from_u128(1));
+		let eta = DistanceEta.calculate_eta_for_ambulance(&tracker(id.0), id, Point::new(5.0, 0.0)).await.unwrap();
+		assert_eq!(eta, Duration::from_secs(300));
+	}
+
+	#[tokio::test]
+	async fn eta_for_unknown_ambulance() {
+		let result = DistanceEta.calculate_eta_for_ambulance(&tracker(Uuid::from_u128(1)), AmbulanceId::new(Uuid::from_u128(2)), Point::new(5.0, 0.0)).await;
+		assert!(matches!(result, Err(AmbulanceEtaError::AmbulanceNotFound)));
+	}
+
+	#[tokio::test]
+	async fn ambulance_with_eta() {
+		let id = AmbulanceId::new(Uuid::from_u128(1));
+		let (ambulance, eta) = get_ambulance_with_eta(&tracker(id.0), &DistanceEta, id, Point::new(3.0, 0.0)).await.unwrap();
+		assert_eq!(ambulance.id, id);
+		assert_eq!(ambulance.location, Point::new(0.0, 0.0));
+		assert_eq!(eta, Duration::from_secs(180));
+	}
+
+	#[tokio::test]
+	async fn ambulance_with_eta_unknown_ambulance() {
+		let result = get_ambulance_with_eta(&tracker(Uuid::from_u128(1)), &DistanceEta, AmbulanceId::new(Uuid::from_u128(2)), Point::new(3.0, 0.0)).await;
+		assert!(matches!(result, Err(AmbulanceEtaError::AmbulanceNotFound)));
+	}
+
+	#[test]
+	fn spread_eta_is_ordered() {
+		let (low, high) = spread_eta(Duration::from_secs(600), DEFAULT_ETA_SPREAD);
+		assert!(low <= Duration::from_secs(600) && Duration::from_secs(600) <= high);
+
+		let (low, high) = spread_eta(Duration::from_secs(600), 0.5);
+		assert_eq!(low, Duration::from_secs(300));
+		assert_eq!(high, Duration::from_secs(900));
+
+		let (low, high) = spread_eta(Duration::from_secs(600), 0.0);
+		assert_eq!(low, high);
+
+		let (low, _) = spread_eta(Duration::from_secs(600), 5.0);
+		assert_eq!(low, Duration::ZERO);
+	}
+
+	#[test]
+	fn boxed_errors_convert_to_other() {
+		use std::error::Error;
+
+		let boxed: Box<dyn Error + Send + Sync> = Box::new(std::io::Error::new(std::io::ErrorKind::Other, "failed"));
+		let e: EtaError = boxed.into();
+		assert!(matches!(e, EtaError::Other(_)));
+		assert_eq!(e.to_string(), "other error: failed");
+		// the boxed error is kept rather than only its message
+		assert!(e.source().unwrap().downcast_ref::<std::io::Error>().is_some());
+	}
+}
\ No newline at end of file
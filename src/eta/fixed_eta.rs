@@ -0,0 +1,68 @@
+use std::time::Duration;
+use geo_types::Point;
+use sqlx::types::Uuid;
+use crate::eta::eta_finder::{EtaError, EtaFinder};
+
+/// Reports the same ETA for every route, for tests and for running without a routing provider.
+/// The ETAs are labelled `fixed` unless another source is set.
+pub struct FixedEta(Duration, String);
+
+#[async_trait::async_trait]
+impl EtaFinder for FixedEta {
+	async fn calculate_eta(&self, _ambulance_id: Uuid, _from: Point, _to: Point) -> Result<Duration, EtaError> {
+		Ok(self.0)
+	}
+
+	fn source(&self) -> &str {
+		&self.1
+	}
+}
+
+impl FixedEta {
+	pub fn new(eta: Duration) -> Self {
+		Self(eta, "fixed".to_string())
+	}
+
+	/// Labels the ETAs with the source, such as to stand in for a particular provider
+	pub fn with_source(mut self, source: &str) -> Self {
+		self.1 = source.to_string();
+		self
+	}
+}
+
+/// Never finds a route, failing every calculation with [EtaError::NoRoute], for testing how
+/// failed calculations are handled
+pub struct NoRouteEta;
+
+#[async_trait::async_trait]
+impl EtaFinder for NoRouteEta {
+	async fn calculate_eta(&self, _ambulance_id: Uuid, _from: Point, _to: Point) -> Result<Duration, EtaError> {
+		Err(EtaError::NoRoute)
+	}
+
+	fn source(&self) -> &str {
+		"no_route"
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn fixed_eta_ignores_route() {
+		let finder = FixedEta::new(Duration::from_secs(90));
+		let eta = finder.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(10.0, 10.0)).await.unwrap();
+		assert_eq!(eta, Duration::from_secs(90));
+		assert_eq!(finder.calculate_eta_with_source(Uuid::nil(), Point::new(1.0, 1.0), Point::new(1.0, 1.0)).await.unwrap(), (Duration::from_secs(90), "fixed".to_string()));
+
+		let finder = finder.with_source("mapbox");
+		assert_eq!(finder.calculate_eta_with_source(Uuid::nil(), Point::new(1.0, 1.0), Point::new(1.0, 1.0)).await.unwrap(), (Duration::from_secs(90), "mapbox".to_string()));
+	}
+
+	#[tokio::test]
+	async fn no_route_eta_always_fails() {
+		let result = NoRouteEta.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(10.0, 10.0)).await;
+		assert!(matches!(result, Err(EtaError::NoRoute)), "{:?}", result);
+	}
+}
\ No newline at end of file
@@ -1,47 +1,592 @@
-use std::error::Error;
 use std::time::Duration;
-use geo_types::Point;
+use geo_types::{LineString, Point};
 use sqlx::types::Uuid;
-use crate::eta::eta_finder::EtaFinder;
+use crate::eta::eta_finder::{EtaError, EtaFinder, EtaOptions};
 
-pub struct MapboxEta(String, reqwest::Client);
+pub struct MapboxEta {
+	api_key: String,
+	client: reqwest::Client,
+	base_url: String,
+	timeout: Option<Duration>,
+	/// Road classes directions may use
+	include: Vec<String>,
+	/// Annotations requested with directions
+	annotations: Vec<String>
+}
+
+/// The base URL of the Mapbox API
+pub const MAPBOX_API_URL: &str = "https://api.mapbox.com";
+
+/// The road classes directions may use unless configured otherwise
+pub const DEFAULT_INCLUDE: [&str; 3] = ["hov2", "hov3", "hot"];
+
+/// A short route across central London used by [MapboxEta::check_credentials]
+const CREDENTIALS_CHECK_ROUTE: (Point, Point) = (Point(geo_types::Coord { x: -0.1276, y: 51.5072 }), Point(geo_types::Coord { x: -0.1246, y: 51.5007 }));
 
 #[inline(always)]
-fn build_request_url(from: Point, to: Point, api_key: &str) -> String {
-	format!("https://api.mapbox.com/directions/v5/mapbox/driving-traffic/{},{};{},{}?include=hov2,hov3,hot&overview=false&access_token={}",
+fn build_request_url(mapbox: &MapboxEta, from: Point, to: Point, options: &EtaOptions, geometry: bool) -> String {
+	let MapboxEta { api_key, base_url, include, annotations, .. } = mapbox;
+	let mut url = format!("{}/directions/v5/mapbox/driving-traffic/{},{};{},{}?",
+			base_url,
 			from.x(),
 			from.y(),
 			to.x(),
-			to.y(),
+			to.y()
+	);
+
+	if !include.is_empty() {
+		url.push_str("include=");
+		url.push_str(&include.join(","));
+		url.push('&');
+	}
+	if geometry {
+		url.push_str("overview=full&geometries=geojson");
+	} else {
+		url.push_str("overview=false");
+	}
+	if !annotations.is_empty() {
+		url.push_str("&annotations=");
+		url.push_str(&annotations.join(","));
+	}
+
+	let exclude: Vec<&str> = [
+		(options.exclude_tolls, "toll"),
+		(options.exclude_ferries, "ferry"),
+		(options.exclude_motorways, "motorway")
+	].into_iter().filter(|(enabled, _)| *enabled).map(|(_, class)| class).collect();
+	if !exclude.is_empty() {
+		url.push_str("&exclude=");
+		url.push_str(&exclude.join(","));
+	}
+
+	if let Some(depart_at) = options.depart_at {
+		url.push_str(&format!("&depart_at={}", depart_at.format("%Y-%m-%dT%H:%MZ")));
+	}
+
+	url.push_str("&access_token=");
+	url.push_str(api_key);
+	url
+}
+
+/// Builds a matrix request where the nth source is the start of the nth pair and the nth
+/// destination is its end, so that the durations of the pairs lie along the diagonal
+#[inline(always)]
+fn build_matrix_url(base_url: &str, pairs: &[(Point, Point)], api_key: &str) -> String {
+	let coordinates: Vec<String> = pairs.iter().map(|(from, _)| from)
+		.chain(pairs.iter().map(|(_, to)| to))
+		.map(|pt| format!("{},{}", pt.x(), pt.y()))
+		.collect();
+	let sources: Vec<String> = (0..pairs.len()).map(|i| i.to_string()).collect();
+	let destinations: Vec<String> = (pairs.len()..pairs.len() * 2).map(|i| i.to_string()).collect();
+
+	format!("{}/directions-matrix/v1/mapbox/driving-traffic/{}?sources={}&destinations={}&annotations=duration&access_token={}",
+			base_url,
+			coordinates.join(";"),
+			sources.join(";"),
+			destinations.join(";"),
 			api_key
 	)
 }
 
-#[derive(Debug, thiserror::Error)]
-enum MapboxError {
-	#[error("No routes returned")]
-	NoRoutes
+/// Rejects points outside the WGS84 longitude and latitude ranges, which Mapbox would reject with
+/// an unhelpful error after counting the request against the quota
+fn validate_coordinates(points: impl IntoIterator<Item = Point>) -> Result<(), EtaError> {
+	if points.into_iter().all(|pt| (-180.0..=180.0).contains(&pt.x()) && (-90.0..=90.0).contains(&pt.y())) {
+		Ok(())
+	} else {
+		Err(EtaError::InvalidCoordinates)
+	}
+}
+
+/// Classifies a failed request by whether it timed out or never reached Mapbox
+fn classify_request_error(e: reqwest::Error) -> EtaError {
+	if e.is_timeout() {
+		EtaError::Timeout
+	} else if e.is_connect() || e.is_request() || e.is_body() {
+		EtaError::Network(e.into())
+	} else {
+		EtaError::Other(e.into())
+	}
+}
+
+/// A route's duration along with the path it takes
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteEta {
+	pub duration: Duration,
+	pub geometry: LineString
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct GeoJsonLineString {
+	#[serde(rename = "type")]
+	kind: String,
+	coordinates: Vec<[f64; 2]>
+}
+
+impl GeoJsonLineString {
+	fn into_line_string(self) -> Result<LineString, EtaError> {
+		if self.kind != "LineString" {
+			return Err(EtaError::Provider(format!("expected a LineString route geometry, found {}", self.kind)));
+		}
+		Ok(self.coordinates.into_iter().map(|[x, y]| (x, y)).collect::<Vec<_>>().into())
+	}
 }
 
 #[derive(serde::Deserialize, Debug)]
 struct Route {
-	duration: f64
+	duration: f64,
+	/// Duration under typical traffic conditions, only returned for the driving-traffic profile
+	duration_typical: Option<f64>,
+	/// Only returned when the full overview is requested as GeoJSON
+	geometry: Option<GeoJsonLineString>
+}
+
+impl Route {
+	/// Orders the live traffic and typical traffic durations into an (optimistic, pessimistic)
+	/// range, falling back to the live duration alone if no typical duration is returned
+	fn eta_range(&self) -> Result<(Duration, Duration), EtaError> {
+		let duration = route_duration(self.duration)?;
+		let typical = self.duration_typical.map(route_duration).transpose()?.unwrap_or(duration);
+		Ok((duration.min(typical), duration.max(typical)))
+	}
+}
+
+/// Converts a duration in seconds returned by Mapbox, which is an [EtaError::Provider] error if it
+/// is negative or not finite
+fn route_duration(seconds: f64) -> Result<Duration, EtaError> {
+	Duration::try_from_secs_f64(seconds).map_err(|e| EtaError::Provider(format!("invalid mapbox duration {}: {}", seconds, e)))
 }
+
 #[derive(serde::Deserialize, Debug)]
 struct MapboxResponse {
 	routes: Vec<Route>
 }
 
+#[derive(serde::Deserialize, Debug)]
+struct MatrixResponse {
+	/// Durations in seconds from each source to each destination, or null where unroutable
+	#[serde(default)]
+	durations: Vec<Vec<Option<f64>>>
+}
+
+impl MatrixResponse {
+	/// Returns the duration of each of the requested pairs, using [EtaError::NoRoute] for pairs
+	/// which are null or missing from the response
+	fn pair_durations(&self, pairs: usize) -> Vec<Result<Duration, EtaError>> {
+		(0..pairs).map(|i| {
+			self.durations.get(i)
+				.and_then(|row| row.get(i))
+				.copied()
+				.flatten()
+				.filter(|duration| duration.is_finite() && *duration >= 0.0)
+				.map(Duration::from_secs_f64)
+				.ok_or(EtaError::NoRoute)
+		}).collect()
+	}
+}
+
 #[async_trait::async_trait]
 impl EtaFinder for MapboxEta {
-	async fn calculate_eta(&self, _ambulance_id: Uuid, from: Point, to: Point) -> Result<Duration, Box<dyn Error>> {
-		let resp: MapboxResponse = serde_json::from_slice(&*self.1.get(
-			build_request_url(from, to, &*self.0)
-		).send().await?.bytes().await?)?;
+	async fn calculate_eta(&self, ambulance_id: Uuid, from: Point, to: Point) -> Result<Duration, EtaError> {
+		self.calculate_eta_with_options(ambulance_id, from, to, &EtaOptions::default()).await
+	}
+
+	fn source(&self) -> &str {
+		"mapbox"
+	}
 
-		Ok(Duration::from_secs_f64(resp.routes.first().ok_or(MapboxError::NoRoutes)?.duration))
+	async fn calculate_eta_range(&self, _ambulance_id: Uuid, from: Point, to: Point) -> Result<(Duration, Duration), EtaError> {
+		self.fetch_route(from, to, &EtaOptions::default(), false).await?.eta_range()
+	}
+
+	async fn calculate_eta_with_options(&self, _ambulance_id: Uuid, from: Point, to: Point, options: &EtaOptions) -> Result<Duration, EtaError> {
+		route_duration(self.fetch_route(from, to, options, false).await?.duration)
 	}
 }
 impl MapboxEta {
-	pub fn new(api_key: String) -> Self { Self(api_key, reqwest::Client::new()) }
+	pub fn new(api_key: String) -> Self {
+		Self {
+			api_key,
+			client: reqwest::Client::new(),
+			base_url: MAPBOX_API_URL.to_string(),
+			timeout: None,
+			include: DEFAULT_INCLUDE.map(String::from).to_vec(),
+			annotations: Vec::new()
+		}
+	}
+
+	/// Sets the base URL requests are sent to, such as when going through a proxy
+	pub fn with_base_url(mut self, base_url: &str) -> Self {
+		self.base_url = base_url.trim_end_matches('/').to_string();
+		self
+	}
+
+	/// Sets how long a request may take before failing with [EtaError::Timeout]
+	pub fn with_timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = Some(timeout);
+		self
+	}
+
+	/// Sets the road classes directions may use, defaulting to [DEFAULT_INCLUDE]. An empty list
+	/// omits the `include` parameter entirely.
+	pub fn with_include(mut self, include: &[&str]) -> Self {
+		self.include = include.iter().map(|class| class.to_string()).collect();
+		self
+	}
+
+	/// Sets the annotations requested with directions, such as `duration` and `distance`. None are
+	/// requested by default.
+	pub fn with_annotations(mut self, annotations: &[&str]) -> Self {
+		self.annotations = annotations.iter().map(|annotation| annotation.to_string()).collect();
+		self
+	}
+
+	/// Calculates the ETA of each (from, to) pair with a single matrix request, in the order given.
+	///
+	/// The outer error is returned if the request as a whole fails, or is
+	/// [EtaError::InvalidCoordinates] without a request if any point is out of range. Pairs which
+	/// Mapbox cannot route are [EtaError::NoRoute] without affecting the rest. Mapbox limits how
+	/// many coordinates a single request may contain.
+	pub async fn calculate_etas(&self, pairs: &[(Point, Point)]) -> Result<Vec<Result<Duration, EtaError>>, EtaError> {
+		if pairs.is_empty() {
+			return Ok(Vec::new());
+		}
+
+		validate_coordinates(pairs.iter().flat_map(|(from, to)| [*from, *to]))?;
+		let resp: MatrixResponse = self.get_json(build_matrix_url(&*self.base_url, pairs, &*self.api_key)).await?;
+		Ok(resp.pair_durations(pairs.len()))
+	}
+
+	/// Calculates an ETA along with the route Mapbox expects the ambulance to take, such as for
+	/// drawing on a map. The route is only requested here, as it considerably enlarges the response.
+	pub async fn calculate_eta_with_route(&self, from: Point, to: Point, options: &EtaOptions) -> Result<RouteEta, EtaError> {
+		let route = self.fetch_route(from, to, options, true).await?;
+		let geometry = route.geometry
+			.ok_or_else(|| EtaError::Provider("mapbox did not return the route geometry".to_string()))?
+			.into_line_string()?;
+		Ok(RouteEta {
+			duration: route_duration(route.duration)?,
+			geometry
+		})
+	}
+
+	/// Checks that Mapbox accepts the configured key by requesting a short, known route, returning
+	/// [EtaError::Unauthorized] if it is rejected. Intended to be run once at startup rather than
+	/// alongside ETA calculations.
+	pub async fn check_credentials(&self) -> Result<(), EtaError> {
+		self.fetch_route(CREDENTIALS_CHECK_ROUTE.0, CREDENTIALS_CHECK_ROUTE.1, &EtaOptions::default(), false).await?;
+		Ok(())
+	}
+
+	/// Requests directions and returns the first route, including its geometry if requested
+	async fn fetch_route(&self, from: Point, to: Point, options: &EtaOptions, geometry: bool) -> Result<Route, EtaError> {
+		validate_coordinates([from, to])?;
+		let resp: MapboxResponse = self.get_json(build_request_url(self, from, to, options, geometry)).await?;
+		resp.routes.into_iter().next().ok_or(EtaError::NoRoute)
+	}
+
+	/// Sends a request and parses the JSON response, classifying any failure
+	async fn get_json<T: serde::de::DeserializeOwned>(&self, url: String) -> Result<T, EtaError> {
+		let mut request = self.client.get(url);
+		if let Some(timeout) = self.timeout {
+			request = request.timeout(timeout);
+		}
+
+		let resp = request.send().await.map_err(classify_request_error)?;
+		let status = resp.status();
+		if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+			return Err(EtaError::Unauthorized);
+		}
+		if !status.is_success() {
+			return Err(EtaError::Provider(format!("mapbox responded with {}", status)));
+		}
+
+		let body = resp.bytes().await.map_err(classify_request_error)?;
+		serde_json::from_slice(&*body)
+			.map_err(|e| EtaError::Provider(format!("invalid mapbox response: {}", e)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sqlx::types::chrono::Utc;
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	use tokio::net::TcpListener;
+
+	/// Starts a server which answers a single request with the raw response, or never answers if
+	/// there is none, returning its base URL
+	async fn serve_once(response: Option<&'static str>) -> String {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let addr = listener.local_addr().unwrap();
+
+		tokio::spawn(async move {
+			let (mut socket, _) = listener.accept().await.unwrap();
+			let mut buf = [0u8; 4096];
+			let _ = socket.read(&mut buf).await;
+			match response {
+				Some(response) => socket.write_all(response.as_bytes()).await.unwrap(),
+				None => tokio::time::sleep(Duration::from_secs(30)).await
+			}
+		});
+
+		format!("http://{}", addr)
+	}
+
+	/// Starts a server which answers a single request with the JSON body, returning its base URL
+	async fn serve_json(body: &str) -> String {
+		serve_once(Some(Box::leak(format!(
+			"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+			body.len(),
+			body
+		).into_boxed_str()))).await
+	}
+
+	#[tokio::test]
+	async fn eta_range_uses_typical_duration() {
+		for (body, expected) in [
+			(r#"{"routes":[{"duration":900.0,"duration_typical":600.0,"distance":1000.0}],"code":"Ok"}"#, (600, 900)),
+			(r#"{"routes":[{"duration":300.0,"duration_typical":420.0}],"code":"Ok"}"#, (300, 420)),
+			(r#"{"routes":[{"duration":300.0}],"code":"Ok"}"#, (300, 300))
+		] {
+			let url = serve_json(body).await;
+			let mapbox = MapboxEta::new("key".to_string()).with_base_url(&url);
+
+			let (low, high) = mapbox.calculate_eta_range(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap();
+			assert!(low <= high);
+			assert_eq!((low, high), (Duration::from_secs(expected.0), Duration::from_secs(expected.1)));
+		}
+	}
+
+	fn mapbox() -> MapboxEta {
+		MapboxEta::new("key".to_string())
+	}
+
+	#[test]
+	fn request_url_without_options() {
+		let url = build_request_url(&mapbox(), Point::new(1.5, 2.5), Point::new(3.5, 4.5), &EtaOptions::default(), false);
+		assert_eq!(url, "https://api.mapbox.com/directions/v5/mapbox/driving-traffic/1.5,2.5;3.5,4.5?include=hov2,hov3,hot&overview=false&access_token=key");
+	}
+
+	#[test]
+	fn request_url_with_exclusions() {
+		let options = EtaOptions {
+			exclude_tolls: true,
+			exclude_motorways: true,
+			..EtaOptions::default()
+		};
+		let url = build_request_url(&mapbox(), Point::new(0.0, 0.0), Point::new(1.0, 1.0), &options, false);
+		assert!(url.contains("&exclude=toll,motorway&"), "{}", url);
+		assert!(!url.contains("ferry"));
+
+		let options = EtaOptions {
+			exclude_ferries: true,
+			..EtaOptions::default()
+		};
+		let url = build_request_url(&mapbox(), Point::new(0.0, 0.0), Point::new(1.0, 1.0), &options, false);
+		assert!(url.contains("&exclude=ferry&"), "{}", url);
+	}
+
+	#[test]
+	fn request_url_with_depart_at() {
+		use sqlx::types::chrono::TimeZone;
+
+		let options = EtaOptions {
+			depart_at: Some(Utc.with_ymd_and_hms(2025, 3, 4, 15, 30, 0).unwrap()),
+			..EtaOptions::default()
+		};
+		let url = build_request_url(&mapbox(), Point::new(0.0, 0.0), Point::new(1.0, 1.0), &options, false);
+		assert!(url.contains("&depart_at=2025-03-04T15:30Z&"), "{}", url);
+		assert!(url.ends_with("&access_token=key"));
+	}
+
+	#[test]
+	fn request_url_without_include() {
+		let url = build_request_url(&mapbox().with_include(&[]), Point::new(1.5, 2.5), Point::new(3.5, 4.5), &EtaOptions::default(), false);
+		assert_eq!(url, "https://api.mapbox.com/directions/v5/mapbox/driving-traffic/1.5,2.5;3.5,4.5?overview=false&access_token=key");
+	}
+
+	#[test]
+	fn request_url_with_geometry() {
+		let url = build_request_url(&mapbox().with_include(&[]), Point::new(1.5, 2.5), Point::new(3.5, 4.5), &EtaOptions::default(), true);
+		assert_eq!(url, "https://api.mapbox.com/directions/v5/mapbox/driving-traffic/1.5,2.5;3.5,4.5?overview=full&geometries=geojson&access_token=key");
+	}
+
+	#[test]
+	fn configured_include_and_annotations() {
+		let eta = MapboxEta::new("key".to_string())
+			.with_include(&["hov2"])
+			.with_annotations(&["duration", "distance"]);
+		let url = build_request_url(&eta, Point::new(1.5, 2.5), Point::new(3.5, 4.5), &EtaOptions::default(), false);
+		assert_eq!(url, "https://api.mapbox.com/directions/v5/mapbox/driving-traffic/1.5,2.5;3.5,4.5?include=hov2&overview=false&annotations=duration,distance&access_token=key");
+
+		let eta = MapboxEta::new("key".to_string()).with_include(&[]);
+		let url = build_request_url(&eta, Point::new(1.5, 2.5), Point::new(3.5, 4.5), &EtaOptions::default(), false);
+		assert!(!url.contains("include"), "{}", url);
+	}
+
+	#[tokio::test]
+	async fn timeout_maps_to_timeout() {
+		let url = serve_once(None).await;
+		let mapbox = MapboxEta::new("key".to_string())
+			.with_base_url(&url)
+			.with_timeout(Duration::from_millis(100));
+
+		let result = mapbox.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await;
+		assert!(matches!(result, Err(EtaError::Timeout)), "{:?}", result);
+	}
+
+	#[tokio::test]
+	async fn server_error_maps_to_provider() {
+		let url = serve_once(Some("HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")).await;
+		let mapbox = MapboxEta::new("key".to_string()).with_base_url(&url);
+
+		let result = mapbox.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await;
+		assert!(matches!(result, Err(EtaError::Provider(_))), "{:?}", result);
+	}
+
+	#[tokio::test]
+	async fn empty_routes_maps_to_no_route() {
+		let url = serve_once(Some("HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 25\r\nconnection: close\r\n\r\n{\"routes\":[],\"code\":\"Ok\"}")).await;
+		let mapbox = MapboxEta::new("key".to_string()).with_base_url(&url);
+
+		let result = mapbox.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await;
+		assert!(matches!(result, Err(EtaError::NoRoute)), "{:?}", result);
+	}
+
+	#[test]
+	fn matrix_url_puts_pairs_on_diagonal() {
+		let url = build_matrix_url(MAPBOX_API_URL, &[
+			(Point::new(0.0, 0.5), Point::new(1.0, 1.5)),
+			(Point::new(2.0, 2.5), Point::new(3.0, 3.5))
+		], "key");
+		assert_eq!(url, "https://api.mapbox.com/directions-matrix/v1/mapbox/driving-traffic/0,0.5;2,2.5;1,1.5;3,3.5?sources=0;1&destinations=2;3&annotations=duration&access_token=key");
+	}
+
+	#[test]
+	fn matrix_null_entry_is_no_route() {
+		let resp: MatrixResponse = serde_json::from_str(
+			r#"{"code":"Ok","durations":[[120.0,null,900.0],[300.0,null,60.0],[15.0,30.0,240.0]]}"#
+		).unwrap();
+
+		let etas = resp.pair_durations(3);
+		assert_eq!(etas.len(), 3);
+		assert_eq!(etas[0].as_ref().unwrap(), &Duration::from_secs(120));
+		assert!(matches!(etas[1], Err(EtaError::NoRoute)), "{:?}", etas[1]);
+		assert_eq!(etas[2].as_ref().unwrap(), &Duration::from_secs(240));
+	}
+
+	#[test]
+	fn matrix_missing_entries_are_no_route() {
+		let resp: MatrixResponse = serde_json::from_str(r#"{"durations":[[120.0],[]]}"#).unwrap();
+		let etas = resp.pair_durations(3);
+		assert_eq!(etas.len(), 3);
+		assert!(etas[0].is_ok());
+		assert!(matches!(etas[1], Err(EtaError::NoRoute)));
+		assert!(matches!(etas[2], Err(EtaError::NoRoute)));
+
+		let resp: MatrixResponse = serde_json::from_str(r#"{"code":"NoRoute"}"#).unwrap();
+		assert!(resp.pair_durations(2).iter().all(|eta| matches!(eta, Err(EtaError::NoRoute))));
+	}
+
+	#[tokio::test]
+	async fn calculate_etas_maps_each_pair() {
+		const BODY: &str = r#"{"code":"Ok","durations":[[60.0,null],[null,null]]}"#;
+		let url = serve_json(BODY).await;
+		let mapbox = MapboxEta::new("key".to_string()).with_base_url(&url);
+
+		let etas = mapbox.calculate_etas(&[
+			(Point::new(0.0, 0.0), Point::new(1.0, 1.0)),
+			(Point::new(2.0, 2.0), Point::new(3.0, 3.0))
+		]).await.unwrap();
+		assert_eq!(etas.len(), 2);
+		assert_eq!(etas[0].as_ref().unwrap(), &Duration::from_secs(60));
+		assert!(matches!(etas[1], Err(EtaError::NoRoute)), "{:?}", etas[1]);
+	}
+
+	#[tokio::test]
+	async fn calculate_eta_with_route_decodes_geometry() {
+		const BODY: &str = r#"{"code":"Ok","routes":[{"duration":240.5,"geometry":{"type":"LineString","coordinates":[[-73.98,40.74],[-73.97,40.76],[-73.96,40.78]]}}]}"#;
+		let url = serve_json(BODY).await;
+		let mapbox = MapboxEta::new("key".to_string()).with_base_url(&url);
+
+		let route = mapbox.calculate_eta_with_route(Point::new(-73.98, 40.74), Point::new(-73.96, 40.78), &EtaOptions::default()).await.unwrap();
+		assert_eq!(route.duration, Duration::from_secs_f64(240.5));
+		assert_eq!(route.geometry, LineString::from(vec![(-73.98, 40.74), (-73.97, 40.76), (-73.96, 40.78)]));
+	}
+
+	#[tokio::test]
+	async fn calculate_eta_with_route_rejects_negative_duration() {
+		const BODY: &str = r#"{"code":"Ok","routes":[{"duration":-240.5,"geometry":{"type":"LineString","coordinates":[[-73.98,40.74],[-73.96,40.78]]}}]}"#;
+		let url = serve_json(BODY).await;
+		let mapbox = MapboxEta::new("key".to_string()).with_base_url(&url);
+
+		let result = mapbox.calculate_eta_with_route(Point::new(-73.98, 40.74), Point::new(-73.96, 40.78), &EtaOptions::default()).await;
+		assert!(matches!(result, Err(EtaError::Provider(_))), "{:?}", result);
+	}
+
+	#[test]
+	fn eta_range_rejects_invalid_durations() {
+		for body in [r#"{"duration":-1.0}"#, r#"{"duration":300.0,"duration_typical":-60.0}"#] {
+			let route: Route = serde_json::from_str(body).unwrap();
+			assert!(matches!(route.eta_range(), Err(EtaError::Provider(_))), "{}", body);
+		}
+	}
+
+	#[test]
+	fn route_geometry_must_be_line_string() {
+		let resp: MapboxResponse = serde_json::from_str(
+			r#"{"routes":[{"duration":60.0,"geometry":{"type":"Point","coordinates":[[0.0,0.0]]}}]}"#
+		).unwrap();
+		let geometry = resp.routes.into_iter().next().unwrap().geometry.unwrap();
+		assert!(matches!(geometry.into_line_string(), Err(EtaError::Provider(_))));
+
+		// without a requested overview there is no geometry
+		let resp: MapboxResponse = serde_json::from_str(r#"{"routes":[{"duration":60.0}]}"#).unwrap();
+		assert!(resp.routes[0].geometry.is_none());
+	}
+
+	#[tokio::test]
+	async fn check_credentials_rejected_key() {
+		let url = serve_once(Some("HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")).await;
+		let mapbox = MapboxEta::new("expired".to_string()).with_base_url(&url);
+
+		let result = mapbox.check_credentials().await;
+		assert!(matches!(result, Err(EtaError::Unauthorized)), "{:?}", result);
+	}
+
+	#[tokio::test]
+	async fn check_credentials_accepted_key() {
+		const BODY: &str = r#"{"code":"Ok","routes":[{"duration":120.0}]}"#;
+		let url = serve_json(BODY).await;
+		let mapbox = MapboxEta::new("key".to_string()).with_base_url(&url);
+
+		mapbox.check_credentials().await.unwrap();
+	}
+
+	#[tokio::test]
+	async fn out_of_range_coordinates_are_rejected_locally() {
+		// nothing listens here, so any request would fail with a network error
+		let mapbox = MapboxEta::new("key".to_string()).with_base_url("http://127.0.0.1:9");
+
+		for (from, to) in [
+			(Point::new(51.5072, -0.1276), Point::new(-0.1246, 91.0)),
+			(Point::new(-180.5, 0.0), Point::new(1.0, 1.0)),
+			(Point::new(0.0, 0.0), Point::new(f64::NAN, 1.0))
+		] {
+			let result = mapbox.calculate_eta(Uuid::nil(), from, to).await;
+			assert!(matches!(result, Err(EtaError::InvalidCoordinates)), "{:?}", result);
+		}
+		let result = mapbox.calculate_etas(&[(Point::new(0.0, 0.0), Point::new(1.0, 1.0)), (Point::new(0.0, 95.0), Point::new(1.0, 1.0))]).await;
+		assert!(matches!(result, Err(EtaError::InvalidCoordinates)), "{:?}", result);
+	}
+
+	#[tokio::test]
+	async fn in_range_coordinates_are_requested() {
+		let url = serve_once(Some("HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 40\r\nconnection: close\r\n\r\n{\"routes\":[{\"duration\":90}],\"code\":\"Ok\"}")).await;
+		let mapbox = MapboxEta::new("key".to_string()).with_base_url(&url);
+
+		let result = mapbox.calculate_eta(Uuid::nil(), Point::new(-180.0, -90.0), Point::new(180.0, 90.0)).await;
+		assert_eq!(result.unwrap(), Duration::from_secs(90));
+	}
 }
\ No newline at end of file
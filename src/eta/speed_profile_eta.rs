@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use geo_types::Point;
+use sqlx::types::Uuid;
+use crate::eta::eta_finder::{EtaError, EtaFinder, EtaOptions};
+
+/// Adjusts the inner finder's ETAs for how quickly each ambulance travels compared to the vehicle
+/// the finder assumes, such as a motorcycle responder being faster than an ambulance. Each ETA is
+/// multiplied by the ambulance's factor, so a factor of `0.8` gives an ETA 20% shorter. Ambulances
+/// without a factor use `1.0`.
+pub struct SpeedProfileEta(Box<dyn EtaFinder + Send + Sync>, HashMap<Uuid, f64>);
+
+#[async_trait::async_trait]
+impl EtaFinder for SpeedProfileEta {
+	async fn calculate_eta(&self, ambulance_id: Uuid, from: Point, to: Point) -> Result<Duration, EtaError> {
+		self.adjust(ambulance_id, self.0.calculate_eta(ambulance_id, from, to).await?)
+	}
+
+	fn source(&self) -> &str {
+		self.0.source()
+	}
+
+	async fn calculate_eta_with_source(&self, ambulance_id: Uuid, from: Point, to: Point) -> Result<(Duration, String), EtaError> {
+		let (eta, source) = self.0.calculate_eta_with_source(ambulance_id, from, to).await?;
+		Ok((self.adjust(ambulance_id, eta)?, source))
+	}
+
+	async fn calculate_eta_range(&self, ambulance_id: Uuid, from: Point, to: Point) -> Result<(Duration, Duration), EtaError> {
+		let (optimistic, pessimistic) = self.0.calculate_eta_range(ambulance_id, from, to).await?;
+		Ok((self.adjust(ambulance_id, optimistic)?, self.adjust(ambulance_id, pessimistic)?))
+	}
+
+	async fn calculate_eta_with_options(&self, ambulance_id: Uuid, from: Point, to: Point, options: &EtaOptions) -> Result<Duration, EtaError> {
+		self.adjust(ambulance_id, self.0.calculate_eta_with_options(ambulance_id, from, to, options).await?)
+	}
+
+	async fn calculate_eta_with_options_and_source(&self, ambulance_id: Uuid, from: Point, to: Point, options: &EtaOptions) -> Result<(Duration, String), EtaError> {
+		let (eta, source) = self.0.calculate_eta_with_options_and_source(ambulance_id, from, to, options).await?;
+		Ok((self.adjust(ambulance_id, eta)?, source))
+	}
+}
+
+impl SpeedProfileEta {
+	pub fn new(inner: Box<dyn EtaFinder + Send + Sync>) -> Self {
+		Self(inner, HashMap::new())
+	}
+
+	/// Sets the factor the ambulance's ETAs are multiplied by
+	pub fn with_factor(mut self, ambulance_id: Uuid, factor: f64) -> Self {
+		self.1.insert(ambulance_id, factor);
+		self
+	}
+
+	/// Sets the factors of many ambulances at once, replacing any already set for them
+	pub fn with_factors(mut self, factors: HashMap<Uuid, f64>) -> Self {
+		self.1.extend(factors);
+		self
+	}
+
+	/// Applies the ambulance's factor, failing rather than panicking if the factor is negative or
+	/// not finite
+	fn adjust(&self, ambulance_id: Uuid, eta: Duration) -> Result<Duration, EtaError> {
+		match self.1.get(&ambulance_id) {
+			Some(factor) => Duration::try_from_secs_f64(eta.as_secs_f64() * factor)
+				.map_err(|_| EtaError::Other(format!("invalid speed factor {} for ambulance {}", factor, ambulance_id).into())),
+			None => Ok(eta)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::eta::fixed_eta::FixedEta;
+
+	#[tokio::test]
+	async fn factor_scales_eta() {
+		let fast = Uuid::from_u128(1);
+		let standard = Uuid::from_u128(2);
+		let unknown = Uuid::from_u128(3);
+		let finder = SpeedProfileEta::new(Box::new(FixedEta::new(Duration::from_secs(100))))
+			.with_factor(fast, 0.8)
+			.with_factor(standard, 1.0);
+		let (from, to) = (Point::new(0.0, 0.0), Point::new(1.0, 1.0));
+
+		assert_eq!(finder.calculate_eta(fast, from, to).await.unwrap(), Duration::from_secs(80));
+		assert_eq!(finder.calculate_eta(standard, from, to).await.unwrap(), Duration::from_secs(100));
+		assert_eq!(finder.calculate_eta(unknown, from, to).await.unwrap(), Duration::from_secs(100));
+		assert_eq!(finder.calculate_eta_with_source(fast, from, to).await.unwrap(), (Duration::from_secs(80), "fixed".to_string()));
+		assert_eq!(finder.calculate_eta_with_options(fast, from, to, &EtaOptions::default()).await.unwrap(), Duration::from_secs(80));
+
+		let (optimistic, pessimistic) = finder.calculate_eta_range(fast, from, to).await.unwrap();
+		assert!((optimistic.as_secs_f64() - 68.0).abs() < 1e-6, "{:?}", optimistic);
+		assert!((pessimistic.as_secs_f64() - 92.0).abs() < 1e-6, "{:?}", pessimistic);
+	}
+
+	#[tokio::test]
+	async fn invalid_factor_is_an_error() {
+		let ambulance = Uuid::from_u128(1);
+		let finder = SpeedProfileEta::new(Box::new(FixedEta::new(Duration::from_secs(100))))
+			.with_factors(HashMap::from([(ambulance, -1.0)]));
+
+		let result = finder.calculate_eta(ambulance, Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await;
+		assert!(matches!(result, Err(EtaError::Other(_))), "{:?}", result);
+	}
+}
\ No newline at end of file
@@ -0,0 +1,131 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use geo_types::Point;
+use sqlx::types::Uuid;
+use crate::eta::eta_finder::{EtaError, EtaFinder, EtaOptions};
+
+/// Receives the provider label, the time taken and whether the calculation succeeded
+pub type LatencyRecorder = Arc<dyn Fn(&str, Duration, bool) + Send + Sync>;
+
+/// Measures how long each calculation of the inner finder takes, logging it at debug level along
+/// with the provider label. Results are passed through unchanged, and failed calculations are
+/// measured as well.
+pub struct TimedEta(Box<dyn EtaFinder + Send + Sync>, Option<LatencyRecorder>);
+
+#[async_trait::async_trait]
+impl EtaFinder for TimedEta {
+	async fn calculate_eta(&self, ambulance_id: Uuid, from: Point, to: Point) -> Result<Duration, EtaError> {
+		self.time(self.0.calculate_eta(ambulance_id, from, to)).await
+	}
+
+	fn source(&self) -> &str {
+		self.0.source()
+	}
+
+	async fn calculate_eta_with_source(&self, ambulance_id: Uuid, from: Point, to: Point) -> Result<(Duration, String), EtaError> {
+		self.time(self.0.calculate_eta_with_source(ambulance_id, from, to)).await
+	}
+
+	async fn calculate_eta_range(&self, ambulance_id: Uuid, from: Point, to: Point) -> Result<(Duration, Duration), EtaError> {
+		self.time(self.0.calculate_eta_range(ambulance_id, from, to)).await
+	}
+
+	async fn calculate_eta_with_options(&self, ambulance_id: Uuid, from: Point, to: Point, options: &EtaOptions) -> Result<Duration, EtaError> {
+		self.time(self.0.calculate_eta_with_options(ambulance_id, from, to, options)).await
+	}
+
+	async fn calculate_eta_with_options_and_source(&self, ambulance_id: Uuid, from: Point, to: Point, options: &EtaOptions) -> Result<(Duration, String), EtaError> {
+		self.time(self.0.calculate_eta_with_options_and_source(ambulance_id, from, to, options)).await
+	}
+}
+
+impl TimedEta {
+	pub fn new(inner: Box<dyn EtaFinder + Send + Sync>) -> Self {
+		Self(inner, None)
+	}
+
+	/// Additionally passes each measurement to the recorder, such as to export it as a metric
+	pub fn with_recorder(mut self, recorder: LatencyRecorder) -> Self {
+		self.1 = Some(recorder);
+		self
+	}
+
+	async fn time<T>(&self, calculation: impl Future<Output = Result<T, EtaError>>) -> Result<T, EtaError> {
+		let start = Instant::now();
+		let result = calculation.await;
+		let latency = start.elapsed();
+
+		let source = self.0.source();
+		match &result {
+			Ok(_) => tracing::debug!(source, latency_ms = latency.as_millis() as u64, "calculated eta"),
+			Err(e) => tracing::debug!(source, latency_ms = latency.as_millis() as u64, error = e.category(), "failed to calculate eta")
+		}
+		if let Some(recorder) = &self.1 {
+			recorder(source, latency, result.is_ok());
+		}
+		result
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Mutex;
+
+	/// Sleeps before answering, failing if no ETA is given
+	struct SlowEta(Duration, Option<Duration>);
+
+	#[async_trait::async_trait]
+	impl EtaFinder for SlowEta {
+		async fn calculate_eta(&self, _ambulance_id: Uuid, _from: Point, _to: Point) -> Result<Duration, EtaError> {
+			tokio::time::sleep(self.0).await;
+			self.1.ok_or(EtaError::NoRoute)
+		}
+
+		fn source(&self) -> &str {
+			"slow"
+		}
+	}
+
+	fn recorded() -> (LatencyRecorder, Arc<Mutex<Vec<(String, Duration, bool)>>>) {
+		let records = Arc::new(Mutex::new(Vec::new()));
+		let sink = records.clone();
+		let recorder: LatencyRecorder = Arc::new(move |source: &str, latency, succeeded| {
+			sink.lock().unwrap().push((source.to_string(), latency, succeeded));
+		});
+		(recorder, records)
+	}
+
+	#[tokio::test]
+	async fn records_latency_of_success() {
+		let (recorder, records) = recorded();
+		let finder = TimedEta::new(Box::new(SlowEta(Duration::from_millis(50), Some(Duration::from_secs(300)))))
+			.with_recorder(recorder);
+
+		let eta = finder.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap();
+		assert_eq!(eta, Duration::from_secs(300));
+		assert_eq!(finder.source(), "slow");
+
+		let records = records.lock().unwrap();
+		assert_eq!(records.len(), 1);
+		assert_eq!(records[0].0, "slow");
+		assert!(records[0].1 >= Duration::from_millis(50), "{:?}", records[0].1);
+		assert!(records[0].2);
+	}
+
+	#[tokio::test]
+	async fn records_latency_of_failure() {
+		let (recorder, records) = recorded();
+		let finder = TimedEta::new(Box::new(SlowEta(Duration::from_millis(20), None)))
+			.with_recorder(recorder);
+
+		let result = finder.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await;
+		assert!(matches!(result, Err(EtaError::NoRoute)), "{:?}", result);
+
+		let records = records.lock().unwrap();
+		assert_eq!(records.len(), 1);
+		assert!(records[0].1 >= Duration::from_millis(20), "{:?}", records[0].1);
+		assert!(!records[0].2);
+	}
+}
\ No newline at end of file
@@ -0,0 +1,45 @@
+//! Ready made axum handlers over the managers, translating their errors into HTTP statuses.
+//! Enabled by the `http` feature.
+#![cfg(feature = "http")]
+
+mod api_error;
+mod auth;
+mod handlers;
+
+pub use api_error::*;
+pub use auth::*;
+pub use handlers::*;
+
+use crate::data::{AccountManager, AmbulanceReader, SettingsManager};
+use crate::eta::eta_finder::EtaFinder;
+use axum::routing::{delete, get, post};
+use axum::Router;
+use std::sync::Arc;
+
+/// The managers shared by every handler
+#[derive(Clone)]
+pub struct AppState {
+	pub accounts: Arc<dyn AccountManager + Send + Sync>,
+	pub settings: Arc<dyn SettingsManager + Send + Sync>,
+	pub ambulances: Arc<dyn AmbulanceReader + Send + Sync>,
+	pub eta: Arc<dyn EtaFinder + Send + Sync>
+}
+
+/// Builds a router with every handler mounted at the paths described in `api.yaml`, expecting
+/// sessions as `Authorization: Bearer <hex token>`
+pub fn router(state: AppState) -> Router {
+	Router::new()
+		.route("/auth/login", post(login))
+		.route("/auth/logout", post(logout))
+		.route("/auth/change-password", post(change_password))
+		.route("/admin/users", post(create_user))
+		.route("/admin/users/{user_id}", delete(delete_user))
+		.route("/admin/users/{user_id}/reset-password", post(reset_password))
+		.route("/users/me/settings", get(get_settings).post(set_settings))
+		.route("/users/me/phones", get(get_phones).post(new_phone))
+		.route("/users/me/phones/{phone_id}", delete(delete_phone))
+		.route("/ambulances", get(get_recent_ambulances))
+		.route("/ambulances/{ambulance_id}", get(get_ambulance))
+		.route("/ambulances/{ambulance_id}/eta", get(get_eta))
+		.with_state(state)
+}
\ No newline at end of file
@@ -0,0 +1,210 @@
+use crate::data::{AccountChangePasswordError, AccountCreationError, AccountLoginError, AccountOwnerManageError, AmbulanceTrackerError, DeletePhoneError, SessionRetrievalError, SettingsError, TokenParseError};
+use crate::eta::eta_finder::EtaError;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use std::fmt::Display;
+
+/// An error response, sent as an `ErrorResponse` JSON body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+	pub status: StatusCode,
+	pub message: String
+}
+
+#[derive(serde::Serialize)]
+struct ErrorResponse<'a> {
+	error: &'a str
+}
+
+impl ApiError {
+	pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+		Self {
+			status,
+			message: message.into()
+		}
+	}
+
+	/// Logs the cause of an unexpected error, without exposing it to the client
+	pub fn internal(cause: impl Display) -> Self {
+		tracing::error!(error = %cause, "internal error handling request");
+		Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal server error")
+	}
+}
+
+impl IntoResponse for ApiError {
+	fn into_response(self) -> Response {
+		(self.status, Json(ErrorResponse { error: &self.message })).into_response()
+	}
+}
+
+impl From<AccountCreationError> for ApiError {
+	fn from(e: AccountCreationError) -> Self {
+		match e {
+			AccountCreationError::InvalidOwnerRole => ApiError::new(StatusCode::FORBIDDEN, e.to_string()),
+			AccountCreationError::OwnerNotFound => ApiError::new(StatusCode::NOT_FOUND, e.to_string()),
+			AccountCreationError::PasswordTooShort(_) => ApiError::new(StatusCode::BAD_REQUEST, e.to_string()),
+			AccountCreationError::Other(e) => ApiError::internal(e)
+		}
+	}
+}
+
+impl From<AccountOwnerManageError> for ApiError {
+	fn from(e: AccountOwnerManageError) -> Self {
+		match e {
+			AccountOwnerManageError::UserNotFound => ApiError::new(StatusCode::NOT_FOUND, e.to_string()),
+			AccountOwnerManageError::NotOwner
+			| AccountOwnerManageError::InsufficientRole => ApiError::new(StatusCode::FORBIDDEN, e.to_string()),
+			AccountOwnerManageError::Other(e) => ApiError::internal(e)
+		}
+	}
+}
+
+impl From<AccountChangePasswordError> for ApiError {
+	fn from(e: AccountChangePasswordError) -> Self {
+		match e {
+			AccountChangePasswordError::UserNotFound => ApiError::new(StatusCode::NOT_FOUND, e.to_string()),
+			AccountChangePasswordError::IncorrectPassword => ApiError::new(StatusCode::UNAUTHORIZED, e.to_string()),
+			AccountChangePasswordError::PasswordReused
+			| AccountChangePasswordError::PasswordTooShort(_) => ApiError::new(StatusCode::BAD_REQUEST, e.to_string()),
+			AccountChangePasswordError::Other(e) => ApiError::internal(e)
+		}
+	}
+}
+
+impl From<AccountLoginError> for ApiError {
+	fn from(e: AccountLoginError) -> Self {
+		match e {
+			// an unknown user is indistinguishable from a wrong password, so usernames cannot be probed
+			AccountLoginError::UserNotFound | AccountLoginError::IncorrectPassword | AccountLoginError::InvalidCredentials =>
+				ApiError::new(StatusCode::UNAUTHORIZED, "incorrect username or password"),
+			AccountLoginError::InvalidLoginToken => ApiError::new(StatusCode::UNAUTHORIZED, e.to_string()),
+			AccountLoginError::Other(e) => ApiError::internal(e)
+		}
+	}
+}
+
+impl From<SessionRetrievalError> for ApiError {
+	fn from(e: SessionRetrievalError) -> Self {
+		match e {
+			SessionRetrievalError::InvalidToken => ApiError::new(StatusCode::UNAUTHORIZED, e.to_string()),
+			SessionRetrievalError::InvalidPurpose => ApiError::new(StatusCode::FORBIDDEN, e.to_string()),
+			SessionRetrievalError::Other(e) => ApiError::internal(e)
+		}
+	}
+}
+
+impl From<TokenParseError> for ApiError {
+	fn from(e: TokenParseError) -> Self {
+		ApiError::new(StatusCode::UNAUTHORIZED, e.to_string())
+	}
+}
+
+impl From<SettingsError> for ApiError {
+	fn from(e: SettingsError) -> Self {
+		match e {
+			SettingsError::UserNotFound => ApiError::new(StatusCode::NOT_FOUND, e.to_string()),
+			SettingsError::EtaAlertTooLong
+			| SettingsError::InvalidHospitalLocation
+			| SettingsError::InvalidPhone(_)
+			| SettingsError::TooManyPhones => ApiError::new(StatusCode::BAD_REQUEST, e.to_string()),
+			SettingsError::DuplicatePhone(_) => ApiError::new(StatusCode::CONFLICT, e.to_string()),
+			SettingsError::Other(e) => ApiError::internal(e)
+		}
+	}
+}
+
+impl From<DeletePhoneError> for ApiError {
+	fn from(e: DeletePhoneError) -> Self {
+		match e {
+			DeletePhoneError::UserNotFound | DeletePhoneError::PhoneNotFound => ApiError::new(StatusCode::NOT_FOUND, e.to_string()),
+			DeletePhoneError::Other(e) => ApiError::internal(e)
+		}
+	}
+}
+
+impl From<AmbulanceTrackerError> for ApiError {
+	fn from(e: AmbulanceTrackerError) -> Self {
+		match e {
+			AmbulanceTrackerError::AmbulanceNotFound => ApiError::new(StatusCode::NOT_FOUND, e.to_string()),
+			AmbulanceTrackerError::InvalidGeometry
+			| AmbulanceTrackerError::ImplausibleLocation
+			| AmbulanceTrackerError::InvalidName
+			| AmbulanceTrackerError::TimestampInFuture
+			| AmbulanceTrackerError::InvalidTimeRange
+			| AmbulanceTrackerError::WindowTooLarge => ApiError::new(StatusCode::BAD_REQUEST, e.to_string()),
+			AmbulanceTrackerError::Timeout => ApiError::new(StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+			AmbulanceTrackerError::Other(e) => ApiError::internal(e)
+		}
+	}
+}
+
+impl From<EtaError> for ApiError {
+	fn from(e: EtaError) -> Self {
+		match e {
+			EtaError::NoRoute => ApiError::new(StatusCode::NOT_FOUND, e.to_string()),
+			EtaError::InvalidCoordinates => ApiError::new(StatusCode::BAD_REQUEST, e.to_string()),
+			EtaError::Timeout => ApiError::new(StatusCode::GATEWAY_TIMEOUT, e.to_string()),
+			EtaError::Network(_) | EtaError::Provider(_) | EtaError::Unauthorized => {
+				tracing::warn!(error = %e, "eta provider unavailable");
+				ApiError::new(StatusCode::BAD_GATEWAY, "eta provider unavailable")
+			},
+			EtaError::Other(e) => ApiError::internal(e)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn account_errors_map_to_statuses() {
+		assert_eq!(ApiError::from(AccountCreationError::InvalidOwnerRole).status, StatusCode::FORBIDDEN);
+		assert_eq!(ApiError::from(AccountCreationError::OwnerNotFound).status, StatusCode::NOT_FOUND);
+		assert_eq!(ApiError::from(AccountOwnerManageError::UserNotFound).status, StatusCode::NOT_FOUND);
+		assert_eq!(ApiError::from(AccountOwnerManageError::NotOwner).status, StatusCode::FORBIDDEN);
+		assert_eq!(ApiError::from(AccountChangePasswordError::PasswordReused).status, StatusCode::BAD_REQUEST);
+		assert_eq!(ApiError::from(AccountChangePasswordError::PasswordTooShort(8)).status, StatusCode::BAD_REQUEST);
+		assert_eq!(ApiError::from(AccountChangePasswordError::IncorrectPassword).status, StatusCode::UNAUTHORIZED);
+		assert_eq!(ApiError::from(AccountLoginError::IncorrectPassword).status, StatusCode::UNAUTHORIZED);
+		assert_eq!(ApiError::from(AccountLoginError::UserNotFound), ApiError::from(AccountLoginError::IncorrectPassword));
+		assert_eq!(ApiError::from(AccountLoginError::InvalidCredentials), ApiError::from(AccountLoginError::IncorrectPassword));
+		assert_eq!(ApiError::from(AccountLoginError::InvalidLoginToken).status, StatusCode::UNAUTHORIZED);
+		assert_eq!(ApiError::from(SessionRetrievalError::InvalidToken).status, StatusCode::UNAUTHORIZED);
+		assert_eq!(ApiError::from(SessionRetrievalError::InvalidPurpose).status, StatusCode::FORBIDDEN);
+		assert_eq!(ApiError::from(TokenParseError::InvalidCharacter).status, StatusCode::UNAUTHORIZED);
+	}
+
+	#[test]
+	fn other_errors_are_not_exposed() {
+		let error = ApiError::from(AccountCreationError::Other("connection string with password".into()));
+		assert_eq!(error.status, StatusCode::INTERNAL_SERVER_ERROR);
+		assert!(!error.message.contains("password"));
+	}
+
+	#[test]
+	fn settings_and_ambulance_errors_map_to_statuses() {
+		assert_eq!(ApiError::from(SettingsError::UserNotFound).status, StatusCode::NOT_FOUND);
+		assert_eq!(ApiError::from(SettingsError::EtaAlertTooLong).status, StatusCode::BAD_REQUEST);
+		assert_eq!(ApiError::from(SettingsError::InvalidHospitalLocation).status, StatusCode::BAD_REQUEST);
+		assert_eq!(ApiError::from(SettingsError::TooManyPhones).status, StatusCode::BAD_REQUEST);
+		assert_eq!(ApiError::from(SettingsError::DuplicatePhone("+15551234567".to_string())).status, StatusCode::CONFLICT);
+		assert_eq!(ApiError::from(DeletePhoneError::PhoneNotFound).status, StatusCode::NOT_FOUND);
+		assert_eq!(ApiError::from(AmbulanceTrackerError::AmbulanceNotFound).status, StatusCode::NOT_FOUND);
+		assert_eq!(ApiError::from(AmbulanceTrackerError::InvalidName).status, StatusCode::BAD_REQUEST);
+		assert_eq!(ApiError::from(AmbulanceTrackerError::ImplausibleLocation).status, StatusCode::BAD_REQUEST);
+		assert_eq!(ApiError::from(AmbulanceTrackerError::WindowTooLarge).status, StatusCode::BAD_REQUEST);
+		assert_eq!(ApiError::from(AmbulanceTrackerError::Timeout).status, StatusCode::SERVICE_UNAVAILABLE);
+	}
+
+	#[test]
+	fn eta_errors_map_to_statuses() {
+		assert_eq!(ApiError::from(EtaError::NoRoute).status, StatusCode::NOT_FOUND);
+		assert_eq!(ApiError::from(EtaError::InvalidCoordinates).status, StatusCode::BAD_REQUEST);
+		assert_eq!(ApiError::from(EtaError::Timeout).status, StatusCode::GATEWAY_TIMEOUT);
+		assert_eq!(ApiError::from(EtaError::Provider("503".to_string())).status, StatusCode::BAD_GATEWAY);
+		assert_eq!(ApiError::from(EtaError::Unauthorized).status, StatusCode::BAD_GATEWAY);
+		assert_eq!(ApiError::from(EtaError::Other("failed".into())).status, StatusCode::INTERNAL_SERVER_ERROR);
+	}
+}
\ No newline at end of file
@@ -0,0 +1,143 @@
+use crate::data::{AccountId, SessionRetrievalPurpose, SessionToken};
+use crate::http::{ApiError, AppState};
+use axum::extract::FromRequestParts;
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
+
+/// The account of the session in the request's `Authorization: Bearer <hex token>` header.
+///
+/// Rejects the request with 401 if the token is missing or invalid, and with 403 if the account
+/// must change its password first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthenticatedAccount(pub AccountId);
+
+/// As [AuthenticatedAccount], but also accepts accounts which must change their password, for use
+/// only by the password change route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordChangeAccount(pub AccountId);
+
+/// Reads the session token from an `Authorization: Bearer <hex token>` header
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Result<SessionToken, ApiError> {
+	let token = headers.get(AUTHORIZATION)
+		.and_then(|value| value.to_str().ok())
+		.and_then(|value| value.strip_prefix("Bearer "))
+		.ok_or_else(|| ApiError::new(StatusCode::UNAUTHORIZED, "a bearer session token is required"))?;
+	Ok(SessionToken::from_hex(token.trim())?)
+}
+
+/// Resolves the request's session token to its account, keeping the session alive
+async fn authenticate(parts: &Parts, state: &AppState, purpose: SessionRetrievalPurpose) -> Result<AccountId, ApiError> {
+	let token = bearer_token(&parts.headers)?;
+	let account = state.accounts.retrieve_account(&token, purpose).await?;
+	state.accounts.touch_session(&token).await?;
+	Ok(account)
+}
+
+impl FromRequestParts<AppState> for AuthenticatedAccount {
+	type Rejection = ApiError;
+
+	async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+		authenticate(parts, state, SessionRetrievalPurpose::Other).await.map(Self)
+	}
+}
+
+impl FromRequestParts<AppState> for PasswordChangeAccount {
+	type Rejection = ApiError;
+
+	async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+		authenticate(parts, state, SessionRetrievalPurpose::ChangePassword).await.map(Self)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::data::AccountManager;
+	use crate::eta::fixed_eta::NoRouteEta;
+	use crate::sql::sql_account_manager::SqlAccountManager;
+	use crate::sql::sql_ambulance_tracker::SQLAmbulanceTracker;
+	use crate::sql::sql_settings_manager::SQLSettingsManager;
+	use axum::body::Body;
+	use axum::http::Request;
+	use axum::routing::get;
+	use axum::Router;
+	use sqlx::PgPool;
+	use std::sync::Arc;
+	use tower::ServiceExt;
+
+	/// A router which responds with the extracted account id
+	fn app(pool: PgPool) -> Router {
+		Router::new()
+			.route("/any", get(|AuthenticatedAccount(id): AuthenticatedAccount| async move { id.0.to_string() }))
+			.route("/password", get(|PasswordChangeAccount(id): PasswordChangeAccount| async move { id.0.to_string() }))
+			.with_state(AppState {
+				accounts: Arc::new(SqlAccountManager::new(pool.clone())),
+				settings: Arc::new(SQLSettingsManager::new(pool.clone())),
+				ambulances: Arc::new(SQLAmbulanceTracker::new(pool)),
+				eta: Arc::new(NoRouteEta)
+			})
+	}
+
+	async fn get_status(app: &Router, uri: &str, authorization: Option<String>) -> (StatusCode, String) {
+		let mut request = Request::builder().uri(uri);
+		if let Some(authorization) = authorization {
+			request = request.header(AUTHORIZATION, authorization);
+		}
+
+		let response = app.clone().oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+		let status = response.status();
+		let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+		(status, String::from_utf8(bytes.to_vec()).unwrap())
+	}
+
+	#[sqlx::test]
+	async fn valid_token_extracts_account(pool: PgPool) {
+		let app = app(pool.clone());
+		let acc = SqlAccountManager::new(pool);
+		let (id, password) = acc.create_site_admin("root").await.unwrap();
+		let token = acc.login("root", &password).await.unwrap();
+		acc.change_password(&id, &password, &password).await.unwrap();
+
+		let (status, body) = get_status(&app, "/any", Some(format!("Bearer {}", token.to_hex()))).await;
+		assert_eq!(status, StatusCode::OK);
+		assert_eq!(body, id.0.to_string());
+
+		let (status, body) = get_status(&app, "/password", Some(format!("Bearer {}", token.to_hex().to_uppercase()))).await;
+		assert_eq!(status, StatusCode::OK);
+		assert_eq!(body, id.0.to_string());
+	}
+
+	#[sqlx::test]
+	async fn invalid_token_is_unauthorized(pool: PgPool) {
+		let app = app(pool.clone());
+		let acc = SqlAccountManager::new(pool);
+		let (id, password) = acc.create_site_admin("root").await.unwrap();
+		let token = acc.login("root", &password).await.unwrap();
+		acc.change_password(&id, &password, &password).await.unwrap();
+
+		assert_eq!(get_status(&app, "/any", None).await.0, StatusCode::UNAUTHORIZED);
+		assert_eq!(get_status(&app, "/any", Some(token.to_hex())).await.0, StatusCode::UNAUTHORIZED);
+		assert_eq!(get_status(&app, "/any", Some("Bearer 1234".to_string())).await.0, StatusCode::UNAUTHORIZED);
+		assert_eq!(get_status(&app, "/any", Some(format!("Bearer {}", SessionToken::new([0; 32]).to_hex()))).await.0, StatusCode::UNAUTHORIZED);
+
+		// a destroyed session is no longer valid
+		acc.destroy_session(&token).await.unwrap();
+		assert_eq!(get_status(&app, "/any", Some(format!("Bearer {}", token.to_hex()))).await.0, StatusCode::UNAUTHORIZED);
+		assert_eq!(get_status(&app, "/password", Some(format!("Bearer {}", token.to_hex()))).await.0, StatusCode::UNAUTHORIZED);
+	}
+
+	#[sqlx::test]
+	async fn password_change_required(pool: PgPool) {
+		let app = app(pool.clone());
+		let acc = SqlAccountManager::new(pool);
+		let (id, password) = acc.create_site_admin("root").await.unwrap();
+		let token = acc.login("root", &password).await.unwrap();
+
+		assert_eq!(get_status(&app, "/any", Some(format!("Bearer {}", token.to_hex()))).await.0, StatusCode::FORBIDDEN);
+
+		let (status, body) = get_status(&app, "/password", Some(format!("Bearer {}", token.to_hex()))).await;
+		assert_eq!(status, StatusCode::OK);
+		assert_eq!(body, id.0.to_string());
+	}
+}
\ No newline at end of file
@@ -0,0 +1,447 @@
+use crate::data::{AccountId, AccountRole, Ambulance, AmbulanceId, AmbulanceTrackerError, PhoneNumber, SettingsError, UserSettings};
+use crate::http::auth::bearer_token;
+use crate::http::{ApiError, AppState, AuthenticatedAccount, PasswordChangeAccount};
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::Json;
+use geo_types::Point;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+use std::time::Duration;
+
+/// How recently an ambulance must have been updated to be listed, unless the request specifies
+const DEFAULT_UPDATED_WITHIN: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+	pub lat: f64,
+	pub lng: f64
+}
+
+impl From<Point> for Location {
+	fn from(pt: Point) -> Self {
+		Self { lat: pt.y(), lng: pt.x() }
+	}
+}
+
+impl From<Location> for Point {
+	fn from(location: Location) -> Self {
+		Point::new(location.lng, location.lat)
+	}
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+	pub username: String,
+	pub password: String
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoginResponse {
+	pub user_id: Uuid,
+	pub requires_password_reset: bool,
+	/// The hex encoded session token, sent back as `Authorization: Bearer <token>`
+	pub session_token: String
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+	pub current_password: String,
+	pub new_password: String
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateUserRequest {
+	pub username: String,
+	pub role: AccountRole
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateUserResponse {
+	pub user_id: Uuid,
+	pub temporary_password: String
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResetPasswordResponse {
+	pub temporary_password: String
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SettingsBody {
+	pub hospital_location: Option<Location>,
+	pub default_eta_alert_ms: u64
+}
+
+#[derive(Debug, Serialize)]
+pub struct PhoneResponse {
+	pub phone_id: Uuid,
+	pub label: String,
+	pub number: String
+}
+
+impl From<PhoneNumber> for PhoneResponse {
+	fn from(phone: PhoneNumber) -> Self {
+		Self {
+			phone_id: phone.phone_id,
+			label: phone.label,
+			number: phone.number
+		}
+	}
+}
+
+#[derive(Debug, Serialize)]
+pub struct PhonesResponse {
+	pub phones: Vec<PhoneResponse>
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PhoneCreateRequest {
+	pub label: String,
+	pub number: String
+}
+
+#[derive(Debug, Serialize)]
+pub struct AmbulanceResponse {
+	pub ambulance_id: Uuid,
+	pub ambulance_name: String,
+	pub location: Location,
+	/// RFC 3339 timestamp of the last location update
+	pub last_updated: String
+}
+
+impl From<Ambulance> for AmbulanceResponse {
+	fn from(ambulance: Ambulance) -> Self {
+		Self {
+			ambulance_id: ambulance.id.0,
+			ambulance_name: ambulance.name,
+			location: ambulance.location.into(),
+			last_updated: ambulance.last_updated.to_rfc3339()
+		}
+	}
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecentAmbulancesQuery {
+	pub updated_within_secs: Option<u64>
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EtaQuery {
+	pub lat: Option<f64>,
+	pub lng: Option<f64>
+}
+
+#[derive(Debug, Serialize)]
+pub struct EtaResponse {
+	pub eta_secs: u64,
+	pub source: String
+}
+
+pub async fn login(State(state): State<AppState>, Json(body): Json<LoginRequest>) -> Result<Json<LoginResponse>, ApiError> {
+	let login = state.accounts.login_detailed(&body.username, &body.password).await?;
+
+	Ok(Json(LoginResponse {
+		user_id: login.account_id.0,
+		requires_password_reset: login.password_reset_needed,
+		session_token: login.token.to_hex()
+	}))
+}
+
+pub async fn logout(State(state): State<AppState>, headers: HeaderMap) -> Result<StatusCode, ApiError> {
+	let token = bearer_token(&headers)?;
+	state.accounts.destroy_session(&token).await.map_err(ApiError::internal)?;
+	Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn change_password(State(state): State<AppState>, PasswordChangeAccount(account): PasswordChangeAccount, headers: HeaderMap, Json(body): Json<ChangePasswordRequest>) -> Result<Json<serde_json::Value>, ApiError> {
+	let token = bearer_token(&headers)?;
+	state.accounts.change_password_in_session(&account, &token, &body.current_password, &body.new_password).await?;
+	Ok(Json(serde_json::json!({ "status": "password changed" })))
+}
+
+pub async fn create_user(State(state): State<AppState>, AuthenticatedAccount(owner): AuthenticatedAccount, Json(body): Json<CreateUserRequest>) -> Result<(StatusCode, Json<CreateUserResponse>), ApiError> {
+	let (user_id, temporary_password) = state.accounts.create_account(&owner, body.role, &body.username).await?;
+	Ok((StatusCode::CREATED, Json(CreateUserResponse {
+		user_id: user_id.0,
+		temporary_password: temporary_password.into_inner()
+	})))
+}
+
+pub async fn delete_user(State(state): State<AppState>, AuthenticatedAccount(owner): AuthenticatedAccount, Path(user_id): Path<Uuid>) -> Result<StatusCode, ApiError> {
+	state.accounts.delete_account(&owner, &AccountId::new(user_id)).await?;
+	Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn reset_password(State(state): State<AppState>, AuthenticatedAccount(owner): AuthenticatedAccount, Path(user_id): Path<Uuid>) -> Result<Json<ResetPasswordResponse>, ApiError> {
+	let temporary_password = state.accounts.reset_password(&owner, &AccountId::new(user_id)).await?;
+	Ok(Json(ResetPasswordResponse { temporary_password: temporary_password.into_inner() }))
+}
+
+pub async fn get_settings(State(state): State<AppState>, AuthenticatedAccount(account): AuthenticatedAccount) -> Result<Json<SettingsBody>, ApiError> {
+	let settings = state.settings.get_settings(account).await?;
+	Ok(Json(SettingsBody {
+		hospital_location: settings.hospital_location.map(Location::from),
+		default_eta_alert_ms: settings.default_eta_alert.as_millis() as u64
+	}))
+}
+
+pub async fn set_settings(State(state): State<AppState>, AuthenticatedAccount(account): AuthenticatedAccount, Json(body): Json<SettingsBody>) -> Result<StatusCode, ApiError> {
+	let settings = UserSettings::builder()
+		.hospital_location(body.hospital_location.map(Point::from))
+		.default_eta_alert(Duration::from_millis(body.default_eta_alert_ms))
+		.build()
+		.map_err(SettingsError::from)?;
+	state.settings.set_settings(account, settings).await?;
+	Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_phones(State(state): State<AppState>, AuthenticatedAccount(account): AuthenticatedAccount) -> Result<Json<PhonesResponse>, ApiError> {
+	let phones = state.settings.get_phones(account).await?;
+	Ok(Json(PhonesResponse { phones: phones.into_iter().map(PhoneResponse::from).collect() }))
+}
+
+/// Adds a phone, returning every phone the user has
+pub async fn new_phone(State(state): State<AppState>, AuthenticatedAccount(account): AuthenticatedAccount, Json(body): Json<PhoneCreateRequest>) -> Result<(StatusCode, Json<PhonesResponse>), ApiError> {
+	// the number is validated and normalized by the settings manager
+	state.settings.new_phone(account, &body.number, &body.label).await?;
+	let phones = state.settings.get_phones(account).await?;
+	Ok((StatusCode::CREATED, Json(PhonesResponse { phones: phones.into_iter().map(PhoneResponse::from).collect() })))
+}
+
+pub async fn delete_phone(State(state): State<AppState>, AuthenticatedAccount(account): AuthenticatedAccount, Path(phone_id): Path<Uuid>) -> Result<StatusCode, ApiError> {
+	state.settings.delete_phone(account, phone_id).await?;
+	Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_recent_ambulances(State(state): State<AppState>, _: AuthenticatedAccount, Query(query): Query<RecentAmbulancesQuery>) -> Result<Json<Vec<AmbulanceResponse>>, ApiError> {
+	let updated_within = query.updated_within_secs.map(Duration::from_secs).unwrap_or(DEFAULT_UPDATED_WITHIN);
+	let ambulances = state.ambulances.get_recently_updated(updated_within).await?;
+	Ok(Json(ambulances.into_iter().map(AmbulanceResponse::from).collect()))
+}
+
+pub async fn get_ambulance(State(state): State<AppState>, _: AuthenticatedAccount, Path(ambulance_id): Path<Uuid>) -> Result<Json<AmbulanceResponse>, ApiError> {
+	let ambulance = state.ambulances.get_ambulance(AmbulanceId::new(ambulance_id)).await?
+		.ok_or(AmbulanceTrackerError::AmbulanceNotFound)?;
+	Ok(Json(ambulance.into()))
+}
+
+/// Calculates the ETA of an ambulance to the specified location, or to the user's hospital if none
+/// is specified
+pub async fn get_eta(State(state): State<AppState>, AuthenticatedAccount(account): AuthenticatedAccount, Path(ambulance_id): Path<Uuid>, Query(query): Query<EtaQuery>) -> Result<Json<EtaResponse>, ApiError> {
+	let ambulance = state.ambulances.get_ambulance(AmbulanceId::new(ambulance_id)).await?
+		.ok_or(AmbulanceTrackerError::AmbulanceNotFound)?;
+
+	let destination = match (query.lat, query.lng) {
+		(Some(lat), Some(lng)) => Point::new(lng, lat),
+		(None, None) => state.settings.get_settings(account).await?.hospital_location
+			.ok_or_else(|| ApiError::new(StatusCode::CONFLICT, "hospital location must be specified"))?,
+		_ => return Err(ApiError::new(StatusCode::BAD_REQUEST, "both lat and lng must be specified"))
+	};
+
+	let (eta, source) = state.eta.calculate_eta_with_source(ambulance_id, ambulance.location, destination).await?;
+	Ok(Json(EtaResponse {
+		eta_secs: eta.as_secs(),
+		source
+	}))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::eta::fixed_eta::FixedEta;
+	use crate::http::router;
+	use crate::sql::sql_account_manager::SqlAccountManager;
+	use crate::sql::sql_ambulance_tracker::SQLAmbulanceTracker;
+	use crate::sql::sql_settings_manager::SQLSettingsManager;
+	use crate::data::{AmbulanceTracker, SessionToken};
+	use axum::body::Body;
+	use axum::http::header::AUTHORIZATION;
+	use axum::http::{Method, Request};
+	use axum::Router;
+	use sqlx::types::chrono::Utc;
+	use sqlx::PgPool;
+	use std::sync::Arc;
+	use tower::ServiceExt;
+
+	fn app(pool: PgPool) -> Router {
+		router(AppState {
+			accounts: Arc::new(SqlAccountManager::new(pool.clone())),
+			settings: Arc::new(SQLSettingsManager::new(pool.clone())),
+			ambulances: Arc::new(SQLAmbulanceTracker::new(pool)),
+			eta: Arc::new(FixedEta::new(Duration::from_secs(600)).with_source("stub"))
+		})
+	}
+
+	async fn send(app: &Router, method: Method, uri: &str, token: Option<&str>, body: Option<serde_json::Value>) -> (StatusCode, serde_json::Value) {
+		let mut request = Request::builder().method(method).uri(uri);
+		if let Some(token) = token {
+			request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+		}
+		let request = match body {
+			Some(body) => request.header("content-type", "application/json").body(Body::from(body.to_string())),
+			None => request.body(Body::empty())
+		}.unwrap();
+
+		let response = app.clone().oneshot(request).await.unwrap();
+		let status = response.status();
+		let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+		(status, serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null))
+	}
+
+	/// Logs in and changes the temporary password, returning a session token usable for anything
+	async fn login_as(app: &Router, username: &str, password: &str) -> String {
+		let (status, body) = send(app, Method::POST, "/auth/login", None, Some(serde_json::json!({ "username": username, "password": password }))).await;
+		assert_eq!(status, StatusCode::OK, "{}", body);
+		let token = body["session_token"].as_str().unwrap().to_string();
+
+		let (status, _) = send(app, Method::POST, "/auth/change-password", Some(&token), Some(serde_json::json!({ "current_password": password, "new_password": password }))).await;
+		assert_eq!(status, StatusCode::OK);
+		token
+	}
+
+	async fn site_admin(pool: &PgPool, app: &Router) -> (AccountId, String) {
+		let (id, password) = SqlAccountManager::new(pool.clone()).create_site_admin("root").await.unwrap();
+		(id, login_as(app, "root", &password).await)
+	}
+
+	#[sqlx::test]
+	async fn test_login(pool: PgPool) {
+		let app = app(pool.clone());
+		let (site_admin_id, password) = SqlAccountManager::new(pool).create_site_admin("root").await.unwrap();
+
+		let (status, body) = send(&app, Method::POST, "/auth/login", None, Some(serde_json::json!({ "username": "root", "password": password.as_str() }))).await;
+		assert_eq!(status, StatusCode::OK);
+		assert_eq!(body["user_id"], site_admin_id.0.to_string());
+		assert_eq!(body["requires_password_reset"], true);
+
+		// the temporary password must be changed first
+		let token = body["session_token"].as_str().unwrap().to_string();
+		let (status, _) = send(&app, Method::GET, "/users/me/settings", Some(&token), None).await;
+		assert_eq!(status, StatusCode::FORBIDDEN);
+
+		let (status, _) = send(&app, Method::POST, "/auth/login", None, Some(serde_json::json!({ "username": "root", "password": "wrong" }))).await;
+		assert_eq!(status, StatusCode::UNAUTHORIZED);
+		let (status, _) = send(&app, Method::POST, "/auth/login", None, Some(serde_json::json!({ "username": "nobody", "password": "wrong" }))).await;
+		assert_eq!(status, StatusCode::UNAUTHORIZED);
+	}
+
+	#[sqlx::test]
+	async fn test_authentication_required(pool: PgPool) {
+		let app = app(pool.clone());
+		let (_, token) = site_admin(&pool, &app).await;
+
+		let (status, _) = send(&app, Method::GET, "/users/me/settings", None, None).await;
+		assert_eq!(status, StatusCode::UNAUTHORIZED);
+		let (status, _) = send(&app, Method::GET, "/users/me/settings", Some("not hex"), None).await;
+		assert_eq!(status, StatusCode::UNAUTHORIZED);
+		let (status, _) = send(&app, Method::GET, "/users/me/settings", Some(&SessionToken::new([0; 32]).to_hex()), None).await;
+		assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+		let (status, _) = send(&app, Method::POST, "/auth/logout", Some(&token), None).await;
+		assert_eq!(status, StatusCode::NO_CONTENT);
+		let (status, _) = send(&app, Method::GET, "/users/me/settings", Some(&token), None).await;
+		assert_eq!(status, StatusCode::UNAUTHORIZED);
+	}
+
+	#[sqlx::test]
+	async fn test_change_password_incorrect(pool: PgPool) {
+		let app = app(pool.clone());
+		let (_, token) = site_admin(&pool, &app).await;
+
+		let (status, _) = send(&app, Method::POST, "/auth/change-password", Some(&token), Some(serde_json::json!({ "current_password": "wrong", "new_password": "new" }))).await;
+		assert_eq!(status, StatusCode::UNAUTHORIZED);
+	}
+
+	#[sqlx::test]
+	async fn test_account_management(pool: PgPool) {
+		let app = app(pool.clone());
+		let (_, root_token) = site_admin(&pool, &app).await;
+
+		let (status, body) = send(&app, Method::POST, "/admin/users", Some(&root_token), Some(serde_json::json!({ "username": "admin", "role": "admin" }))).await;
+		assert_eq!(status, StatusCode::CREATED);
+		let admin_id = body["user_id"].as_str().unwrap().to_string();
+		let admin_token = login_as(&app, "admin", body["temporary_password"].as_str().unwrap()).await;
+
+		// an admin can only create users
+		let (status, _) = send(&app, Method::POST, "/admin/users", Some(&admin_token), Some(serde_json::json!({ "username": "admin2", "role": "admin" }))).await;
+		assert_eq!(status, StatusCode::FORBIDDEN);
+
+		let (status, body) = send(&app, Method::POST, &format!("/admin/users/{}/reset-password", admin_id), Some(&root_token), None).await;
+		assert_eq!(status, StatusCode::OK);
+		assert!(body["temporary_password"].is_string());
+
+		let (status, _) = send(&app, Method::POST, &format!("/admin/users/{}/reset-password", Uuid::nil()), Some(&root_token), None).await;
+		assert_eq!(status, StatusCode::NOT_FOUND);
+
+		let (status, _) = send(&app, Method::DELETE, &format!("/admin/users/{}", admin_id), Some(&root_token), None).await;
+		assert_eq!(status, StatusCode::NO_CONTENT);
+		let (status, _) = send(&app, Method::DELETE, &format!("/admin/users/{}", admin_id), Some(&root_token), None).await;
+		assert_eq!(status, StatusCode::NOT_FOUND);
+	}
+
+	#[sqlx::test]
+	async fn test_settings_and_phones(pool: PgPool) {
+		let app = app(pool.clone());
+		let (_, token) = site_admin(&pool, &app).await;
+
+		let settings = serde_json::json!({ "hospital_location": { "lat": 40.5, "lng": -74.25 }, "default_eta_alert_ms": 1_800_000 });
+		let (status, _) = send(&app, Method::POST, "/users/me/settings", Some(&token), Some(settings.clone())).await;
+		assert_eq!(status, StatusCode::NO_CONTENT);
+		let (status, body) = send(&app, Method::GET, "/users/me/settings", Some(&token), None).await;
+		assert_eq!(status, StatusCode::OK);
+		assert_eq!(body, settings);
+
+		let too_long = serde_json::json!({ "hospital_location": null, "default_eta_alert_ms": 7 * 60 * 60 * 1000 });
+		let (status, _) = send(&app, Method::POST, "/users/me/settings", Some(&token), Some(too_long)).await;
+		assert_eq!(status, StatusCode::BAD_REQUEST);
+		let out_of_range = serde_json::json!({ "hospital_location": { "lat": 95.0, "lng": -74.25 }, "default_eta_alert_ms": 1_800_000 });
+		let (status, _) = send(&app, Method::POST, "/users/me/settings", Some(&token), Some(out_of_range)).await;
+		assert_eq!(status, StatusCode::BAD_REQUEST);
+		let (_, body) = send(&app, Method::GET, "/users/me/settings", Some(&token), None).await;
+		assert_eq!(body, settings);
+
+		let (status, body) = send(&app, Method::POST, "/users/me/phones", Some(&token), Some(serde_json::json!({ "label": "Home", "number": "0123456789" }))).await;
+		assert_eq!(status, StatusCode::CREATED);
+		let phone_id = body["phones"][0]["phone_id"].as_str().unwrap().to_string();
+
+		let (status, _) = send(&app, Method::POST, "/users/me/phones", Some(&token), Some(serde_json::json!({ "label": "Home", "number": "012" }))).await;
+		assert_eq!(status, StatusCode::BAD_REQUEST);
+
+		let (status, body) = send(&app, Method::GET, "/users/me/phones", Some(&token), None).await;
+		assert_eq!(status, StatusCode::OK);
+		assert_eq!(body["phones"].as_array().unwrap().len(), 1);
+
+		let (status, _) = send(&app, Method::DELETE, &format!("/users/me/phones/{}", phone_id), Some(&token), None).await;
+		assert_eq!(status, StatusCode::NO_CONTENT);
+		let (status, _) = send(&app, Method::DELETE, &format!("/users/me/phones/{}", phone_id), Some(&token), None).await;
+		assert_eq!(status, StatusCode::NOT_FOUND);
+	}
+
+	#[sqlx::test]
+	async fn test_ambulances_and_eta(pool: PgPool) {
+		let app = app(pool.clone());
+		let (_, token) = site_admin(&pool, &app).await;
+		let ambulance = SQLAmbulanceTracker::new(pool.clone())
+			.add_ambulance("Ambulance 1", Point::new(1.0, 2.0), Utc::now()).await.unwrap();
+
+		let (status, body) = send(&app, Method::GET, "/ambulances", Some(&token), None).await;
+		assert_eq!(status, StatusCode::OK);
+		assert_eq!(body[0]["ambulance_name"], "Ambulance 1");
+		assert_eq!(body[0]["location"], serde_json::json!({ "lat": 2.0, "lng": 1.0 }));
+
+		let (status, _) = send(&app, Method::GET, &format!("/ambulances/{}", ambulance.id.0), Some(&token), None).await;
+		assert_eq!(status, StatusCode::OK);
+		let (status, _) = send(&app, Method::GET, &format!("/ambulances/{}", Uuid::nil()), Some(&token), None).await;
+		assert_eq!(status, StatusCode::NOT_FOUND);
+
+		// no hospital to default to
+		let (status, _) = send(&app, Method::GET, &format!("/ambulances/{}/eta", ambulance.id.0), Some(&token), None).await;
+		assert_eq!(status, StatusCode::CONFLICT);
+
+		let (status, body) = send(&app, Method::GET, &format!("/ambulances/{}/eta?lat=3&lng=4", ambulance.id.0), Some(&token), None).await;
+		assert_eq!(status, StatusCode::OK);
+		assert_eq!(body["eta_secs"], 600);
+		assert_eq!(body["source"], "stub");
+	}
+}
\ No newline at end of file
@@ -2,4 +2,114 @@ pub mod sql_account_manager;
 pub mod sql_ambulance_tracker;
 pub mod archive_eta;
 pub mod sql_settings_manager;
-pub mod interval_conversion;
\ No newline at end of file
+pub mod sql_tracking_manager;
+pub mod interval_conversion;
+pub mod coordinate_precision;
+pub mod events;
+pub mod geometry_conversion;
+pub mod acquire_retry;
+pub mod schema;
+pub mod shutdown;
+pub mod statement_timeout;
+
+use crate::data::{AccountManager, AmbulanceTracker, SettingsManager, TrackingManager};
+use crate::eta::eta_finder::EtaFinder;
+use crate::sql::schema::quote_identifier;
+use sqlx::migrate::MigrateError;
+use sqlx::PgPool;
+use std::sync::Arc;
+
+/// Applies any migrations in `migrations/` which have not yet been run against the database
+pub async fn run_migrations(pool: &PgPool) -> Result<(), sqlx::migrate::MigrateError> {
+	sqlx::migrate!().run(pool).await
+}
+
+/// Applies the migrations to the tables of a tenant's schema, creating the schema if needed, for
+/// managers configured with its name through their `with_schema`. The schema gets its own
+/// `account_role` and `urgency` types, while PostGIS's are found in the default schema.
+pub async fn run_migrations_in_schema(pool: &PgPool, schema: &str) -> Result<(), MigrateError> {
+	let schema = quote_identifier(schema);
+	// the search path is changed for the whole session, so the connection is closed afterwards
+	// rather than returned to the pool
+	let mut conn = pool.acquire().await.map_err(MigrateError::Execute)?.detach();
+
+	sqlx::query(&format!("CREATE SCHEMA IF NOT EXISTS {schema};")).execute(&mut conn).await.map_err(MigrateError::Execute)?;
+
+	// objects are created in, and the migrations' drops and type checks look at, the first schema
+	// of the search path, while types such as PostGIS's are still found in the default one
+	sqlx::query("SELECT set_config('search_path', $1 || ', ' || current_setting('search_path'), false);")
+		.bind(&schema)
+		.execute(&mut conn)
+		.await
+		.map_err(MigrateError::Execute)?;
+	sqlx::migrate!().run(&mut conn).await
+}
+
+// Returning the managers as trait objects also checks that they are Send and Sync.
+
+/// Creates an [sql_account_manager::SqlAccountManager] with its default configuration, for
+/// applications which only use it through [AccountManager]
+pub fn account_manager(pool: PgPool) -> Arc<dyn AccountManager + Send + Sync> {
+	Arc::new(sql_account_manager::SqlAccountManager::new(pool))
+}
+
+/// Creates an [sql_ambulance_tracker::SQLAmbulanceTracker] with its default configuration, for
+/// applications which only use it through [AmbulanceTracker]
+pub fn ambulance_tracker(pool: PgPool) -> Arc<dyn AmbulanceTracker + Send + Sync> {
+	Arc::new(sql_ambulance_tracker::SQLAmbulanceTracker::new(pool))
+}
+
+/// Creates an [sql_settings_manager::SQLSettingsManager] with its default configuration, for
+/// applications which only use it through [SettingsManager]
+pub fn settings_manager(pool: PgPool) -> Arc<dyn SettingsManager + Send + Sync> {
+	Arc::new(sql_settings_manager::SQLSettingsManager::new(pool))
+}
+
+/// Creates an [sql_tracking_manager::SQLTrackingManager] calculating ETAs with the finder, for
+/// applications which only use it through [TrackingManager]
+pub fn tracking_manager(pool: PgPool, finder: Box<dyn EtaFinder + 'static + Sync + Send>) -> Arc<dyn TrackingManager + Send + Sync> {
+	Arc::new(sql_tracking_manager::SQLTrackingManager::new(pool, finder))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::data::AmbulanceReader;
+	use crate::eta::fixed_eta::NoRouteEta;
+	use geo_types::Point;
+	use sqlx::types::chrono::Utc;
+
+	struct Managers {
+		_accounts: Arc<dyn AccountManager + Send + Sync>,
+		ambulances: Arc<dyn AmbulanceTracker + Send + Sync>,
+		_settings: Arc<dyn SettingsManager + Send + Sync>,
+		_tracking: Arc<dyn TrackingManager + Send + Sync>
+	}
+
+	#[sqlx::test]
+	async fn test_trait_object_constructors(pool: PgPool) {
+		let managers = Managers {
+			_accounts: account_manager(pool.clone()),
+			ambulances: ambulance_tracker(pool.clone()),
+			_settings: settings_manager(pool.clone()),
+			_tracking: tracking_manager(pool, Box::new(NoRouteEta))
+		};
+
+		managers.ambulances.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), Utc::now()).await.unwrap();
+		assert_eq!(managers.ambulances.count_ambulances().await.unwrap(), 1);
+	}
+
+	#[sqlx::test]
+	async fn test_migrating_schema_leaves_default_alone(pool: PgPool) {
+		let ambulances = ambulance_tracker(pool.clone());
+		ambulances.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), Utc::now()).await.unwrap();
+
+		run_migrations_in_schema(&pool, "tenant x").await.unwrap();
+		// running them again finds the schema already migrated
+		run_migrations_in_schema(&pool, "tenant x").await.unwrap();
+
+		assert_eq!(ambulances.count_ambulances().await.unwrap(), 1);
+		let (types,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM pg_type WHERE typname IN ('account_role', 'urgency');").fetch_one(&pool).await.unwrap();
+		assert_eq!(types, 4);
+	}
+}
\ No newline at end of file
@@ -0,0 +1,71 @@
+use sqlx::pool::PoolConnection;
+use sqlx::{PgPool, Postgres};
+use std::time::Duration;
+
+/// How many times to try acquiring a connection when the pool is exhausted, and how long to wait
+/// before the first retry. The wait doubles after each further attempt.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AcquireRetry {
+	pub attempts: u32,
+	pub backoff: Duration
+}
+
+impl AcquireRetry {
+	pub fn new(attempts: u32, backoff: Duration) -> Self {
+		Self { attempts, backoff }
+	}
+}
+
+/// Acquires a connection from the pool, retrying according to the policy if acquiring timed out.
+/// Any other error, such as the pool having been closed, is returned immediately.
+pub async fn acquire(pool: &PgPool, retry: Option<AcquireRetry>) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+	let AcquireRetry { attempts, mut backoff } = retry.unwrap_or(AcquireRetry::new(1, Duration::ZERO));
+
+	let mut attempt = 1;
+	loop {
+		match pool.acquire().await {
+			Err(sqlx::Error::PoolTimedOut) if attempt < attempts => {
+				tokio::time::sleep(backoff).await;
+				backoff *= 2;
+				attempt += 1;
+			},
+			result => return result
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+	fn tiny_pool(options: PgConnectOptions) -> PgPool {
+		PgPoolOptions::new()
+			.max_connections(1)
+			.acquire_timeout(Duration::from_millis(50))
+			.connect_lazy_with(options)
+	}
+
+	#[sqlx::test]
+	async fn retries_exhausted_pool(_: PgPoolOptions, options: PgConnectOptions) {
+		let pool = tiny_pool(options);
+
+		let held = pool.acquire().await.unwrap();
+		assert!(matches!(acquire(&pool, None).await, Err(sqlx::Error::PoolTimedOut)));
+
+		tokio::spawn(async move {
+			tokio::time::sleep(Duration::from_millis(150)).await;
+			drop(held);
+		});
+		acquire(&pool, Some(AcquireRetry::new(10, Duration::from_millis(20)))).await.unwrap();
+	}
+
+	#[sqlx::test]
+	async fn closed_pool_is_not_retried(_: PgPoolOptions, options: PgConnectOptions) {
+		let pool = tiny_pool(options);
+		pool.close().await;
+
+		let result = acquire(&pool, Some(AcquireRetry::new(10, Duration::from_secs(10)))).await;
+		assert!(matches!(result, Err(sqlx::Error::PoolClosed)), "{:?}", result);
+	}
+}
\ No newline at end of file
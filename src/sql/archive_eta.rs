@@ -1,36 +1,502 @@
-use crate::eta::eta_finder::EtaFinder;
+use crate::data::{Clock, SystemClock};
+use crate::eta::eta_finder::{EtaError, EtaFinder, EtaOptions};
+use crate::sql::geometry_conversion::decode_point;
+use crate::sql::schema::Schema;
 use geo_types::{Geometry, Point};
 use geozero::wkb;
-use sqlx::types::chrono::Utc;
+use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::types::Uuid;
 use sqlx::PgPool;
 use std::error::Error;
+use std::sync::Arc;
 use std::time::Duration;
 
-pub struct ArchiveEta(PgPool, Box<dyn EtaFinder + 'static + Sync + Send>);
+pub struct ArchiveEta(PgPool, Box<dyn EtaFinder + 'static + Sync + Send>, bool, bool, Arc<dyn Clock>, Option<Arc<dyn MotionSource + Send + Sync>>, Schema);
+
+/// How an ambulance reported it was moving
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AmbulanceMotion {
+	/// Speed in meters per second
+	pub speed: f64,
+	/// Heading in degrees clockwise from north
+	pub heading: f64
+}
+
+/// Provides the motion of ambulances to archive alongside their ETAs, see
+/// [ArchiveEta::with_motion_source]
+#[async_trait::async_trait]
+pub trait MotionSource {
+	/// Returns the latest motion the ambulance reported, or `None` if it has not reported any
+	async fn motion(&self, ambulance_id: Uuid) -> Result<Option<AmbulanceMotion>, Box<dyn Error>>;
+}
+
+/// An ETA which has been recorded in the archive
+#[derive(Clone, Debug)]
+pub struct ArchivedEta {
+	pub ambulance_id: Uuid,
+	pub current_location: Point,
+	pub destination: Point,
+	/// When the ambulance was expected to arrive, which is `calculated_at` plus the calculated
+	/// duration, or `None` if the calculation failed
+	pub eta: Option<DateTime<Utc>>,
+	pub calculated_at: DateTime<Utc>,
+	/// The provider which calculated the ETA, or `None` if archived before sources were recorded
+	pub source: Option<String>,
+	/// The [EtaError::category] of a failed calculation
+	pub error: Option<String>,
+	/// The ambulance's reported speed when the ETA was calculated, if known
+	pub speed: Option<f64>,
+	/// The ambulance's reported heading when the ETA was calculated, if known
+	pub heading: Option<f64>
+}
 
 /// A wrapper over an ETA finder which uses the SQL backend to archive an ETA whenever a new one is
 /// calculated. Expects that migrations has been executed already.
 #[async_trait::async_trait]
 impl EtaFinder for ArchiveEta {
-	async fn calculate_eta(&self, ambulance_id: Uuid, from: Point, to: Point) -> Result<Duration, Box<dyn Error>> {
-		let eta = self.1.calculate_eta(ambulance_id, from, to).await?;
+	async fn calculate_eta(&self, ambulance_id: Uuid, from: Point, to: Point) -> Result<Duration, EtaError> {
+		Ok(self.calculate_eta_with_source(ambulance_id, from, to).await?.0)
+	}
+
+	fn source(&self) -> &str {
+		self.1.source()
+	}
+
+	async fn calculate_eta_with_source(&self, ambulance_id: Uuid, from: Point, to: Point) -> Result<(Duration, String), EtaError> {
+		let (eta, source) = match self.1.calculate_eta_with_source(ambulance_id, from, to).await {
+			Ok(calculated) => calculated,
+			Err(e) => return Err(self.archive_failure(ambulance_id, from, to, e).await)
+		};
+		Ok((self.archive(ambulance_id, from, to, eta, &source).await?, source))
+	}
+
+	/// Archives the pessimistic end of the range, as the archive holds a single ETA per calculation
+	async fn calculate_eta_range(&self, ambulance_id: Uuid, from: Point, to: Point) -> Result<(Duration, Duration), EtaError> {
+		let (optimistic, pessimistic) = match self.1.calculate_eta_range(ambulance_id, from, to).await {
+			Ok(range) => range,
+			Err(e) => return Err(self.archive_failure(ambulance_id, from, to, e).await)
+		};
+		Ok((optimistic, self.archive(ambulance_id, from, to, pessimistic, self.1.source()).await?))
+	}
+
+	async fn calculate_eta_with_options(&self, ambulance_id: Uuid, from: Point, to: Point, options: &EtaOptions) -> Result<Duration, EtaError> {
+		Ok(self.calculate_eta_with_options_and_source(ambulance_id, from, to, options).await?.0)
+	}
+
+	async fn calculate_eta_with_options_and_source(&self, ambulance_id: Uuid, from: Point, to: Point, options: &EtaOptions) -> Result<(Duration, String), EtaError> {
+		let (eta, source) = match self.1.calculate_eta_with_options_and_source(ambulance_id, from, to, options).await {
+			Ok(calculated) => calculated,
+			Err(e) => return Err(self.archive_failure(ambulance_id, from, to, e).await)
+		};
+		Ok((self.archive(ambulance_id, from, to, eta, &source).await?, source))
+	}
+}
+
+impl ArchiveEta {
+	/// Creates a strict archiver, where a failure to archive an ETA fails the calculation.
+	pub fn new(pool: PgPool, finder: Box<dyn EtaFinder + 'static + Sync + Send>) -> Self {
+		Self(pool, finder, false, false, Arc::new(SystemClock), None, Schema::default())
+	}
+
+	/// Sets whether archiving is best effort. When best effort, a failure to archive is logged and
+	/// the calculated ETA is still returned.
+	pub fn with_best_effort(mut self, best_effort: bool) -> Self {
+		self.2 = best_effort;
+		self
+	}
+
+	/// Sets whether failed calculations are archived as well, with no ETA and the category of the
+	/// error. The error is still returned to the caller.
+	pub fn with_failure_archiving(mut self, archive_failures: bool) -> Self {
+		self.3 = archive_failures;
+		self
+	}
+
+	/// Records calculation times from the clock rather than the system clock
+	pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+		self.4 = clock;
+		self
+	}
+
+	/// Archives the ambulance's motion from the source with each ETA. A failure to read the motion
+	/// is logged and the ETA is archived without it.
+	pub fn with_motion_source(mut self, source: Arc<dyn MotionSource + Send + Sync>) -> Self {
+		self.5 = Some(source);
+		self
+	}
 
-		sqlx::query("INSERT INTO archive_etas(ambulance_id, current_location, destination, eta, calculated_at) VALUES ($1, $2, $3, $4, $5)")
+	/// Archives to, and reads from, the tables in the given Postgres schema, as
+	/// [crate::sql::sql_account_manager::SqlAccountManager::with_schema] does
+	pub fn with_schema(mut self, schema: &str) -> Self {
+		self.6 = Schema::named(schema);
+		self
+	}
+
+	/// Calculates an ETA with the wrapped finder without archiving it, for previews which should
+	/// not count towards the archive
+	pub async fn calculate_eta_no_archive(&self, ambulance_id: Uuid, from: Point, to: Point) -> Result<Duration, EtaError> {
+		self.1.calculate_eta(ambulance_id, from, to).await
+	}
+
+	/// Returns the archived ETAs for an ambulance, most recently calculated first
+	pub async fn get_archived_etas(&self, ambulance_id: Uuid) -> Result<Vec<ArchivedEta>, Box<dyn Error>> {
+		let rows: Vec<(Uuid, wkb::Decode<Geometry>, wkb::Decode<Geometry>, Option<DateTime<Utc>>, DateTime<Utc>, Option<String>, Option<String>, Option<f64>, Option<f64>)> =
+			sqlx::query_as(&self.6.qualify("SELECT ambulance_id, current_location, destination, eta, calculated_at, source, error, speed, heading FROM {schema}archive_etas WHERE ambulance_id=$1 ORDER BY calculated_at DESC;"))
+				.bind(ambulance_id)
+				.fetch_all(&self.0)
+				.await?;
+
+		rows.into_iter().map(|(ambulance_id, current_location, destination, eta, calculated_at, source, error, speed, heading)| -> Result<ArchivedEta, Box<dyn Error>> {
+			Ok(ArchivedEta {
+				ambulance_id,
+				current_location: decode_point(current_location.geometry)?,
+				destination: decode_point(destination.geometry)?,
+				eta,
+				calculated_at,
+				source,
+				error,
+				speed,
+				heading
+			})
+		}).collect()
+	}
+
+	/// Returns the most recently archived successful ETA of an ambulance, along with when it was
+	/// calculated, or `None` if none has been archived
+	pub async fn latest_archived_eta(&self, ambulance_id: Uuid) -> Result<Option<(Duration, DateTime<Utc>)>, Box<dyn Error>> {
+		let row: Option<(DateTime<Utc>, DateTime<Utc>)> =
+			sqlx::query_as(&self.6.qualify("SELECT eta, calculated_at FROM {schema}archive_etas WHERE ambulance_id=$1 AND eta IS NOT NULL ORDER BY calculated_at DESC LIMIT 1;"))
+				.bind(ambulance_id)
+				.fetch_optional(&self.0)
+				.await?;
+
+		row.map(|(eta, calculated_at)| Ok(((eta - calculated_at).to_std()?, calculated_at))).transpose()
+	}
+
+	/// Archives a calculated ETA, returning it unless archiving fails in strict mode
+	async fn archive(&self, ambulance_id: Uuid, from: Point, to: Point, eta: Duration, source: &str) -> Result<Duration, EtaError> {
+		match self.insert(ambulance_id, from, to, Some(eta), source, None).await {
+			Ok(_) => Ok(eta),
+			Err(e) if self.2 => {
+				tracing::warn!(error = %e, %ambulance_id, "failed to archive calculated eta");
+				Ok(eta)
+			},
+			Err(e) => Err(EtaError::Other(e.into()))
+		}
+	}
+
+	/// Archives a failed calculation if enabled, returning the calculation's error. A failure to
+	/// archive is only logged, so that it does not hide the original error.
+	async fn archive_failure(&self, ambulance_id: Uuid, from: Point, to: Point, error: EtaError) -> EtaError {
+		if self.3 {
+			if let Err(e) = self.insert(ambulance_id, from, to, None, self.1.source(), Some(error.category())).await {
+				tracing::warn!(error = %e, %ambulance_id, "failed to archive failed eta calculation");
+			}
+		}
+		error
+	}
+
+	/// Inserts an archive row. The ETA is stored as the time of arrival rather than as a duration,
+	/// as the `eta` column is a timestamp.
+	async fn insert(&self, ambulance_id: Uuid, from: Point, to: Point, eta: Option<Duration>, source: &str, error: Option<&str>) -> Result<(), sqlx::Error> {
+		let now = self.4.now();
+		let motion = match &self.5 {
+			Some(motion_source) => motion_source.motion(ambulance_id).await.unwrap_or_else(|e| {
+				tracing::warn!(error = %e, %ambulance_id, "failed to read ambulance motion to archive");
+				None
+			}),
+			None => None
+		};
+
+		sqlx::query(&self.6.qualify("INSERT INTO {schema}archive_etas(ambulance_id, current_location, destination, eta, calculated_at, source, error, speed, heading) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"))
 			.bind(ambulance_id)
 			.bind(wkb::Encode::<Geometry>(from.into()))
 			.bind(wkb::Encode::<Geometry>(to.into()))
-			.bind(eta)
-			.bind(Utc::now())
+			.bind(eta.map(|eta| now + eta))
+			.bind(now)
+			.bind(source)
+			.bind(error)
+			.bind(motion.map(|motion| motion.speed))
+			.bind(motion.map(|motion| motion.heading))
 			.execute(&self.0)
 			.await?;
-
-		Ok(eta)
+		Ok(())
 	}
 }
 
-impl ArchiveEta {
-	pub fn new(pool: PgPool, finder: Box<dyn EtaFinder + 'static + Sync + Send>) -> Self {
-		Self(pool, finder)
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::eta::fixed_eta::{FixedEta, NoRouteEta};
+
+	/// Reports a range of its own, and reports falling back to another provider when given options
+	struct FallbackEta;
+
+	#[async_trait::async_trait]
+	impl EtaFinder for FallbackEta {
+		async fn calculate_eta(&self, _ambulance_id: Uuid, _from: Point, _to: Point) -> Result<Duration, EtaError> {
+			Ok(Duration::from_secs(300))
+		}
+
+		fn source(&self) -> &str {
+			"primary"
+		}
+
+		async fn calculate_eta_range(&self, _ambulance_id: Uuid, _from: Point, _to: Point) -> Result<(Duration, Duration), EtaError> {
+			Ok((Duration::from_secs(200), Duration::from_secs(400)))
+		}
+
+		async fn calculate_eta_with_options_and_source(&self, _ambulance_id: Uuid, _from: Point, _to: Point, _options: &EtaOptions) -> Result<(Duration, String), EtaError> {
+			Ok((Duration::from_secs(300), "fallback".to_string()))
+		}
+	}
+
+	fn get_archive(pool: PgPool, best_effort: bool) -> ArchiveEta {
+		ArchiveEta::new(pool, Box::new(FixedEta::new(Duration::from_secs(300)).with_source("stub"))).with_best_effort(best_effort)
+	}
+
+	async fn archive_count(pool: &PgPool) -> i64 {
+		sqlx::query_as::<_, (i64,)>("SELECT COUNT(*) FROM archive_etas").fetch_one(pool).await.unwrap().0
+	}
+
+	async fn break_archive(pool: &PgPool) {
+		sqlx::query("DROP TABLE archive_etas").execute(pool).await.unwrap();
+	}
+
+	#[sqlx::test]
+	async fn test_archives_eta(pool: PgPool) {
+		let archive = get_archive(pool.clone(), false);
+
+		let eta = archive.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap();
+		assert_eq!(eta, Duration::from_secs(300));
+		assert_eq!(archive_count(&pool).await, 1);
+
+		let archived = archive.get_archived_etas(Uuid::nil()).await.unwrap();
+		assert_eq!(archived.len(), 1);
+		assert_eq!(archived[0].current_location, Point::new(0.0, 0.0));
+		assert_eq!(archived[0].destination, Point::new(1.0, 1.0));
+		assert_eq!((archived[0].eta.unwrap() - archived[0].calculated_at).num_seconds(), 300);
+		assert_eq!(archived[0].error, None);
+	}
+
+	#[sqlx::test]
+	async fn test_archives_arrival_time(pool: PgPool) {
+		let archive = get_archive(pool.clone(), false);
+		archive.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap();
+
+		// the column holds when the ambulance arrives, not how long it takes
+		let (seconds,): (f64,) = sqlx::query_as("SELECT EXTRACT(EPOCH FROM eta - calculated_at)::float8 FROM archive_etas;")
+			.fetch_one(&pool)
+			.await
+			.unwrap();
+		assert_eq!(seconds, 300.0);
+	}
+
+	#[sqlx::test]
+	async fn test_archive_uses_clock(pool: PgPool) {
+		use crate::data::ManualClock;
+		use sqlx::types::chrono::SubsecRound;
+
+		let now = Utc::now().trunc_subsecs(0) - Duration::from_secs(60 * 60);
+		let archive = get_archive(pool, false).with_clock(Arc::new(ManualClock::new(now)));
+		archive.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap();
+
+		let archived = archive.get_archived_etas(Uuid::nil()).await.unwrap();
+		assert_eq!(archived[0].calculated_at, now);
+		assert_eq!(archived[0].eta, Some(now + Duration::from_secs(300)));
+	}
+
+	#[sqlx::test]
+	async fn test_archives_source(pool: PgPool) {
+		let mapbox_id = Uuid::from_u128(1);
+		let haversine_id = Uuid::from_u128(2);
+
+		let mapbox = ArchiveEta::new(pool.clone(), Box::new(FixedEta::new(Duration::from_secs(300)).with_source("mapbox")));
+		let haversine = ArchiveEta::new(pool.clone(), Box::new(FixedEta::new(Duration::from_secs(600)).with_source("haversine")));
+
+		let (_, source) = mapbox.calculate_eta_with_source(mapbox_id, Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap();
+		assert_eq!(source, "mapbox");
+		haversine.calculate_eta(haversine_id, Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap();
+
+		let archived = mapbox.get_archived_etas(mapbox_id).await.unwrap();
+		assert_eq!(archived.len(), 1);
+		assert_eq!(archived[0].source.as_deref(), Some("mapbox"));
+
+		let archived = mapbox.get_archived_etas(haversine_id).await.unwrap();
+		assert_eq!(archived.len(), 1);
+		assert_eq!(archived[0].source.as_deref(), Some("haversine"));
+	}
+
+	#[sqlx::test]
+	async fn test_archives_range_of_inner_finder(pool: PgPool) {
+		let archive = ArchiveEta::new(pool, Box::new(FallbackEta));
+
+		let range = archive.calculate_eta_range(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap();
+		assert_eq!(range, (Duration::from_secs(200), Duration::from_secs(400)));
+
+		let archived = archive.get_archived_etas(Uuid::nil()).await.unwrap();
+		assert_eq!(archived.len(), 1);
+		assert_eq!((archived[0].eta.unwrap() - archived[0].calculated_at).num_seconds(), 400);
+	}
+
+	#[sqlx::test]
+	async fn test_archives_source_used_with_options(pool: PgPool) {
+		let archive = ArchiveEta::new(pool, Box::new(FallbackEta));
+
+		let options = EtaOptions { exclude_tolls: true, ..EtaOptions::default() };
+		let eta = archive.calculate_eta_with_options(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0), &options).await.unwrap();
+		assert_eq!(eta, Duration::from_secs(300));
+
+		let archived = archive.get_archived_etas(Uuid::nil()).await.unwrap();
+		assert_eq!(archived[0].source.as_deref(), Some("fallback"));
+	}
+
+	#[sqlx::test]
+	async fn test_strict_archive_failure(pool: PgPool) {
+		let archive = get_archive(pool.clone(), false);
+		break_archive(&pool).await;
+
+		let result = archive.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await;
+		assert!(result.is_err());
+	}
+
+	#[sqlx::test]
+	async fn test_best_effort_archive_failure(pool: PgPool) {
+		let archive = get_archive(pool.clone(), true);
+		break_archive(&pool).await;
+
+		let eta = archive.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap();
+		assert_eq!(eta, Duration::from_secs(300));
+	}
+
+	#[sqlx::test]
+	async fn test_archives_failure(pool: PgPool) {
+		let archive = ArchiveEta::new(pool.clone(), Box::new(NoRouteEta)).with_failure_archiving(true);
+
+		let result = archive.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await;
+		assert!(matches!(result, Err(EtaError::NoRoute)), "{:?}", result);
+
+		let archived = archive.get_archived_etas(Uuid::nil()).await.unwrap();
+		assert_eq!(archived.len(), 1);
+		assert_eq!(archived[0].eta, None);
+		assert_eq!(archived[0].error.as_deref(), Some("no_route"));
+		assert_eq!(archived[0].source.as_deref(), Some("no_route"));
+	}
+
+	#[sqlx::test]
+	async fn test_failures_not_archived_by_default(pool: PgPool) {
+		let archive = ArchiveEta::new(pool.clone(), Box::new(NoRouteEta));
+
+		let result = archive.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await;
+		assert!(matches!(result, Err(EtaError::NoRoute)), "{:?}", result);
+		assert_eq!(archive_count(&pool).await, 0);
+	}
+
+	#[sqlx::test]
+	async fn test_latest_archived_eta(pool: PgPool) {
+		use crate::data::ManualClock;
+
+		let now = Utc::now();
+		let clock = Arc::new(ManualClock::new(now));
+		let archive = get_archive(pool.clone(), false).with_clock(clock.clone());
+		assert!(archive.latest_archived_eta(Uuid::nil()).await.unwrap().is_none());
+
+		archive.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap();
+		clock.advance(Duration::from_secs(60));
+		let later = ArchiveEta::new(pool.clone(), Box::new(FixedEta::new(Duration::from_secs(120)).with_source("stub"))).with_clock(clock.clone());
+		later.calculate_eta(Uuid::nil(), Point::new(0.5, 0.5), Point::new(1.0, 1.0)).await.unwrap();
+
+		// failures are not an ETA to display
+		clock.advance(Duration::from_secs(60));
+		let failing = ArchiveEta::new(pool, Box::new(NoRouteEta)).with_failure_archiving(true).with_clock(clock);
+		failing.calculate_eta(Uuid::nil(), Point::new(0.5, 0.5), Point::new(1.0, 1.0)).await.unwrap_err();
+
+		let (eta, calculated_at) = archive.latest_archived_eta(Uuid::nil()).await.unwrap().unwrap();
+		assert_eq!(eta, Duration::from_secs(120));
+		assert_eq!(calculated_at.timestamp_micros(), (now + Duration::from_secs(60)).timestamp_micros());
+	}
+
+	struct StubMotion(Uuid, AmbulanceMotion);
+
+	#[async_trait::async_trait]
+	impl MotionSource for StubMotion {
+		async fn motion(&self, ambulance_id: Uuid) -> Result<Option<AmbulanceMotion>, Box<dyn Error>> {
+			Ok((ambulance_id == self.0).then_some(self.1))
+		}
+	}
+
+	struct FailingMotion;
+
+	#[async_trait::async_trait]
+	impl MotionSource for FailingMotion {
+		async fn motion(&self, _ambulance_id: Uuid) -> Result<Option<AmbulanceMotion>, Box<dyn Error>> {
+			Err("motion unavailable".into())
+		}
+	}
+
+	#[sqlx::test]
+	async fn test_archives_motion(pool: PgPool) {
+		let moving = Uuid::from_u128(1);
+		let motion = AmbulanceMotion { speed: 13.5, heading: 270.0 };
+		let stub = get_archive(pool.clone(), false).with_motion_source(Arc::new(StubMotion(moving, motion)));
+		let failed_eta = ArchiveEta::new(pool.clone(), Box::new(NoRouteEta))
+			.with_failure_archiving(true)
+			.with_motion_source(Arc::new(StubMotion(moving, motion)));
+
+		// the motion is archived with both calculated and failed ETAs
+		stub.calculate_eta(moving, Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap();
+		failed_eta.calculate_eta(moving, Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap_err();
+		let archived = stub.get_archived_etas(moving).await.unwrap();
+		assert_eq!(archived.len(), 2);
+		for archived in archived {
+			assert_eq!((archived.speed, archived.heading), (Some(13.5), Some(270.0)));
+		}
+
+		// an ambulance which has not reported its motion is archived without it
+		stub.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap();
+		let archived = stub.get_archived_etas(Uuid::nil()).await.unwrap();
+		assert_eq!((archived[0].speed, archived[0].heading), (None, None));
+
+		// as is one whose motion could not be read
+		let failed_motion = get_archive(pool, false).with_motion_source(Arc::new(FailingMotion));
+		let other = Uuid::from_u128(2);
+		failed_motion.calculate_eta(other, Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap();
+		let archived = failed_motion.get_archived_etas(other).await.unwrap();
+		assert_eq!((archived[0].speed, archived[0].heading), (None, None));
+	}
+
+	#[sqlx::test]
+	async fn test_schema_is_isolated_from_default(pool: PgPool) {
+		crate::sql::run_migrations_in_schema(&pool, "tenant x").await.unwrap();
+		let default = get_archive(pool.clone(), false);
+		let tenant = ArchiveEta::new(pool.clone(), Box::new(FixedEta::new(Duration::from_secs(600)).with_source("tenant"))).with_schema("tenant x");
+
+		default.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap();
+		tenant.calculate_eta(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap();
+		tenant.calculate_eta(Uuid::nil(), Point::new(0.5, 0.5), Point::new(1.0, 1.0)).await.unwrap();
+
+		// each archive only holds and reads its own ETAs
+		assert_eq!(archive_count(&pool).await, 1);
+		let archived = default.get_archived_etas(Uuid::nil()).await.unwrap();
+		assert_eq!(archived.iter().map(|eta| eta.source.as_deref()).collect::<Vec<_>>(), vec![Some("stub")]);
+		let archived = tenant.get_archived_etas(Uuid::nil()).await.unwrap();
+		assert_eq!(archived.len(), 2);
+		assert!(archived.iter().all(|eta| eta.source.as_deref() == Some("tenant")));
+		assert_eq!(default.latest_archived_eta(Uuid::nil()).await.unwrap().unwrap().0, Duration::from_secs(300));
+		assert_eq!(tenant.latest_archived_eta(Uuid::nil()).await.unwrap().unwrap().0, Duration::from_secs(600));
+	}
+
+	#[sqlx::test]
+	async fn test_calculate_eta_no_archive(pool: PgPool) {
+		let archive = get_archive(pool.clone(), false);
+		let eta = archive.calculate_eta_no_archive(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await.unwrap();
+		assert_eq!(eta, Duration::from_secs(300));
+		assert_eq!(archive_count(&pool).await, 0);
+
+		// failures are not archived either
+		let failing = ArchiveEta::new(pool.clone(), Box::new(NoRouteEta)).with_failure_archiving(true);
+		let result = failing.calculate_eta_no_archive(Uuid::nil(), Point::new(0.0, 0.0), Point::new(1.0, 1.0)).await;
+		assert!(matches!(result, Err(EtaError::NoRoute)), "{:?}", result);
+		assert_eq!(archive_count(&pool).await, 0);
 	}
 }
\ No newline at end of file
@@ -0,0 +1,38 @@
+use geo_types::Point;
+
+/// Rounds both coordinates of a point to the specified number of decimal places, or returns it
+/// unchanged if no precision is specified.
+///
+/// Five decimal places of longitude/latitude is roughly one meter.
+pub fn round_point(point: Point, decimal_places: Option<u32>) -> Point {
+	match decimal_places {
+		Some(decimal_places) => {
+			let scale = 10f64.powi(decimal_places as i32);
+			Point::new((point.x() * scale).round() / scale, (point.y() * scale).round() / scale)
+		},
+		None => point
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rounds_to_precision() {
+		let point = round_point(Point::new(1.123456789, -2.987654321), Some(5));
+		assert_eq!(point, Point::new(1.12346, -2.98765));
+
+		let point = round_point(Point::new(1.5, -2.5), Some(0));
+		assert_eq!(point, Point::new(2.0, -3.0));
+
+		let point = Point::new(1.123456789, -2.987654321);
+		assert_eq!(round_point(point, None), point);
+	}
+
+	#[test]
+	fn rounding_is_idempotent() {
+		let once = round_point(Point::new(40.712775, -74.005973), Some(4));
+		assert_eq!(round_point(once, Some(4)), once);
+	}
+}
\ No newline at end of file
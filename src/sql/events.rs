@@ -0,0 +1,162 @@
+use futures::{Stream, StreamExt};
+use sqlx::postgres::PgListener;
+use sqlx::types::Uuid;
+use sqlx::{Connection, PgConnection, PgExecutor, PgPool};
+
+/// The channel [BackendEvent]s about accounts are sent to
+pub const ACCOUNT_EVENTS: &str = "account_events";
+/// The channel [BackendEvent]s about phones are sent to
+pub const PHONE_EVENTS: &str = "phone_events";
+/// The channel [BackendEvent]s about user settings are sent to
+pub const SETTINGS_EVENTS: &str = "settings_events";
+/// The channel [BackendEvent]s about ambulances are sent to
+pub const AMBULANCE_EVENTS: &str = "ambulance_events";
+
+/// A change made through one of the managers, as sent to a `NOTIFY` channel. Only identifies what
+/// changed, so that sensitive fields are never exposed to listeners.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct BackendEvent {
+	/// The kind of entity which changed, such as `phone`
+	pub entity: String,
+	/// The id of the entity which changed. For settings this is the user's id.
+	pub id: Uuid,
+	/// What happened to the entity, such as `created`
+	pub action: String
+}
+
+impl BackendEvent {
+	pub(crate) fn new(entity: &str, id: Uuid, action: &str) -> Self {
+		Self {
+			entity: entity.to_string(),
+			id,
+			action: action.to_string()
+		}
+	}
+
+	/// The entity and action together, such as `phone_created`
+	pub fn name(&self) -> String {
+		format!("{}_{}", self.entity, self.action)
+	}
+
+	/// The channel this event is sent to
+	pub fn channel(&self) -> String {
+		format!("{}_events", self.entity)
+	}
+}
+
+/// Sends an event to its channel. When executed within a transaction the event is only delivered
+/// once the transaction commits.
+pub(crate) async fn notify<'e, E: PgExecutor<'e>>(executor: E, event: &BackendEvent) -> Result<(), sqlx::Error> {
+	let payload = serde_json::to_string(event).map_err(|e| sqlx::Error::Encode(e.into()))?;
+	sqlx::query("SELECT pg_notify($1, $2);")
+		.bind(event.channel())
+		.bind(payload)
+		.execute(executor)
+		.await?;
+	Ok(())
+}
+
+/// Sends an event after a change has already been made, logging rather than returning a failure
+/// so that the change is not reported as failed.
+pub(crate) async fn emit(pool: &PgPool, event: BackendEvent) {
+	if let Err(e) = notify(pool, &event).await {
+		tracing::warn!(error = %e, event = %event.name(), id = %event.id, "failed to emit backend event");
+	}
+}
+
+/// Sends an event as part of the transaction the connection is in, so it is only delivered once
+/// the transaction commits. As with [emit] a failure is logged rather than returned, and it is
+/// contained in a savepoint so that it does not abort the transaction.
+pub(crate) async fn emit_in_transaction(conn: &mut PgConnection, event: BackendEvent) {
+	let result = async {
+		let mut savepoint = conn.begin().await?;
+		notify(&mut *savepoint, &event).await?;
+		savepoint.commit().await
+	}.await;
+	if let Err(e) = result {
+		tracing::warn!(error = %e, event = %event.name(), id = %event.id, "failed to emit backend event");
+	}
+}
+
+/// Listens on the specified channels, such as [PHONE_EVENTS], returning a stream of the events
+/// sent to them. Notifications which are not events are skipped.
+pub async fn subscribe_events(pool: &PgPool, channels: &[&str]) -> Result<impl Stream<Item = BackendEvent>, sqlx::Error> {
+	let mut listener = PgListener::connect_with(pool).await?;
+	listener.listen_all(channels.iter().copied()).await?;
+
+	Ok(listener.into_stream().filter_map(|notification| async move {
+		match notification {
+			Ok(notification) => match serde_json::from_str(notification.payload()) {
+				Ok(event) => Some(event),
+				Err(e) => {
+					tracing::warn!(error = %e, channel = notification.channel(), "ignoring notification which is not a backend event");
+					None
+				}
+			},
+			Err(e) => {
+				tracing::warn!(error = %e, "failed to receive backend event");
+				None
+			}
+		}
+	}))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::data::{AccountManager, AccountRole, SettingsManager};
+	use crate::sql::sql_account_manager::SqlAccountManager;
+	use crate::sql::sql_settings_manager::SQLSettingsManager;
+	use std::time::Duration;
+
+	#[test]
+	fn event_payload_only_identifies_entity() {
+		let event = BackendEvent::new("phone", Uuid::nil(), "created");
+		assert_eq!(event.name(), "phone_created");
+		assert_eq!(event.channel(), PHONE_EVENTS);
+		assert_eq!(
+			serde_json::to_string(&event).unwrap(),
+			r#"{"entity":"phone","id":"00000000-0000-0000-0000-000000000000","action":"created"}"#
+		);
+	}
+
+	#[sqlx::test]
+	async fn creating_phone_emits_event(pool: PgPool) {
+		let acc = SqlAccountManager::new(pool.clone());
+		let (user, _) = acc.create_site_admin("root").await.unwrap();
+
+		let mut events = Box::pin(subscribe_events(&pool, &[PHONE_EVENTS]).await.unwrap());
+
+		let phone = SQLSettingsManager::new(pool).new_phone(user, "0123456789", "Home").await.unwrap();
+
+		let event = tokio::time::timeout(Duration::from_secs(5), events.next()).await
+			.expect("event should be received")
+			.unwrap();
+		assert_eq!(event.name(), "phone_created");
+		assert_eq!(event.id, phone.phone_id);
+	}
+
+	#[sqlx::test]
+	async fn deleting_account_emits_event_for_owned_accounts(pool: PgPool) {
+		let acc = SqlAccountManager::new(pool.clone());
+		let (site_admin, _) = acc.create_site_admin("root").await.unwrap();
+		let (admin, _) = acc.create_account(&site_admin, AccountRole::Admin, "admin").await.unwrap();
+		let (user, _) = acc.create_account(&admin, AccountRole::User, "user").await.unwrap();
+
+		let mut events = Box::pin(subscribe_events(&pool, &[ACCOUNT_EVENTS]).await.unwrap());
+		acc.delete_account(&site_admin, &admin).await.unwrap();
+
+		let mut deleted = Vec::new();
+		for _ in 0..2 {
+			let event = tokio::time::timeout(Duration::from_secs(5), events.next()).await
+				.expect("event should be received")
+				.unwrap();
+			assert_eq!(event.name(), "account_deleted");
+			deleted.push(event.id);
+		}
+		deleted.sort();
+		let mut expected = vec![admin.0, user.0];
+		expected.sort();
+		assert_eq!(deleted, expected);
+	}
+}
\ No newline at end of file
@@ -0,0 +1,71 @@
+use geo_types::{Geometry, Point};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum GeometryError {
+	#[error("no geometry was stored")]
+	Missing,
+	#[error("unsupported geometry {0}, expected a point")]
+	Unsupported(&'static str),
+}
+
+/// Converts a stored geometry into a point.
+///
+/// Legacy rows may hold a multipoint of a single point rather than a point, so both are accepted.
+/// Any other geometry, including a multipoint of several points, is an error.
+pub fn decode_point(geometry: Option<Geometry>) -> Result<Point, GeometryError> {
+	match geometry.ok_or(GeometryError::Missing)? {
+		Geometry::Point(point) => Ok(point),
+		Geometry::MultiPoint(points) if points.0.len() == 1 => Ok(points.0[0]),
+		Geometry::MultiPoint(_) => Err(GeometryError::Unsupported("MultiPoint with more or less than one point")),
+		Geometry::Line(_) => Err(GeometryError::Unsupported("Line")),
+		Geometry::LineString(_) => Err(GeometryError::Unsupported("LineString")),
+		Geometry::Polygon(_) => Err(GeometryError::Unsupported("Polygon")),
+		Geometry::MultiLineString(_) => Err(GeometryError::Unsupported("MultiLineString")),
+		Geometry::MultiPolygon(_) => Err(GeometryError::Unsupported("MultiPolygon")),
+		Geometry::GeometryCollection(_) => Err(GeometryError::Unsupported("GeometryCollection")),
+		Geometry::Rect(_) => Err(GeometryError::Unsupported("Rect")),
+		Geometry::Triangle(_) => Err(GeometryError::Unsupported("Triangle")),
+	}
+}
+
+/// As [decode_point], but for nullable columns where no geometry is not an error
+pub fn decode_optional_point(geometry: Option<Geometry>) -> Result<Option<Point>, GeometryError> {
+	geometry.map(|geometry| decode_point(Some(geometry))).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use geo_types::{line_string, MultiPoint};
+
+	#[test]
+	fn decodes_point() {
+		let point = Point::new(1.5, -2.5);
+		assert_eq!(decode_point(Some(Geometry::Point(point))), Ok(point));
+		assert_eq!(decode_optional_point(Some(Geometry::Point(point))), Ok(Some(point)));
+	}
+
+	#[test]
+	fn decodes_single_point_multipoint() {
+		let point = Point::new(1.5, -2.5);
+		let geometry = Geometry::MultiPoint(MultiPoint::new(vec![point]));
+		assert_eq!(decode_point(Some(geometry)), Ok(point));
+	}
+
+	#[test]
+	fn rejects_other_geometries() {
+		let line = Geometry::LineString(line_string![(x: 0.0, y: 0.0), (x: 1.0, y: 1.0)]);
+		assert_eq!(decode_point(Some(line)), Err(GeometryError::Unsupported("LineString")));
+
+		let several = Geometry::MultiPoint(MultiPoint::new(vec![Point::new(0.0, 0.0), Point::new(1.0, 1.0)]));
+		assert!(matches!(decode_point(Some(several)), Err(GeometryError::Unsupported(_))));
+		assert!(matches!(decode_point(Some(Geometry::MultiPoint(MultiPoint::new(vec![])))), Err(GeometryError::Unsupported(_))));
+	}
+
+	#[test]
+	fn missing_geometry() {
+		assert_eq!(decode_point(None), Err(GeometryError::Missing));
+		assert_eq!(decode_optional_point(None), Ok(None));
+	}
+}
\ No newline at end of file
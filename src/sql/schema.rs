@@ -0,0 +1,34 @@
+/// The Postgres schema holding a manager's tables, for deployments which keep each tenant in its
+/// own schema. Queries name their tables as `{schema}table`, which the default schema leaves to
+/// the connection's `search_path`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Schema(String);
+
+impl Schema {
+	/// The schema with the given name, quoted so that any name can be used
+	pub fn named(name: &str) -> Self {
+		Self(format!("{}.", quote_identifier(name)))
+	}
+
+	/// Replaces each `{schema}` in the query with this schema's prefix
+	pub fn qualify(&self, query: &str) -> String {
+		query.replace("{schema}", &self.0)
+	}
+}
+
+/// Quotes a name for use as a Postgres identifier
+pub fn quote_identifier(name: &str) -> String {
+	format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn qualifies_tables() {
+		let query = "SELECT 1 FROM {schema}accounts JOIN {schema}sessions USING (user_id);";
+		assert_eq!(Schema::default().qualify(query), "SELECT 1 FROM accounts JOIN sessions USING (user_id);");
+		assert_eq!(Schema::named("tenant \"x\"").qualify(query), "SELECT 1 FROM \"tenant \"\"x\"\"\".accounts JOIN \"tenant \"\"x\"\"\".sessions USING (user_id);");
+	}
+}
\ No newline at end of file
@@ -0,0 +1,89 @@
+use sqlx::PgPool;
+use std::time::Duration;
+
+/// How long [Shutdown::shutdown] waits for in-flight operations by default
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether in-flight operations finished before the pool was closed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DrainOutcome {
+	/// Every connection was returned to the pool and closed
+	Drained,
+	/// The drain timeout elapsed with this many connections still in use. They are closed as soon
+	/// as they are returned, so their operations may have been interrupted.
+	TimedOut { in_flight: u32 }
+}
+
+/// Coordinates stopping the backend. Once shut down the pool accepts no new operations, which fail
+/// with [sqlx::Error::PoolClosed], while operations already holding a connection may finish.
+pub struct Shutdown(PgPool, Duration);
+
+impl Shutdown {
+	pub fn new(pool: PgPool) -> Self {
+		Self(pool, DEFAULT_DRAIN_TIMEOUT)
+	}
+
+	/// Sets how long to wait for in-flight operations before giving up on them
+	pub fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+		self.1 = timeout;
+		self
+	}
+
+	/// Closes the pool, waiting up to the drain timeout for in-flight operations to finish
+	pub async fn shutdown(&self) -> DrainOutcome {
+		let close = self.0.close();
+		match tokio::time::timeout(self.1, close).await {
+			Ok(()) => DrainOutcome::Drained,
+			Err(_) => {
+				let in_flight = self.0.size().saturating_sub(self.0.num_idle() as u32);
+				tracing::warn!(in_flight, "pool closed before in-flight operations finished");
+				DrainOutcome::TimedOut { in_flight }
+			}
+		}
+	}
+
+	pub fn is_shut_down(&self) -> bool {
+		self.0.is_closed()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::data::{AmbulanceTracker, AmbulanceTrackerError};
+	use crate::sql::sql_ambulance_tracker::SQLAmbulanceTracker;
+	use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+
+	#[sqlx::test]
+	async fn drains_in_flight_operations(_: PgPoolOptions, options: PgConnectOptions) {
+		let pool = PgPoolOptions::new().connect_lazy_with(options);
+		let tracker = SQLAmbulanceTracker::new(pool.clone());
+		let shutdown = Shutdown::new(pool.clone());
+
+		let mut conn = pool.acquire().await.unwrap();
+		let in_flight = tokio::spawn(async move {
+			sqlx::query("SELECT pg_sleep(0.2);").execute(&mut *conn).await
+		});
+
+		assert_eq!(shutdown.shutdown().await, DrainOutcome::Drained);
+		assert!(in_flight.await.unwrap().is_ok());
+		assert!(shutdown.is_shut_down());
+		assert!(pool.is_closed());
+
+		let result = tracker.count_ambulances().await;
+		assert!(matches!(result, Err(AmbulanceTrackerError::Other(_))), "{:?}", result);
+	}
+
+	#[sqlx::test]
+	async fn reports_timed_out_drain(_: PgPoolOptions, options: PgConnectOptions) {
+		let pool = PgPoolOptions::new().connect_lazy_with(options);
+		let shutdown = Shutdown::new(pool.clone()).with_drain_timeout(Duration::from_millis(50));
+
+		let held = pool.acquire().await.unwrap();
+		assert_eq!(shutdown.shutdown().await, DrainOutcome::TimedOut { in_flight: 1 });
+		assert!(shutdown.is_shut_down());
+		drop(held);
+
+		assert!(matches!(pool.acquire().await, Err(sqlx::Error::PoolClosed)));
+	}
+}
\ No newline at end of file
@@ -1,140 +1,419 @@
-use crate::data::{AccountChangePasswordError, AccountCreationError, AccountId, AccountLoginError, AccountManager, AccountOwnerManageError, AccountRole, SessionRetrievalError, SessionRetrievalPurpose, SessionToken};
+use crate::data::{authorize, AccountAction, AccountChangePasswordError, AccountCreationError, AccountId, AccountInfo, AccountLoginError, AccountManager, AccountOwnerManageError, AccountRole, AccountTarget, AuthzError, LoginSuccess, PageParams, Redacted, SessionRetrievalError, SessionRetrievalPurpose, SessionToken, SettingsError, Clock, SystemClock, DEFAULT_ETA_ALERT, MAX_ETA_ALERT};
+use crate::sql::acquire_retry::{acquire, AcquireRetry};
+use crate::sql::events::{emit_in_transaction, notify, BackendEvent};
+use crate::sql::schema::Schema;
 use argon2::Argon2;
-use rand::TryRngCore;
-use sqlx::PgPool;
+use rand::rngs::OsRng;
+use rand::TryCryptoRng;
+use sha2::{Digest, Sha256};
+use sqlx::postgres::types::PgInterval;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::pool::PoolConnection;
+use sqlx::{Connection, PgConnection, PgPool, Postgres, Transaction};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub struct SqlAccountManager {
+	pool: PgPool,
+	/// How many previous passwords an account may not reuse
+	password_history: usize,
+	session_ttl: Option<Duration>,
+	default_eta_alert: Duration,
+	clock: Arc<dyn Clock>,
+	min_password_length: Option<usize>,
+	schema: Schema,
+	password_change_sessions: PasswordChangeSessionPolicy,
+	/// Whether login reports unknown users and wrong passwords alike as invalid credentials
+	hide_login_failures: bool,
+	login_token_ttl: Duration,
+	acquire_retry: Option<AcquireRetry>
+}
 
-pub struct SqlAccountManager(PgPool);
+/// How long a token from [AccountManager::issue_login_token] remains valid unless configured
+pub const DEFAULT_LOGIN_TOKEN_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Which of an account's sessions remain valid after it changes its password
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PasswordChangeSessionPolicy {
+	/// Every session remains valid
+	#[default]
+	KeepAll,
+	/// Only the session the change was made from remains valid, see
+	/// [AccountManager::change_password_in_session]. Changes made without a session invalidate
+	/// every session.
+	KeepCurrent,
+	/// Every session is invalidated, including the one the change was made from
+	KillAll
+}
 
 #[async_trait::async_trait]
 impl AccountManager for SqlAccountManager {
-	async fn create_account(&self, owner_id: &AccountId, account_role: AccountRole, username: &str) -> Result<(AccountId, String), AccountCreationError> {
-		let (owner_role,): (AccountRole,) =
-			sqlx::query_as("SELECT role FROM accounts WHERE user_id=$1;")
-				.bind(owner_id.0)
-				.fetch_optional(&self.0)
-				.await
-				.map_err(|e| AccountCreationError::Other(e.into()))?
-				.ok_or(AccountCreationError::OwnerNotFound)?;
-		
-		if owner_role.can_own(account_role) {
-			self.unchecked_create_account(username, account_role, Some(owner_id)).await.map_err(|e| AccountCreationError::Other(e.into()))
-		} else {
-			Err(AccountCreationError::InvalidOwnerRole)
+	async fn create_account(&self, owner_id: &AccountId, account_role: AccountRole, username: &str) -> Result<(AccountId, Redacted<String>), AccountCreationError> {
+		let mut conn = self.connection().await.map_err(|e| AccountCreationError::Other(e.into()))?;
+		let mut tx = conn.begin().await.map_err(|e| AccountCreationError::Other(e.into()))?;
+		let created = self.create_account_tx(&mut tx, owner_id, account_role, username).await?;
+		tx.commit().await.map_err(|e| AccountCreationError::Other(e.into()))?;
+		Ok(created)
+	}
+
+	async fn create_admin_with_user(&self, site_admin_id: &AccountId, admin_username: &str, user_username: &str) -> Result<(AccountId, Redacted<String>, AccountId, Redacted<String>), AccountCreationError> {
+		let mut conn = self.connection().await.map_err(|e| AccountCreationError::Other(e.into()))?;
+		let mut tx = conn.begin().await.map_err(|e| AccountCreationError::Other(e.into()))?;
+		let (admin_id, admin_password) = self.create_account_tx(&mut tx, site_admin_id, AccountRole::Admin, admin_username).await?;
+		let (user_id, user_password) = self.create_account_tx(&mut tx, &admin_id, AccountRole::User, user_username).await?;
+		tx.commit().await.map_err(|e| AccountCreationError::Other(e.into()))?;
+		Ok((admin_id, admin_password, user_id, user_password))
+	}
+
+	async fn create_account_with_password(&self, owner_id: &AccountId, account_role: AccountRole, username: &str, password: &str, reset_needed: bool) -> Result<AccountId, AccountCreationError> {
+		if let Some(min_length) = self.min_password_length {
+			if password.chars().count() < min_length {
+				return Err(AccountCreationError::PasswordTooShort(min_length));
+			}
 		}
+
+		let mut conn = self.connection().await.map_err(|e| AccountCreationError::Other(e.into()))?;
+		let mut tx = conn.begin().await.map_err(|e| AccountCreationError::Other(e.into()))?;
+		check_can_create(&mut tx, &self.schema, owner_id, account_role).await?;
+		let account_id = self.insert_account(&mut tx, username, account_role, Some(owner_id), password, reset_needed).await.map_err(|e| AccountCreationError::Other(e.into()))?;
+		tx.commit().await.map_err(|e| AccountCreationError::Other(e.into()))?;
+		Ok(account_id)
 	}
 
-	async fn reset_password(&self, owner_id: &AccountId, account_id: &AccountId) -> Result<String, AccountOwnerManageError> {
-		let password = random_password(16).map_err(|e| AccountOwnerManageError::Other(e.into()))?;
-		let salt = random_salt().map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+	async fn reset_password(&self, owner_id: &AccountId, account_id: &AccountId) -> Result<Redacted<String>, AccountOwnerManageError> {
+		let password = random_password(&mut OsRng, 16).map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		let salt = random_salt(&mut OsRng).map_err(|e| AccountOwnerManageError::Other(e.into()))?;
 		let hash = hash_password(password.as_bytes(), &salt).map_err(|e| AccountOwnerManageError::Other(e.into()))?;
 
-		match sqlx::query_as::<_, (i32,)>("UPDATE accounts SET password_salt=$3, password_hash=$4 WHERE user_id=$1 AND owner_id=$2 RETURNING 1;")
+		let mut conn = self.connection().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		let mut tx = conn.begin().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		check_authorized(&mut tx, &self.schema, owner_id, AccountAction::ResetPassword, account_id).await?;
+
+		sqlx::query(&self.schema.qualify("UPDATE {schema}accounts SET password_salt=$2, password_hash=$3, password_reset_needed=true WHERE user_id=$1;"))
 			.bind(account_id.0)
-			.bind(owner_id.0)
 			.bind(salt)
 			.bind(hash)
-			.fetch_optional(&self.0)
-			.await.map_err(|e| AccountOwnerManageError::Other(e.into()))? {
-			Some(_) => Ok(password),
-			None => Err(AccountOwnerManageError::UserNotFound)
-		}
+			.execute(&mut *tx)
+			.await
+			.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+
+		tx.commit().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		Ok(Redacted::new(password))
 	}
 
 	async fn delete_account(&self, owner_id: &AccountId, account_id: &AccountId) -> Result<(), AccountOwnerManageError> {
-		match sqlx::query_as::<_, (i32,)>("DELETE FROM accounts WHERE user_id=$1 AND owner_id=$2 RETURNING 1;")
-			.bind(account_id.0)
-			.bind(owner_id.0)
-			.fetch_optional(&self.0)
-			.await.map_err(|e| AccountOwnerManageError::Other(e.into()))? {
-			Some(_) => Ok(()),
-			None => Err(AccountOwnerManageError::UserNotFound)
-		}
+		let mut conn = self.connection().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		let mut tx = conn.begin().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		self.delete_account_tx(&mut tx, owner_id, account_id).await?;
+		tx.commit().await.map_err(|e| AccountOwnerManageError::Other(e.into()))
 	}
 
-	async fn change_password(&self, account_id: &AccountId, current_password: &str, new_password: &str) -> Result<(), AccountChangePasswordError> {
-		let (current_hash, current_salt): ([u8; 32], [u8; 16]) =
-			sqlx::query_as("SELECT password_hash, password_salt FROM accounts WHERE user_id=$1;")
-			.bind(account_id.0)
-			.fetch_optional(&self.0)
+	async fn delete_admin_reassigning(&self, site_admin_id: &AccountId, admin_id: &AccountId, new_admin_id: &AccountId) -> Result<(), AccountOwnerManageError> {
+		let mut conn = self.connection().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		let mut tx = conn.begin().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+
+		// both admins are locked so that neither changes before the users are moved
+		let (admin_owner, admin_role, new_admin_role): (Option<sqlx::types::Uuid>, AccountRole, AccountRole) =
+			sqlx::query_as(&self.schema.qualify("SELECT admin.owner_id, admin.role, new_admin.role FROM {schema}accounts admin, {schema}accounts new_admin WHERE admin.user_id=$1 AND new_admin.user_id=$2 FOR UPDATE;"))
+				.bind(admin_id.0)
+				.bind(new_admin_id.0)
+				.fetch_optional(&mut *tx)
+				.await
+				.map_err(|e| AccountOwnerManageError::Other(e.into()))?
+				.ok_or(AccountOwnerManageError::UserNotFound)?;
+
+		if admin_owner != Some(site_admin_id.0) {
+			return Err(AccountOwnerManageError::NotOwner);
+		}
+		if admin_role != AccountRole::Admin || new_admin_role != AccountRole::Admin || admin_id == new_admin_id {
+			return Err(AccountOwnerManageError::InsufficientRole);
+		}
+
+		sqlx::query(&self.schema.qualify("UPDATE {schema}accounts SET owner_id=$2 WHERE owner_id=$1;"))
+			.bind(admin_id.0)
+			.bind(new_admin_id.0)
+			.execute(&mut *tx)
 			.await
-			.map_err(|e| AccountChangePasswordError::Other(e.into()))?
-			.ok_or(AccountChangePasswordError::UserNotFound)?;
+			.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
 
-		let check_hash = hash_password(current_password.as_bytes(), &current_salt).map_err(|e| AccountChangePasswordError::Other(e.into()))?;
-		if check_hash == current_hash {
-			let new_salt = random_salt().map_err(|e| AccountChangePasswordError::Other(e.into()))?;
-			let new_hash = hash_password(new_password.as_bytes(), &new_salt).map_err(|e| AccountChangePasswordError::Other(e.into()))?;
+		self.delete_account_tx(&mut tx, site_admin_id, admin_id).await?;
+		tx.commit().await.map_err(|e| AccountOwnerManageError::Other(e.into()))
+	}
 
-			sqlx::query("UPDATE accounts SET password_salt=$2, password_hash=$3, password_reset_needed=false WHERE user_id=$1")
+	async fn get_owner(&self, account_id: &AccountId) -> Result<Option<AccountId>, AccountOwnerManageError> {
+		let (owner_id,): (Option<sqlx::types::Uuid>,) =
+			sqlx::query_as(&self.schema.qualify("SELECT owner_id FROM {schema}accounts WHERE user_id=$1;"))
 				.bind(account_id.0)
-				.bind(new_salt)
-				.bind(new_hash)
-				.execute(&self.0)
+				.fetch_optional(&mut *self.connection().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?)
 				.await
-				.map_err(|e| AccountChangePasswordError::Other(e.into()))?;
+				.map_err(|e| AccountOwnerManageError::Other(e.into()))?
+				.ok_or(AccountOwnerManageError::UserNotFound)?;
+
+		Ok(owner_id.map(AccountId::new))
+	}
+
+	async fn password_reset_required(&self, account_id: &AccountId) -> Result<bool, AccountOwnerManageError> {
+		let (password_reset_needed,): (bool,) =
+			sqlx::query_as(&self.schema.qualify("SELECT password_reset_needed FROM {schema}accounts WHERE user_id=$1;"))
+				.bind(account_id.0)
+				.fetch_optional(&mut *self.connection().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?)
+				.await
+				.map_err(|e| AccountOwnerManageError::Other(e.into()))?
+				.ok_or(AccountOwnerManageError::UserNotFound)?;
+
+		Ok(password_reset_needed)
+	}
+
+	async fn force_logout(&self, actor_id: &AccountId, target_id: &AccountId) -> Result<u64, AccountOwnerManageError> {
+		let mut conn = self.connection().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		let mut tx = conn.begin().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		check_authorized(&mut tx, &self.schema, actor_id, AccountAction::Suspend, target_id).await?;
+
+		let result = sqlx::query(&self.schema.qualify("DELETE FROM {schema}sessions WHERE user_id=$1;"))
+			.bind(target_id.0)
+			.execute(&mut *tx)
+			.await
+			.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		tx.commit().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		Ok(result.rows_affected())
+	}
+
+	async fn set_session_ttl(&self, actor_id: &AccountId, target_id: &AccountId, ttl: Option<Duration>) -> Result<(), AccountOwnerManageError> {
+		let ttl = ttl.map(PgInterval::try_from).transpose().map_err(|e| AccountOwnerManageError::Other(e as Box<dyn Error>))?;
+
+		// the roles are read with the target locked, so that the role checked is the one updated
+		let mut conn = self.connection().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		let mut tx = conn.begin().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		let (actor_role, target_role): (AccountRole, AccountRole) =
+			sqlx::query_as(&self.schema.qualify("SELECT actor.role, target.role FROM {schema}accounts actor, {schema}accounts target WHERE actor.user_id=$1 AND target.user_id=$2 FOR UPDATE OF target;"))
+				.bind(actor_id.0)
+				.bind(target_id.0)
+				.fetch_optional(&mut *tx)
+				.await
+				.map_err(|e| AccountOwnerManageError::Other(e.into()))?
+				.ok_or(AccountOwnerManageError::UserNotFound)?;
 
-			Ok(())
-		} else {
-			Err(AccountChangePasswordError::IncorrectPassword)
+		if !actor_role.can_manage(target_role) {
+			return Err(AccountOwnerManageError::InsufficientRole);
 		}
+
+		sqlx::query(&self.schema.qualify("UPDATE {schema}accounts SET session_ttl_override=$2 WHERE user_id=$1;"))
+			.bind(target_id.0)
+			.bind(ttl)
+			.execute(&mut *tx)
+			.await
+			.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		tx.commit().await.map_err(|e| AccountOwnerManageError::Other(e.into()))
 	}
 
-	async fn destroy_session(&self, token: &SessionToken) -> Result<(), Box<dyn Error>> {
-		sqlx::query("DELETE FROM sessions WHERE session_id=$1;")
-			.bind(token.0)
-			.execute(&self.0)
-			.await?;
-		Ok(())
+	async fn list_all_accounts(&self, actor_id: &AccountId, page: PageParams) -> Result<Vec<AccountInfo>, AccountOwnerManageError> {
+		let (actor_role,): (AccountRole,) =
+			sqlx::query_as(&self.schema.qualify("SELECT role FROM {schema}accounts WHERE user_id=$1;"))
+				.bind(actor_id.0)
+				.fetch_optional(&mut *self.connection().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?)
+				.await
+				.map_err(|e| AccountOwnerManageError::Other(e.into()))?
+				.ok_or(AccountOwnerManageError::UserNotFound)?;
+
+		if actor_role != AccountRole::SiteAdmin {
+			return Err(AccountOwnerManageError::InsufficientRole);
+		}
+		if page.limit <= 0 {
+			return Ok(Vec::new());
+		}
+
+		let rows: Vec<(sqlx::types::Uuid, String, AccountRole, DateTime<Utc>)> =
+			sqlx::query_as(&self.schema.qualify("SELECT user_id, username, role, created_at FROM {schema}accounts WHERE $1::timestamptz IS NULL OR (created_at, user_id) > ($1, $2) ORDER BY created_at, user_id LIMIT $3;"))
+				.bind(page.after.map(|cursor| cursor.time))
+				.bind(page.after.map(|cursor| cursor.id))
+				.bind(page.limit)
+				.fetch_all(&mut *self.connection().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?)
+				.await
+				.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+
+		// usernames are stored padded to a fixed width
+		Ok(rows.into_iter().map(|(user_id, username, role, created_at)| AccountInfo {
+			id: AccountId(user_id),
+			username: username.trim_end().to_string(),
+			role,
+			created_at
+		}).collect())
+	}
+
+	async fn change_password(&self, account_id: &AccountId, current_password: &str, new_password: &str) -> Result<(), AccountChangePasswordError> {
+		self.update_password(account_id, None, current_password, new_password).await
+	}
+
+	async fn change_password_in_session(&self, account_id: &AccountId, session_token: &SessionToken, current_password: &str, new_password: &str) -> Result<(), AccountChangePasswordError> {
+		self.update_password(account_id, Some(session_token), current_password, new_password).await
+	}
+
+	async fn destroy_session(&self, token: &SessionToken) -> Result<u64, Box<dyn Error>> {
+		Ok(
+			sqlx::query(&self.schema.qualify("DELETE FROM {schema}sessions WHERE session_id=$1;"))
+				.bind(token.0)
+				.execute(&mut *self.connection().await?)
+				.await?
+				.rows_affected()
+		)
 	}
 
 	async fn login(&self, username: &str, password: &str) -> Result<SessionToken, AccountLoginError> {
-		let (hash, salt, user_id): ([u8; 32], [u8; 16], sqlx::types::Uuid) =
-			sqlx::query_as("SELECT password_hash, password_salt, user_id FROM accounts WHERE username=$1;")
+		self.login_detailed(username, password).await.map(|success| success.token)
+	}
+
+	async fn login_detailed(&self, username: &str, password: &str) -> Result<LoginSuccess, AccountLoginError> {
+		let account: Option<([u8; 32], [u8; 16], sqlx::types::Uuid, bool)> =
+			sqlx::query_as(&self.schema.qualify("SELECT password_hash, password_salt, user_id, password_reset_needed FROM {schema}accounts WHERE username=$1;"))
 				.bind(username)
-				.fetch_optional(&self.0)
+				.fetch_optional(&mut *self.connection().await.map_err(|e| AccountLoginError::Other(e.into()))?)
 				.await
-				.map_err(|e| AccountLoginError::Other(e.into()))?
-				.ok_or(AccountLoginError::UserNotFound)?;
+				.map_err(|e| AccountLoginError::Other(e.into()))?;
 
-		let check_hash = hash_password(password.as_bytes(), &salt)
+		let Some((hash, salt, user_id, password_reset_needed)) = account else {
+			if self.hide_login_failures {
+				// hashing anyway keeps an unknown username from answering noticeably faster
+				hash_password(password.as_bytes(), &[0; 16]).map_err(|e| AccountLoginError::Other(e.into()))?;
+				return Err(AccountLoginError::InvalidCredentials);
+			}
+			return Err(AccountLoginError::UserNotFound);
+		};
+
+		if !verify_password_hash(password.as_bytes(), &salt, &hash).map_err(|e| AccountLoginError::Other(e.into()))? {
+			return Err(if self.hide_login_failures { AccountLoginError::InvalidCredentials } else { AccountLoginError::IncorrectPassword });
+		}
+
+		let session = random_session(&mut OsRng).map_err(|e| AccountLoginError::Other(e.into()))?;
+		sqlx::query(&self.schema.qualify("INSERT INTO {schema}sessions (session_id, user_id, last_used_at) VALUES ($1, $2, $3)"))
+			.bind(session.0)
+			.bind(user_id)
+			.bind(self.clock.now())
+			.execute(&mut *self.connection().await.map_err(|e| AccountLoginError::Other(e.into()))?)
+			.await
 			.map_err(|e| AccountLoginError::Other(e.into()))?;
 
-		if hash == check_hash {
-			let session = random_session().map_err(|e| AccountLoginError::Other(e.into()))?;
+		Ok(LoginSuccess {
+			account_id: AccountId::new(user_id),
+			token: session,
+			password_reset_needed
+		})
+	}
 
-			sqlx::query("INSERT INTO sessions (session_id, user_id) VALUES ($1, $2)")
-				.bind(session.0)
-				.bind(user_id)
-				.execute(&self.0)
+	async fn issue_login_token(&self, owner_id: &AccountId, account_id: &AccountId) -> Result<Redacted<String>, AccountOwnerManageError> {
+		// login tokens share the format of session tokens
+		let token = random_session(&mut OsRng).map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		let hash = hash_login_token(&token);
+
+		// the token is only stored if the checks on the account pass, which are read in the same
+		// statement to tell the failures apart
+		let target: Option<(bool, AccountRole)> =
+			sqlx::query_as(&self.schema.qualify("WITH target AS (SELECT user_id, COALESCE(owner_id=$2, false) AS owned, role FROM {schema}accounts WHERE user_id=$1), issued AS (INSERT INTO {schema}login_tokens(token_hash, user_id, expires_at) SELECT $3, user_id, $4 FROM target WHERE owned AND role='user' RETURNING 1) SELECT owned, role FROM target;"))
+				.bind(account_id.0)
+				.bind(owner_id.0)
+				.bind(hash)
+				.bind(self.clock.now() + self.login_token_ttl)
+				.fetch_optional(&mut *self.connection().await.map_err(|e| AccountOwnerManageError::Other(e.into()))?)
 				.await
-				.map_err(|e| AccountLoginError::Other(e.into()))?;
-			Ok(session)
-		} else {
-			Err(AccountLoginError::IncorrectPassword)
+				.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+
+		match target {
+			None => Err(AccountOwnerManageError::UserNotFound),
+			Some((false, _)) => Err(AccountOwnerManageError::NotOwner),
+			Some((true, AccountRole::User)) => Ok(Redacted::new(token.to_hex())),
+			Some((true, _)) => Err(AccountOwnerManageError::InsufficientRole)
 		}
 	}
 
+	async fn login_with_token(&self, token: &str) -> Result<SessionToken, AccountLoginError> {
+		let token = SessionToken::from_hex(token.trim()).map_err(|_| AccountLoginError::InvalidLoginToken)?;
+		let hash = hash_login_token(&token);
+
+		// deleting the token and creating the session in one statement keeps it single use even when
+		// presented concurrently, and clears out expired tokens as they are tried
+		let session = random_session(&mut OsRng).map_err(|e| AccountLoginError::Other(e.into()))?;
+		sqlx::query_as::<_, (sqlx::types::Uuid,)>(&self.schema.qualify("WITH consumed AS (DELETE FROM {schema}login_tokens WHERE token_hash=$1 RETURNING user_id, expires_at) INSERT INTO {schema}sessions (session_id, user_id, last_used_at, from_login_token) SELECT $2, user_id, $3, true FROM consumed WHERE expires_at>$3 RETURNING user_id;"))
+			.bind(hash)
+			.bind(session.0)
+			.bind(self.clock.now())
+			.fetch_optional(&mut *self.connection().await.map_err(|e| AccountLoginError::Other(e.into()))?)
+			.await
+			.map_err(|e| AccountLoginError::Other(e.into()))?
+			.ok_or(AccountLoginError::InvalidLoginToken)?;
+
+		Ok(session)
+	}
+
+	async fn verify_password(&self, account_id: &AccountId, password: &str) -> Result<bool, AccountLoginError> {
+		let (hash, salt): ([u8; 32], [u8; 16]) =
+			sqlx::query_as(&self.schema.qualify("SELECT password_hash, password_salt FROM {schema}accounts WHERE user_id=$1;"))
+				.bind(account_id.0)
+				.fetch_optional(&mut *self.connection().await.map_err(|e| AccountLoginError::Other(e.into()))?)
+				.await
+				.map_err(|e| AccountLoginError::Other(e.into()))?
+				.ok_or(AccountLoginError::UserNotFound)?;
+
+		verify_password_hash(password.as_bytes(), &salt, &hash).map_err(|e| AccountLoginError::Other(e.into()))
+	}
+
 	async fn retrieve_account(&self, session_token: &SessionToken, purpose: SessionRetrievalPurpose) -> Result<AccountId, SessionRetrievalError> {
-		let (account_id, password_reset_needed): (sqlx::types::Uuid, bool) =
-			sqlx::query_as("SELECT accounts.user_id, accounts.password_reset_needed FROM sessions JOIN accounts ON sessions.user_id=accounts.user_id WHERE sessions.session_id=$1;")
+		self.retrieve_session(session_token, purpose).await.map(|(account_id, _)| account_id)
+	}
+
+	async fn retrieve_session(&self, session_token: &SessionToken, purpose: SessionRetrievalPurpose) -> Result<(AccountId, AccountRole), SessionRetrievalError> {
+		// a session from a login token is not held back by a pending password reset
+		let (account_id, password_reset_needed, role): (sqlx::types::Uuid, bool, AccountRole) =
+			sqlx::query_as(&self.schema.qualify("SELECT accounts.user_id, accounts.password_reset_needed AND NOT sessions.from_login_token, accounts.role FROM {schema}sessions JOIN {schema}accounts ON sessions.user_id=accounts.user_id WHERE sessions.session_id=$1 AND (COALESCE(accounts.session_ttl_override, $2::interval) IS NULL OR sessions.last_used_at>$3::timestamptz - COALESCE(accounts.session_ttl_override, $2::interval));"))
 			.bind(session_token.0)
-			.fetch_optional(&self.0)
+			.bind(self.session_ttl()?)
+			.bind(self.clock.now())
+			.fetch_optional(&mut *self.connection().await.map_err(|e| SessionRetrievalError::Other(e.into()))?)
 			.await
 			.map_err(|e| SessionRetrievalError::Other(e.into()))?
 			.ok_or(SessionRetrievalError::InvalidToken)?;
 
-		match (purpose, password_reset_needed) {
-			(SessionRetrievalPurpose::Other, true) => Err(SessionRetrievalError::InvalidPurpose),
-			_ => Ok(AccountId(account_id))
+		match (purpose, password_reset_needed, role) {
+			(SessionRetrievalPurpose::Other | SessionRetrievalPurpose::AdminAction, true, _) => Err(SessionRetrievalError::InvalidPurpose),
+			(SessionRetrievalPurpose::AdminAction, false, AccountRole::User) => Err(SessionRetrievalError::InvalidPurpose),
+			_ => Ok((AccountId(account_id), role))
+		}
+	}
+
+	async fn touch_session(&self, session_token: &SessionToken) -> Result<(), SessionRetrievalError> {
+		match sqlx::query_as::<_, (i32,)>(&self.schema.qualify("UPDATE {schema}sessions SET last_used_at=$3 FROM {schema}accounts WHERE sessions.session_id=$1 AND accounts.user_id=sessions.user_id AND (COALESCE(accounts.session_ttl_override, $2::interval) IS NULL OR sessions.last_used_at>$3::timestamptz - COALESCE(accounts.session_ttl_override, $2::interval)) RETURNING 1;"))
+			.bind(session_token.0)
+			.bind(self.session_ttl()?)
+			.bind(self.clock.now())
+			.fetch_optional(&mut *self.connection().await.map_err(|e| SessionRetrievalError::Other(e.into()))?)
+			.await
+			.map_err(|e| SessionRetrievalError::Other(e.into()))? {
+			Some(_) => Ok(()),
+			None => Err(SessionRetrievalError::InvalidToken)
+		}
+	}
+
+	async fn rotate_session(&self, session_token: &SessionToken) -> Result<SessionToken, SessionRetrievalError> {
+		let rotated = random_session(&mut OsRng).map_err(|e| SessionRetrievalError::Other(e.into()))?;
+
+		// a single statement, so the old token stops working exactly when the new one starts
+		match sqlx::query_as::<_, (i32,)>(&self.schema.qualify("UPDATE {schema}sessions SET session_id=$4, last_used_at=$3 FROM {schema}accounts WHERE sessions.session_id=$1 AND accounts.user_id=sessions.user_id AND (COALESCE(accounts.session_ttl_override, $2::interval) IS NULL OR sessions.last_used_at>$3::timestamptz - COALESCE(accounts.session_ttl_override, $2::interval)) RETURNING 1;"))
+			.bind(session_token.0)
+			.bind(self.session_ttl()?)
+			.bind(self.clock.now())
+			.bind(rotated.0)
+			.fetch_optional(&mut *self.connection().await.map_err(|e| SessionRetrievalError::Other(e.into()))?)
+			.await
+			.map_err(|e| SessionRetrievalError::Other(e.into()))? {
+			Some(_) => Ok(rotated),
+			None => Err(SessionRetrievalError::InvalidToken)
 		}
 	}
 }
 
-/// Creates a random secure password of the specified length.
+/// Creates a random secure password of the specified length using the given generator.
 /// Allowed characters are alphanumeric and `!@#$%^&*()-_=+`
-fn random_password(length: usize) -> Result<String, Box<dyn Error>> {
+fn random_password<R: TryCryptoRng + ?Sized>(rng: &mut R, length: usize) -> Result<String, R::Error> {
 	const ALLOWED_CHARS: [char; 76] = ['A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L',
 		'M', 'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z', 'a', 'b', 'c', 'd',
 		'e', 'f', 'g', 'h', 'i', 'j', 'k', 'l', 'm', 'n', 'o', 'p', 'q', 'r', 's', 't', 'u', 'v',
@@ -147,18 +426,20 @@ fn random_password(length: usize) -> Result<String, Box<dyn Error>> {
 
 	for _ in 0..length {
 		if rand < ALLOWED_CHARS.len() as u128 {
-			rand += rand::rngs::OsRng.try_next_u64()? as u128;
+			rand += rng.try_next_u64()? as u128;
 		}
 		password.push(ALLOWED_CHARS[(rand % ALLOWED_CHARS.len() as u128) as usize]);
+		// consume the randomness used, so every character does not come from the same value
+		rand /= ALLOWED_CHARS.len() as u128;
 	}
 
 	Ok(password)
 }
 
-/// Creates a random secure 16 byte salt
-fn random_salt() -> Result<[u8; 16], Box<dyn Error>> {
+/// Creates a random secure 16 byte salt using the given generator
+fn random_salt<R: TryCryptoRng + ?Sized>(rng: &mut R) -> Result<[u8; 16], R::Error> {
 	let mut result = [0u8; 16];
-	rand::rngs::OsRng.try_fill_bytes(&mut result)?;
+	rng.try_fill_bytes(&mut result)?;
 	Ok(result)
 }
 
@@ -179,38 +460,353 @@ fn hash_password(password: &[u8], salt: &[u8]) -> Result<[u8; 32], HashError> {
 	Ok(out)
 }
 
-/// Creates a random secure session token
-fn random_session() -> Result<SessionToken, Box<dyn Error>> {
+/// Compares two password hashes in constant time, so that the comparison does not reveal how many
+/// leading bytes matched
+fn hashes_equal(a: &[u8; 32], b: &[u8; 32]) -> bool {
+	a.iter().zip(b.iter()).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Checks whether a password matches a stored hash and salt. Fails if the inputs cannot be hashed,
+/// such as when the salt is too short.
+fn verify_password_hash(password: &[u8], salt: &[u8], expected: &[u8; 32]) -> Result<bool, HashError> {
+	let check_hash = hash_password(password, salt)?;
+	Ok(hashes_equal(&check_hash, expected))
+}
+
+/// Hashes a login token for storage. Login tokens are random enough that a fast unsalted hash
+/// cannot be reversed, and leaving out the salt lets a presented token be looked up by its hash.
+fn hash_login_token(token: &SessionToken) -> [u8; 32] {
+	Sha256::digest(token.0).into()
+}
+
+/// Creates a random secure session token using the given generator
+fn random_session<R: TryCryptoRng + ?Sized>(rng: &mut R) -> Result<SessionToken, R::Error> {
 	let mut result = [0u8; 32];
-	rand::rngs::OsRng.try_fill_bytes(&mut result)?;
+	rng.try_fill_bytes(&mut result)?;
 	Ok(SessionToken(result))
 }
 
+/// Checks that the owner exists and may create an account of the role, looking up accounts in the
+/// schema given by [SqlAccountManager::with_schema]
+async fn check_can_create(conn: &mut PgConnection, schema: &Schema, owner_id: &AccountId, account_role: AccountRole) -> Result<(), AccountCreationError> {
+	let (owner_role,): (AccountRole,) =
+		sqlx::query_as(&schema.qualify("SELECT role FROM {schema}accounts WHERE user_id=$1;"))
+			.bind(owner_id.0)
+			.fetch_optional(conn)
+			.await
+			.map_err(|e| AccountCreationError::Other(e.into()))?
+			.ok_or(AccountCreationError::OwnerNotFound)?;
+
+	let target = AccountTarget { role: account_role, owner: Some(*owner_id) };
+	authorize(owner_role, owner_id, AccountAction::Create, &target).map_err(|_| AccountCreationError::InvalidOwnerRole)
+}
+
+/// Checks that both accounts exist and that the actor may perform the action on the target,
+/// locking the target so that its owner and role cannot change before the caller's transaction
+/// commits
+async fn check_authorized(conn: &mut PgConnection, schema: &Schema, actor_id: &AccountId, action: AccountAction, target_id: &AccountId) -> Result<(), AccountOwnerManageError> {
+	let (target_role, target_owner, actor_role): (AccountRole, Option<sqlx::types::Uuid>, Option<AccountRole>) =
+		sqlx::query_as(&schema.qualify("SELECT role, owner_id, (SELECT role FROM {schema}accounts WHERE user_id=$2) FROM {schema}accounts WHERE user_id=$1 FOR UPDATE;"))
+			.bind(target_id.0)
+			.bind(actor_id.0)
+			.fetch_optional(conn)
+			.await
+			.map_err(|e| AccountOwnerManageError::Other(e.into()))?
+			.ok_or(AccountOwnerManageError::UserNotFound)?;
+
+	// an owner-gated action by a missing actor is reported the same as one by any non-owner
+	let actor_role = match (actor_role, action) {
+		(Some(role), _) => role,
+		(None, AccountAction::Suspend) => return Err(AccountOwnerManageError::UserNotFound),
+		(None, _) => return Err(AccountOwnerManageError::NotOwner)
+	};
+
+	let target = AccountTarget { role: target_role, owner: target_owner.map(AccountId::new) };
+	authorize(actor_role, actor_id, action, &target).map_err(|e| match e {
+		AuthzError::NotOwner => AccountOwnerManageError::NotOwner,
+		AuthzError::InvalidRole => AccountOwnerManageError::InsufficientRole
+	})
+}
+
 impl SqlAccountManager {
-	async fn unchecked_create_account(&self, username: &str, role: AccountRole, owner: Option<&AccountId>) -> Result<(AccountId, String), Box<dyn Error>> {
-		let password = random_password(16)?;
-		let salt = random_salt()?;
+	async fn unchecked_create_account(&self, username: &str, role: AccountRole, owner: Option<&AccountId>) -> Result<(AccountId, Redacted<String>), Box<dyn Error>> {
+		let password = random_password(&mut OsRng, 16)?;
+		let mut conn = self.connection().await?;
+		let mut tx = conn.begin().await?;
+		let account_id = self.insert_account(&mut tx, username, role, owner, &password, true).await?;
+		tx.commit().await?;
+		Ok((account_id, Redacted::new(password)))
+	}
+
+	async fn connection(&self) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+		acquire(&self.pool, self.acquire_retry).await
+	}
+
+	/// Inserts an account, sending its created event once the surrounding transaction commits
+	async fn insert_account(&self, conn: &mut PgConnection, username: &str, role: AccountRole, owner: Option<&AccountId>, password: &str, reset_needed: bool) -> Result<AccountId, Box<dyn Error>> {
+		let salt = random_salt(&mut OsRng)?;
 		let hash = hash_password(password.as_bytes(), &salt)?;
 
-		let (account_id, ) = sqlx::query_as("INSERT INTO accounts(username, password_hash, password_salt, role, owner_id) VALUES ($1, $2, $3, $4, $5) RETURNING user_id;")
+		let pref_eta = PgInterval::try_from(self.default_eta_alert).map_err(|e| e as Box<dyn Error>)?;
+
+		let (account_id, ) = sqlx::query_as(&self.schema.qualify("INSERT INTO {schema}accounts(username, password_hash, password_salt, role, owner_id, pref_eta, password_reset_needed, created_at) VALUES ($1, $2, $3, $4::text::{schema}account_role, $5, $6, $7, $8) RETURNING user_id;"))
 			.bind(username)
 			.bind(hash)
 			.bind(salt)
 			.bind(role)
 			.bind(owner.map(|acc| acc.0))
-			.fetch_one(&self.0)
+			.bind(pref_eta)
+			.bind(reset_needed)
+			.bind(self.clock.now())
+			.fetch_one(&mut *conn)
 			.await?;
 
-		Ok((AccountId::new(account_id), password))
+		emit_in_transaction(&mut *conn, BackendEvent::new("account", account_id, "created")).await;
+		Ok(AccountId::new(account_id))
+	}
+
+	/// Changes the password as [AccountManager::change_password] does, then invalidates sessions
+	/// according to the configured [PasswordChangeSessionPolicy], keeping the current session if
+	/// there is one and the policy allows it
+	async fn update_password(&self, account_id: &AccountId, current_session: Option<&SessionToken>, current_password: &str, new_password: &str) -> Result<(), AccountChangePasswordError> {
+		if let Some(min_length) = self.min_password_length {
+			if new_password.chars().count() < min_length {
+				return Err(AccountChangePasswordError::PasswordTooShort(min_length));
+			}
+		}
+
+		let mut conn = self.connection().await.map_err(|e| AccountChangePasswordError::Other(e.into()))?;
+		let mut tx = conn.begin().await.map_err(|e| AccountChangePasswordError::Other(e.into()))?;
+
+		// the account is locked until the new password is written, so that a concurrent change cannot
+		// be verified against the same current password and then overwritten
+		let (current_hash, current_salt): ([u8; 32], [u8; 16]) =
+			sqlx::query_as(&self.schema.qualify("SELECT password_hash, password_salt FROM {schema}accounts WHERE user_id=$1 FOR UPDATE;"))
+			.bind(account_id.0)
+			.fetch_optional(&mut *tx)
+			.await
+			.map_err(|e| AccountChangePasswordError::Other(e.into()))?
+			.ok_or(AccountChangePasswordError::UserNotFound)?;
+
+		if !verify_password_hash(current_password.as_bytes(), &current_salt, &current_hash).map_err(|e| AccountChangePasswordError::Other(e.into()))? {
+			return Err(AccountChangePasswordError::IncorrectPassword);
+		}
+
+		if self.password_history > 0 {
+			let previous: Vec<([u8; 32], [u8; 16])> =
+				sqlx::query_as(&self.schema.qualify("SELECT password_hash, password_salt FROM {schema}password_history WHERE user_id=$1 ORDER BY history_id DESC LIMIT $2;"))
+					.bind(account_id.0)
+					.bind(self.password_history as i64 - 1)
+					.fetch_all(&mut *tx)
+					.await
+					.map_err(|e| AccountChangePasswordError::Other(e.into()))?;
+
+			for (hash, salt) in std::iter::once((current_hash, current_salt)).chain(previous) {
+				if verify_password_hash(new_password.as_bytes(), &salt, &hash).map_err(|e| AccountChangePasswordError::Other(e.into()))? {
+					return Err(AccountChangePasswordError::PasswordReused);
+				}
+			}
+		}
+
+		let new_salt = random_salt(&mut OsRng).map_err(|e| AccountChangePasswordError::Other(e.into()))?;
+		let new_hash = hash_password(new_password.as_bytes(), &new_salt).map_err(|e| AccountChangePasswordError::Other(e.into()))?;
+
+		if self.password_history > 0 {
+			sqlx::query(&self.schema.qualify("INSERT INTO {schema}password_history(user_id, password_hash, password_salt) VALUES ($1, $2, $3);"))
+				.bind(account_id.0)
+				.bind(current_hash)
+				.bind(current_salt)
+				.execute(&mut *tx)
+				.await
+				.map_err(|e| AccountChangePasswordError::Other(e.into()))?;
+
+			// the current password is always checked, so only the length - 1 before it are kept
+			sqlx::query(&self.schema.qualify("DELETE FROM {schema}password_history WHERE user_id=$1 AND history_id NOT IN (SELECT history_id FROM {schema}password_history WHERE user_id=$1 ORDER BY history_id DESC LIMIT $2);"))
+				.bind(account_id.0)
+				.bind(self.password_history as i64 - 1)
+				.execute(&mut *tx)
+				.await
+				.map_err(|e| AccountChangePasswordError::Other(e.into()))?;
+		}
+
+		sqlx::query(&self.schema.qualify("UPDATE {schema}accounts SET password_salt=$2, password_hash=$3, password_reset_needed=false WHERE user_id=$1"))
+			.bind(account_id.0)
+			.bind(new_salt)
+			.bind(new_hash)
+			.execute(&mut *tx)
+			.await
+			.map_err(|e| AccountChangePasswordError::Other(e.into()))?;
+
+		let kept_session = match self.password_change_sessions {
+			PasswordChangeSessionPolicy::KeepAll => None,
+			PasswordChangeSessionPolicy::KeepCurrent => Some(current_session.map(|token| token.0)),
+			PasswordChangeSessionPolicy::KillAll => Some(None)
+		};
+		if let Some(kept_session) = kept_session {
+			sqlx::query(&self.schema.qualify("DELETE FROM {schema}sessions WHERE user_id=$1 AND session_id IS DISTINCT FROM $2;"))
+				.bind(account_id.0)
+				.bind(kept_session)
+				.execute(&mut *tx)
+				.await
+				.map_err(|e| AccountChangePasswordError::Other(e.into()))?;
+		}
+
+		tx.commit().await.map_err(|e| AccountChangePasswordError::Other(e.into()))?;
+		Ok(())
+	}
+
+	/// Creates an account as [AccountManager::create_account] does, but within the caller's
+	/// transaction, so that it can be committed or rolled back along with other changes such as
+	/// [crate::sql::sql_settings_manager::SQLSettingsManager::set_settings_tx]
+	pub async fn create_account_tx(&self, tx: &mut Transaction<'_, Postgres>, owner_id: &AccountId, account_role: AccountRole, username: &str) -> Result<(AccountId, Redacted<String>), AccountCreationError> {
+		check_can_create(tx, &self.schema, owner_id, account_role).await?;
+
+		let password = random_password(&mut OsRng, 16).map_err(|e| AccountCreationError::Other(e.into()))?;
+		let account_id = self.insert_account(tx, username, account_role, Some(owner_id), &password, true).await.map_err(|e| AccountCreationError::Other(e.into()))?;
+		Ok((account_id, Redacted::new(password)))
+	}
+
+	/// Deletes an account as [AccountManager::delete_account] does, but within the caller's
+	/// transaction
+	async fn delete_account_tx(&self, tx: &mut Transaction<'_, Postgres>, owner_id: &AccountId, account_id: &AccountId) -> Result<(), AccountOwnerManageError> {
+		check_authorized(tx, &self.schema, owner_id, AccountAction::Delete, account_id).await?;
+
+		// the account along with every account it owns, directly or indirectly
+		let accounts: Vec<sqlx::types::Uuid> =
+			sqlx::query_as::<_, (sqlx::types::Uuid,)>(&self.schema.qualify("WITH RECURSIVE owned AS (SELECT user_id FROM {schema}accounts WHERE user_id=$1 UNION ALL SELECT accounts.user_id FROM {schema}accounts JOIN owned ON accounts.owner_id=owned.user_id) SELECT user_id FROM owned;"))
+				.bind(account_id.0)
+				.fetch_all(&mut **tx)
+				.await
+				.map_err(|e| AccountOwnerManageError::Other(e.into()))?
+				.into_iter()
+				.map(|(user_id,)| user_id)
+				.collect();
+
+		// owned resources are removed explicitly rather than relying on the schema's cascades
+		for statement in [
+			self.schema.qualify("DELETE FROM {schema}eta_notifications WHERE tracking_id IN (SELECT tracking_id FROM {schema}live_tracking_sessions WHERE user_id=ANY($1)) OR phone_id IN (SELECT phone_id FROM {schema}phone_numbers WHERE user_id=ANY($1));"),
+			self.schema.qualify("DELETE FROM {schema}live_tracking_sessions WHERE user_id=ANY($1);"),
+			self.schema.qualify("DELETE FROM {schema}phone_numbers WHERE user_id=ANY($1);"),
+			self.schema.qualify("DELETE FROM {schema}sessions WHERE user_id=ANY($1);"),
+			self.schema.qualify("DELETE FROM {schema}login_tokens WHERE user_id=ANY($1);"),
+			self.schema.qualify("DELETE FROM {schema}accounts WHERE user_id=ANY($1);"),
+		] {
+			sqlx::query(&statement)
+				.bind(&accounts)
+				.execute(&mut **tx)
+				.await
+				.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		}
+
+		for account in &accounts {
+			notify(&mut **tx, &BackendEvent::new("account", *account, "deleted"))
+				.await
+				.map_err(|e| AccountOwnerManageError::Other(e.into()))?;
+		}
+
+		Ok(())
 	}
 
 	/// Creates a new AmbulanceTracker using the specified connection as the backend.
 	/// It is expected that the migrations file has been executed already.
 	pub fn new(pool: PgPool) -> Self {
-		Self(pool)
+		Self {
+			pool,
+			password_history: 0,
+			session_ttl: None,
+			default_eta_alert: DEFAULT_ETA_ALERT,
+			clock: Arc::new(SystemClock),
+			min_password_length: None,
+			schema: Schema::default(),
+			password_change_sessions: PasswordChangeSessionPolicy::KeepAll,
+			hide_login_failures: false,
+			login_token_ttl: DEFAULT_LOGIN_TOKEN_TTL,
+			acquire_retry: None
+		}
+	}
+
+	/// Expires sessions which have not been used for longer than the TTL. By default sessions
+	/// never expire.
+	pub fn with_session_ttl(mut self, ttl: Duration) -> Self {
+		self.session_ttl = Some(ttl);
+		self
 	}
 
-	pub async fn create_site_admin(&self, username: &str) -> Result<(AccountId, String), Box<dyn Error>> {
+	/// The default session TTL as an interval, which an account's `session_ttl_override` replaces.
+	/// Sessions last used at or before the current time minus the TTL have expired.
+	fn session_ttl(&self) -> Result<Option<PgInterval>, SessionRetrievalError> {
+		self.session_ttl.map(PgInterval::try_from).transpose().map_err(|e| SessionRetrievalError::Other(e as Box<dyn Error>))
+	}
+
+	/// Reads the current time from the clock rather than the system clock
+	pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+		self.clock = clock;
+		self
+	}
+
+	/// Rejects changing to any of the last `length` passwords of an account, including the current
+	/// one. Passwords replaced by [AccountManager::reset_password] are not remembered. A length of
+	/// zero, the default, allows any password.
+	pub fn with_password_history(mut self, length: usize) -> Self {
+		self.password_history = length;
+		self
+	}
+
+	/// Refuses logins with an unknown username or a wrong password alike with
+	/// [AccountLoginError::InvalidCredentials], so that callers cannot probe which usernames exist.
+	/// By default the two are reported separately.
+	pub fn with_hidden_login_failures(mut self, hidden: bool) -> Self {
+		self.hide_login_failures = hidden;
+		self
+	}
+
+	/// Sets how long tokens from [AccountManager::issue_login_token] remain valid, by default
+	/// [DEFAULT_LOGIN_TOKEN_TTL]
+	pub fn with_login_token_ttl(mut self, ttl: Duration) -> Self {
+		self.login_token_ttl = ttl;
+		self
+	}
+
+	/// Retries acquiring a connection when the pool is exhausted, rather than failing as soon as
+	/// acquiring times out. By default each method gives up after the first attempt.
+	pub fn with_acquire_retry(mut self, retry: AcquireRetry) -> Self {
+		self.acquire_retry = Some(retry);
+		self
+	}
+
+	/// Sets which sessions remain valid after a password change, by default all of them
+	pub fn with_password_change_sessions(mut self, policy: PasswordChangeSessionPolicy) -> Self {
+		self.password_change_sessions = policy;
+		self
+	}
+
+	/// Rejects changing to, or creating an account with, a password with fewer than `length`
+	/// characters. By default any password, including an empty one, is allowed.
+	pub fn with_min_password_length(mut self, length: usize) -> Self {
+		self.min_password_length = Some(length);
+		self
+	}
+
+	/// Sets the default ETA alert given to new accounts, [DEFAULT_ETA_ALERT] unless changed.
+	/// Returns [SettingsError::EtaAlertTooLong] if it is not shorter than [MAX_ETA_ALERT].
+	pub fn with_default_eta_alert(mut self, default_eta_alert: Duration) -> Result<Self, SettingsError> {
+		if default_eta_alert >= MAX_ETA_ALERT {
+			return Err(SettingsError::EtaAlertTooLong);
+		}
+		self.default_eta_alert = default_eta_alert;
+		Ok(self)
+	}
+
+	/// Uses the tables in the given Postgres schema rather than whichever the connection's
+	/// `search_path` resolves, so that separate tenants can share a pool. The schema must already
+	/// be migrated with [crate::sql::run_migrations_in_schema], which gives it its own
+	/// `account_role` type.
+	pub fn with_schema(mut self, schema: &str) -> Self {
+		self.schema = Schema::named(schema);
+		self
+	}
+
+	pub async fn create_site_admin(&self, username: &str) -> Result<(AccountId, Redacted<String>), Box<dyn Error>> {
 		self.unchecked_create_account(username, AccountRole::SiteAdmin, None).await
 	}
 }
@@ -220,10 +816,78 @@ mod tests {
 	use super::*;
 	use sqlx::PgPool;
 
+	use rand::rngs::StdRng;
+	use rand::{CryptoRng, RngCore, SeedableRng};
+
 	fn mgr(pool: PgPool) -> SqlAccountManager {
 		SqlAccountManager::new(pool)
 	}
 
+	/// A predictable generator which counts up by one from its seed
+	struct CountingRng(u64);
+
+	impl RngCore for CountingRng {
+		fn next_u32(&mut self) -> u32 {
+			self.next_u64() as u32
+		}
+
+		fn next_u64(&mut self) -> u64 {
+			self.0 += 1;
+			self.0
+		}
+
+		fn fill_bytes(&mut self, dest: &mut [u8]) {
+			for byte in dest {
+				*byte = self.next_u64() as u8;
+			}
+		}
+	}
+
+	impl CryptoRng for CountingRng {}
+
+	#[test]
+	fn random_password_known_output() {
+		assert_eq!(random_password(&mut CountingRng(0), 16).unwrap(), "BCDEFGHIJKLMNOPQ");
+		assert_eq!(random_password(&mut CountingRng(74), 4).unwrap(), "+ACD");
+	}
+
+	#[test]
+	fn random_password_characters_vary() {
+		use std::collections::HashSet;
+
+		let mut rng = StdRng::seed_from_u64(7);
+		let mut seen = HashSet::new();
+		for _ in 0..100 {
+			let password = random_password(&mut rng, 16).unwrap();
+			let distinct: HashSet<char> = password.chars().collect();
+			// reusing the same random value for every character would repeat a single one
+			assert!(distinct.len() > 8, "{}", password);
+			seen.extend(distinct);
+		}
+		assert_eq!(seen.len(), 76);
+	}
+
+	#[test]
+	fn random_password_is_deterministic_for_seed() {
+		let password = random_password(&mut StdRng::seed_from_u64(42), 64).unwrap();
+		assert_eq!(password, random_password(&mut StdRng::seed_from_u64(42), 64).unwrap());
+		assert_ne!(password, random_password(&mut StdRng::seed_from_u64(43), 64).unwrap());
+
+		assert_eq!(password.chars().count(), 64);
+		assert!(password.chars().all(|c| c.is_ascii_alphanumeric() || "!@#$%^&*()-_=+".contains(c)), "{}", password);
+		assert!(password.chars().any(|c| c != password.chars().next().unwrap()), "{}", password);
+	}
+
+	#[test]
+	fn random_salt_and_session_use_generator() {
+		let salt = random_salt(&mut CountingRng(0)).unwrap();
+		assert_eq!(salt[0], 1);
+		assert_eq!(salt[15], 16);
+
+		let session = random_session(&mut StdRng::seed_from_u64(7)).unwrap();
+		assert_eq!(session, random_session(&mut StdRng::seed_from_u64(7)).unwrap());
+	}
+
 	#[sqlx::test]
 	async fn site_admin_can_create_admin(pool: PgPool) {
 		let mgr = mgr(pool);
@@ -295,7 +959,12 @@ mod tests {
 		let wrong_result =
 			mgr.reset_password(&user_id, &user_id).await;
 
-		assert!(matches!(wrong_result, Err(AccountOwnerManageError::UserNotFound)));
+		assert!(matches!(wrong_result, Err(AccountOwnerManageError::NotOwner)));
+		assert!(matches!(mgr.reset_password(&site_admin_id, &user_id).await, Err(AccountOwnerManageError::NotOwner)));
+
+		// Missing account
+		let missing = AccountId::new(sqlx::types::Uuid::nil());
+		assert!(matches!(mgr.reset_password(&admin_id, &missing).await, Err(AccountOwnerManageError::UserNotFound)));
 
 		// Correct owner
 		let new_pw =
@@ -304,6 +973,55 @@ mod tests {
 		assert!(!new_pw.is_empty());
 	}
 
+	#[sqlx::test]
+	async fn password_reset_requires_password_change(pool: PgPool) {
+		let mgr = mgr(pool);
+
+		let (site_admin_id, _) = mgr.unchecked_create_account("root", AccountRole::SiteAdmin, None).await.unwrap();
+		let (admin_id, temp_pass) =
+			mgr.create_account(&site_admin_id, AccountRole::Admin, "a1").await.unwrap();
+		mgr.change_password(&admin_id, &temp_pass, "new password").await.unwrap();
+
+		let token = mgr.login("a1", "new password").await.unwrap();
+		assert_eq!(mgr.retrieve_account(&token, SessionRetrievalPurpose::Other).await.unwrap(), admin_id);
+
+		let reset_pass = mgr.reset_password(&site_admin_id, &admin_id).await.unwrap();
+		let token = mgr.login("a1", &reset_pass).await.unwrap();
+
+		// the reset password may only be used to change the password
+		assert!(matches!(mgr.retrieve_account(&token, SessionRetrievalPurpose::Other).await, Err(SessionRetrievalError::InvalidPurpose)));
+		assert_eq!(mgr.retrieve_account(&token, SessionRetrievalPurpose::ChangePassword).await.unwrap(), admin_id);
+
+		mgr.change_password(&admin_id, &reset_pass, "another password").await.unwrap();
+		assert_eq!(mgr.retrieve_account(&token, SessionRetrievalPurpose::Other).await.unwrap(), admin_id);
+	}
+
+	#[sqlx::test]
+	async fn acquire_retry_waits_for_busy_pool(_: sqlx::postgres::PgPoolOptions, options: sqlx::postgres::PgConnectOptions) {
+		let pool = sqlx::postgres::PgPoolOptions::new()
+			.max_connections(1)
+			.acquire_timeout(Duration::from_millis(20))
+			.connect_lazy_with(options);
+		let mgr = Arc::new(mgr(pool.clone()).with_acquire_retry(AcquireRetry::new(20, Duration::from_millis(10))));
+		let (site_admin_id, _) = mgr.unchecked_create_account("root", AccountRole::SiteAdmin, None).await.unwrap();
+
+		// the only connection is busy for longer than the acquire timeout
+		let held = pool.acquire().await.unwrap();
+		let tasks: Vec<_> = (0..4).map(|i| {
+			let mgr = mgr.clone();
+			tokio::spawn(async move {
+				let admin_id = mgr.create_account(&site_admin_id, AccountRole::Admin, &format!("a{i}")).await.map_err(|e| e.to_string())?;
+				mgr.get_owner(&admin_id).await.map_err(|e| e.to_string())
+			})
+		}).collect();
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		drop(held);
+
+		for task in tasks {
+			assert_eq!(task.await.unwrap().unwrap(), Some(site_admin_id));
+		}
+	}
+
 	#[sqlx::test]
 	async fn delete_account_removes_user_and_resources(pool: PgPool) {
 		let mgr = mgr(pool);
@@ -316,7 +1034,11 @@ mod tests {
 
 		// Deleting with wrong owner must fail
 		let wrong_res = mgr.delete_account(&user_id, &admin_id).await;
-		assert!(matches!(wrong_res, Err(AccountOwnerManageError::UserNotFound)));
+		assert!(matches!(wrong_res, Err(AccountOwnerManageError::NotOwner)));
+
+		// Deleting a missing account is distinguished from a wrong owner
+		let missing = AccountId::new(sqlx::types::Uuid::nil());
+		assert!(matches!(mgr.delete_account(&admin_id, &missing).await, Err(AccountOwnerManageError::UserNotFound)));
 
 		// Delete with correct owner
 		mgr.delete_account(&admin_id, &user_id).await.expect("admin should delete user");
@@ -326,6 +1048,92 @@ mod tests {
 		assert!(matches!(login_res, Err(AccountLoginError::UserNotFound)));
 	}
 
+	#[sqlx::test]
+	async fn force_logout_requires_higher_role(pool: PgPool) {
+		let mgr = mgr(pool);
+
+		let (site_admin_id, _) = mgr.unchecked_create_account("root", AccountRole::SiteAdmin, None).await.unwrap();
+		let (admin_id, _) =
+			mgr.create_account(&site_admin_id, AccountRole::Admin, "a1").await.unwrap();
+		let (user_id, user_pass) =
+			mgr.create_account(&admin_id, AccountRole::User, "u1").await.unwrap();
+
+		let first = mgr.login("u1", &user_pass).await.unwrap();
+		let second = mgr.login("u1", &user_pass).await.unwrap();
+
+		// a user cannot force anyone out, including themselves
+		assert!(matches!(mgr.force_logout(&user_id, &user_id).await, Err(AccountOwnerManageError::InsufficientRole)));
+		assert!(matches!(mgr.force_logout(&user_id, &admin_id).await, Err(AccountOwnerManageError::InsufficientRole)));
+		assert!(mgr.retrieve_account(&first, SessionRetrievalPurpose::ChangePassword).await.is_ok());
+
+		assert_eq!(mgr.force_logout(&admin_id, &user_id).await.unwrap(), 2);
+		assert!(matches!(mgr.retrieve_account(&first, SessionRetrievalPurpose::ChangePassword).await, Err(SessionRetrievalError::InvalidToken)));
+		assert!(matches!(mgr.retrieve_account(&second, SessionRetrievalPurpose::ChangePassword).await, Err(SessionRetrievalError::InvalidToken)));
+		assert_eq!(mgr.force_logout(&admin_id, &user_id).await.unwrap(), 0);
+
+		// a site admin is not limited to accounts it owns directly
+		mgr.login("u1", &user_pass).await.unwrap();
+		assert_eq!(mgr.force_logout(&site_admin_id, &user_id).await.unwrap(), 1);
+
+		assert!(matches!(mgr.force_logout(&admin_id, &AccountId::new(sqlx::types::Uuid::nil())).await, Err(AccountOwnerManageError::UserNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn get_owner_follows_ownership_chain(pool: PgPool) {
+		let mgr = mgr(pool);
+
+		let (site_admin_id, _) = mgr.unchecked_create_account("root", AccountRole::SiteAdmin, None).await.unwrap();
+		let (admin_id, _) =
+			mgr.create_account(&site_admin_id, AccountRole::Admin, "a1").await.unwrap();
+		let (user_id, _) =
+			mgr.create_account(&admin_id, AccountRole::User, "u1").await.unwrap();
+
+		assert_eq!(mgr.get_owner(&user_id).await.unwrap(), Some(admin_id));
+		assert_eq!(mgr.get_owner(&admin_id).await.unwrap(), Some(site_admin_id));
+		assert_eq!(mgr.get_owner(&site_admin_id).await.unwrap(), None);
+
+		mgr.delete_account(&admin_id, &user_id).await.unwrap();
+		assert!(matches!(mgr.get_owner(&user_id).await, Err(AccountOwnerManageError::UserNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn delete_account_removes_owned_resources(pool: PgPool) {
+		use crate::data::SettingsManager;
+		use crate::sql::sql_settings_manager::SQLSettingsManager;
+
+		let mgr = mgr(pool.clone());
+		let settings = SQLSettingsManager::new(pool.clone());
+
+		let (site_admin_id, _) = mgr.unchecked_create_account("root", AccountRole::SiteAdmin, None).await.unwrap();
+		let (admin_id, admin_pass) =
+			mgr.create_account(&site_admin_id, AccountRole::Admin, "a1").await.unwrap();
+		let (user_id, user_pass) =
+			mgr.create_account(&admin_id, AccountRole::User, "u1").await.unwrap();
+
+		settings.new_phone(admin_id, "0123456789", "admin phone").await.unwrap();
+		settings.new_phone(user_id, "9876543210", "user phone").await.unwrap();
+		let admin_token = mgr.login("a1", &admin_pass).await.unwrap();
+		let user_token = mgr.login("u1", &user_pass).await.unwrap();
+
+		// deleting the admin also removes the user it owns
+		mgr.delete_account(&site_admin_id, &admin_id).await.unwrap();
+
+		for (table, id) in [("phone_numbers", admin_id), ("phone_numbers", user_id), ("sessions", admin_id), ("sessions", user_id), ("accounts", user_id)] {
+			let (count,): (i64,) = sqlx::query_as(&format!("SELECT COUNT(*) FROM {} WHERE user_id=$1", table))
+				.bind(id.0)
+				.fetch_one(&pool)
+				.await
+				.unwrap();
+			assert_eq!(count, 0, "{} rows remain for {:?}", table, id);
+		}
+
+		assert!(matches!(mgr.retrieve_account(&admin_token, SessionRetrievalPurpose::ChangePassword).await, Err(SessionRetrievalError::InvalidToken)));
+		assert!(matches!(mgr.retrieve_account(&user_token, SessionRetrievalPurpose::ChangePassword).await, Err(SessionRetrievalError::InvalidToken)));
+
+		// the site admin is untouched
+		assert_eq!(mgr.get_owner(&site_admin_id).await.unwrap(), None);
+	}
+
 	#[sqlx::test]
 	async fn login_requires_correct_password(pool: PgPool) {
 		let mgr = mgr(pool);
@@ -343,6 +1151,223 @@ mod tests {
 		assert_eq!(token.0.len(), 32);
 	}
 
+	#[sqlx::test]
+	async fn verify_password_does_not_create_session(pool: PgPool) {
+		let mgr = mgr(pool.clone());
+
+		let (site_admin_id, _) = mgr.unchecked_create_account("root", AccountRole::SiteAdmin, None).await.unwrap();
+		let (admin_id, temp_pass) =
+			mgr.create_account(&site_admin_id, AccountRole::Admin, "a1").await.unwrap();
+
+		assert!(mgr.verify_password(&admin_id, &temp_pass).await.unwrap());
+		assert!(!mgr.verify_password(&admin_id, "badpw").await.unwrap());
+
+		let (sessions,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM sessions").fetch_one(&pool).await.unwrap();
+		assert_eq!(sessions, 0);
+
+		mgr.delete_account(&site_admin_id, &admin_id).await.unwrap();
+		assert!(matches!(mgr.verify_password(&admin_id, &temp_pass).await, Err(AccountLoginError::UserNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn password_history_rejects_recent_passwords(pool: PgPool) {
+		let mgr = SqlAccountManager::new(pool.clone()).with_password_history(2);
+
+		let (id, temp_pass) = mgr.create_site_admin("root").await.unwrap();
+		mgr.change_password(&id, &temp_pass, "first password").await.unwrap();
+		mgr.change_password(&id, "first password", "second password").await.unwrap();
+
+		// the immediately previous and current passwords are both rejected
+		assert!(matches!(mgr.change_password(&id, "second password", "first password").await, Err(AccountChangePasswordError::PasswordReused)));
+		assert!(matches!(mgr.change_password(&id, "second password", "second password").await, Err(AccountChangePasswordError::PasswordReused)));
+		assert!(mgr.login("root", "second password").await.is_ok());
+
+		// a new password is accepted and recorded
+		mgr.change_password(&id, "second password", "third password").await.unwrap();
+		assert!(mgr.login("root", "third password").await.is_ok());
+		assert!(matches!(mgr.change_password(&id, "third password", "second password").await, Err(AccountChangePasswordError::PasswordReused)));
+
+		// history beyond the length is pruned, so older passwords may be used again
+		let (kept,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM password_history WHERE user_id=$1;")
+			.bind(id.0)
+			.fetch_one(&pool)
+			.await
+			.unwrap();
+		assert_eq!(kept, 1);
+		mgr.change_password(&id, "third password", "first password").await.unwrap();
+	}
+
+	#[sqlx::test]
+	async fn concurrent_password_changes_verify_in_turn(pool: PgPool) {
+		let mgr = mgr(pool);
+
+		let (id, temp_pass) = mgr.create_site_admin("root").await.unwrap();
+		let (first, second) = tokio::join!(
+			mgr.change_password(&id, &temp_pass, "first password"),
+			mgr.change_password(&id, &temp_pass, "second password")
+		);
+
+		// whichever change runs second no longer finds the temporary password
+		let (kept, rejected) = match (first, second) {
+			(Ok(()), Err(e)) => ("first password", e),
+			(Err(e), Ok(())) => ("second password", e),
+			results => panic!("expected exactly one change to succeed, found {:?}", results)
+		};
+		assert!(matches!(rejected, AccountChangePasswordError::IncorrectPassword), "{:?}", rejected);
+		assert!(mgr.verify_password(&id, kept).await.unwrap());
+	}
+
+	#[sqlx::test]
+	async fn min_password_length(pool: PgPool) {
+		let mgr = SqlAccountManager::new(pool.clone()).with_min_password_length(8);
+
+		let (id, temp_pass) = mgr.create_site_admin("root").await.unwrap();
+		assert!(matches!(mgr.change_password(&id, &temp_pass, "").await, Err(AccountChangePasswordError::PasswordTooShort(8))));
+		assert!(matches!(mgr.change_password(&id, &temp_pass, "1234567").await, Err(AccountChangePasswordError::PasswordTooShort(8))));
+		// characters are counted rather than bytes
+		assert!(matches!(mgr.change_password(&id, &temp_pass, "ééééééé").await, Err(AccountChangePasswordError::PasswordTooShort(8))));
+		assert!(mgr.verify_password(&id, &temp_pass).await.unwrap());
+
+		mgr.change_password(&id, &temp_pass, "12345678").await.unwrap();
+		assert!(mgr.verify_password(&id, "12345678").await.unwrap());
+
+		// without a minimum, any password is accepted
+		let mgr = SqlAccountManager::new(pool);
+		mgr.change_password(&id, "12345678", "").await.unwrap();
+		assert!(mgr.verify_password(&id, "").await.unwrap());
+	}
+
+	#[sqlx::test]
+	async fn password_history_disabled_by_default(pool: PgPool) {
+		let mgr = mgr(pool.clone());
+
+		let (id, temp_pass) = mgr.create_site_admin("root").await.unwrap();
+		mgr.change_password(&id, &temp_pass, &temp_pass).await.unwrap();
+
+		let (kept,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM password_history WHERE user_id=$1;")
+			.bind(id.0)
+			.fetch_one(&pool)
+			.await
+			.unwrap();
+		assert_eq!(kept, 0);
+	}
+
+	#[sqlx::test]
+	async fn touch_session_extends_lifetime(pool: PgPool) {
+		let mgr = SqlAccountManager::new(pool.clone()).with_session_ttl(Duration::from_secs(60 * 60));
+
+		let (id, temp_pass) = mgr.create_site_admin("root").await.unwrap();
+		let token = mgr.login("root", &temp_pass).await.unwrap();
+
+		// nearly expired
+		sqlx::query("UPDATE sessions SET last_used_at=now() - INTERVAL '59 minutes' WHERE session_id=$1;")
+			.bind(token.0)
+			.execute(&pool)
+			.await
+			.unwrap();
+		mgr.touch_session(&token).await.unwrap();
+
+		let (idle,): (f64,) = sqlx::query_as("SELECT EXTRACT(EPOCH FROM now() - last_used_at)::float8 FROM sessions WHERE session_id=$1;")
+			.bind(token.0)
+			.fetch_one(&pool)
+			.await
+			.unwrap();
+		assert!(idle < 60.0, "session was not extended, idle for {}", idle);
+		assert_eq!(mgr.retrieve_account(&token, SessionRetrievalPurpose::ChangePassword).await.unwrap(), id);
+	}
+
+	#[sqlx::test]
+	async fn rotate_session_replaces_token(pool: PgPool) {
+		let mgr = mgr(pool);
+
+		let (id, temp_pass) = mgr.create_site_admin("root").await.unwrap();
+		let token = mgr.login("root", &temp_pass).await.unwrap();
+
+		let rotated = mgr.rotate_session(&token).await.unwrap();
+		assert_ne!(rotated, token);
+		assert!(matches!(mgr.retrieve_account(&token, SessionRetrievalPurpose::ChangePassword).await, Err(SessionRetrievalError::InvalidToken)));
+		assert_eq!(mgr.retrieve_account(&rotated, SessionRetrievalPurpose::ChangePassword).await.unwrap(), id);
+
+		// the old token cannot be rotated again
+		assert!(matches!(mgr.rotate_session(&token).await, Err(SessionRetrievalError::InvalidToken)));
+		assert_eq!(mgr.destroy_session(&rotated).await.unwrap(), 1);
+		assert!(matches!(mgr.rotate_session(&rotated).await, Err(SessionRetrievalError::InvalidToken)));
+	}
+
+	#[sqlx::test]
+	async fn session_expiry_boundary(pool: PgPool) {
+		use crate::data::ManualClock;
+		use sqlx::types::chrono::SubsecRound;
+
+		let clock = Arc::new(ManualClock::new(Utc::now().trunc_subsecs(0)));
+		let mgr = SqlAccountManager::new(pool).with_session_ttl(Duration::from_secs(60 * 60)).with_clock(clock.clone());
+
+		let (id, temp_pass) = mgr.create_site_admin("root").await.unwrap();
+		let token = mgr.login("root", &temp_pass).await.unwrap();
+
+		// still valid up to the last microsecond of the ttl
+		clock.advance(Duration::from_secs(60 * 60) - Duration::from_micros(1));
+		assert_eq!(mgr.retrieve_account(&token, SessionRetrievalPurpose::ChangePassword).await.unwrap(), id);
+
+		// touching restarts the ttl from the clock's time
+		mgr.touch_session(&token).await.unwrap();
+		clock.advance(Duration::from_secs(60 * 60) - Duration::from_micros(1));
+		assert_eq!(mgr.retrieve_account(&token, SessionRetrievalPurpose::ChangePassword).await.unwrap(), id);
+
+		clock.advance(Duration::from_micros(1));
+		assert!(matches!(mgr.retrieve_account(&token, SessionRetrievalPurpose::ChangePassword).await, Err(SessionRetrievalError::InvalidToken)));
+		assert!(matches!(mgr.touch_session(&token).await, Err(SessionRetrievalError::InvalidToken)));
+	}
+
+	#[sqlx::test]
+	async fn expired_sessions_are_invalid(pool: PgPool) {
+		let mgr = SqlAccountManager::new(pool.clone()).with_session_ttl(Duration::from_secs(60 * 60));
+
+		let (_, temp_pass) = mgr.create_site_admin("root").await.unwrap();
+		let token = mgr.login("root", &temp_pass).await.unwrap();
+		sqlx::query("UPDATE sessions SET last_used_at=now() - INTERVAL '2 hours' WHERE session_id=$1;")
+			.bind(token.0)
+			.execute(&pool)
+			.await
+			.unwrap();
+
+		assert!(matches!(mgr.touch_session(&token).await, Err(SessionRetrievalError::InvalidToken)));
+		assert!(matches!(mgr.retrieve_account(&token, SessionRetrievalPurpose::ChangePassword).await, Err(SessionRetrievalError::InvalidToken)));
+
+		// without a ttl, sessions never expire
+		assert!(SqlAccountManager::new(pool).touch_session(&token).await.is_ok());
+		assert!(matches!(mgr.touch_session(&SessionToken::new([0; 32])).await, Err(SessionRetrievalError::InvalidToken)));
+	}
+
+	#[test]
+	fn verify_password_hash_checks_password() {
+		let salt = [3u8; 16];
+		let hash = hash_password(b"correct horse", &salt).unwrap();
+
+		assert!(verify_password_hash(b"correct horse", &salt, &hash).unwrap());
+		assert!(!verify_password_hash(b"correct horse!", &salt, &hash).unwrap());
+		assert!(!verify_password_hash(b"correct horse", &[4u8; 16], &hash).unwrap());
+	}
+
+	#[test]
+	fn verify_password_hash_rejects_malformed_salt() {
+		let hash = [0u8; 32];
+		assert!(verify_password_hash(b"password", &[], &hash).is_err());
+		assert!(verify_password_hash(b"password", &[1u8; 4], &hash).is_err());
+	}
+
+	#[test]
+	fn hashes_equal_compares_every_byte() {
+		let a = [7u8; 32];
+		let mut b = a;
+		assert!(hashes_equal(&a, &b));
+		b[31] = 0;
+		assert!(!hashes_equal(&a, &b));
+		b[31] = 7;
+		b[0] = 0;
+		assert!(!hashes_equal(&a, &b));
+	}
+
 	#[sqlx::test]
 	async fn destroy_session_invalidates_token(pool: PgPool) {
 		let mgr = mgr(pool);
@@ -355,13 +1380,16 @@ mod tests {
 			mgr.login("a1", &temp_pass).await.expect("should log in");
 
 		// Destroy it
-		mgr.destroy_session(&token).await.expect("destroy should succeed");
+		assert_eq!(mgr.destroy_session(&token).await.expect("destroy should succeed"), 1);
 
 		// Retrieval should now fail
 		let res =
 			mgr.retrieve_account(&token, SessionRetrievalPurpose::Other).await;
 
 		assert!(matches!(res, Err(SessionRetrievalError::InvalidToken)));
+
+		// destroying it again is a no-op
+		assert_eq!(mgr.destroy_session(&token).await.expect("destroy should succeed"), 0);
 	}
 
 	#[sqlx::test]
@@ -397,5 +1425,344 @@ mod tests {
 				.expect("session retrieval must succeed");
 		assert_eq!(retrieved, admin_id, "retrieve_account should return correct account");
 	}
-}
 
+	#[sqlx::test]
+	async fn test_default_eta_alert(pool: PgPool) {
+		use crate::data::SettingsManager;
+		use crate::sql::sql_settings_manager::SQLSettingsManager;
+
+		let settings = SQLSettingsManager::new(pool.clone());
+
+		let (site_admin_id, _) = mgr(pool.clone()).create_site_admin("sa").await.unwrap();
+		assert_eq!(settings.get_settings(site_admin_id).await.unwrap().default_eta_alert, DEFAULT_ETA_ALERT);
+
+		let mgr = mgr(pool).with_default_eta_alert(Duration::from_secs(10 * 60)).unwrap();
+		let (admin_id, _) = mgr.create_account(&site_admin_id, AccountRole::Admin, "a1").await.unwrap();
+		assert_eq!(settings.get_settings(admin_id).await.unwrap().default_eta_alert, Duration::from_secs(10 * 60));
+
+		let (sa2_id, _) = mgr.create_site_admin("sa2").await.unwrap();
+		assert_eq!(settings.get_settings(sa2_id).await.unwrap().default_eta_alert, Duration::from_secs(10 * 60));
+	}
+
+	#[sqlx::test]
+	async fn test_default_eta_alert_too_long(pool: PgPool) {
+		assert!(matches!(mgr(pool.clone()).with_default_eta_alert(MAX_ETA_ALERT), Err(SettingsError::EtaAlertTooLong)));
+		assert!(mgr(pool).with_default_eta_alert(MAX_ETA_ALERT - Duration::from_secs(1)).is_ok());
+	}
+
+	#[sqlx::test]
+	async fn create_account_with_password_can_log_in(pool: PgPool) {
+		let mgr = mgr(pool.clone());
+
+		let (site_admin_id, _) = mgr.create_site_admin("root").await.unwrap();
+		let admin_id = mgr.create_account_with_password(&site_admin_id, AccountRole::Admin, "a1", "known password", false).await.unwrap();
+
+		let session = mgr.login("a1", "known password").await.unwrap();
+		let (reset_needed,): (bool,) = sqlx::query_as("SELECT password_reset_needed FROM accounts WHERE user_id=$1;")
+			.bind(admin_id.0)
+			.fetch_one(&pool)
+			.await
+			.unwrap();
+		assert!(!reset_needed);
+		assert!(mgr.destroy_session(&session).await.unwrap() > 0);
+
+		let user_id = mgr.create_account_with_password(&admin_id, AccountRole::User, "u1", "another password", true).await.unwrap();
+		mgr.login("u1", "another password").await.unwrap();
+		let (reset_needed,): (bool,) = sqlx::query_as("SELECT password_reset_needed FROM accounts WHERE user_id=$1;")
+			.bind(user_id.0)
+			.fetch_one(&pool)
+			.await
+			.unwrap();
+		assert!(reset_needed);
+	}
+
+	#[sqlx::test]
+	async fn create_account_with_password_checks_owner_and_length(pool: PgPool) {
+		let mgr = mgr(pool).with_min_password_length(8);
+
+		let (site_admin_id, _) = mgr.create_site_admin("root").await.unwrap();
+		assert!(matches!(mgr.create_account_with_password(&site_admin_id, AccountRole::User, "u1", "long enough", false).await, Err(AccountCreationError::InvalidOwnerRole)));
+		assert!(matches!(mgr.create_account_with_password(&AccountId::new(sqlx::types::Uuid::nil()), AccountRole::User, "u1", "long enough", false).await, Err(AccountCreationError::OwnerNotFound)));
+		assert!(matches!(mgr.create_account_with_password(&site_admin_id, AccountRole::Admin, "a1", "short", false).await, Err(AccountCreationError::PasswordTooShort(8))));
+		assert!(matches!(mgr.login("a1", "long enough").await, Err(AccountLoginError::UserNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn password_reset_required_until_changed(pool: PgPool) {
+		let mgr = mgr(pool);
+
+		let (site_admin_id, _) = mgr.create_site_admin("root").await.unwrap();
+		let (admin_id, temp_pass) =
+			mgr.create_account(&site_admin_id, AccountRole::Admin, "a1").await.unwrap();
+		assert!(mgr.password_reset_required(&admin_id).await.unwrap());
+
+		mgr.change_password(&admin_id, &temp_pass, "new password").await.unwrap();
+		assert!(!mgr.password_reset_required(&admin_id).await.unwrap());
+
+		mgr.reset_password(&site_admin_id, &admin_id).await.unwrap();
+		assert!(mgr.password_reset_required(&admin_id).await.unwrap());
+
+		assert!(matches!(mgr.password_reset_required(&AccountId::new(sqlx::types::Uuid::nil())).await, Err(AccountOwnerManageError::UserNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn admin_action_requires_admin_role(pool: PgPool) {
+		let mgr = mgr(pool);
+
+		let (site_admin_id, _) = mgr.create_site_admin("root").await.unwrap();
+		let admin_id = mgr.create_account_with_password(&site_admin_id, AccountRole::Admin, "a1", "admin password", false).await.unwrap();
+		mgr.create_account_with_password(&admin_id, AccountRole::User, "u1", "user password", false).await.unwrap();
+
+		let admin_token = mgr.login("a1", "admin password").await.unwrap();
+		assert_eq!(mgr.retrieve_session(&admin_token, SessionRetrievalPurpose::AdminAction).await.unwrap(), (admin_id, AccountRole::Admin));
+
+		let user_token = mgr.login("u1", "user password").await.unwrap();
+		assert!(matches!(mgr.retrieve_session(&user_token, SessionRetrievalPurpose::AdminAction).await, Err(SessionRetrievalError::InvalidPurpose)));
+		assert!(matches!(mgr.retrieve_account(&user_token, SessionRetrievalPurpose::AdminAction).await, Err(SessionRetrievalError::InvalidPurpose)));
+		assert_eq!(mgr.retrieve_session(&user_token, SessionRetrievalPurpose::Other).await.unwrap().1, AccountRole::User);
+
+		// a pending password reset takes precedence over the role
+		mgr.reset_password(&site_admin_id, &admin_id).await.unwrap();
+		assert!(matches!(mgr.retrieve_session(&admin_token, SessionRetrievalPurpose::AdminAction).await, Err(SessionRetrievalError::InvalidPurpose)));
+	}
+
+	#[sqlx::test]
+	async fn list_all_accounts_pages_for_site_admin(pool: PgPool) {
+		use crate::data::{Cursor, ManualClock};
+
+		let clock = Arc::new(ManualClock::new(Utc::now()));
+		let mgr = mgr(pool).with_clock(clock.clone());
+
+		let (site_admin_id, _) = mgr.create_site_admin("root").await.unwrap();
+		clock.advance(Duration::from_secs(1));
+		let (admin_id, _) = mgr.create_account(&site_admin_id, AccountRole::Admin, "a1").await.unwrap();
+		let mut expected = vec![site_admin_id, admin_id];
+		for username in ["u1", "u2", "u3"] {
+			clock.advance(Duration::from_secs(1));
+			expected.push(mgr.create_account(&admin_id, AccountRole::User, username).await.unwrap().0);
+		}
+
+		let mut listed = Vec::new();
+		let mut after = None;
+		loop {
+			let page = mgr.list_all_accounts(&site_admin_id, PageParams { after, limit: 2 }).await.unwrap();
+			assert!(page.len() <= 2);
+			match page.last() {
+				Some(last) => after = Some(Cursor::from(last)),
+				None => break
+			}
+			listed.extend(page);
+		}
+		assert_eq!(listed.iter().map(|account| account.id).collect::<Vec<_>>(), expected);
+		assert_eq!(listed[1].username, "a1");
+		assert_eq!(listed[1].role, AccountRole::Admin);
+
+		let everything = PageParams { after: None, limit: 10 };
+		assert!(matches!(mgr.list_all_accounts(&admin_id, everything).await, Err(AccountOwnerManageError::InsufficientRole)));
+		assert!(matches!(mgr.list_all_accounts(&expected[2], everything).await, Err(AccountOwnerManageError::InsufficientRole)));
+		assert!(matches!(mgr.list_all_accounts(&AccountId::new(sqlx::types::Uuid::nil()), everything).await, Err(AccountOwnerManageError::UserNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn schema_is_isolated_from_default(pool: PgPool) {
+		crate::sql::run_migrations_in_schema(&pool, "tenant x").await.unwrap();
+
+		let default = mgr(pool.clone());
+		let tenant = mgr(pool.clone()).with_schema("tenant x");
+
+		let (default_root, default_pass) = default.create_site_admin("root").await.unwrap();
+		let (tenant_root, tenant_pass) = tenant.create_site_admin("root").await.unwrap();
+		let (tenant_user, _) = tenant.create_account(&tenant_root, AccountRole::Admin, "a1").await.unwrap();
+
+		let token = tenant.login("root", &tenant_pass).await.unwrap();
+		assert_eq!(tenant.retrieve_account(&token, SessionRetrievalPurpose::ChangePassword).await.unwrap(), tenant_root);
+		assert!(matches!(default.retrieve_account(&token, SessionRetrievalPurpose::ChangePassword).await, Err(SessionRetrievalError::InvalidToken)));
+		assert!(matches!(default.login("root", &tenant_pass).await, Err(AccountLoginError::IncorrectPassword)));
+		assert!(matches!(default.login("a1", &tenant_pass).await, Err(AccountLoginError::UserNotFound)));
+		default.login("root", &default_pass).await.unwrap();
+
+		let everything = PageParams { after: None, limit: 10 };
+		let tenant_accounts: Vec<AccountId> = tenant.list_all_accounts(&tenant_root, everything).await.unwrap().into_iter().map(|account| account.id).collect();
+		assert_eq!(tenant_accounts, vec![tenant_root, tenant_user]);
+		assert_eq!(default.list_all_accounts(&default_root, everything).await.unwrap().len(), 1);
+
+		assert!(matches!(default.delete_account(&tenant_root, &tenant_user).await, Err(AccountOwnerManageError::UserNotFound)));
+		tenant.delete_account(&tenant_root, &tenant_user).await.unwrap();
+		assert!(matches!(tenant.get_owner(&tenant_user).await, Err(AccountOwnerManageError::UserNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn session_ttl_override(pool: PgPool) {
+		use crate::data::ManualClock;
+		use sqlx::types::chrono::SubsecRound;
+
+		let clock = Arc::new(ManualClock::new(Utc::now().trunc_subsecs(0)));
+		let mgr = SqlAccountManager::new(pool).with_session_ttl(Duration::from_secs(60 * 60)).with_clock(clock.clone());
+
+		let (root, _) = mgr.create_site_admin("root").await.unwrap();
+		let (admin, admin_pass) = mgr.create_account(&root, AccountRole::Admin, "admin").await.unwrap();
+		let (other, other_pass) = mgr.create_account(&root, AccountRole::Admin, "other").await.unwrap();
+		mgr.set_session_ttl(&root, &admin, Some(Duration::from_secs(60))).await.unwrap();
+
+		// the override expires the account's sessions sooner than the default does others
+		let token = mgr.login("admin", &admin_pass).await.unwrap();
+		let other_token = mgr.login("other", &other_pass).await.unwrap();
+		clock.advance(Duration::from_secs(59));
+		mgr.touch_session(&token).await.unwrap();
+		clock.advance(Duration::from_secs(60));
+		assert!(matches!(mgr.retrieve_account(&token, SessionRetrievalPurpose::ChangePassword).await, Err(SessionRetrievalError::InvalidToken)));
+		assert!(matches!(mgr.touch_session(&token).await, Err(SessionRetrievalError::InvalidToken)));
+		assert!(matches!(mgr.rotate_session(&token).await, Err(SessionRetrievalError::InvalidToken)));
+		assert_eq!(mgr.retrieve_account(&other_token, SessionRetrievalPurpose::ChangePassword).await.unwrap(), other);
+
+		// clearing it restores the default
+		mgr.set_session_ttl(&root, &admin, None).await.unwrap();
+		assert_eq!(mgr.retrieve_account(&token, SessionRetrievalPurpose::ChangePassword).await.unwrap(), admin);
+		clock.advance(Duration::from_secs(60 * 60 - 60));
+		assert!(matches!(mgr.retrieve_account(&token, SessionRetrievalPurpose::ChangePassword).await, Err(SessionRetrievalError::InvalidToken)));
+
+		// only a higher role may change it
+		assert!(matches!(mgr.set_session_ttl(&admin, &other, None).await, Err(AccountOwnerManageError::InsufficientRole)));
+		assert!(matches!(mgr.set_session_ttl(&root, &AccountId::new(sqlx::types::Uuid::nil()), None).await, Err(AccountOwnerManageError::UserNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn create_admin_with_user_is_atomic(pool: PgPool) {
+		let mgr = mgr(pool);
+		let (root, _) = mgr.create_site_admin("root").await.unwrap();
+
+		let (admin, admin_pass, user, user_pass) = mgr.create_admin_with_user(&root, "partner", "partner user").await.unwrap();
+		assert_eq!(mgr.get_owner(&admin).await.unwrap(), Some(root));
+		assert_eq!(mgr.get_owner(&user).await.unwrap(), Some(admin));
+		assert!(mgr.verify_password(&admin, &admin_pass).await.unwrap());
+		assert!(mgr.verify_password(&user, &user_pass).await.unwrap());
+
+		// a taken user username leaves no admin behind
+		let result = mgr.create_admin_with_user(&root, "second", "partner user").await;
+		assert!(matches!(result, Err(AccountCreationError::Other(_))), "{:?}", result);
+		assert!(matches!(mgr.login("second", "anything").await, Err(AccountLoginError::UserNotFound)));
+
+		assert!(matches!(mgr.create_admin_with_user(&admin, "third", "third user").await, Err(AccountCreationError::InvalidOwnerRole)));
+	}
+
+	#[sqlx::test]
+	async fn password_change_session_policies(pool: PgPool) {
+		let policies = [
+			(PasswordChangeSessionPolicy::KeepAll, true, true),
+			(PasswordChangeSessionPolicy::KeepCurrent, true, false),
+			(PasswordChangeSessionPolicy::KillAll, false, false)
+		];
+		for (i, (policy, current_survives, other_survives)) in policies.into_iter().enumerate() {
+			let mgr = mgr(pool.clone()).with_password_change_sessions(policy);
+			let username = format!("root{}", i);
+			let (id, temp_pass) = mgr.create_site_admin(&username).await.unwrap();
+			let current = mgr.login(&username, &temp_pass).await.unwrap();
+			let other = mgr.login(&username, &temp_pass).await.unwrap();
+
+			mgr.change_password_in_session(&id, &current, &temp_pass, "new password").await.unwrap();
+			assert_eq!(mgr.retrieve_account(&current, SessionRetrievalPurpose::Other).await.is_ok(), current_survives, "{:?}", policy);
+			assert_eq!(mgr.retrieve_account(&other, SessionRetrievalPurpose::Other).await.is_ok(), other_survives, "{:?}", policy);
+		}
+
+		// without the current session, keeping the current session keeps none
+		let mgr = mgr(pool).with_password_change_sessions(PasswordChangeSessionPolicy::KeepCurrent);
+		let (id, temp_pass) = mgr.create_site_admin("root").await.unwrap();
+		let token = mgr.login("root", &temp_pass).await.unwrap();
+		mgr.change_password(&id, &temp_pass, "new password").await.unwrap();
+		assert!(matches!(mgr.retrieve_account(&token, SessionRetrievalPurpose::Other).await, Err(SessionRetrievalError::InvalidToken)));
+	}
+
+	#[sqlx::test]
+	async fn login_outcomes(pool: PgPool) {
+		let mgr = mgr(pool.clone());
+		let (root, _) = mgr.create_site_admin("root").await.unwrap();
+		let (admin, temp_pass) = mgr.create_account(&root, AccountRole::Admin, "a1").await.unwrap();
+
+		let success = mgr.login_detailed("a1", &temp_pass).await.unwrap();
+		assert_eq!(success.account_id, admin);
+		assert!(success.password_reset_needed);
+		assert_eq!(mgr.retrieve_account(&success.token, SessionRetrievalPurpose::ChangePassword).await.unwrap(), admin);
+
+		mgr.change_password(&admin, &temp_pass, "new password").await.unwrap();
+		assert!(!mgr.login_detailed("a1", "new password").await.unwrap().password_reset_needed);
+
+		assert!(matches!(mgr.login_detailed("a1", "wrong").await, Err(AccountLoginError::IncorrectPassword)));
+		assert!(matches!(mgr.login_detailed("nobody", "wrong").await, Err(AccountLoginError::UserNotFound)));
+
+		let hidden = SqlAccountManager::new(pool).with_hidden_login_failures(true);
+		assert!(matches!(hidden.login_detailed("a1", "wrong").await, Err(AccountLoginError::InvalidCredentials)));
+		assert!(matches!(hidden.login_detailed("nobody", "wrong").await, Err(AccountLoginError::InvalidCredentials)));
+		assert!(matches!(hidden.login("nobody", "wrong").await, Err(AccountLoginError::InvalidCredentials)));
+		assert_eq!(hidden.login_detailed("a1", "new password").await.unwrap().account_id, admin);
+	}
+
+	#[sqlx::test]
+	async fn delete_admin_reassigning_keeps_users(pool: PgPool) {
+		let mgr = mgr(pool);
+
+		let (site_admin_id, _) = mgr.unchecked_create_account("root", AccountRole::SiteAdmin, None).await.unwrap();
+		let (admin_id, _) = mgr.create_account(&site_admin_id, AccountRole::Admin, "a1").await.unwrap();
+		let (new_admin_id, _) = mgr.create_account(&site_admin_id, AccountRole::Admin, "a2").await.unwrap();
+		let (user_id, user_pass) = mgr.create_account(&admin_id, AccountRole::User, "u1").await.unwrap();
+
+		// the new owner must be a different admin, and the site admin must own the deleted admin
+		assert!(matches!(mgr.delete_admin_reassigning(&site_admin_id, &admin_id, &user_id).await, Err(AccountOwnerManageError::InsufficientRole)));
+		assert!(matches!(mgr.delete_admin_reassigning(&site_admin_id, &admin_id, &admin_id).await, Err(AccountOwnerManageError::InsufficientRole)));
+		assert!(matches!(mgr.delete_admin_reassigning(&new_admin_id, &admin_id, &new_admin_id).await, Err(AccountOwnerManageError::NotOwner)));
+		let missing = AccountId::new(sqlx::types::Uuid::nil());
+		assert!(matches!(mgr.delete_admin_reassigning(&site_admin_id, &admin_id, &missing).await, Err(AccountOwnerManageError::UserNotFound)));
+		assert_eq!(mgr.get_owner(&user_id).await.unwrap(), Some(admin_id));
+
+		mgr.delete_admin_reassigning(&site_admin_id, &admin_id, &new_admin_id).await.unwrap();
+
+		assert!(matches!(mgr.get_owner(&admin_id).await, Err(AccountOwnerManageError::UserNotFound)));
+		assert_eq!(mgr.get_owner(&user_id).await.unwrap(), Some(new_admin_id));
+		mgr.login("u1", &user_pass).await.unwrap();
+
+		// the new admin manages the user as its own
+		mgr.reset_password(&new_admin_id, &user_id).await.unwrap();
+	}
+
+	#[sqlx::test]
+	async fn login_tokens_are_single_use_and_expire(pool: PgPool) {
+		use crate::data::ManualClock;
+		use sqlx::types::chrono::SubsecRound;
+
+		let clock = Arc::new(ManualClock::new(Utc::now().trunc_subsecs(0)));
+		let mgr = mgr(pool).with_clock(clock.clone()).with_login_token_ttl(Duration::from_secs(10 * 60));
+
+		let (site_admin_id, _) = mgr.unchecked_create_account("root", AccountRole::SiteAdmin, None).await.unwrap();
+		let (admin_id, _) = mgr.create_account(&site_admin_id, AccountRole::Admin, "a1").await.unwrap();
+		let (user_id, password) = mgr.create_account(&admin_id, AccountRole::User, "u1").await.unwrap();
+
+		// only the owner may issue a token, and only for users
+		assert!(matches!(mgr.issue_login_token(&site_admin_id, &user_id).await, Err(AccountOwnerManageError::NotOwner)));
+		assert!(matches!(mgr.issue_login_token(&site_admin_id, &admin_id).await, Err(AccountOwnerManageError::InsufficientRole)));
+		let missing = AccountId::new(sqlx::types::Uuid::nil());
+		assert!(matches!(mgr.issue_login_token(&admin_id, &missing).await, Err(AccountOwnerManageError::UserNotFound)));
+
+		let token = mgr.issue_login_token(&admin_id, &user_id).await.unwrap();
+		let session = mgr.login_with_token(&token).await.unwrap();
+		assert_eq!(mgr.retrieve_account(&session, SessionRetrievalPurpose::Other).await.unwrap(), user_id);
+		assert!(matches!(mgr.login_with_token(&token).await, Err(AccountLoginError::InvalidLoginToken)));
+
+		// rotating keeps the session usable, while a password login still needs the reset
+		let session = mgr.rotate_session(&session).await.unwrap();
+		assert_eq!(mgr.retrieve_account(&session, SessionRetrievalPurpose::Other).await.unwrap(), user_id);
+		let password_session = mgr.login("u1", &password).await.unwrap();
+		assert!(matches!(mgr.retrieve_account(&password_session, SessionRetrievalPurpose::Other).await, Err(SessionRetrievalError::InvalidPurpose)));
+
+		// a token is valid up to its expiry
+		let token = mgr.issue_login_token(&admin_id, &user_id).await.unwrap();
+		let expired = mgr.issue_login_token(&admin_id, &user_id).await.unwrap();
+		clock.advance(Duration::from_secs(10 * 60) - Duration::from_micros(1));
+		mgr.login_with_token(&token).await.unwrap();
+		clock.advance(Duration::from_micros(1));
+		assert!(matches!(mgr.login_with_token(&expired).await, Err(AccountLoginError::InvalidLoginToken)));
+
+		let never_issued = SessionToken::new([0; 32]).to_hex();
+		for invalid in ["", "not hex", never_issued.as_str()] {
+			assert!(matches!(mgr.login_with_token(invalid).await, Err(AccountLoginError::InvalidLoginToken)), "{}", invalid);
+		}
+	}
+}
\ No newline at end of file
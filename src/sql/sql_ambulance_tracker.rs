@@ -1,79 +1,383 @@
-use crate::data::{Ambulance, AmbulanceTracker, AmbulanceTrackerError};
+use crate::data::{normalize_ambulance_name, Ambulance, AmbulanceId, AmbulanceReader, AmbulanceTracker, AmbulanceTrackerError, Clock, Cursor, MAX_AMBULANCE_NAME_LENGTH, SystemClock, UpdateOutcome};
+use crate::sql::acquire_retry::{acquire, AcquireRetry};
+use crate::sql::coordinate_precision::round_point;
+use crate::sql::events::{emit, BackendEvent};
+use crate::sql::geometry_conversion::decode_point;
+use crate::sql::schema::Schema;
+use crate::sql::statement_timeout::{begin_with_timeout, is_statement_timeout, StatementTimeouts};
+use futures::{Stream, StreamExt, TryStreamExt};
 use geo_types::{Geometry, Point};
 use geozero::wkb;
 use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::types::Uuid;
-use sqlx::PgPool;
+use sqlx::pool::PoolConnection;
+use sqlx::{PgPool, Postgres};
+use std::borrow::Cow;
 use std::error::Error;
+use std::io::Write;
+use std::sync::Arc;
 use std::time::Duration;
 
-pub struct SQLAmbulanceTracker(PgPool);
+pub struct SQLAmbulanceTracker {
+	pool: PgPool,
+	max_clock_skew: Duration,
+	/// Decimal places stored locations are rounded to
+	coordinate_precision: Option<u32>,
+	max_recent_window: Duration,
+	clock: Arc<dyn Clock>,
+	acquire_retry: Option<AcquireRetry>,
+	/// Meters per second above which an update is rejected as implausible
+	max_speed: Option<f64>,
+	statement_timeouts: StatementTimeouts,
+	schema: Schema
+}
+
+/// The default amount a fetched timestamp may be ahead of the server's clock
+pub const DEFAULT_MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// The default largest window which may be requested of the recently updated queries
+pub const DEFAULT_MAX_RECENT_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How many ambulances [SQLAmbulanceTracker::stream_recently_updated] reads per statement
+pub const STREAM_BATCH_SIZE: i64 = 500;
+
+/// The formats [SQLAmbulanceTracker::export_recently_updated] can write
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+	/// Comma separated values with a `lon,lat,name,last_update` header row
+	Csv,
+	/// One JSON object per line with `lon`, `lat`, `name` and `last_update` fields
+	Ndjson
+}
+
+type AmbulanceRow = (Uuid, Option<String>, wkb::Decode<Geometry>, DateTime<Utc>);
+
+/// Converts a row of `ambulance_id, ambulance_name, location, last_update` into an ambulance
+fn ambulance_from_row((id, name, location, last_updated): AmbulanceRow) -> Result<Ambulance, AmbulanceTrackerError> {
+	Ok(Ambulance {
+		id: AmbulanceId(id),
+		name: name.unwrap_or(id.to_string()),
+		location: decode_point(location.geometry).map_err(|_| AmbulanceTrackerError::InvalidGeometry)?,
+		last_updated
+	})
+}
+
+/// Quotes a CSV field if it contains a separator, quote or line break
+fn csv_field(field: &str) -> Cow<'_, str> {
+	if field.contains([',', '"', '\n', '\r']) {
+		Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+	} else {
+		Cow::Borrowed(field)
+	}
+}
+
+/// Maps a query error, distinguishing statements cancelled by the statement timeout
+fn query_error(e: sqlx::Error) -> AmbulanceTrackerError {
+	if is_statement_timeout(&e) {
+		AmbulanceTrackerError::Timeout
+	} else {
+		AmbulanceTrackerError::Other(e.into())
+	}
+}
+
+/// Reads the name and location of a GeoJSON feature to import, describing why if it cannot be
+/// imported
+fn parse_feature(feature: &serde_json::Value) -> Result<(&str, Point), String> {
+	if feature["type"] != "Feature" {
+		return Err("not a Feature".to_string());
+	}
+	let geometry = &feature["geometry"];
+	if geometry["type"] != "Point" {
+		return Err("the geometry is not a Point".to_string());
+	}
+	let location = match geometry["coordinates"].as_array().map(|coordinates| coordinates.as_slice()) {
+		Some([x, y, ..]) => match (x.as_f64(), y.as_f64()) {
+			(Some(x), Some(y)) if (-180.0..=180.0).contains(&x) && (-90.0..=90.0).contains(&y) => Point::new(x, y),
+			_ => return Err("the coordinates are not a longitude and latitude within range".to_string())
+		},
+		_ => return Err("the Point does not have two coordinates".to_string())
+	};
+	let name = feature["properties"]["name"].as_str()
+		.ok_or("there is no name property")?;
+	let name = normalize_ambulance_name(name)
+		.ok_or_else(|| format!("the name must not be empty or longer than {} characters", MAX_AMBULANCE_NAME_LENGTH))?;
+	Ok((name, location))
+}
+
+/// Whether a point lies within the WGS84 longitude and latitude ranges and is not exactly (0, 0),
+/// which GPS units commonly report when they have no fix
+fn is_plausible_point(location: &Point) -> bool {
+	location.x().abs() <= 180.0 && location.y().abs() <= 90.0 && (location.x(), location.y()) != (0.0, 0.0)
+}
+
+/// Ensures a point can be meaningfully stored as a location
+fn validate_location(location: &Point) -> Result<(), AmbulanceTrackerError> {
+	if location.x().is_finite() && location.y().is_finite() {
+		Ok(())
+	} else {
+		Err(AmbulanceTrackerError::InvalidGeometry)
+	}
+}
 
 #[async_trait::async_trait]
 impl AmbulanceTracker for SQLAmbulanceTracker {
-	async fn add_ambulance(&self, name: &str, location: Point, fetched: DateTime<Utc>) -> Result<Ambulance, Box<dyn Error>> {
-		let (id,): (Uuid,) =
-			sqlx::query_as("INSERT INTO ambulances(ambulance_name, location, last_update) VALUES ($1, $2, $3) RETURNING ambulance_id;")
-				.bind(name)
-				.bind(wkb::Encode::<Geometry>(location.clone().into()))
-				.bind(fetched)
-				.fetch_one(&self.0)
-				.await?;
+	async fn add_ambulance(&self, name: &str, location: Point, fetched: DateTime<Utc>) -> Result<Ambulance, AmbulanceTrackerError> {
+		let name = normalize_ambulance_name(name).ok_or(AmbulanceTrackerError::InvalidName)?;
+		validate_location(&location)?;
+		let location = round_point(location, self.coordinate_precision);
 
-		Ok(Ambulance {
-			id,
-			name: name.to_string(),
-			location,
-			last_updated: fetched
-		})
+		let mut added = self.insert_ambulances(&[(name.to_string(), location)], fetched).await?;
+		Ok(added.remove(0))
 	}
 
-	async fn update_ambulance(&self, id: Uuid, location: Point, fetched: DateTime<Utc>) -> Result<(), AmbulanceTrackerError> {
-		match
-			sqlx::query_as::<_, (i32,)>("WITH updated AS (UPDATE ambulances SET location=$2, last_update=$3 WHERE ambulance_id=$1 AND last_update<$3 RETURNING 1) SELECT CASE WHEN EXISTS (SELECT 1 FROM ambulances WHERE ambulance_id=$1) THEN 1 ELSE 0 END;")
-				.bind(id)
+	async fn add_ambulance_idempotent(&self, name: &str, location: Point, fetched: DateTime<Utc>, key: &str) -> Result<Ambulance, AmbulanceTrackerError> {
+		let name = normalize_ambulance_name(name).ok_or(AmbulanceTrackerError::InvalidName)?;
+		validate_location(&location)?;
+		let location = round_point(location, self.coordinate_precision);
+
+		let inserted: Option<(Uuid,)> =
+			sqlx::query_as(&self.schema.qualify("INSERT INTO {schema}ambulances(ambulance_name, location, last_update, idempotency_key) VALUES ($1, $2, $3, $4) ON CONFLICT (idempotency_key) DO NOTHING RETURNING ambulance_id;"))
+				.bind(name)
 				.bind(wkb::Encode::<Geometry>(location.into()))
 				.bind(fetched)
-				.fetch_one(&self.0)
+				.bind(key)
+				.fetch_optional(&mut *self.connection().await?)
 				.await
-				.map_err(|e| AmbulanceTrackerError::Other(e.into()))?
-				.0 {
-			1 => Ok(()),
-			0 => Err(AmbulanceTrackerError::AmbulanceNotFound),
-			_ => panic!("invalid sql")
-		}
-	}
-
-	async fn get_recently_updated(&self, last_updated: Duration) -> Result<Vec<Ambulance>, Box<dyn Error>> {
-		let ambulances: Vec<(Uuid, Option<String>, wkb::Decode<Geometry>, DateTime<Utc>)> =
-			sqlx::query_as("SELECT ambulance_id, ambulance_name, location, last_update FROM ambulances WHERE last_update>$1;")
-				.bind(Utc::now() - last_updated)
-				.fetch_all(&self.0)
-				.await?;
-
-		Ok(ambulances.into_iter().map(|(id, name, location, last_updated)| Ambulance {
-			id,
-			name: name.unwrap_or(id.to_string()),
-			// not null column
-			location: location.geometry.unwrap().try_into().unwrap(),
-			last_updated
+				.map_err(|e| AmbulanceTrackerError::Other(e.into()))?;
+
+		if let Some((id,)) = inserted {
+			emit(&self.pool, BackendEvent::new("ambulance", id, "created")).await;
+			return Ok(Ambulance {
+				id: AmbulanceId(id),
+				name: name.to_string(),
+				location,
+				last_updated: fetched
+			});
+		}
+
+		// the key was used before, so the original ambulance is returned instead, unless it was deleted
+		sqlx::query_as::<_, AmbulanceRow>(&self.schema.qualify("SELECT ambulance_id, ambulance_name, location, last_update FROM {schema}ambulances WHERE idempotency_key=$1 AND deleted_at IS NULL;"))
+			.bind(key)
+			.fetch_optional(&mut *self.connection().await?)
+			.await
+			.map_err(|e| AmbulanceTrackerError::Other(e.into()))?
+			.ok_or(AmbulanceTrackerError::AmbulanceNotFound)
+			.and_then(ambulance_from_row)
+	}
+
+	async fn update_ambulance(&self, id: AmbulanceId, location: Point, fetched: DateTime<Utc>) -> Result<(), AmbulanceTrackerError> {
+		self.try_update_ambulance(id, location, fetched).await.map(|_| ())
+	}
+
+	async fn update_ambulance_include_deleted(&self, id: AmbulanceId, location: Point, fetched: DateTime<Utc>) -> Result<(), AmbulanceTrackerError> {
+		self.apply_update(id, location, fetched, true).await.map(|_| ())
+	}
+
+	async fn try_update_ambulance(&self, id: AmbulanceId, location: Point, fetched: DateTime<Utc>) -> Result<bool, AmbulanceTrackerError> {
+		self.apply_update(id, location, fetched, false).await
+	}
+
+	async fn bulk_update_locations(&self, updates: &[(AmbulanceId, Point, DateTime<Utc>)]) -> Result<Vec<UpdateOutcome>, AmbulanceTrackerError> {
+		let latest_allowed = self.clock.now() + self.max_clock_skew;
+		let mut ids = Vec::with_capacity(updates.len());
+		let mut locations = Vec::with_capacity(updates.len());
+		let mut fetched_times = Vec::with_capacity(updates.len());
+		for (id, location, fetched) in updates {
+			validate_location(location)?;
+			if *fetched > latest_allowed {
+				return Err(AmbulanceTrackerError::TimestampInFuture);
+			}
+			let location = round_point(*location, self.coordinate_precision);
+			ids.push(id.0);
+			locations.push(wkb::Encode::<Geometry>(location.into()));
+			fetched_times.push(*fetched);
+		}
+
+		// only the newest update of each ambulance is applied, and the outer select sees the table as
+		// it was before the update, so existence is unaffected
+		let mut conn = self.connection().await?;
+		let mut tx = begin_with_timeout(&mut conn, self.statement_timeouts.write).await.map_err(query_error)?;
+		let rows = sqlx::query_as::<_, (bool, bool)>(&self.schema.qualify("WITH input AS (SELECT * FROM UNNEST($1::uuid[], $2::geometry[], $3::timestamptz[]) WITH ORDINALITY AS t(ambulance_id, location, fetched, ord)), latest AS (SELECT DISTINCT ON (ambulance_id) ambulance_id, location, fetched, ord FROM input ORDER BY ambulance_id, fetched DESC, ord DESC), updated AS (UPDATE {schema}ambulances SET location=latest.location, last_update=latest.fetched FROM latest WHERE ambulances.ambulance_id=latest.ambulance_id AND ambulances.last_update<latest.fetched AND ambulances.deleted_at IS NULL RETURNING latest.ord) SELECT EXISTS (SELECT 1 FROM {schema}ambulances WHERE ambulance_id=input.ambulance_id AND deleted_at IS NULL), EXISTS (SELECT 1 FROM updated WHERE updated.ord=input.ord) FROM input ORDER BY input.ord;"))
+			.bind(ids)
+			.bind(locations)
+			.bind(fetched_times)
+			.fetch_all(&mut *tx)
+			.await
+			.map_err(query_error)?;
+		tx.commit().await.map_err(query_error)?;
+
+		Ok(rows.into_iter().map(|row| match row {
+			(true, true) => UpdateOutcome::Applied,
+			(true, false) => UpdateOutcome::Skipped,
+			(false, _) => UpdateOutcome::NotFound
 		}).collect())
 	}
 
-	async fn get_ambulance(&self, id: Uuid) -> Result<Option<Ambulance>, Box<dyn Error>> {
-		let ambulance: Option<(Uuid, Option<String>, wkb::Decode<Geometry>, DateTime<Utc>)> =
-			sqlx::query_as("SELECT ambulance_id, ambulance_name, location, last_update FROM ambulances WHERE ambulance_id=$1")
-				.bind(id)
-				.fetch_optional(&self.0)
-				.await?;
+	async fn soft_delete_ambulance(&self, id: AmbulanceId) -> Result<(), AmbulanceTrackerError> {
+		let deleted = sqlx::query(&self.schema.qualify("UPDATE {schema}ambulances SET deleted_at=$2 WHERE ambulance_id=$1 AND deleted_at IS NULL;"))
+			.bind(id.0)
+			.bind(self.clock.now())
+			.execute(&mut *self.connection().await?)
+			.await
+			.map_err(|e| AmbulanceTrackerError::Other(e.into()))?
+			.rows_affected();
+		if deleted == 0 {
+			return Err(AmbulanceTrackerError::AmbulanceNotFound);
+		}
 
-		Ok(ambulance.map(|(id, name, location, last_updated)| Ambulance {
-			id,
-			name: name.unwrap_or(id.to_string()),
-			// not null column
-			location: location.geometry.unwrap().try_into().unwrap(),
-			last_updated
-		}))
+		emit(&self.pool, BackendEvent::new("ambulance", id.0, "deleted")).await;
+		Ok(())
+	}
+
+	async fn restore_ambulance(&self, id: AmbulanceId) -> Result<(), AmbulanceTrackerError> {
+		// the previous value is read from the locked row so that only an actual restore is announced
+		let (was_deleted,): (bool,) =
+			sqlx::query_as(&self.schema.qualify("WITH current AS (SELECT deleted_at IS NOT NULL AS deleted FROM {schema}ambulances WHERE ambulance_id=$1 FOR UPDATE), restored AS (UPDATE {schema}ambulances SET deleted_at=NULL WHERE ambulance_id=$1 AND deleted_at IS NOT NULL RETURNING 1) SELECT deleted FROM current;"))
+				.bind(id.0)
+				.fetch_optional(&mut *self.connection().await?)
+				.await
+				.map_err(|e| AmbulanceTrackerError::Other(e.into()))?
+				.ok_or(AmbulanceTrackerError::AmbulanceNotFound)?;
+
+		if was_deleted {
+			emit(&self.pool, BackendEvent::new("ambulance", id.0, "restored")).await;
+		}
+		Ok(())
+	}
+}
+
+#[async_trait::async_trait]
+impl AmbulanceReader for SQLAmbulanceTracker {
+	async fn get_recently_updated(&self, last_updated: Duration) -> Result<Vec<Ambulance>, AmbulanceTrackerError> {
+		let (from, to) = self.recent_window(last_updated)?;
+		self.get_updated_between(from, to).await
+	}
+
+	async fn get_recently_updated_page(&self, last_updated: Duration, after: Option<Cursor>, limit: i64) -> Result<Vec<Ambulance>, AmbulanceTrackerError> {
+		let (from, to) = self.recent_window(last_updated)?;
+		if limit <= 0 {
+			return Ok(Vec::new());
+		}
+
+		// without a previous entry, start from just past the newest possible key
+		let (after_time, after_id) = match after {
+			Some(cursor) => (cursor.time, cursor.id),
+			None => (to, Uuid::max())
+		};
+
+		let mut conn = self.connection().await?;
+		let mut tx = begin_with_timeout(&mut conn, self.statement_timeouts.read).await.map_err(query_error)?;
+		let rows = sqlx::query_as::<_, AmbulanceRow>(&self.schema.qualify("SELECT ambulance_id, ambulance_name, location, last_update FROM {schema}ambulances WHERE last_update BETWEEN $1 AND $2 AND (last_update, ambulance_id) < ($3, $4) AND deleted_at IS NULL ORDER BY last_update DESC, ambulance_id DESC LIMIT $5;"))
+			.bind(from)
+			.bind(to)
+			.bind(after_time)
+			.bind(after_id)
+			.bind(limit)
+			.fetch_all(&mut *tx)
+			.await
+			.map_err(query_error)?;
+		tx.commit().await.map_err(query_error)?;
+		rows.into_iter().map(ambulance_from_row).collect()
+	}
+
+	async fn get_updated_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Ambulance>, AmbulanceTrackerError> {
+		if from > to {
+			return Err(AmbulanceTrackerError::InvalidTimeRange);
+		}
+
+		let mut conn = self.connection().await?;
+		let mut tx = begin_with_timeout(&mut conn, self.statement_timeouts.read).await.map_err(query_error)?;
+		let rows = sqlx::query_as::<_, AmbulanceRow>(&self.schema.qualify("SELECT ambulance_id, ambulance_name, location, last_update FROM {schema}ambulances WHERE last_update BETWEEN $1 AND $2 AND deleted_at IS NULL;"))
+			.bind(from)
+			.bind(to)
+			.fetch_all(&mut *tx)
+			.await
+			.map_err(query_error)?;
+		tx.commit().await.map_err(query_error)?;
+		rows.into_iter().map(ambulance_from_row).collect()
+	}
+
+	async fn count_recently_updated(&self, last_updated: Duration) -> Result<i64, AmbulanceTrackerError> {
+		let (from, to) = self.recent_window(last_updated)?;
+		let mut conn = self.connection().await?;
+		let mut tx = begin_with_timeout(&mut conn, self.statement_timeouts.read).await.map_err(query_error)?;
+		let (count,): (i64,) =
+			sqlx::query_as(&self.schema.qualify("SELECT COUNT(*) FROM {schema}ambulances WHERE last_update BETWEEN $1 AND $2 AND deleted_at IS NULL;"))
+				.bind(from)
+				.bind(to)
+				.fetch_one(&mut *tx)
+				.await
+				.map_err(query_error)?;
+		tx.commit().await.map_err(query_error)?;
+		Ok(count)
+	}
+
+	async fn count_ambulances(&self) -> Result<i64, AmbulanceTrackerError> {
+		let mut conn = self.connection().await?;
+		let mut tx = begin_with_timeout(&mut conn, self.statement_timeouts.read).await.map_err(query_error)?;
+		let (count,): (i64,) =
+			sqlx::query_as(&self.schema.qualify("SELECT COUNT(*) FROM {schema}ambulances WHERE deleted_at IS NULL;"))
+				.fetch_one(&mut *tx)
+				.await
+				.map_err(query_error)?;
+		tx.commit().await.map_err(query_error)?;
+		Ok(count)
+	}
+
+	async fn get_ambulance(&self, id: AmbulanceId) -> Result<Option<Ambulance>, AmbulanceTrackerError> {
+		sqlx::query_as::<_, AmbulanceRow>(&self.schema.qualify("SELECT ambulance_id, ambulance_name, location, last_update FROM {schema}ambulances WHERE ambulance_id=$1 AND deleted_at IS NULL"))
+			.bind(id.0)
+			.fetch_optional(&mut *self.connection().await?)
+			.await
+			.map_err(|e| AmbulanceTrackerError::Other(e.into()))?
+			.map(ambulance_from_row)
+			.transpose()
+	}
+
+	async fn ambulance_exists(&self, id: AmbulanceId) -> Result<bool, AmbulanceTrackerError> {
+		let (exists,): (bool,) = sqlx::query_as(&self.schema.qualify("SELECT EXISTS(SELECT 1 FROM {schema}ambulances WHERE ambulance_id=$1 AND deleted_at IS NULL);"))
+			.bind(id.0)
+			.fetch_one(&mut *self.connection().await?)
+			.await
+			.map_err(|e| AmbulanceTrackerError::Other(e.into()))?;
+		Ok(exists)
+	}
+
+	async fn search_by_name(&self, query: &str, limit: i64) -> Result<Vec<Ambulance>, AmbulanceTrackerError> {
+		let query = query.trim();
+		if query.is_empty() || limit <= 0 {
+			return Ok(Vec::new());
+		}
+
+		// wildcards in the query are matched literally
+		let pattern = format!("%{}%", query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+
+		let mut conn = self.connection().await?;
+		let mut tx = begin_with_timeout(&mut conn, self.statement_timeouts.read).await.map_err(query_error)?;
+		let rows = sqlx::query_as::<_, AmbulanceRow>(&self.schema.qualify("SELECT ambulance_id, ambulance_name, location, last_update FROM {schema}ambulances WHERE ambulance_name ILIKE $1 AND deleted_at IS NULL ORDER BY ambulance_name LIMIT $2;"))
+			.bind(pattern)
+			.bind(limit)
+			.fetch_all(&mut *tx)
+			.await
+			.map_err(query_error)?;
+		tx.commit().await.map_err(query_error)?;
+		rows.into_iter().map(ambulance_from_row).collect()
+	}
+
+	async fn get_ambulances_by_staleness(&self, limit: i64) -> Result<Vec<Ambulance>, AmbulanceTrackerError> {
+		if limit <= 0 {
+			return Ok(Vec::new());
+		}
+
+		let mut conn = self.connection().await?;
+		let mut tx = begin_with_timeout(&mut conn, self.statement_timeouts.read).await.map_err(query_error)?;
+		let rows = sqlx::query_as::<_, AmbulanceRow>(&self.schema.qualify("SELECT ambulance_id, ambulance_name, location, last_update FROM {schema}ambulances WHERE deleted_at IS NULL ORDER BY last_update ASC, ambulance_id ASC LIMIT $1;"))
+			.bind(limit)
+			.fetch_all(&mut *tx)
+			.await
+			.map_err(query_error)?;
+		tx.commit().await.map_err(query_error)?;
+		rows.into_iter().map(ambulance_from_row).collect()
 	}
 }
 
@@ -81,15 +385,262 @@ impl SQLAmbulanceTracker {
 	/// Creates a new AmbulanceTracker using the specified connection as the backend.
 	/// It is expected that the migrations file has been executed already.
 	pub fn new(pool: PgPool) -> Self {
-		Self(pool)
+		Self {
+			pool,
+			max_clock_skew: DEFAULT_MAX_CLOCK_SKEW,
+			coordinate_precision: None,
+			max_recent_window: DEFAULT_MAX_RECENT_WINDOW,
+			clock: Arc::new(SystemClock),
+			acquire_retry: None,
+			max_speed: None,
+			statement_timeouts: StatementTimeouts::default(),
+			schema: Schema::default()
+		}
+	}
+
+	/// Sets the largest window the recently updated queries accept before failing with
+	/// [AmbulanceTrackerError::WindowTooLarge]
+	pub fn with_max_recent_window(mut self, max_recent_window: Duration) -> Self {
+		self.max_recent_window = max_recent_window;
+		self
+	}
+
+	/// Returns the time range covered by a recently updated query, rejecting oversized windows.
+	/// Updates may be ahead of the clock by up to the allowed skew.
+	fn recent_window(&self, last_updated: Duration) -> Result<(DateTime<Utc>, DateTime<Utc>), AmbulanceTrackerError> {
+		if last_updated > self.max_recent_window {
+			return Err(AmbulanceTrackerError::WindowTooLarge);
+		}
+		let now = self.clock.now();
+		Ok((now - last_updated, now + self.max_clock_skew))
+	}
+
+	/// Streams the ambulances which [AmbulanceReader::get_recently_updated] would return, most
+	/// recently updated first, without collecting them all first. The window is checked before the
+	/// first query starts.
+	///
+	/// The rows are read in batches of [STREAM_BATCH_SIZE], each in its own statement bounded by
+	/// the read timeout, so an ambulance updated while streaming may be skipped or not yet moved.
+	pub fn stream_recently_updated(&self, last_updated: Duration) -> Result<impl Stream<Item = Result<Ambulance, AmbulanceTrackerError>> + Send + '_, AmbulanceTrackerError> {
+		let (from, to) = self.recent_window(last_updated)?;
+
+		// each batch continues after the last row of the previous one, as the pages do
+		let batches = futures::stream::try_unfold(Some((to, Uuid::max())), move |after| async move {
+			let Some((after_time, after_id)) = after else {
+				return Ok(None);
+			};
+
+			let mut conn = self.connection().await?;
+			let mut tx = begin_with_timeout(&mut conn, self.statement_timeouts.read).await.map_err(query_error)?;
+			let rows = sqlx::query_as::<_, AmbulanceRow>(&self.schema.qualify("SELECT ambulance_id, ambulance_name, location, last_update FROM {schema}ambulances WHERE last_update BETWEEN $1 AND $2 AND (last_update, ambulance_id) < ($3, $4) AND deleted_at IS NULL ORDER BY last_update DESC, ambulance_id DESC LIMIT $5;"))
+				.bind(from)
+				.bind(to)
+				.bind(after_time)
+				.bind(after_id)
+				.bind(STREAM_BATCH_SIZE)
+				.fetch_all(&mut *tx)
+				.await
+				.map_err(query_error)?;
+			tx.commit().await.map_err(query_error)?;
+
+			let next = match rows.last() {
+				Some((id, _, _, last_update)) if rows.len() as i64 == STREAM_BATCH_SIZE => Some((*last_update, *id)),
+				_ => None
+			};
+			Ok(Some((rows, next)))
+		});
+
+		Ok(batches
+			.map_ok(|rows| futures::stream::iter(rows.into_iter().map(ambulance_from_row)))
+			.try_flatten())
+	}
+
+	/// Counts the distinct ambulances which reported a location between `from` and `to` inclusive,
+	/// excluding soft deleted ones. Each ambulance only stores its latest update, so earlier updates
+	/// are only seen if they were copied to `archive_ambulance_locations`.
+	pub async fn distinct_active_ambulances(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<i64, Box<dyn Error>> {
+		if from > to {
+			return Err(AmbulanceTrackerError::InvalidTimeRange.into());
+		}
+
+		let mut conn = self.connection().await?;
+		let mut tx = begin_with_timeout(&mut conn, self.statement_timeouts.read).await.map_err(query_error)?;
+		let (count,): (i64,) =
+			sqlx::query_as(&self.schema.qualify("SELECT COUNT(DISTINCT ambulance_id) FROM (SELECT ambulance_id FROM {schema}ambulances WHERE last_update BETWEEN $1 AND $2 AND deleted_at IS NULL UNION ALL SELECT ambulance_id FROM {schema}archive_ambulance_locations WHERE time BETWEEN $1 AND $2 AND ambulance_id IN (SELECT ambulance_id FROM {schema}ambulances WHERE deleted_at IS NULL)) AS updates;"))
+				.bind(from)
+				.bind(to)
+				.fetch_one(&mut *tx)
+				.await
+				.map_err(query_error)?;
+		tx.commit().await.map_err(query_error)?;
+		Ok(count)
+	}
+
+	/// Writes the ambulances which [Self::stream_recently_updated] would return to the writer as they
+	/// arrive, giving each location as longitude then latitude and the last update in RFC 3339.
+	pub async fn export_recently_updated(&self, last_updated: Duration, format: ExportFormat, mut writer: impl Write) -> Result<(), Box<dyn Error>> {
+		let mut ambulances = self.stream_recently_updated(last_updated)?;
+
+		if format == ExportFormat::Csv {
+			writeln!(writer, "lon,lat,name,last_update")?;
+		}
+		while let Some(ambulance) = ambulances.next().await {
+			let ambulance = ambulance?;
+			let last_update = ambulance.last_updated.to_rfc3339();
+			match format {
+				ExportFormat::Csv => writeln!(writer, "{},{},{},{}", ambulance.location.x(), ambulance.location.y(), csv_field(&ambulance.name), last_update)?,
+				ExportFormat::Ndjson => writeln!(writer, "{}", serde_json::json!({
+					"lon": ambulance.location.x(),
+					"lat": ambulance.location.y(),
+					"name": ambulance.name,
+					"last_update": last_update
+				}))?
+			}
+		}
+		writer.flush()?;
+		Ok(())
+	}
+
+	/// Adds an ambulance for each feature of a GeoJSON FeatureCollection, such as a fleet's initial
+	/// positions, all in a single statement. Each feature must have a Point geometry within the
+	/// WGS84 ranges and a `name` property following the rules of [AmbulanceTracker::add_ambulance].
+	/// The ambulances are recorded as updated now.
+	///
+	/// Nothing is added if any feature is malformed, and the error names the first such feature.
+	pub async fn import_ambulances_geojson(&self, geojson: &str) -> Result<Vec<Ambulance>, Box<dyn Error>> {
+		let collection: serde_json::Value = serde_json::from_str(geojson)?;
+		if collection["type"] != "FeatureCollection" {
+			return Err("expected a GeoJSON FeatureCollection".into());
+		}
+		let features = collection["features"].as_array().ok_or("the FeatureCollection has no features array")?;
+
+		let mut ambulances = Vec::with_capacity(features.len());
+		for (i, feature) in features.iter().enumerate() {
+			let (name, location) = parse_feature(feature).map_err(|reason| format!("feature {}: {}", i, reason))?;
+			ambulances.push((name.to_string(), round_point(location, self.coordinate_precision)));
+		}
+
+		Ok(self.insert_ambulances(&ambulances, self.clock.now()).await?)
+	}
+
+	/// Adds the named ambulances at their locations in a single statement, so either all or none
+	/// are added. The names and locations must already be validated.
+	async fn insert_ambulances(&self, ambulances: &[(String, Point)], fetched: DateTime<Utc>) -> Result<Vec<Ambulance>, AmbulanceTrackerError> {
+		let names: Vec<&str> = ambulances.iter().map(|(name, _)| name.as_str()).collect();
+		let locations: Vec<wkb::Encode<Geometry>> = ambulances.iter().map(|(_, location)| wkb::Encode((*location).into())).collect();
+
+		let added = sqlx::query_as::<_, AmbulanceRow>(&self.schema.qualify("INSERT INTO {schema}ambulances(ambulance_name, location, last_update) SELECT name, location, $3 FROM UNNEST($1::text[], $2::geometry[]) WITH ORDINALITY AS t(name, location, ord) ORDER BY ord RETURNING ambulance_id, ambulance_name, location, last_update;"))
+			.bind(names)
+			.bind(locations)
+			.bind(fetched)
+			.fetch_all(&mut *self.connection().await?)
+			.await
+			.map_err(|e| AmbulanceTrackerError::Other(e.into()))?
+			.into_iter()
+			.map(ambulance_from_row)
+			.collect::<Result<Vec<_>, _>>()?;
+
+		for ambulance in &added {
+			emit(&self.pool, BackendEvent::new("ambulance", ambulance.id.0, "created")).await;
+		}
+		Ok(added)
+	}
+
+	/// Sets how far ahead of the server's clock a fetched timestamp may be before an update is
+	/// rejected.
+	pub fn with_max_clock_skew(mut self, max_clock_skew: Duration) -> Self {
+		self.max_clock_skew = max_clock_skew;
+		self
+	}
+
+	/// Sets the number of decimal places locations are rounded to before being stored. By default
+	/// locations are stored at full precision.
+	pub fn with_coordinate_precision(mut self, decimal_places: u32) -> Self {
+		self.coordinate_precision = Some(decimal_places);
+		self
+	}
+
+	/// Reads the current time from the clock rather than the system clock
+	pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+		self.clock = clock;
+		self
+	}
+
+	/// Retries acquiring a connection when the pool is exhausted, rather than failing with
+	/// [AmbulanceTrackerError::Other] as soon as acquiring times out.
+	pub fn with_acquire_retry(mut self, retry: AcquireRetry) -> Self {
+		self.acquire_retry = Some(retry);
+		self
+	}
+
+	/// Rejects location updates which are likely GPS glitches with
+	/// [AmbulanceTrackerError::ImplausibleLocation]: points outside the WGS84 range, exactly (0, 0),
+	/// or further from the previous location than the ambulance could have travelled at
+	/// `max_speed` meters per second since its last update. Only individual updates are checked,
+	/// not [AmbulanceTracker::bulk_update_locations].
+	pub fn with_plausibility_check(mut self, max_speed: f64) -> Self {
+		self.max_speed = Some(max_speed);
+		self
+	}
+
+	/// Cancels statements which run longer than the timeouts, failing with
+	/// [AmbulanceTrackerError::Timeout]. The read timeout bounds the listing and counting queries
+	/// and each batch of [Self::stream_recently_updated], and the write timeout bounds
+	/// [AmbulanceTracker::bulk_update_locations]. Other methods only touch single rows and are not
+	/// bounded.
+	pub fn with_statement_timeouts(mut self, timeouts: StatementTimeouts) -> Self {
+		self.statement_timeouts = timeouts;
+		self
+	}
+
+	/// Uses the tables in the given Postgres schema, as
+	/// [crate::sql::sql_account_manager::SqlAccountManager::with_schema] does
+	pub fn with_schema(mut self, schema: &str) -> Self {
+		self.schema = Schema::named(schema);
+		self
+	}
+
+	/// Updates the location as [AmbulanceTracker::try_update_ambulance] does, treating soft deleted
+	/// ambulances as missing unless `include_deleted` is set
+	async fn apply_update(&self, id: AmbulanceId, location: Point, fetched: DateTime<Utc>, include_deleted: bool) -> Result<bool, AmbulanceTrackerError> {
+		validate_location(&location)?;
+		if fetched > self.clock.now() + self.max_clock_skew {
+			return Err(AmbulanceTrackerError::TimestampInFuture);
+		}
+		if self.max_speed.is_some() && !is_plausible_point(&location) {
+			return Err(AmbulanceTrackerError::ImplausibleLocation);
+		}
+		let location = round_point(location, self.coordinate_precision);
+
+		// the previous location is locked and compared against in the same statement, and the outer
+		// select sees the table as it was before the update, so existence is unaffected
+		match
+			sqlx::query_as::<_, (bool, bool, bool)>(&self.schema.qualify("WITH current AS (SELECT last_update<$3 AS newer, ($4::float8 IS NOT NULL AND ST_DistanceSphere(location, ST_SetSRID($2, 4326)) > $4 * EXTRACT(EPOCH FROM ($3 - last_update))) AS implausible FROM {schema}ambulances WHERE ambulance_id=$1 AND ($5 OR deleted_at IS NULL) FOR UPDATE), updated AS (UPDATE {schema}ambulances SET location=$2, last_update=$3 FROM current WHERE ambulance_id=$1 AND current.newer AND NOT current.implausible RETURNING 1) SELECT EXISTS (SELECT 1 FROM current), COALESCE((SELECT newer AND implausible FROM current), false), EXISTS (SELECT 1 FROM updated);"))
+				.bind(id.0)
+				.bind(wkb::Encode::<Geometry>(location.into()))
+				.bind(fetched)
+				.bind(self.max_speed)
+				.bind(include_deleted)
+				.fetch_one(&mut *self.connection().await?)
+				.await
+				.map_err(|e| AmbulanceTrackerError::Other(e.into()))? {
+			(true, true, _) => Err(AmbulanceTrackerError::ImplausibleLocation),
+			(true, false, applied) => Ok(applied),
+			(false, _, _) => Err(AmbulanceTrackerError::AmbulanceNotFound)
+		}
+	}
+
+	async fn connection(&self) -> Result<PoolConnection<Postgres>, AmbulanceTrackerError> {
+		acquire(&self.pool, self.acquire_retry).await.map_err(|e| AmbulanceTrackerError::Other(e.into()))
 	}
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use crate::data::ReadOnlyAmbulanceTracker;
 	use geo_types::Point;
-	use sqlx::types::chrono::Utc;
+	use sqlx::types::chrono::{SubsecRound, Utc};
 	use std::str::FromStr;
 
 	fn get_tracker(pool: PgPool) -> SQLAmbulanceTracker {
@@ -166,7 +717,7 @@ mod tests {
 		assert_eq!(updated_ambulance.location, same_location);
 
 		// Test Case 7: Update a non-existing ambulance
-		let invalid_id = Uuid::from_str("22200000-0000-0000-0000-000000000001").unwrap();
+		let invalid_id = AmbulanceId(Uuid::from_str("22200000-0000-0000-0000-000000000001").unwrap());
 		let new_location = Point::new(4.0, 4.0).into();
 		let result = tracker.update_ambulance(invalid_id, new_location, Utc::now()).await;
 		assert!(matches!(result, Err(AmbulanceTrackerError::AmbulanceNotFound)));
@@ -184,7 +735,7 @@ mod tests {
 	impl From<Ambulance> for SortAmb {
 		fn from(value: Ambulance) -> Self {
 			let pt: Point = value.location.try_into().unwrap();
-			Self(value.id, value.name, pt.0.x, pt.0.y, CloseEnoughDateTime(value.last_updated))
+			Self(value.id.0, value.name, pt.0.x, pt.0.y, CloseEnoughDateTime(value.last_updated))
 		}
 	}
 
@@ -220,6 +771,123 @@ mod tests {
 		assert_eq!(ambulances, vec![a2]);
 	}
 
+	#[sqlx::test]
+	async fn test_recent_window_limit(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool.clone());
+		tracker.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), Utc::now()).await.unwrap();
+
+		assert_eq!(tracker.get_recently_updated(DEFAULT_MAX_RECENT_WINDOW).await.unwrap().len(), 1);
+		let too_large = DEFAULT_MAX_RECENT_WINDOW + Duration::from_secs(1);
+		assert!(matches!(tracker.get_recently_updated(too_large).await, Err(AmbulanceTrackerError::WindowTooLarge)));
+		assert!(matches!(tracker.count_recently_updated(too_large).await, Err(AmbulanceTrackerError::WindowTooLarge)));
+		assert!(matches!(tracker.get_recently_updated_page(too_large, None, 10).await, Err(AmbulanceTrackerError::WindowTooLarge)));
+
+		let tracker = get_tracker(pg_pool).with_max_recent_window(Duration::from_secs(60));
+		assert!(matches!(tracker.get_recently_updated(Duration::from_secs(120)).await, Err(AmbulanceTrackerError::WindowTooLarge)));
+		assert_eq!(tracker.count_recently_updated(Duration::from_secs(60)).await.unwrap(), 1);
+	}
+
+	#[sqlx::test]
+	async fn test_recently_updated_window_boundary(pg_pool: PgPool) {
+		use crate::data::ManualClock;
+		use sqlx::types::chrono::SubsecRound;
+
+		let now = Utc::now().trunc_subsecs(0);
+		let clock = Arc::new(ManualClock::new(now));
+		let tracker = get_tracker(pg_pool).with_max_clock_skew(Duration::from_secs(60)).with_clock(clock.clone());
+
+		let ambulance = tracker.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), now - Duration::from_secs(10 * 60)).await.unwrap();
+
+		// the window is inclusive of its start
+		assert_eq!(tracker.get_recently_updated(Duration::from_secs(10 * 60)).await.unwrap().len(), 1);
+		clock.advance(Duration::from_micros(1));
+		assert!(tracker.get_recently_updated(Duration::from_secs(10 * 60)).await.unwrap().is_empty());
+		assert_eq!(tracker.count_recently_updated(Duration::from_secs(10 * 60)).await.unwrap(), 0);
+
+		// and of the allowed skew ahead of the clock
+		clock.set(now);
+		tracker.update_ambulance(ambulance.id, Point::new(1.0, 1.0), now + Duration::from_secs(60)).await.unwrap();
+		assert_eq!(tracker.get_recently_updated(Duration::ZERO).await.unwrap().len(), 1);
+		assert!(matches!(tracker.update_ambulance(ambulance.id, Point::new(1.0, 1.0), now + Duration::from_secs(60) + Duration::from_micros(1)).await, Err(AmbulanceTrackerError::TimestampInFuture)));
+	}
+
+	#[sqlx::test]
+	async fn test_recently_updated_excludes_future_updates(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool.clone()).with_max_clock_skew(Duration::from_secs(60));
+		let ambulance = tracker.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), Utc::now()).await.unwrap();
+		assert_eq!(tracker.get_recently_updated(Duration::from_secs(60)).await.unwrap().len(), 1);
+
+		// written past the tracker, further ahead than the allowed skew
+		sqlx::query("UPDATE ambulances SET last_update=$1 WHERE ambulance_id=$2;")
+			.bind(Utc::now() + Duration::from_secs(10 * 60))
+			.bind(ambulance.id.0)
+			.execute(&pg_pool)
+			.await
+			.unwrap();
+		assert!(tracker.get_recently_updated(Duration::from_secs(60)).await.unwrap().is_empty());
+		assert_eq!(tracker.count_recently_updated(Duration::from_secs(60)).await.unwrap(), 0);
+	}
+
+	#[sqlx::test]
+	async fn test_get_recently_updated_page(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+
+		let now = Utc::now();
+		let mut expected = Vec::new();
+		for i in 0..5 {
+			let fetched = now - Duration::from_secs(60 * i);
+			expected.push(tracker.add_ambulance(&format!("Ambulance {}", i), Point::new(0.0, 0.0), fetched).await.unwrap().id);
+		}
+		// shares a timestamp with the first, so ties are broken by id
+		expected.insert(1, tracker.add_ambulance("Ambulance 5", Point::new(0.0, 0.0), now).await.unwrap().id);
+		if expected[0].0 < expected[1].0 {
+			expected.swap(0, 1);
+		}
+		tracker.add_ambulance("Old", Point::new(0.0, 0.0), now - Duration::from_secs(60 * 60)).await.unwrap();
+
+		let mut pages = Vec::new();
+		let mut after = None;
+		loop {
+			let page = tracker.get_recently_updated_page(Duration::from_secs(30 * 60), after, 4).await.unwrap();
+			if page.is_empty() {
+				break;
+			}
+			// callers only ever see the cursor as a string
+			after = page.last().map(|a| Cursor::from(a).to_string().parse().unwrap());
+			pages.push(page.into_iter().map(|a| a.id).collect::<Vec<_>>());
+		}
+
+		assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), vec![4, 2]);
+		assert_eq!(pages.concat(), expected);
+		assert!(tracker.get_recently_updated_page(Duration::from_secs(30 * 60), None, 0).await.unwrap().is_empty());
+	}
+
+	#[sqlx::test]
+	async fn test_get_updated_between(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+
+		use sqlx::types::chrono::SubsecRound;
+
+		// whole seconds, so that the bounds survive being stored at microsecond precision
+		let start = (Utc::now() - Duration::from_secs(60 * 60)).trunc_subsecs(0);
+		let end = start + Duration::from_secs(120);
+		let before = tracker.add_ambulance("Before", Point::new(0.0, 0.0), start - Duration::from_secs(60)).await.unwrap();
+		let inside = tracker.add_ambulance("Inside", Point::new(1.0, 1.0), start + Duration::from_secs(60)).await.unwrap();
+		let on_end = tracker.add_ambulance("End", Point::new(2.0, 2.0), end).await.unwrap();
+		tracker.add_ambulance("After", Point::new(3.0, 3.0), Utc::now()).await.unwrap();
+
+		let mut ids: Vec<_> = tracker.get_updated_between(start, end).await.unwrap().into_iter().map(|a| a.id.0).collect();
+		ids.sort();
+		let mut expected = vec![inside.id.0, on_end.id.0];
+		expected.sort();
+		assert_eq!(ids, expected);
+
+		let ids: Vec<_> = tracker.get_updated_between(start - Duration::from_secs(120), start).await.unwrap().into_iter().map(|a| a.id).collect();
+		assert_eq!(ids, vec![before.id]);
+
+		assert!(matches!(tracker.get_updated_between(start, start - Duration::from_secs(1)).await, Err(AmbulanceTrackerError::InvalidTimeRange)));
+	}
+
 	#[sqlx::test]
 	async fn test_get_ambulance(pg_pool: PgPool) {
 		let tracker = get_tracker(pg_pool);
@@ -236,7 +904,7 @@ mod tests {
 		assert_eq!(retrieved.name, ambulance.name);
 
 		// Test Case 12: Get a non-existing ambulance
-		let invalid_id = Uuid::from_str("20000000-0000-0000-0000-000000000001").unwrap();
+		let invalid_id = AmbulanceId(Uuid::from_str("20000000-0000-0000-0000-000000000001").unwrap());
 		let retrieved = tracker.get_ambulance(invalid_id).await.unwrap();
 		assert!(retrieved.is_none());
 
@@ -248,4 +916,576 @@ mod tests {
 		let updated_ambulance = tracker.get_ambulance(ambulance.id).await.unwrap().unwrap();
 		assert_eq!(updated_ambulance.location, new_location);
 	}
+
+	#[sqlx::test]
+	async fn test_count_ambulances(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+
+		assert_eq!(tracker.count_ambulances().await.unwrap(), 0);
+		assert_eq!(tracker.count_recently_updated(Duration::from_secs(120)).await.unwrap(), 0);
+
+		tracker.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), Utc::now() - Duration::from_secs(65)).await.unwrap();
+		tracker.add_ambulance("Ambulance 2", Point::new(1.0, 1.0), Utc::now()).await.unwrap();
+		tracker.add_ambulance("Ambulance 3", Point::new(2.0, 2.0), Utc::now()).await.unwrap();
+
+		assert_eq!(tracker.count_ambulances().await.unwrap(), 3);
+		assert_eq!(tracker.count_recently_updated(Duration::from_secs(120)).await.unwrap(), 3);
+		assert_eq!(tracker.count_recently_updated(Duration::from_secs(60)).await.unwrap(), 2);
+		assert_eq!(tracker.count_recently_updated(Duration::from_secs(0)).await.unwrap(), 0);
+
+		// counts must agree with the listing
+		let listed = tracker.get_recently_updated(Duration::from_secs(60)).await.unwrap();
+		assert_eq!(listed.len() as i64, tracker.count_recently_updated(Duration::from_secs(60)).await.unwrap());
+	}
+
+	#[sqlx::test]
+	async fn test_invalid_geometry(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+
+		let result = tracker.add_ambulance("Ambulance 1", Point::new(f64::NAN, 0.0), Utc::now()).await;
+		assert!(matches!(result, Err(AmbulanceTrackerError::InvalidGeometry)));
+
+		let result = tracker.add_ambulance("Ambulance 1", Point::new(0.0, f64::INFINITY), Utc::now()).await;
+		assert!(matches!(result, Err(AmbulanceTrackerError::InvalidGeometry)));
+		assert_eq!(tracker.count_ambulances().await.unwrap(), 0);
+
+		let ambulance = tracker.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), Utc::now()).await.unwrap();
+		let result = tracker.update_ambulance(ambulance.id, Point::new(f64::NAN, f64::NAN), Utc::now() + Duration::from_secs(10)).await;
+		assert!(matches!(result, Err(AmbulanceTrackerError::InvalidGeometry)));
+
+		let retrieved = tracker.get_ambulance(ambulance.id).await.unwrap().unwrap();
+		assert_eq!(retrieved.location, Point::new(0.0, 0.0));
+	}
+
+	#[sqlx::test]
+	async fn test_update_ambulance_future_timestamp(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+
+		let ambulance = tracker.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), Utc::now()).await.unwrap();
+
+		// a bad clock far in the future is rejected
+		let far_future = Utc::now() + Duration::from_secs(60 * 60 * 24);
+		let result = tracker.update_ambulance(ambulance.id, Point::new(1.0, 1.0), far_future).await;
+		assert!(matches!(result, Err(AmbulanceTrackerError::TimestampInFuture)));
+
+		let retrieved = tracker.get_ambulance(ambulance.id).await.unwrap().unwrap();
+		assert_eq!(retrieved.location, Point::new(0.0, 0.0));
+
+		// and does not block a legitimate update afterward
+		let location = Point::new(2.0, 2.0);
+		tracker.update_ambulance(ambulance.id, location, Utc::now() + Duration::from_secs(1)).await.unwrap();
+		let retrieved = tracker.get_ambulance(ambulance.id).await.unwrap().unwrap();
+		assert_eq!(retrieved.location, location);
+
+		// small amounts of skew are tolerated
+		tracker.update_ambulance(ambulance.id, Point::new(3.0, 3.0), Utc::now() + Duration::from_secs(60)).await.unwrap();
+	}
+
+	#[sqlx::test]
+	async fn test_try_update_ambulance(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+
+		let fetched = Utc::now();
+		let ambulance = tracker.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), fetched).await.unwrap();
+
+		// fresh timestamp is applied
+		let applied = tracker.try_update_ambulance(ambulance.id, Point::new(1.0, 1.0), fetched + Duration::from_secs(5)).await.unwrap();
+		assert!(applied);
+
+		// stale timestamp is skipped
+		let applied = tracker.try_update_ambulance(ambulance.id, Point::new(2.0, 2.0), fetched).await.unwrap();
+		assert!(!applied);
+
+		// equal timestamp is also skipped
+		let applied = tracker.try_update_ambulance(ambulance.id, Point::new(2.0, 2.0), fetched + Duration::from_secs(5)).await.unwrap();
+		assert!(!applied);
+
+		let retrieved = tracker.get_ambulance(ambulance.id).await.unwrap().unwrap();
+		assert_eq!(retrieved.location, Point::new(1.0, 1.0));
+
+		let invalid_id = AmbulanceId(Uuid::from_str("22200000-0000-0000-0000-000000000001").unwrap());
+		let result = tracker.try_update_ambulance(invalid_id, Point::new(1.0, 1.0), Utc::now()).await;
+		assert!(matches!(result, Err(AmbulanceTrackerError::AmbulanceNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn test_bulk_update_locations(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+
+		let fetched = Utc::now() - Duration::from_secs(60);
+		let fresh = tracker.add_ambulance("Fresh", Point::new(0.0, 0.0), fetched).await.unwrap();
+		let stale = tracker.add_ambulance("Stale", Point::new(0.0, 0.0), fetched).await.unwrap();
+		let repeated = tracker.add_ambulance("Repeated", Point::new(0.0, 0.0), fetched).await.unwrap();
+		let missing = AmbulanceId(Uuid::from_str("22200000-0000-0000-0000-000000000001").unwrap());
+
+		let outcomes = tracker.bulk_update_locations(&[
+			(fresh.id, Point::new(1.0, 1.0), fetched + Duration::from_secs(10)),
+			(stale.id, Point::new(2.0, 2.0), fetched),
+			(missing, Point::new(3.0, 3.0), fetched + Duration::from_secs(10)),
+			(repeated.id, Point::new(4.0, 4.0), fetched + Duration::from_secs(20)),
+			(repeated.id, Point::new(5.0, 5.0), fetched + Duration::from_secs(10)),
+		]).await.unwrap();
+		assert_eq!(outcomes, vec![UpdateOutcome::Applied, UpdateOutcome::Skipped, UpdateOutcome::NotFound, UpdateOutcome::Applied, UpdateOutcome::Skipped]);
+
+		assert_eq!(tracker.get_ambulance(fresh.id).await.unwrap().unwrap().location, Point::new(1.0, 1.0));
+		assert_eq!(tracker.get_ambulance(stale.id).await.unwrap().unwrap().location, Point::new(0.0, 0.0));
+		let retrieved = tracker.get_ambulance(repeated.id).await.unwrap().unwrap();
+		assert_eq!(retrieved.location, Point::new(4.0, 4.0));
+		assert_eq!(retrieved.last_updated.timestamp_micros(), (fetched + Duration::from_secs(20)).timestamp_micros());
+
+		assert!(tracker.bulk_update_locations(&[]).await.unwrap().is_empty());
+	}
+
+	#[sqlx::test]
+	async fn test_bulk_update_locations_rejects_batch(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+
+		let fetched = Utc::now() - Duration::from_secs(60);
+		let ambulance = tracker.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), fetched).await.unwrap();
+
+		let result = tracker.bulk_update_locations(&[
+			(ambulance.id, Point::new(1.0, 1.0), Utc::now()),
+			(ambulance.id, Point::new(f64::NAN, 1.0), Utc::now()),
+		]).await;
+		assert!(matches!(result, Err(AmbulanceTrackerError::InvalidGeometry)), "{:?}", result);
+
+		let result = tracker.bulk_update_locations(&[
+			(ambulance.id, Point::new(1.0, 1.0), Utc::now() + DEFAULT_MAX_CLOCK_SKEW + Duration::from_secs(60)),
+		]).await;
+		assert!(matches!(result, Err(AmbulanceTrackerError::TimestampInFuture)), "{:?}", result);
+
+		assert_eq!(tracker.get_ambulance(ambulance.id).await.unwrap().unwrap().location, Point::new(0.0, 0.0));
+	}
+
+	#[sqlx::test]
+	async fn test_update_ambulance_database_error(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool.clone());
+		let ambulance = tracker.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), Utc::now() - Duration::from_secs(60)).await.unwrap();
+		tracker.update_ambulance(ambulance.id, Point::new(1.0, 1.0), Utc::now()).await.unwrap();
+
+		// a failed statement is reported rather than treated as a missing or stale ambulance
+		pg_pool.close().await;
+		let result = tracker.update_ambulance(ambulance.id, Point::new(2.0, 2.0), Utc::now()).await;
+		assert!(matches!(result, Err(AmbulanceTrackerError::Other(_))), "{:?}", result);
+		let result = tracker.try_update_ambulance(ambulance.id, Point::new(2.0, 2.0), Utc::now()).await;
+		assert!(matches!(result, Err(AmbulanceTrackerError::Other(_))), "{:?}", result);
+	}
+
+	#[sqlx::test]
+	async fn test_add_ambulance_name_validation(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+
+		let result = tracker.add_ambulance("", Point::new(0.0, 0.0), Utc::now()).await;
+		assert!(matches!(result, Err(AmbulanceTrackerError::InvalidName)));
+
+		let result = tracker.add_ambulance(" \t\n ", Point::new(0.0, 0.0), Utc::now()).await;
+		assert!(matches!(result, Err(AmbulanceTrackerError::InvalidName)));
+
+		let result = tracker.add_ambulance(&"a".repeat(256), Point::new(0.0, 0.0), Utc::now()).await;
+		assert!(matches!(result, Err(AmbulanceTrackerError::InvalidName)));
+		assert_eq!(tracker.count_ambulances().await.unwrap(), 0);
+
+		let ambulance = tracker.add_ambulance("  Ambulance 1 ", Point::new(0.0, 0.0), Utc::now()).await.unwrap();
+		assert_eq!(ambulance.name, "Ambulance 1");
+		let retrieved = tracker.get_ambulance(ambulance.id).await.unwrap().unwrap();
+		assert_eq!(retrieved.name, "Ambulance 1");
+
+		// multibyte names are limited by characters rather than bytes
+		let name = "é".repeat(255);
+		let ambulance = tracker.add_ambulance(&name, Point::new(0.0, 0.0), Utc::now()).await.unwrap();
+		assert_eq!(ambulance.name, name);
+	}
+
+	#[sqlx::test]
+	async fn test_read_only_tracker(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool.clone());
+		let ambulance = tracker.add_ambulance("Ambulance 1", Point::new(1.0, 2.0), Utc::now()).await.unwrap();
+
+		// only reader methods are available on the read only tracker
+		let reader = ReadOnlyAmbulanceTracker::new(Box::new(get_tracker(pg_pool)));
+
+		let retrieved = reader.get_ambulance(ambulance.id).await.unwrap().unwrap();
+		assert_eq!(retrieved.name, "Ambulance 1");
+		assert_eq!(retrieved.location, Point::new(1.0, 2.0));
+		assert_eq!(reader.get_recently_updated(Duration::from_secs(60)).await.unwrap().len(), 1);
+		assert_eq!(reader.count_recently_updated(Duration::from_secs(60)).await.unwrap(), 1);
+		assert_eq!(reader.count_ambulances().await.unwrap(), 1);
+	}
+
+	#[sqlx::test]
+	async fn test_search_by_name(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+
+		for name in ["Medic 12", "medic 7", "Rescue 12", "Engine 3", "100% Medic_1"] {
+			tracker.add_ambulance(name, Point::new(0.0, 0.0), Utc::now()).await.unwrap();
+		}
+
+		let names = |ambulances: Vec<Ambulance>| ambulances.into_iter().map(|a| a.name).collect::<Vec<_>>();
+
+		let found = names(tracker.search_by_name("MEDIC", 10).await.unwrap());
+		assert_eq!(found.len(), 3);
+		assert!(found.contains(&"Medic 12".to_string()));
+		assert!(found.contains(&"medic 7".to_string()));
+		assert!(found.contains(&"100% Medic_1".to_string()));
+
+		let found = names(tracker.search_by_name("12", 10).await.unwrap());
+		assert_eq!(found.len(), 2);
+		assert!(found.contains(&"Medic 12".to_string()));
+		assert!(found.contains(&"Rescue 12".to_string()));
+
+		assert_eq!(tracker.search_by_name("medic", 2).await.unwrap().len(), 2);
+
+		// wildcards are literal
+		assert_eq!(names(tracker.search_by_name("%", 10).await.unwrap()), vec!["100% Medic_1".to_string()]);
+		assert_eq!(names(tracker.search_by_name("c_1", 10).await.unwrap()), vec!["100% Medic_1".to_string()]);
+
+		assert!(tracker.search_by_name("", 10).await.unwrap().is_empty());
+		assert!(tracker.search_by_name("   ", 10).await.unwrap().is_empty());
+		assert!(tracker.search_by_name("ladder", 10).await.unwrap().is_empty());
+	}
+
+	#[sqlx::test]
+	async fn test_coordinate_precision(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool).with_coordinate_precision(5);
+
+		let ambulance = tracker.add_ambulance("Ambulance 1", Point::new(1.123456789, -2.987654321), Utc::now()).await.unwrap();
+		assert_eq!(ambulance.location, Point::new(1.12346, -2.98765));
+		let retrieved = tracker.get_ambulance(ambulance.id).await.unwrap().unwrap();
+		assert_eq!(retrieved.location, Point::new(1.12346, -2.98765));
+
+		tracker.update_ambulance(ambulance.id, Point::new(3.000004, 4.999996), Utc::now() + Duration::from_secs(1)).await.unwrap();
+		let retrieved = tracker.get_ambulance(ambulance.id).await.unwrap().unwrap();
+		assert_eq!(retrieved.location, Point::new(3.0, 5.0));
+	}
+
+	#[sqlx::test]
+	async fn test_stream_recently_updated(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+
+		let now = Utc::now();
+		let older = tracker.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), now - Duration::from_secs(120)).await.unwrap();
+		let newer = tracker.add_ambulance("Ambulance 2", Point::new(1.0, 1.0), now - Duration::from_secs(60)).await.unwrap();
+		tracker.add_ambulance("Ambulance 3", Point::new(2.0, 2.0), now - Duration::from_secs(3600)).await.unwrap();
+
+		let mut stream = Box::pin(tracker.stream_recently_updated(Duration::from_secs(600)).unwrap());
+		assert_eq!(stream.next().await.unwrap().unwrap().id, newer.id);
+		assert_eq!(stream.next().await.unwrap().unwrap().id, older.id);
+		assert!(stream.next().await.is_none());
+
+		assert!(matches!(tracker.stream_recently_updated(DEFAULT_MAX_RECENT_WINDOW * 2), Err(AmbulanceTrackerError::WindowTooLarge)));
+	}
+
+	#[sqlx::test]
+	async fn test_stream_recently_updated_across_batches(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool.clone());
+
+		// several ambulances share each update time, so batches also end between equal times
+		sqlx::query("INSERT INTO ambulances(ambulance_name, location, last_update) SELECT 'Ambulance ' || i, ST_SetSRID(ST_MakePoint(0, 0), 4326), now() - (i % 7) * interval '1 second' FROM generate_series(1, $1) AS i;")
+			.bind(STREAM_BATCH_SIZE * 2 + 1)
+			.execute(&pg_pool)
+			.await
+			.unwrap();
+
+		let streamed: Vec<_> = tracker.stream_recently_updated(Duration::from_secs(600)).unwrap().try_collect().await.unwrap();
+		let listed = tracker.get_recently_updated(Duration::from_secs(600)).await.unwrap();
+		let ids: std::collections::HashSet<_> = streamed.iter().map(|a| a.id).collect();
+		assert_eq!(ids.len() as i64, STREAM_BATCH_SIZE * 2 + 1);
+		assert_eq!(ids, listed.iter().map(|a| a.id).collect());
+		assert!(streamed.windows(2).all(|pair| pair[0].last_updated >= pair[1].last_updated));
+	}
+
+	#[sqlx::test]
+	async fn test_acquire_retry_under_concurrency(_: sqlx::postgres::PgPoolOptions, options: sqlx::postgres::PgConnectOptions) {
+		let pool = sqlx::postgres::PgPoolOptions::new()
+			.max_connections(1)
+			.acquire_timeout(Duration::from_millis(20))
+			.connect_lazy_with(options);
+		let tracker = Arc::new(get_tracker(pool.clone()).with_acquire_retry(AcquireRetry::new(20, Duration::from_millis(10))));
+		let ambulance = tracker.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), Utc::now() - Duration::from_secs(60)).await.unwrap();
+
+		// the only connection is busy for longer than the acquire timeout
+		let held = pool.acquire().await.unwrap();
+		let tasks: Vec<_> = (0..8).map(|_| {
+			let tracker = tracker.clone();
+			tokio::spawn(async move {
+				let found = tracker.get_ambulance(ambulance.id).await.map_err(|e| e.to_string())?;
+				let count = tracker.count_ambulances().await.map_err(|e| e.to_string())?;
+				Ok::<_, String>((found.is_some(), count))
+			})
+		}).collect();
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		drop(held);
+
+		for task in tasks {
+			assert_eq!(task.await.unwrap().unwrap(), (true, 1));
+		}
+	}
+
+	#[sqlx::test]
+	async fn test_get_ambulances_by_staleness(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+
+		let now = Utc::now();
+		let recent = tracker.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), now - Duration::from_secs(60)).await.unwrap();
+		let stalest = tracker.add_ambulance("Ambulance 2", Point::new(1.0, 1.0), now - Duration::from_secs(7200)).await.unwrap();
+		let stale = tracker.add_ambulance("Ambulance 3", Point::new(2.0, 2.0), now - Duration::from_secs(3600)).await.unwrap();
+
+		let ids: Vec<AmbulanceId> = tracker.get_ambulances_by_staleness(10).await.unwrap().into_iter().map(|a| a.id).collect();
+		assert_eq!(ids, vec![stalest.id, stale.id, recent.id]);
+
+		let ids: Vec<AmbulanceId> = tracker.get_ambulances_by_staleness(2).await.unwrap().into_iter().map(|a| a.id).collect();
+		assert_eq!(ids, vec![stalest.id, stale.id]);
+
+		assert!(tracker.get_ambulances_by_staleness(0).await.unwrap().is_empty());
+	}
+
+	#[sqlx::test]
+	async fn test_plausibility_check(pg_pool: PgPool) {
+		// roughly 180 km/h
+		let tracker = get_tracker(pg_pool).with_plausibility_check(50.0);
+
+		let start = Utc::now() - Duration::from_secs(600);
+		let ambulance = tracker.add_ambulance("Ambulance 1", Point::new(-0.1276, 51.5072), start).await.unwrap();
+
+		for location in [Point::new(181.0, 0.5), Point::new(10.0, -90.5), Point::new(0.0, 0.0)] {
+			let result = tracker.update_ambulance(ambulance.id, location, start + Duration::from_secs(60)).await;
+			assert!(matches!(result, Err(AmbulanceTrackerError::ImplausibleLocation)), "{:?}", result);
+		}
+
+		// London to Paris in a minute
+		let result = tracker.update_ambulance(ambulance.id, Point::new(2.3522, 48.8566), start + Duration::from_secs(60)).await;
+		assert!(matches!(result, Err(AmbulanceTrackerError::ImplausibleLocation)), "{:?}", result);
+		let stored = tracker.get_ambulance(ambulance.id).await.unwrap().unwrap();
+		assert_eq!(stored.location, Point::new(-0.1276, 51.5072));
+		assert_eq!(stored.last_updated.timestamp_micros(), start.timestamp_micros());
+
+		// about a kilometer in a minute
+		assert!(tracker.try_update_ambulance(ambulance.id, Point::new(-0.1246, 51.5160), start + Duration::from_secs(60)).await.unwrap());
+
+		// a stale update is skipped rather than judged
+		assert!(!tracker.try_update_ambulance(ambulance.id, Point::new(2.3522, 48.8566), start).await.unwrap());
+
+		// without the check the same jump is accepted
+		let unchecked = get_tracker(tracker.0.clone());
+		unchecked.update_ambulance(ambulance.id, Point::new(2.3522, 48.8566), start + Duration::from_secs(120)).await.unwrap();
+	}
+
+	#[sqlx::test]
+	async fn test_add_ambulance_idempotent(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+
+		let fetched = Utc::now();
+		let first = tracker.add_ambulance_idempotent("Ambulance 1", Point::new(0.0, 0.0), fetched, "request-1").await.unwrap();
+		let retried = tracker.add_ambulance_idempotent("Ambulance 1", Point::new(0.0, 0.0), fetched, "request-1").await.unwrap();
+		assert_eq!(retried.id, first.id);
+		assert_eq!(retried.name, "Ambulance 1");
+		assert_eq!(tracker.count_ambulances().await.unwrap(), 1);
+
+		let other = tracker.add_ambulance_idempotent("Ambulance 1", Point::new(0.0, 0.0), fetched, "request-2").await.unwrap();
+		assert_ne!(other.id, first.id);
+		assert_eq!(tracker.count_ambulances().await.unwrap(), 2);
+
+		// ambulances added without a key never conflict
+		tracker.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), fetched).await.unwrap();
+		tracker.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), fetched).await.unwrap();
+		assert_eq!(tracker.count_ambulances().await.unwrap(), 4);
+	}
+
+	#[sqlx::test]
+	async fn test_soft_deleted_ambulance(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+		let fetched = Utc::now() - Duration::from_secs(60);
+		let ambulance = tracker.add_ambulance("Ambulance 1", Point::new(1.0, 1.0), fetched).await.unwrap();
+
+		tracker.soft_delete_ambulance(ambulance.id).await.unwrap();
+		assert!(matches!(tracker.soft_delete_ambulance(ambulance.id).await, Err(AmbulanceTrackerError::AmbulanceNotFound)));
+		assert!(tracker.get_ambulance(ambulance.id).await.unwrap().is_none());
+
+		// updates are refused without changing the stored location
+		let update = |seconds| fetched + Duration::from_secs(seconds);
+		assert!(matches!(tracker.update_ambulance(ambulance.id, Point::new(2.0, 2.0), update(10)).await, Err(AmbulanceTrackerError::AmbulanceNotFound)));
+		assert!(matches!(tracker.try_update_ambulance(ambulance.id, Point::new(2.0, 2.0), update(10)).await, Err(AmbulanceTrackerError::AmbulanceNotFound)));
+		assert_eq!(tracker.bulk_update_locations(&[(ambulance.id, Point::new(2.0, 2.0), update(10))]).await.unwrap(), vec![UpdateOutcome::NotFound]);
+
+		// unless deleted ambulances are included, in which case it stays deleted
+		tracker.update_ambulance_include_deleted(ambulance.id, Point::new(3.0, 3.0), update(20)).await.unwrap();
+		assert!(tracker.get_ambulance(ambulance.id).await.unwrap().is_none());
+
+		tracker.restore_ambulance(ambulance.id).await.unwrap();
+		tracker.restore_ambulance(ambulance.id).await.unwrap();
+		assert_eq!(tracker.get_ambulance(ambulance.id).await.unwrap().unwrap().location, Point::new(3.0, 3.0));
+		tracker.update_ambulance(ambulance.id, Point::new(4.0, 4.0), update(30)).await.unwrap();
+		assert_eq!(tracker.get_ambulance(ambulance.id).await.unwrap().unwrap().location, Point::new(4.0, 4.0));
+
+		let invalid_id = AmbulanceId(Uuid::nil());
+		assert!(matches!(tracker.soft_delete_ambulance(invalid_id).await, Err(AmbulanceTrackerError::AmbulanceNotFound)));
+		assert!(matches!(tracker.restore_ambulance(invalid_id).await, Err(AmbulanceTrackerError::AmbulanceNotFound)));
+		assert!(matches!(tracker.update_ambulance_include_deleted(invalid_id, Point::new(1.0, 1.0), update(40)).await, Err(AmbulanceTrackerError::AmbulanceNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn test_soft_deleted_ambulance_is_not_listed(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+		let fetched = Utc::now().trunc_subsecs(0) - Duration::from_secs(60);
+		let kept = tracker.add_ambulance("Ambulance 1", Point::new(1.0, 1.0), fetched).await.unwrap();
+		let deleted = tracker.add_ambulance_idempotent("Ambulance 2", Point::new(2.0, 2.0), fetched, "request-1").await.unwrap();
+		tracker.soft_delete_ambulance(deleted.id).await.unwrap();
+
+		let ids = |ambulances: Vec<Ambulance>| ambulances.into_iter().map(|a| a.id).collect::<Vec<_>>();
+		let window = Duration::from_secs(600);
+		assert_eq!(ids(tracker.get_recently_updated(window).await.unwrap()), vec![kept.id]);
+		assert_eq!(ids(tracker.get_recently_updated_page(window, None, 10).await.unwrap()), vec![kept.id]);
+		assert_eq!(ids(tracker.get_updated_between(fetched, fetched).await.unwrap()), vec![kept.id]);
+		assert_eq!(ids(tracker.search_by_name("ambulance", 10).await.unwrap()), vec![kept.id]);
+		assert_eq!(ids(tracker.get_ambulances_by_staleness(10).await.unwrap()), vec![kept.id]);
+		assert_eq!(tracker.count_recently_updated(window).await.unwrap(), 1);
+		assert_eq!(tracker.count_ambulances().await.unwrap(), 1);
+
+		let streamed: Vec<_> = tracker.stream_recently_updated(window).unwrap().collect().await;
+		assert_eq!(streamed.into_iter().map(|a| a.unwrap().id).collect::<Vec<_>>(), vec![kept.id]);
+		let mut csv = Vec::new();
+		tracker.export_recently_updated(window, ExportFormat::Csv, &mut csv).await.unwrap();
+		assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 2);
+
+		// retrying the request which added it does not bring it back
+		let retried = tracker.add_ambulance_idempotent("Ambulance 2", Point::new(2.0, 2.0), fetched, "request-1").await;
+		assert!(matches!(retried, Err(AmbulanceTrackerError::AmbulanceNotFound)));
+		assert_eq!(tracker.count_ambulances().await.unwrap(), 1);
+	}
+
+	#[sqlx::test]
+	async fn test_export_recently_updated(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+		let fetched = Utc::now().trunc_subsecs(0) - Duration::from_secs(60);
+		tracker.add_ambulance("Ambulance 1", Point::new(-0.1276, 51.5072), fetched).await.unwrap();
+		tracker.add_ambulance("Ambulance \"2\", east", Point::new(2.5, -3.25), fetched + Duration::from_secs(30)).await.unwrap();
+
+		let mut csv = Vec::new();
+		tracker.export_recently_updated(Duration::from_secs(600), ExportFormat::Csv, &mut csv).await.unwrap();
+		assert_eq!(String::from_utf8(csv).unwrap(), format!(
+			"lon,lat,name,last_update\n2.5,-3.25,\"Ambulance \"\"2\"\", east\",{}\n-0.1276,51.5072,Ambulance 1,{}\n",
+			(fetched + Duration::from_secs(30)).to_rfc3339(),
+			fetched.to_rfc3339()
+		));
+
+		let mut ndjson = Vec::new();
+		tracker.export_recently_updated(Duration::from_secs(600), ExportFormat::Ndjson, &mut ndjson).await.unwrap();
+		let rows: Vec<serde_json::Value> = String::from_utf8(ndjson).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+		assert_eq!(rows.len(), 2);
+		assert_eq!(rows[1], serde_json::json!({ "lon": -0.1276, "lat": 51.5072, "name": "Ambulance 1", "last_update": fetched.to_rfc3339() }));
+
+		let mut rejected = Vec::new();
+		assert!(tracker.export_recently_updated(DEFAULT_MAX_RECENT_WINDOW * 2, ExportFormat::Csv, &mut rejected).await.is_err());
+		assert!(rejected.is_empty());
+	}
+
+	#[sqlx::test]
+	async fn test_ambulance_exists(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+		let ambulance = tracker.add_ambulance("Ambulance 1", Point::new(1.0, 1.0), Utc::now()).await.unwrap();
+
+		assert!(tracker.ambulance_exists(ambulance.id).await.unwrap());
+		assert!(!tracker.ambulance_exists(AmbulanceId(Uuid::nil())).await.unwrap());
+
+		tracker.soft_delete_ambulance(ambulance.id).await.unwrap();
+		assert!(!tracker.ambulance_exists(ambulance.id).await.unwrap());
+	}
+
+	#[sqlx::test]
+	async fn test_import_ambulances_geojson(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool);
+		let geojson = r#"{
+			"type": "FeatureCollection",
+			"features": [
+				{ "type": "Feature", "geometry": { "type": "Point", "coordinates": [-0.1276, 51.5072] }, "properties": { "name": " Ambulance 1 " } },
+				{ "type": "Feature", "geometry": { "type": "Point", "coordinates": [2.3522, 48.8566, 35.0] }, "properties": { "name": "Ambulance 2", "base": "Paris" } }
+			]
+		}"#;
+
+		let mut imported = tracker.import_ambulances_geojson(geojson).await.unwrap();
+		imported.sort_by(|a, b| a.name.cmp(&b.name));
+		assert_eq!(imported.len(), 2);
+		assert_eq!((imported[0].name.as_str(), imported[0].location), ("Ambulance 1", Point::new(-0.1276, 51.5072)));
+		assert_eq!((imported[1].name.as_str(), imported[1].location), ("Ambulance 2", Point::new(2.3522, 48.8566)));
+		let stored = tracker.get_ambulance(imported[1].id).await.unwrap().unwrap();
+		assert_eq!(stored.location, imported[1].location);
+
+		// one malformed feature aborts the whole import
+		let malformed = r#"{
+			"type": "FeatureCollection",
+			"features": [
+				{ "type": "Feature", "geometry": { "type": "Point", "coordinates": [1.0, 1.0] }, "properties": { "name": "Ambulance 3" } },
+				{ "type": "Feature", "geometry": { "type": "Point", "coordinates": [51.5072, -120.0] }, "properties": { "name": "Ambulance 4" } }
+			]
+		}"#;
+		let error = tracker.import_ambulances_geojson(malformed).await.unwrap_err();
+		assert!(error.to_string().starts_with("feature 1:"), "{}", error);
+		assert_eq!(tracker.count_ambulances().await.unwrap(), 2);
+
+		for (geojson, expected) in [
+			("not json", "expected"),
+			(r#"{"type": "Feature"}"#, "expected a GeoJSON FeatureCollection"),
+			(r#"{"type": "FeatureCollection", "features": [{"type": "Feature", "geometry": {"type": "LineString", "coordinates": []}, "properties": {"name": "A"}}]}"#, "feature 0: the geometry is not a Point"),
+			(r#"{"type": "FeatureCollection", "features": [{"type": "Feature", "geometry": {"type": "Point", "coordinates": [1.0, 1.0]}, "properties": {}}]}"#, "feature 0: there is no name property")
+		] {
+			let error = tracker.import_ambulances_geojson(geojson).await.unwrap_err();
+			assert!(error.to_string().contains(expected), "{}", error);
+		}
+	}
+
+	#[sqlx::test]
+	async fn test_statement_timeouts(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool.clone())
+			.with_statement_timeouts(StatementTimeouts::new(Some(Duration::from_millis(100)), Some(Duration::from_millis(100))));
+		let ambulance = tracker.add_ambulance("Ambulance 1", Point::new(1.0, 1.0), Utc::now()).await.unwrap();
+
+		// a lock held elsewhere makes the queries wait until they are cancelled
+		let mut blocker = pg_pool.begin().await.unwrap();
+		sqlx::query("LOCK TABLE ambulances IN ACCESS EXCLUSIVE MODE;").execute(&mut *blocker).await.unwrap();
+
+		assert!(matches!(tracker.count_ambulances().await, Err(AmbulanceTrackerError::Timeout)));
+		assert!(matches!(tracker.get_recently_updated(Duration::from_secs(60)).await, Err(AmbulanceTrackerError::Timeout)));
+		let result = tracker.bulk_update_locations(&[(ambulance.id, Point::new(2.0, 2.0), Utc::now())]).await;
+		assert!(matches!(result, Err(AmbulanceTrackerError::Timeout)), "{:?}", result);
+		let streamed: Vec<_> = tracker.stream_recently_updated(Duration::from_secs(60)).unwrap().collect().await;
+		assert!(matches!(streamed.as_slice(), [Err(AmbulanceTrackerError::Timeout)]), "{:?}", streamed);
+		let exported = tracker.export_recently_updated(Duration::from_secs(60), ExportFormat::Csv, Vec::new()).await.unwrap_err();
+		assert!(matches!(exported.downcast_ref(), Some(AmbulanceTrackerError::Timeout)), "{:?}", exported);
+
+		blocker.rollback().await.unwrap();
+		assert_eq!(tracker.count_ambulances().await.unwrap(), 1);
+	}
+
+	#[sqlx::test]
+	async fn test_distinct_active_ambulances(pg_pool: PgPool) {
+		let tracker = get_tracker(pg_pool.clone());
+		let now = Utc::now().trunc_subsecs(0);
+		let hour = Duration::from_secs(60 * 60);
+
+		let ambulance1 = tracker.add_ambulance("Ambulance 1", Point::new(1.0, 1.0), now - hour).await.unwrap();
+		tracker.update_ambulance(ambulance1.id, Point::new(1.1, 1.1), now).await.unwrap();
+		let ambulance2 = tracker.add_ambulance("Ambulance 2", Point::new(2.0, 2.0), now - 3 * hour).await.unwrap();
+		tracker.add_ambulance("Ambulance 3", Point::new(3.0, 3.0), now - 48 * hour).await.unwrap();
+
+		// earlier updates of both ambulances which were archived overlap the window
+		for (id, time) in [(ambulance1.id, now - hour), (ambulance1.id, now - 2 * hour), (ambulance2.id, now - 2 * hour)] {
+			sqlx::query("INSERT INTO archive_ambulance_locations(ambulance_id, location, time) VALUES ($1, ST_SetSRID(ST_MakePoint(1, 1), 4326), $2);")
+				.bind(id.0)
+				.bind(time)
+				.execute(&pg_pool)
+				.await
+				.unwrap();
+		}
+
+		assert_eq!(tracker.distinct_active_ambulances(now - 24 * hour, now).await.unwrap(), 2);
+		assert_eq!(tracker.distinct_active_ambulances(now - Duration::from_secs(90 * 60), now - Duration::from_secs(1)).await.unwrap(), 1);
+		assert_eq!(tracker.distinct_active_ambulances(now - 72 * hour, now).await.unwrap(), 3);
+		assert_eq!(tracker.distinct_active_ambulances(now + hour, now + 2 * hour).await.unwrap(), 0);
+		assert!(tracker.distinct_active_ambulances(now, now - hour).await.is_err());
+
+		// neither the current nor the archived updates of a deleted ambulance are counted
+		tracker.soft_delete_ambulance(ambulance2.id).await.unwrap();
+		assert_eq!(tracker.distinct_active_ambulances(now - 24 * hour, now).await.unwrap(), 1);
+		assert_eq!(tracker.distinct_active_ambulances(now - 72 * hour, now).await.unwrap(), 2);
+	}
 }
\ No newline at end of file
@@ -1,99 +1,304 @@
+use std::collections::HashMap;
+use std::time::Duration;
 use geo_types::Geometry;
 use geozero::wkb;
-use sqlx::{Error, PgPool};
+use sqlx::pool::PoolConnection;
+use sqlx::{Connection, Error, PgConnection, PgExecutor, PgPool, Postgres, Transaction};
 use sqlx::postgres::types::PgInterval;
+use sqlx::types::chrono::{DateTime, Utc};
 use sqlx::types::Uuid;
-use crate::data::{AccountId, DeletePhoneError, PhoneNumber, SettingsError, SettingsManager, UserSettings};
+use crate::data::{format_phone, normalize_phone_number, AccountId, DeletePhoneError, PhoneNumber, SettingsChange, SettingsError, SettingsManager, UserSettings, MAX_ETA_ALERT, MAX_PHONES_PER_USER};
+use crate::sql::acquire_retry::{acquire, AcquireRetry};
+use crate::sql::coordinate_precision::round_point;
+use crate::sql::events::{emit, notify, BackendEvent};
+use crate::sql::geometry_conversion::decode_optional_point;
 use crate::sql::interval_conversion::convert_interval;
+use crate::sql::schema::Schema;
 
-pub struct SQLSettingsManager(PgPool);
+pub struct SQLSettingsManager(PgPool, Option<u32>, bool, Option<AcquireRetry>, Schema);
 
-#[inline(always)]
-fn phone_pretty(phone: &str) -> String {
-	format!("({}) {}-{}", &phone[0..3], &phone[3..6], &phone[6..10])
+/// Validates an ETA alert against [MAX_ETA_ALERT] and converts it for storage
+fn eta_alert_interval(eta_alert: Duration) -> Result<PgInterval, SettingsError> {
+	if eta_alert >= MAX_ETA_ALERT {
+		return Err(SettingsError::EtaAlertTooLong);
+	}
+	PgInterval::try_from(eta_alert).map_err(|e| SettingsError::Other(e))
+}
+
+/// Inserts an already normalized phone. When `unique` is set the row is only inserted if the user
+/// does not already have the number, and is marked so that the partial unique index prevents a
+/// concurrent insert of the same number.
+async fn insert_phone<'c>(executor: impl PgExecutor<'c>, schema: &Schema, user_id: AccountId, phone: &str, label: &str, unique: bool) -> Result<Uuid, SettingsError> {
+	match sqlx::query_as::<_, (Uuid,)>(&schema.qualify("INSERT INTO {schema}phone_numbers(user_id, phone, label, unique_number) SELECT $1, $2, $3, $4 WHERE NOT $4 OR NOT EXISTS (SELECT 1 FROM {schema}phone_numbers WHERE user_id=$1 AND phone=$2) RETURNING phone_id"))
+		.bind(user_id.0)
+		.bind(phone)
+		.bind(label)
+		.bind(unique)
+		.fetch_optional(executor)
+		.await {
+			Err(Error::Database(db)) if db.is_foreign_key_violation() => Err(SettingsError::UserNotFound),
+			Err(Error::Database(db)) if db.is_unique_violation() => Err(SettingsError::DuplicatePhone(phone.to_string())),
+			Err(e) => Err(SettingsError::Other(e.into())),
+			Ok(Some((phone_id,))) => Ok(phone_id),
+			Ok(None) => Err(SettingsError::DuplicatePhone(phone.to_string()))
+		}
+}
+
+/// Returns [SettingsError::TooManyPhones] if adding phones would give the user more than
+/// [MAX_PHONES_PER_USER]. The user's row is locked until the transaction ends, so that concurrent
+/// additions cannot exceed the limit together.
+async fn check_phone_limit(conn: &mut PgConnection, schema: &Schema, user_id: AccountId, adding: usize) -> Result<(), SettingsError> {
+	let (existing,): (i64,) =
+		sqlx::query_as(&schema.qualify("SELECT (SELECT COUNT(*) FROM {schema}phone_numbers WHERE user_id=$1) FROM {schema}accounts WHERE user_id=$1 FOR UPDATE;"))
+			.bind(user_id.0)
+			.fetch_optional(conn)
+			.await
+			.map_err(|e| SettingsError::Other(e.into()))?
+			.ok_or(SettingsError::UserNotFound)?;
+	if existing + adding as i64 > MAX_PHONES_PER_USER {
+		return Err(SettingsError::TooManyPhones);
+	}
+	Ok(())
 }
 
 #[async_trait::async_trait]
 impl SettingsManager for SQLSettingsManager {
 	async fn get_settings(&self, user_id: AccountId) -> Result<UserSettings, SettingsError> {
 		match
-			sqlx::query_as::<_, (wkb::Decode<Geometry>, PgInterval)>("SELECT hospital, pref_eta FROM accounts WHERE user_id = $1")
+			sqlx::query_as::<_, (wkb::Decode<Geometry>, PgInterval)>(&self.4.qualify("SELECT hospital, pref_eta FROM {schema}accounts WHERE user_id = $1"))
 				.bind(user_id.0)
-				.fetch_optional(&self.0)
+				.fetch_optional(&mut *self.connection().await.map_err(|e| SettingsError::Other(e.into()))?)
 				.await
 				.map_err(|e| SettingsError::Other(e.into()))? {
 			Some((hospital_location, pref_eta)) => Ok(UserSettings {
-				hospital_location: hospital_location.geometry.map(|p| p.try_into().expect("invalid database backing")),
+				hospital_location: decode_optional_point(hospital_location.geometry).map_err(|e| SettingsError::Other(e.into()))?,
 				default_eta_alert: convert_interval(pref_eta)
 			}),
 			None => Err(SettingsError::UserNotFound)
 		}
 	}
 
+	async fn get_settings_many(&self, user_ids: &[AccountId]) -> Result<HashMap<AccountId, UserSettings>, SettingsError> {
+		sqlx::query_as::<_, (Uuid, wkb::Decode<Geometry>, PgInterval)>(&self.4.qualify("SELECT user_id, hospital, pref_eta FROM {schema}accounts WHERE user_id = ANY($1)"))
+			.bind(user_ids.iter().map(|id| id.0).collect::<Vec<_>>())
+			.fetch_all(&mut *self.connection().await.map_err(|e| SettingsError::Other(e.into()))?)
+			.await
+			.map_err(|e| SettingsError::Other(e.into()))?
+			.into_iter()
+			.map(|(user_id, hospital_location, pref_eta)| decode_optional_point(hospital_location.geometry)
+				.map(|hospital_location| (AccountId(user_id), UserSettings {
+					hospital_location,
+					default_eta_alert: convert_interval(pref_eta)
+				}))
+				.map_err(|e| SettingsError::Other(e.into())))
+			.collect()
+	}
+
 	async fn set_settings(&self, user_id: AccountId, settings: UserSettings) -> Result<(), SettingsError> {
-		let interval = PgInterval::try_from(settings.default_eta_alert).map_err(|e| SettingsError::Other(e))?;
-		match sqlx::query_as::<_, (i32,)>("UPDATE accounts SET hospital=$2, pref_eta=$3 WHERE user_id=$1 RETURNING 1;")
+		let mut conn = self.connection().await.map_err(|e| SettingsError::Other(e.into()))?;
+		let mut tx = conn.begin().await.map_err(|e| SettingsError::Other(e.into()))?;
+		self.set_settings_tx(&mut tx, user_id, user_id, settings).await?;
+		tx.commit().await.map_err(|e| SettingsError::Other(e.into()))
+	}
+
+	async fn set_default_eta_alert(&self, user_id: AccountId, default_eta_alert: Duration) -> Result<(), SettingsError> {
+		let interval = eta_alert_interval(default_eta_alert)?;
+		// the previous settings are locked so that the recorded change matches the update
+		match sqlx::query_as::<_, (i32,)>(&self.4.qualify("WITH old AS (SELECT hospital, pref_eta FROM {schema}accounts WHERE user_id=$1 FOR UPDATE), updated AS (UPDATE {schema}accounts SET pref_eta=$2 WHERE user_id=$1 RETURNING hospital, pref_eta) INSERT INTO {schema}settings_history(user_id, actor_id, old_hospital, new_hospital, old_pref_eta, new_pref_eta) SELECT $1, $1, old.hospital, updated.hospital, old.pref_eta, updated.pref_eta FROM old, updated RETURNING 1;"))
 			.bind(user_id.0)
-			.bind(settings.hospital_location.map(|pt| wkb::Encode::<Geometry>(pt.into())))
 			.bind(interval)
-			.fetch_optional(&self.0)
+			.fetch_optional(&mut *self.connection().await.map_err(|e| SettingsError::Other(e.into()))?)
 			.await
 			.map_err(|e| SettingsError::Other(e.into()))? {
-			Some(_) => Ok(()),
+			Some(_) => {
+				emit(&self.0, BackendEvent::new("settings", user_id.0, "updated")).await;
+				Ok(())
+			},
 			None => Err(SettingsError::UserNotFound)
 		}
 	}
 
+	async fn get_settings_history(&self, user_id: AccountId) -> Result<Vec<SettingsChange>, SettingsError> {
+		// ensure user exists
+		if sqlx::query_as::<_, (i32,)>(&self.4.qualify("SELECT 1 FROM {schema}accounts WHERE user_id=$1"))
+			.bind(user_id.0).fetch_optional(&mut *self.connection().await.map_err(|e| SettingsError::Other(e.into()))?).await.map_err(|e| SettingsError::Other(e.into()))?.is_none() {
+			return Err(SettingsError::UserNotFound);
+		}
+
+		sqlx::query_as::<_, (Option<Uuid>, wkb::Decode<Geometry>, wkb::Decode<Geometry>, PgInterval, PgInterval, DateTime<Utc>)>(&self.4.qualify("SELECT actor_id, old_hospital, new_hospital, old_pref_eta, new_pref_eta, changed_at FROM {schema}settings_history WHERE user_id=$1 ORDER BY history_id"))
+			.bind(user_id.0)
+			.fetch_all(&mut *self.connection().await.map_err(|e| SettingsError::Other(e.into()))?)
+			.await
+			.map_err(|e| SettingsError::Other(e.into()))?
+			.into_iter()
+			.map(|(actor_id, old_hospital, new_hospital, old_pref_eta, new_pref_eta, changed_at)| Ok(SettingsChange {
+				actor_id: actor_id.map(AccountId),
+				before: UserSettings {
+					hospital_location: decode_optional_point(old_hospital.geometry).map_err(|e| SettingsError::Other(e.into()))?,
+					default_eta_alert: convert_interval(old_pref_eta)
+				},
+				after: UserSettings {
+					hospital_location: decode_optional_point(new_hospital.geometry).map_err(|e| SettingsError::Other(e.into()))?,
+					default_eta_alert: convert_interval(new_pref_eta)
+				},
+				changed_at
+			}))
+			.collect()
+	}
+
 	async fn get_phones(&self, user_id: AccountId) -> Result<Vec<PhoneNumber>, SettingsError> {
 		// ensure user exists
-		if sqlx::query_as::<_, (i32,)>("SELECT 1 FROM accounts WHERE user_id=$1")
-			.bind(user_id.0).fetch_optional(&self.0).await.map_err(|e| SettingsError::Other(e.into()))?.is_none() {
+		if sqlx::query_as::<_, (i32,)>(&self.4.qualify("SELECT 1 FROM {schema}accounts WHERE user_id=$1"))
+			.bind(user_id.0).fetch_optional(&mut *self.connection().await.map_err(|e| SettingsError::Other(e.into()))?).await.map_err(|e| SettingsError::Other(e.into()))?.is_none() {
 			return Err(SettingsError::UserNotFound);
 		}
 
 		Ok(
-			sqlx::query_as::<_, (Uuid, String, Option<String>)>("SELECT phone_id, phone, label FROM phone_numbers WHERE user_id=$1")
+			sqlx::query_as::<_, PhoneRow>(&self.4.qualify("SELECT phone_id, phone, label FROM {schema}phone_numbers WHERE user_id=$1"))
 				.bind(user_id.0)
-				.fetch_all(&self.0)
+				.fetch_all(&mut *self.connection().await.map_err(|e| SettingsError::Other(e.into()))?)
 				.await
 				.map_err(|e| SettingsError::Other(e.into()))?
 				.into_iter()
-				.map(|(phone_id, phone, label)| PhoneNumber {
-					phone_id,
-					label: label.unwrap_or_else(|| phone_pretty(&*phone)),
-					number: phone,
-				})
+				.map(phone_from_row)
 				.collect()
 		)
 	}
 
-	async fn new_phone(&self, user_id: AccountId, phone: &str, label: &str) -> Result<PhoneNumber, SettingsError> {
-		match sqlx::query_as::<_, (Uuid,)>("INSERT INTO phone_numbers(user_id, phone, label) VALUES ($1, $2, $3) RETURNING phone_id")
+	async fn search_phones(&self, user_id: AccountId, query: &str) -> Result<Vec<PhoneNumber>, SettingsError> {
+		let query = query.trim();
+		if query.is_empty() {
+			return self.get_phones(user_id).await;
+		}
+
+		// wildcards in the query are matched literally
+		let pattern = format!("%{}%", query.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+
+		// selecting from accounts distinguishes a missing user from one without matching phones, which
+		// is a single row of nulls
+		let rows = sqlx::query_as::<_, (Option<Uuid>, Option<String>, Option<String>)>(&self.4.qualify("SELECT phone_numbers.phone_id, phone_numbers.phone, phone_numbers.label FROM {schema}accounts LEFT JOIN {schema}phone_numbers ON phone_numbers.user_id=accounts.user_id AND (phone_numbers.label ILIKE $2 OR phone_numbers.phone ILIKE $2) WHERE accounts.user_id=$1"))
 			.bind(user_id.0)
-			.bind(phone)
-			.bind(label)
-			.fetch_one(&self.0)
-			.await {
-				Err(Error::Database(db)) if db.is_foreign_key_violation() => Err(SettingsError::UserNotFound),
-				Err(e) => Err(SettingsError::Other(e.into())),
-				Ok((phone_id, )) => Ok(PhoneNumber {
-					phone_id,
-					label: label.to_string(),
-					number: phone.to_string()
-				})
-			}
+			.bind(pattern)
+			.fetch_all(&mut *self.connection().await.map_err(|e| SettingsError::Other(e.into()))?)
+			.await
+			.map_err(|e| SettingsError::Other(e.into()))?;
+
+		if rows.is_empty() {
+			return Err(SettingsError::UserNotFound);
+		}
+		Ok(rows.into_iter()
+			.filter_map(|(phone_id, phone, label)| Some(phone_from_row((phone_id?, phone?, label))))
+			.collect())
 	}
 
-	async fn delete_phone(&self, user_id: AccountId, phone_id: Uuid) -> Result<(), DeletePhoneError> {
-		match sqlx::query_as::<_, (i32,)>("DELETE FROM phone_numbers WHERE user_id=$1 AND phone_id=$2 RETURNING 1;")
+	async fn count_phones(&self, user_id: AccountId) -> Result<i64, SettingsError> {
+		// selecting from accounts distinguishes a missing user from one without phones
+		match sqlx::query_as::<_, (i64,)>(&self.4.qualify("SELECT (SELECT COUNT(*) FROM {schema}phone_numbers WHERE user_id=$1) FROM {schema}accounts WHERE user_id=$1"))
 			.bind(user_id.0)
-			.bind(phone_id)
-			.fetch_optional(&self.0)
+			.fetch_optional(&mut *self.connection().await.map_err(|e| SettingsError::Other(e.into()))?)
 			.await
-			.map_err(|e| DeletePhoneError::Other(e.into()))? {
-			Some(_) => Ok(()),
-			None => Err(DeletePhoneError::PhoneNotFound)
+			.map_err(|e| SettingsError::Other(e.into()))? {
+			Some((count,)) => Ok(count),
+			None => Err(SettingsError::UserNotFound)
+		}
+	}
+
+	async fn new_phone(&self, user_id: AccountId, phone: &str, label: &str) -> Result<PhoneNumber, SettingsError> {
+		let phone = normalize_phone_number(phone).ok_or_else(|| SettingsError::InvalidPhone(phone.to_string()))?;
+
+		let mut conn = self.connection().await.map_err(|e| SettingsError::Other(e.into()))?;
+		let mut tx = conn.begin().await.map_err(|e| SettingsError::Other(e.into()))?;
+		check_phone_limit(&mut tx, &self.4, user_id, 1).await?;
+		let phone_id = insert_phone(&mut *tx, &self.4, user_id, &phone, label, self.2).await?;
+		tx.commit().await.map_err(|e| SettingsError::Other(e.into()))?;
+
+		emit(&self.0, BackendEvent::new("phone", phone_id, "created")).await;
+		Ok(PhoneNumber {
+			phone_id,
+			label: label.to_string(),
+			number: phone
+		})
+	}
+
+	async fn import_phones(&self, user_id: AccountId, phones: &[(String, String)]) -> Result<Vec<PhoneNumber>, SettingsError> {
+		let phones = phones.iter()
+			.map(|(number, label)| match normalize_phone_number(number) {
+				Some(normalized) => Ok((normalized, label)),
+				None => Err(SettingsError::InvalidPhone(number.clone()))
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		let mut conn = self.connection().await.map_err(|e| SettingsError::Other(e.into()))?;
+		let mut tx = conn.begin().await.map_err(|e| SettingsError::Other(e.into()))?;
+		check_phone_limit(&mut tx, &self.4, user_id, phones.len()).await?;
+
+		let mut imported = Vec::with_capacity(phones.len());
+		for (number, label) in phones {
+			// numbers repeated within the import are caught here too, as earlier rows are visible
+			let phone_id = insert_phone(&mut *tx, &self.4, user_id, &number, label, self.2).await?;
+			imported.push(PhoneNumber {
+				phone_id,
+				label: label.clone(),
+				number
+			});
+		}
+
+		tx.commit().await.map_err(|e| SettingsError::Other(e.into()))?;
+
+		for phone in &imported {
+			emit(&self.0, BackendEvent::new("phone", phone.phone_id, "created")).await;
+		}
+		Ok(imported)
+	}
+
+	async fn delete_phone(&self, user_id: AccountId, phone_id: Uuid) -> Result<(), DeletePhoneError> {
+		// the user's existence is checked in the same statement to distinguish an unknown user
+		let (user_exists, deleted): (bool, bool) =
+			sqlx::query_as(&self.4.qualify("WITH deleted AS (DELETE FROM {schema}phone_numbers WHERE user_id=$1 AND phone_id=$2 RETURNING 1) SELECT EXISTS(SELECT 1 FROM {schema}accounts WHERE user_id=$1), EXISTS(SELECT 1 FROM deleted);"))
+				.bind(user_id.0)
+				.bind(phone_id)
+				.fetch_one(&mut *self.connection().await.map_err(|e| DeletePhoneError::Other(e.into()))?)
+				.await
+				.map_err(|e| DeletePhoneError::Other(e.into()))?;
+
+		match (user_exists, deleted) {
+			(false, _) => Err(DeletePhoneError::UserNotFound),
+			(true, false) => Err(DeletePhoneError::PhoneNotFound),
+			(true, true) => {
+				emit(&self.0, BackendEvent::new("phone", phone_id, "deleted")).await;
+				Ok(())
+			}
+		}
+	}
+
+	async fn delete_all_phones(&self, user_id: AccountId) -> Result<u64, SettingsError> {
+		let (user_exists, deleted): (bool, Vec<Uuid>) =
+			sqlx::query_as(&self.4.qualify("WITH deleted AS (DELETE FROM {schema}phone_numbers WHERE user_id=$1 RETURNING phone_id) SELECT EXISTS(SELECT 1 FROM {schema}accounts WHERE user_id=$1), ARRAY(SELECT phone_id FROM deleted);"))
+				.bind(user_id.0)
+				.fetch_one(&mut *self.connection().await.map_err(|e| SettingsError::Other(e.into()))?)
+				.await
+				.map_err(|e| SettingsError::Other(e.into()))?;
+
+		if !user_exists {
+			return Err(SettingsError::UserNotFound);
+		}
+
+		for phone_id in &deleted {
+			emit(&self.0, BackendEvent::new("phone", *phone_id, "deleted")).await;
 		}
+		Ok(deleted.len() as u64)
+	}
+}
+
+type PhoneRow = (Uuid, String, Option<String>);
+
+/// Converts a row of `phone_id, phone, label` into a phone, labelling unlabelled phones with their
+/// formatted number
+fn phone_from_row((phone_id, phone, label): PhoneRow) -> PhoneNumber {
+	PhoneNumber {
+		phone_id,
+		label: label.unwrap_or_else(|| format_phone(&phone)),
+		number: phone,
 	}
 }
 
@@ -101,14 +306,73 @@ impl SQLSettingsManager {
 	/// Creates a new AmbulanceTracker using the specified connection as the backend.
 	/// It is expected that the migrations file has been executed already.
 	pub fn new(pool: PgPool) -> Self {
-		Self(pool)
+		Self(pool, None, false, None, Schema::default())
+	}
+
+	/// Updates a user's settings as [SettingsManager::set_settings] does, but within the caller's
+	/// transaction, so that it can be committed or rolled back along with other changes such as
+	/// [crate::sql::sql_account_manager::SqlAccountManager::create_account_tx]. The change is
+	/// recorded in the settings history as made by the actor.
+	pub async fn set_settings_tx(&self, tx: &mut Transaction<'_, Postgres>, actor_id: AccountId, user_id: AccountId, settings: UserSettings) -> Result<(), SettingsError> {
+		// settings built directly rather than through the builder are checked the same way
+		let settings = UserSettings::builder()
+			.hospital_location(settings.hospital_location)
+			.default_eta_alert(settings.default_eta_alert)
+			.build()?;
+		let interval = eta_alert_interval(settings.default_eta_alert)?;
+
+		// the previous settings are locked so that the recorded change matches the update
+		sqlx::query_as::<_, (i32,)>(&self.4.qualify("WITH old AS (SELECT hospital, pref_eta FROM {schema}accounts WHERE user_id=$1 FOR UPDATE), updated AS (UPDATE {schema}accounts SET hospital=$2, pref_eta=$3 WHERE user_id=$1 RETURNING hospital, pref_eta) INSERT INTO {schema}settings_history(user_id, actor_id, old_hospital, new_hospital, old_pref_eta, new_pref_eta) SELECT $1, $4, old.hospital, updated.hospital, old.pref_eta, updated.pref_eta FROM old, updated RETURNING 1;"))
+			.bind(user_id.0)
+			.bind(settings.hospital_location.map(|pt| wkb::Encode::<Geometry>(round_point(pt, self.1).into())))
+			.bind(interval)
+			.bind(actor_id.0)
+			.fetch_optional(&mut **tx)
+			.await
+			.map_err(|e| SettingsError::Other(e.into()))?
+			.ok_or(SettingsError::UserNotFound)?;
+
+		// delivered only once the transaction commits
+		notify(&mut **tx, &BackendEvent::new("settings", user_id.0, "updated")).await.map_err(|e| SettingsError::Other(e.into()))
+	}
+
+	/// Sets the number of decimal places hospital locations are rounded to before being stored. By
+	/// default locations are stored at full precision.
+	pub fn with_coordinate_precision(mut self, decimal_places: u32) -> Self {
+		self.1 = Some(decimal_places);
+		self
+	}
+
+	/// Rejects phones which the user already has, after normalization, with
+	/// [SettingsError::DuplicatePhone]. By default duplicates are allowed.
+	pub fn with_unique_phones(mut self) -> Self {
+		self.2 = true;
+		self
+	}
+
+	/// Retries acquiring a connection when the pool is exhausted, rather than failing with
+	/// [SettingsError::Other] as soon as acquiring times out. Events are still sent without
+	/// retrying.
+	pub fn with_acquire_retry(mut self, retry: AcquireRetry) -> Self {
+		self.3 = Some(retry);
+		self
+	}
+
+	/// Uses the tables in the given Postgres schema, as
+	/// [crate::sql::sql_account_manager::SqlAccountManager::with_schema] does
+	pub fn with_schema(mut self, schema: &str) -> Self {
+		self.4 = Schema::named(schema);
+		self
+	}
+
+	async fn connection(&self) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+		acquire(&self.0, self.3).await
 	}
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
-	use std::time::Duration;
 	use crate::data::{AccountManager, AccountRole};
 	use crate::sql::sql_account_manager::SqlAccountManager;
 
@@ -148,6 +412,29 @@ mod tests {
 		}
 	}
 
+	#[sqlx::test]
+	async fn test_get_settings_many(pool: PgPool) {
+		let (settings_manager, user1, user2, _, non_existent_user) = get_settings_manager(pool).await.unwrap();
+
+		let user1_settings = UserSettings {
+			hospital_location: Some(geo_types::Point::new(40.7128, -74.0060)),
+			default_eta_alert: Duration::from_secs(60 * 30),
+		};
+		settings_manager.set_settings(user1, user1_settings.clone()).await.unwrap();
+
+		let result = settings_manager.get_settings_many(&[user1, user2, non_existent_user]).await.unwrap();
+		assert_eq!(result.len(), 2);
+		assert!(!result.contains_key(&non_existent_user));
+
+		let retrieved1 = &result[&user1];
+		assert_eq!(retrieved1.hospital_location, user1_settings.hospital_location);
+		assert_eq!(retrieved1.default_eta_alert, user1_settings.default_eta_alert);
+
+		let retrieved2 = &result[&user2];
+		assert_eq!(retrieved2.hospital_location, None);
+		assert_eq!(retrieved2.default_eta_alert, Duration::from_secs(60 * 15));
+	}
+
 	#[sqlx::test]
 	async fn test_set_settings_existing_user(pool: PgPool) {
 		let (settings_manager, user1, _, _, _) = get_settings_manager(pool).await.unwrap();
@@ -166,6 +453,20 @@ mod tests {
 		assert_eq!(retrieved_settings.hospital_location, new_settings.hospital_location); // Example check for lat
 	}
 
+	#[sqlx::test]
+	async fn test_set_settings_coordinate_precision(pool: PgPool) {
+		let (_, user1, _, _, _) = get_settings_manager(pool.clone()).await.unwrap();
+		let settings_manager = SQLSettingsManager::new(pool).with_coordinate_precision(3);
+
+		settings_manager.set_settings(user1, UserSettings {
+			hospital_location: Some(geo_types::Point::new(40.712776, -74.005974)),
+			default_eta_alert: Duration::from_secs(60 * 15),
+		}).await.unwrap();
+
+		let retrieved_settings = settings_manager.get_settings(user1).await.unwrap();
+		assert_eq!(retrieved_settings.hospital_location, Some(geo_types::Point::new(40.713, -74.006)));
+	}
+
 	#[sqlx::test]
 	async fn test_set_settings_non_existent_user(pool: PgPool) {
 		let (settings_manager, _, _, _, non_existent_user) = get_settings_manager(pool).await.unwrap();
@@ -183,6 +484,71 @@ mod tests {
 		}
 	}
 
+	#[sqlx::test]
+	async fn test_set_settings_eta_alert_too_long(pool: PgPool) {
+		let (settings_manager, user1, _, _, _) = get_settings_manager(pool).await.unwrap();
+
+		let result = settings_manager.set_settings(user1, UserSettings {
+			hospital_location: None,
+			default_eta_alert: MAX_ETA_ALERT,
+		}).await;
+		assert!(matches!(result, Err(SettingsError::EtaAlertTooLong)), "{:?}", result);
+	}
+
+	#[sqlx::test]
+	async fn test_set_settings_hospital_out_of_range(pool: PgPool) {
+		let (settings_manager, user1, _, _, _) = get_settings_manager(pool).await.unwrap();
+
+		for location in [geo_types::Point::new(-74.0060, 95.0), geo_types::Point::new(181.0, 40.7128)] {
+			let result = settings_manager.set_settings(user1, UserSettings {
+				hospital_location: Some(location),
+				default_eta_alert: Duration::from_secs(60 * 15),
+			}).await;
+			assert!(matches!(result, Err(SettingsError::InvalidHospitalLocation)), "{:?}", result);
+		}
+		assert_eq!(settings_manager.get_settings(user1).await.unwrap().hospital_location, None);
+	}
+
+	#[sqlx::test]
+	async fn test_set_default_eta_alert(pool: PgPool) {
+		let (settings_manager, user1, _, _, _) = get_settings_manager(pool).await.unwrap();
+
+		let hospital_location = Some(geo_types::Point::new(40.7128, -74.0060));
+		settings_manager.set_settings(user1, UserSettings {
+			hospital_location,
+			default_eta_alert: Duration::from_secs(60 * 15),
+		}).await.unwrap();
+
+		settings_manager.set_default_eta_alert(user1, Duration::from_secs(60 * 45)).await.unwrap();
+
+		let retrieved_settings = settings_manager.get_settings(user1).await.unwrap();
+		assert_eq!(retrieved_settings.default_eta_alert, Duration::from_secs(60 * 45));
+		assert_eq!(retrieved_settings.hospital_location, hospital_location);
+
+		settings_manager.set_default_eta_alert(user1, MAX_ETA_ALERT - Duration::from_secs(1)).await.unwrap();
+	}
+
+	#[sqlx::test]
+	async fn test_set_default_eta_alert_too_long(pool: PgPool) {
+		let (settings_manager, user1, _, _, _) = get_settings_manager(pool).await.unwrap();
+
+		let result = settings_manager.set_default_eta_alert(user1, MAX_ETA_ALERT).await;
+		assert!(matches!(result, Err(SettingsError::EtaAlertTooLong)), "{:?}", result);
+
+		let retrieved_settings = settings_manager.get_settings(user1).await.unwrap();
+		assert_eq!(retrieved_settings.default_eta_alert, Duration::from_secs(60 * 15));
+	}
+
+	#[sqlx::test]
+	async fn test_set_default_eta_alert_non_existent_user(pool: PgPool) {
+		let (settings_manager, _, _, _, non_existent_user) = get_settings_manager(pool).await.unwrap();
+
+		match settings_manager.set_default_eta_alert(non_existent_user, Duration::from_secs(60 * 30)).await {
+			Err(SettingsError::UserNotFound) => (),
+			result => panic!("Expected UserNotFound error, found {:?}", result),
+		}
+	}
+
 	#[sqlx::test]
 	async fn test_get_phones_existing_user(pool: PgPool) {
 		let (settings_manager, user1, _, _, _) = get_settings_manager(pool).await.unwrap();
@@ -207,6 +573,29 @@ mod tests {
 		}
 	}
 
+	#[sqlx::test]
+	async fn test_count_phones(pool: PgPool) {
+		let (settings_manager, user1, user2, _, _) = get_settings_manager(pool).await.unwrap();
+
+		assert_eq!(settings_manager.count_phones(user1).await.unwrap(), 0);
+
+		settings_manager.new_phone(user2, "0123456789", "Home").await.unwrap();
+		settings_manager.new_phone(user2, "0123456789", "Work").await.unwrap();
+		settings_manager.new_phone(user2, "9876543210", "Mobile").await.unwrap();
+		assert_eq!(settings_manager.count_phones(user2).await.unwrap(), 3);
+		assert_eq!(settings_manager.count_phones(user1).await.unwrap(), 0);
+	}
+
+	#[sqlx::test]
+	async fn test_count_phones_non_existent_user(pool: PgPool) {
+		let (settings_manager, _, _, _, non_existent_user) = get_settings_manager(pool).await.unwrap();
+
+		match settings_manager.count_phones(non_existent_user).await {
+			Err(SettingsError::UserNotFound) => (),
+			result => panic!("Expected UserNotFound error, found {:?}", result),
+		}
+	}
+
 	#[sqlx::test]
 	async fn test_new_phone_existing_user(pool: PgPool) {
 		let (settings_manager, user1, _, _, _) = get_settings_manager(pool).await.unwrap();
@@ -221,7 +610,7 @@ mod tests {
 		let phones = settings_manager.get_phones(user1).await.unwrap();
 		assert_eq!(phones.len(), 1);
 		assert_eq!(phones[0].label, label);
-		assert_eq!(phones[0].number, phone)
+		assert_eq!(phones[0].number, format!("+1{}", phone))
 	}
 
 	#[sqlx::test]
@@ -261,9 +650,12 @@ mod tests {
 		let result = settings_manager.delete_phone(non_existent_user, phone_id).await;
 		assert!(result.is_err());
 		match result {
-			Err(DeletePhoneError::PhoneNotFound) => (),
-			result => panic!("Expected PhoneNotFound error, found {:?}", result),
+			Err(DeletePhoneError::UserNotFound) => (),
+			result => panic!("Expected UserNotFound error, found {:?}", result),
 		}
+
+		// the phone must not have been deleted
+		assert_eq!(settings_manager.get_phones(user1).await.unwrap().len(), 1);
 	}
 
 	#[sqlx::test]
@@ -281,6 +673,71 @@ mod tests {
 		}
 	}
 
+	#[sqlx::test]
+	async fn test_import_phones(pool: PgPool) {
+		let (settings_manager, user1, _, _, non_existent_user) = get_settings_manager(pool).await.unwrap();
+
+		settings_manager.new_phone(user1, "0123456789", "existing").await.unwrap();
+		let phones = vec![
+			("1112223333".to_string(), "home".to_string()),
+			("4445556666".to_string(), "work".to_string())
+		];
+		let imported = settings_manager.import_phones(user1, &phones).await.unwrap();
+		assert_eq!(imported.len(), 2);
+		assert_eq!(imported[0].number, "+11112223333");
+		assert_eq!(imported[1].label, "work");
+
+		let mut stored: Vec<String> = settings_manager.get_phones(user1).await.unwrap().into_iter().map(|phone| phone.number).collect();
+		stored.sort();
+		assert_eq!(stored, vec!["+10123456789", "+11112223333", "+14445556666"]);
+
+		assert!(matches!(settings_manager.import_phones(non_existent_user, &phones).await, Err(SettingsError::UserNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn test_import_phones_is_atomic(pool: PgPool) {
+		let (settings_manager, user1, _, _, _) = get_settings_manager(pool).await.unwrap();
+
+		let phones = vec![
+			("1112223333".to_string(), "home".to_string()),
+			("555-1234".to_string(), "invalid".to_string())
+		];
+		match settings_manager.import_phones(user1, &phones).await {
+			Err(SettingsError::InvalidPhone(number)) => assert_eq!(number, "555-1234"),
+			result => panic!("Expected InvalidPhone error, found {:?}", result)
+		}
+		assert_eq!(settings_manager.count_phones(user1).await.unwrap(), 0);
+
+		let too_many: Vec<(String, String)> = (0..MAX_PHONES_PER_USER).map(|i| (format!("{:010}", i), String::new())).collect();
+		settings_manager.new_phone(user1, "0123456789", "existing").await.unwrap();
+		assert!(matches!(settings_manager.import_phones(user1, &too_many).await, Err(SettingsError::TooManyPhones)));
+		assert_eq!(settings_manager.count_phones(user1).await.unwrap(), 1);
+	}
+
+	#[sqlx::test]
+	async fn test_new_phone_limit(pool: PgPool) {
+		let (settings_manager, user1, _, _, _) = get_settings_manager(pool).await.unwrap();
+
+		let phones: Vec<(String, String)> = (1..MAX_PHONES_PER_USER).map(|i| (format!("{:010}", i), String::new())).collect();
+		settings_manager.import_phones(user1, &phones).await.unwrap();
+		settings_manager.new_phone(user1, "0123456789", "last").await.unwrap();
+
+		assert!(matches!(settings_manager.new_phone(user1, "5551234567", "one too many").await, Err(SettingsError::TooManyPhones)));
+		assert_eq!(settings_manager.count_phones(user1).await.unwrap(), MAX_PHONES_PER_USER);
+	}
+
+	#[sqlx::test]
+	async fn test_delete_other_users_phone(pool: PgPool) {
+		let (settings_manager, user1, user2, _, _) = get_settings_manager(pool).await.unwrap();
+
+		let phone_id = settings_manager.new_phone(user1, "0123456789", "label").await.unwrap().phone_id;
+		match settings_manager.delete_phone(user2, phone_id).await {
+			Err(DeletePhoneError::PhoneNotFound) => (),
+			result => panic!("Expected PhoneNotFound error, found {:?}", result),
+		}
+		assert_eq!(settings_manager.get_phones(user1).await.unwrap().len(), 1);
+	}
+
 	#[sqlx::test]
 	async fn test_new_phone_duplicate_phone(pool: PgPool) {
 		let (settings_manager, user1, _, _, _) = get_settings_manager(pool).await.unwrap();
@@ -299,4 +756,182 @@ mod tests {
 		let phones = settings_manager.get_phones(user1).await.unwrap();
 		assert_eq!(phones.len(), 2); // Both phones should be there (duplicate allowed)
 	}
+
+	#[sqlx::test]
+	async fn test_new_phone_normalizes(pool: PgPool) {
+		let (settings_manager, user1, _, _, _) = get_settings_manager(pool).await.unwrap();
+
+		let phone = settings_manager.new_phone(user1, "+1 (555) 123-4567", "Mobile").await.unwrap();
+		assert_eq!(phone.number, "+15551234567");
+		assert_eq!(settings_manager.get_phones(user1).await.unwrap()[0].number, "+15551234567");
+
+		let phone = settings_manager.new_phone(user1, "+44 20 7946 0958", "London").await.unwrap();
+		assert_eq!(phone.number, "+442079460958");
+
+		// duplicates after normalization are still allowed by default
+		settings_manager.new_phone(user1, "555.123.4567", "Work").await.unwrap();
+		assert_eq!(settings_manager.count_phones(user1).await.unwrap(), 3);
+
+		match settings_manager.new_phone(user1, "555-1234", "Short").await {
+			Err(SettingsError::InvalidPhone(number)) => assert_eq!(number, "555-1234"),
+			result => panic!("Expected InvalidPhone error, found {:?}", result),
+		}
+	}
+
+	#[sqlx::test]
+	async fn test_new_phone_unique_phones(pool: PgPool) {
+		let (_, user1, user2, _, non_existent_user) = get_settings_manager(pool.clone()).await.unwrap();
+		let settings_manager = SQLSettingsManager::new(pool).with_unique_phones();
+
+		settings_manager.new_phone(user1, "+15551234567", "Mobile").await.unwrap();
+		match settings_manager.new_phone(user1, "(555) 123-4567", "Work").await {
+			Err(SettingsError::DuplicatePhone(number)) => assert_eq!(number, "+15551234567"),
+			result => panic!("Expected DuplicatePhone error, found {:?}", result),
+		}
+		assert_eq!(settings_manager.count_phones(user1).await.unwrap(), 1);
+
+		// uniqueness is per user
+		settings_manager.new_phone(user2, "5551234567", "Mobile").await.unwrap();
+		settings_manager.new_phone(user1, "5559876543", "Home").await.unwrap();
+		assert_eq!(settings_manager.count_phones(user1).await.unwrap(), 2);
+
+		assert!(matches!(settings_manager.new_phone(non_existent_user, "5551234567", "Mobile").await, Err(SettingsError::UserNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn test_import_phones_unique_phones(pool: PgPool) {
+		let (_, user1, _, _, _) = get_settings_manager(pool.clone()).await.unwrap();
+		let settings_manager = SQLSettingsManager::new(pool).with_unique_phones();
+
+		settings_manager.new_phone(user1, "5551234567", "Mobile").await.unwrap();
+		let existing = vec![
+			("1112223333".to_string(), "home".to_string()),
+			("(555) 123-4567".to_string(), "work".to_string())
+		];
+		assert!(matches!(settings_manager.import_phones(user1, &existing).await, Err(SettingsError::DuplicatePhone(_))));
+
+		let repeated = vec![
+			("1112223333".to_string(), "home".to_string()),
+			("+1 111 222 3333".to_string(), "work".to_string())
+		];
+		assert!(matches!(settings_manager.import_phones(user1, &repeated).await, Err(SettingsError::DuplicatePhone(_))));
+		assert_eq!(settings_manager.count_phones(user1).await.unwrap(), 1);
+
+		let imported = settings_manager.import_phones(user1, &repeated[..1]).await.unwrap();
+		assert_eq!(imported[0].number, "+11112223333");
+		assert_eq!(settings_manager.count_phones(user1).await.unwrap(), 2);
+	}
+
+	#[sqlx::test]
+	async fn test_delete_all_phones(pool: PgPool) {
+		let (settings_manager, user1, user2, _, _) = get_settings_manager(pool).await.unwrap();
+
+		for phone in ["5551234567", "5559876543", "1112223333"] {
+			settings_manager.new_phone(user1, phone, "label").await.unwrap();
+		}
+		settings_manager.new_phone(user2, "5551234567", "label").await.unwrap();
+
+		assert_eq!(settings_manager.delete_all_phones(user1).await.unwrap(), 3);
+		assert_eq!(settings_manager.count_phones(user1).await.unwrap(), 0);
+		assert_eq!(settings_manager.count_phones(user2).await.unwrap(), 1);
+
+		assert_eq!(settings_manager.delete_all_phones(user1).await.unwrap(), 0);
+	}
+
+	#[sqlx::test]
+	async fn test_delete_all_phones_non_existent_user(pool: PgPool) {
+		let (settings_manager, _, _, _, non_existent_user) = get_settings_manager(pool).await.unwrap();
+
+		assert!(matches!(settings_manager.delete_all_phones(non_existent_user).await, Err(SettingsError::UserNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn test_search_phones(pool: PgPool) {
+		let (settings_manager, user1, user2, _, non_existent_user) = get_settings_manager(pool).await.unwrap();
+
+		let home = settings_manager.new_phone(user1, "5551234567", "Home").await.unwrap();
+		let mobile = settings_manager.new_phone(user1, "5559876543", "Mobile").await.unwrap();
+		let work = settings_manager.new_phone(user1, "1112223333", "Work mobile").await.unwrap();
+		settings_manager.new_phone(user2, "5550001111", "Mobile").await.unwrap();
+
+		let mut found: Vec<Uuid> = settings_manager.search_phones(user1, "MOBILE").await.unwrap().into_iter().map(|p| p.phone_id).collect();
+		found.sort();
+		let mut expected = vec![mobile.phone_id, work.phone_id];
+		expected.sort();
+		assert_eq!(found, expected);
+
+		let found: Vec<Uuid> = settings_manager.search_phones(user1, "123").await.unwrap().into_iter().map(|p| p.phone_id).collect();
+		assert_eq!(found, vec![home.phone_id]);
+
+		assert!(settings_manager.search_phones(user1, "%").await.unwrap().is_empty());
+		assert_eq!(settings_manager.search_phones(user1, "  ").await.unwrap().len(), 3);
+		assert!(settings_manager.search_phones(user2, "home").await.unwrap().is_empty());
+		assert!(matches!(settings_manager.search_phones(non_existent_user, "home").await, Err(SettingsError::UserNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn test_create_account_and_settings_in_one_transaction(pool: PgPool) {
+		let (_, _, admin, _, _) = get_settings_manager(pool.clone()).await.unwrap();
+		let settings_manager = SQLSettingsManager::new(pool.clone());
+		let account_manager = SqlAccountManager::new(pool.clone());
+		let settings = UserSettings {
+			hospital_location: Some(geo_types::Point::new(1.0, 2.0)),
+			default_eta_alert: Duration::from_secs(600)
+		};
+
+		let mut tx = pool.begin().await.unwrap();
+		let (user, _) = account_manager.create_account_tx(&mut tx, &admin, AccountRole::User, "composed").await.unwrap();
+		settings_manager.set_settings_tx(&mut tx, admin, user, settings.clone()).await.unwrap();
+		tx.commit().await.unwrap();
+		let stored = settings_manager.get_settings(user).await.unwrap();
+		assert_eq!(stored.hospital_location, settings.hospital_location);
+		assert_eq!(stored.default_eta_alert, settings.default_eta_alert);
+
+		// a failure of the second operation leaves no account behind once rolled back
+		let mut tx = pool.begin().await.unwrap();
+		let (user, _) = account_manager.create_account_tx(&mut tx, &admin, AccountRole::User, "rolled back").await.unwrap();
+		let too_long = UserSettings {
+			default_eta_alert: MAX_ETA_ALERT,
+			..settings
+		};
+		assert!(matches!(settings_manager.set_settings_tx(&mut tx, admin, user, too_long).await, Err(SettingsError::EtaAlertTooLong)));
+		tx.rollback().await.unwrap();
+		assert!(matches!(settings_manager.get_settings(user).await, Err(SettingsError::UserNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn test_settings_history(pool: PgPool) {
+		let (settings_manager, user1, _, _, non_existent_user) = get_settings_manager(pool).await.unwrap();
+		assert!(settings_manager.get_settings_history(user1).await.unwrap().is_empty());
+
+		let first = UserSettings {
+			hospital_location: Some(geo_types::Point::new(1.0, 2.0)),
+			default_eta_alert: Duration::from_secs(600)
+		};
+		settings_manager.set_settings(user1, first.clone()).await.unwrap();
+		settings_manager.set_default_eta_alert(user1, Duration::from_secs(300)).await.unwrap();
+
+		let history = settings_manager.get_settings_history(user1).await.unwrap();
+		assert_eq!(history.len(), 2);
+		assert_eq!(history[0].actor_id, Some(user1));
+		assert_eq!(history[0].before.hospital_location, None);
+		assert_eq!(history[0].before.default_eta_alert, Duration::from_secs(15 * 60));
+		assert_eq!(history[0].after.hospital_location, first.hospital_location);
+		assert_eq!(history[0].after.default_eta_alert, Duration::from_secs(600));
+		assert_eq!(history[1].before.hospital_location, first.hospital_location);
+		assert_eq!(history[1].before.default_eta_alert, Duration::from_secs(600));
+		assert_eq!(history[1].after.hospital_location, first.hospital_location);
+		assert_eq!(history[1].after.default_eta_alert, Duration::from_secs(300));
+		assert!(history[0].changed_at <= history[1].changed_at);
+
+		// rejected changes are not recorded
+		let too_long = UserSettings {
+			default_eta_alert: MAX_ETA_ALERT,
+			..first
+		};
+		assert!(settings_manager.set_settings(user1, too_long).await.is_err());
+		assert_eq!(settings_manager.get_settings_history(user1).await.unwrap().len(), 2);
+
+		assert!(matches!(settings_manager.get_settings_history(non_existent_user).await, Err(SettingsError::UserNotFound)));
+	}
 }
\ No newline at end of file
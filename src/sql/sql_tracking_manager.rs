@@ -0,0 +1,749 @@
+use crate::data::{format_phone, AccountId, Ambulance, AmbulanceId, AmbulanceLookupError, Clock, PhoneNumber, SystemClock, TrackedAmbulance, TrackingManager, Urgency, UserLookupError};
+use crate::eta::eta_finder::EtaFinder;
+use crate::sql::acquire_retry::{acquire, AcquireRetry};
+use crate::sql::geometry_conversion::{decode_optional_point, decode_point};
+use crate::sql::interval_conversion::convert_interval;
+use crate::sql::schema::Schema;
+use crate::sql::statement_timeout::{begin_with_timeout, is_statement_timeout, StatementTimeouts};
+use geo_types::{Geometry, Point};
+use geozero::wkb;
+use sqlx::postgres::types::PgInterval;
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::types::Uuid;
+use sqlx::pool::PoolConnection;
+use sqlx::{Connection, Error, PgPool, Postgres};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub struct SQLTrackingManager {
+	pool: PgPool,
+	finder: Box<dyn EtaFinder + 'static + Sync + Send>,
+	clock: Arc<dyn Clock>,
+	acquire_retry: Option<AcquireRetry>,
+	statement_timeouts: StatementTimeouts,
+	schema: Schema
+}
+
+fn query_error(e: sqlx::Error) -> AmbulanceLookupError {
+	if is_statement_timeout(&e) {
+		AmbulanceLookupError::Timeout
+	} else {
+		AmbulanceLookupError::OtherError(e.into())
+	}
+}
+
+type TrackingRow = (Uuid, Option<String>, wkb::Decode<Geometry>, DateTime<Utc>, Option<String>, Urgency, DateTime<Utc>, Option<PgInterval>, Uuid, String, Option<String>, PgInterval);
+
+#[async_trait::async_trait]
+impl TrackingManager for SQLTrackingManager {
+	async fn get_user_tracking(&self, id: AccountId) -> Result<TrackedAmbulance, UserLookupError> {
+		// ensure user exists
+		if sqlx::query_as::<_, (i32,)>(&self.schema.qualify("SELECT 1 FROM {schema}accounts WHERE user_id=$1"))
+			.bind(id.0).fetch_optional(&mut *self.connection().await.map_err(|e| UserLookupError::OtherError(e.into()))?).await.map_err(|e| UserLookupError::OtherError(e.into()))?.is_none() {
+			return Err(UserLookupError::UserNotFound);
+		}
+
+		let (ambulance_id, name, location, last_updated, user_label, urgency, eta, notify_self_at, phone_id, phone, label, notify_at_eta) =
+			sqlx::query_as::<_, TrackingRow>(&self.schema.qualify("SELECT a.ambulance_id, a.ambulance_name, a.location, a.last_update, l.user_description, l.urgency, l.eta, l.notify_self_at, p.phone_id, p.phone, p.label, n.notify_at_eta FROM {schema}live_tracking_sessions l JOIN {schema}ambulances a ON l.ambulance_id=a.ambulance_id JOIN {schema}eta_notifications n ON n.tracking_id=l.tracking_id JOIN {schema}phone_numbers p ON n.phone_id=p.phone_id WHERE l.user_id=$1 AND l.arrived_at IS NULL AND l.eta IS NOT NULL AND a.deleted_at IS NULL ORDER BY l.inserted_at DESC LIMIT 1;"))
+				.bind(id.0)
+				.fetch_optional(&mut *self.connection().await.map_err(|e| UserLookupError::OtherError(e.into()))?)
+				.await
+				.map_err(|e| UserLookupError::OtherError(e.into()))?
+				.ok_or(UserLookupError::NotTracking)?;
+
+		Ok(TrackedAmbulance {
+			ambulance: Ambulance {
+				id: AmbulanceId(ambulance_id),
+				name: name.unwrap_or(ambulance_id.to_string()),
+				location: decode_point(location.geometry).map_err(|e| UserLookupError::OtherError(e.into()))?,
+				last_updated
+			},
+			user_label: user_label.unwrap_or_default(),
+			urgency,
+			phones_tracking: (PhoneNumber {
+				phone_id,
+				label: label.unwrap_or_else(|| format_phone(&phone)),
+				number: phone,
+			}, convert_interval(notify_at_eta)),
+			eta,
+			user_eta_notify: notify_self_at.map(convert_interval)
+		})
+	}
+
+	async fn track_ambulance(&self, id: AccountId, ambulance_id: AmbulanceId, user_label: &str, urgency: Urgency, destination: Option<Point>, phones: (Uuid, Duration)) -> Result<TrackedAmbulance, AmbulanceLookupError> {
+		let (name, location, last_updated): (Option<String>, wkb::Decode<Geometry>, DateTime<Utc>) =
+			sqlx::query_as(&self.schema.qualify("SELECT ambulance_name, location, last_update FROM {schema}ambulances WHERE ambulance_id=$1 AND deleted_at IS NULL;"))
+				.bind(ambulance_id.0)
+				.fetch_optional(&mut *self.connection().await.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?)
+				.await
+				.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?
+				.ok_or(AmbulanceLookupError::AmbulanceNotFound)?;
+		let ambulance = Ambulance {
+			id: ambulance_id,
+			name: name.unwrap_or(ambulance_id.0.to_string()),
+			location: decode_point(location.geometry).map_err(|e| AmbulanceLookupError::OtherError(e.into()))?,
+			last_updated
+		};
+
+		// without an explicit destination, an existing destination is kept, otherwise the ambulance is
+		// assumed to be headed to the user's hospital
+		let (default_destination,): (wkb::Decode<Geometry>,) =
+			sqlx::query_as(&self.schema.qualify("SELECT COALESCE((SELECT destination FROM {schema}live_tracking_sessions WHERE user_id=$1 AND ambulance_id=$2), hospital) FROM {schema}accounts WHERE user_id=$1;"))
+				.bind(id.0)
+				.bind(ambulance_id.0)
+				.fetch_optional(&mut *self.connection().await.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?)
+				.await
+				.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?
+				.ok_or(AmbulanceLookupError::UserNotFound)?;
+		let destination: Point = match destination {
+			Some(destination) => destination,
+			None => decode_optional_point(default_destination.geometry)
+				.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?
+				.ok_or(AmbulanceLookupError::NoDestination)?
+		};
+
+		let eta = self.finder.calculate_eta(ambulance_id.0, ambulance.location, destination).await.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?;
+		let now = self.clock.now();
+
+		let mut conn = self.connection().await.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?;
+		let mut tx = conn.begin().await.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?;
+
+		// the user's alert defaults to their preferred eta, and no row is inserted if the user does not exist
+		let (tracking_id, user_description, stored_urgency, eta, notify_self_at): (Uuid, Option<String>, Urgency, DateTime<Utc>, Option<PgInterval>) = match
+			sqlx::query_as(&self.schema.qualify("INSERT INTO {schema}live_tracking_sessions(user_id, ambulance_id, user_description, urgency, notify_self_at, destination, eta, eta_last_calculated) SELECT $1, $2, $3, $4::text::{schema}urgency, pref_eta, $5, $6, $7 FROM {schema}accounts WHERE user_id=$1 ON CONFLICT (user_id, ambulance_id) DO UPDATE SET user_description=EXCLUDED.user_description, urgency=EXCLUDED.urgency, arrived_at=NULL, destination=EXCLUDED.destination, eta=EXCLUDED.eta, eta_last_calculated=EXCLUDED.eta_last_calculated RETURNING tracking_id, user_description, urgency, eta, notify_self_at;"))
+				.bind(id.0)
+				.bind(ambulance_id.0)
+				.bind(user_label)
+				.bind(urgency)
+				.bind(wkb::Encode::<Geometry>(destination.into()))
+				.bind(now + eta)
+				.bind(now)
+				.fetch_optional(&mut *tx)
+				.await {
+			Err(Error::Database(db)) if db.is_foreign_key_violation() => return Err(AmbulanceLookupError::AmbulanceNotFound),
+			Err(e) => return Err(AmbulanceLookupError::OtherError(e.into())),
+			Ok(None) => return Err(AmbulanceLookupError::UserNotFound),
+			Ok(Some(row)) => row
+		};
+
+		sqlx::query(&self.schema.qualify("DELETE FROM {schema}eta_notifications WHERE tracking_id=$1;"))
+			.bind(tracking_id)
+			.execute(&mut *tx)
+			.await
+			.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?;
+
+		// the phone must belong to the user, otherwise the whole tracking is rolled back
+		let (phone_id, notify_at_eta) = phones;
+		let (phone, label): (String, Option<String>) =
+			sqlx::query_as(&self.schema.qualify("SELECT phone, label FROM {schema}phone_numbers WHERE phone_id=$1 AND user_id=$2;"))
+				.bind(phone_id)
+				.bind(id.0)
+				.fetch_optional(&mut *tx)
+				.await
+				.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?
+				.ok_or(AmbulanceLookupError::PhoneNotFound)?;
+
+		let interval = PgInterval::try_from(notify_at_eta).map_err(|e| AmbulanceLookupError::OtherError(e))?;
+		sqlx::query(&self.schema.qualify("INSERT INTO {schema}eta_notifications(tracking_id, notify_at_eta, phone_id) VALUES ($1, $2, $3);"))
+			.bind(tracking_id)
+			.bind(interval)
+			.bind(phone_id)
+			.execute(&mut *tx)
+			.await
+			.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?;
+
+		tx.commit().await.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?;
+
+		Ok(TrackedAmbulance {
+			ambulance,
+			user_label: user_description.unwrap_or_default(),
+			urgency: stored_urgency,
+			phones_tracking: (PhoneNumber {
+				phone_id,
+				label: label.unwrap_or_else(|| format_phone(&phone)),
+				number: phone,
+			}, notify_at_eta),
+			eta,
+			user_eta_notify: notify_self_at.map(convert_interval)
+		})
+	}
+
+	async fn dismiss_eta_alert(&self, id: AccountId, ambulance_id: AmbulanceId) -> Result<(), AmbulanceLookupError> {
+		match sqlx::query_as::<_, (i32,)>(&self.schema.qualify("UPDATE {schema}live_tracking_sessions SET self_alert_dismissed=true WHERE user_id=$1 AND ambulance_id=$2 RETURNING 1;"))
+			.bind(id.0)
+			.bind(ambulance_id.0)
+			.fetch_optional(&mut *self.connection().await.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?)
+			.await
+			.map_err(|e| AmbulanceLookupError::OtherError(e.into()))? {
+			Some(_) => Ok(()),
+			None => Err(AmbulanceLookupError::AmbulanceNotFound)
+		}
+	}
+
+	async fn set_destination(&self, id: AccountId, ambulance_id: AmbulanceId, destination: Point) -> Result<(), AmbulanceLookupError> {
+		let (location,): (wkb::Decode<Geometry>,) =
+			sqlx::query_as(&self.schema.qualify("SELECT a.location FROM {schema}live_tracking_sessions l JOIN {schema}ambulances a ON l.ambulance_id=a.ambulance_id WHERE l.user_id=$1 AND l.ambulance_id=$2 AND a.deleted_at IS NULL;"))
+				.bind(id.0)
+				.bind(ambulance_id.0)
+				.fetch_optional(&mut *self.connection().await.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?)
+				.await
+				.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?
+				.ok_or(AmbulanceLookupError::AmbulanceNotFound)?;
+		let location = decode_point(location.geometry).map_err(|e| AmbulanceLookupError::OtherError(e.into()))?;
+
+		let eta = self.finder.calculate_eta(ambulance_id.0, location, destination).await.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?;
+		let now = self.clock.now();
+
+		match sqlx::query_as::<_, (i32,)>(&self.schema.qualify("UPDATE {schema}live_tracking_sessions SET destination=$3, eta=$4, eta_last_calculated=$5, self_alert_dismissed=false WHERE user_id=$1 AND ambulance_id=$2 RETURNING 1;"))
+			.bind(id.0)
+			.bind(ambulance_id.0)
+			.bind(wkb::Encode::<Geometry>(destination.into()))
+			.bind(now + eta)
+			.bind(now)
+			.fetch_optional(&mut *self.connection().await.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?)
+			.await
+			.map_err(|e| AmbulanceLookupError::OtherError(e.into()))? {
+			Some(_) => Ok(()),
+			None => Err(AmbulanceLookupError::AmbulanceNotFound)
+		}
+	}
+
+	async fn stop_tracking_ambulance(&self, id: AccountId, ambulance_id: AmbulanceId) -> Result<(), AmbulanceLookupError> {
+		// the user's existence is checked in the same statement to distinguish an unknown user
+		let (user_exists, deleted): (bool, bool) =
+			sqlx::query_as(&self.schema.qualify("WITH deleted AS (DELETE FROM {schema}live_tracking_sessions WHERE user_id=$1 AND ambulance_id=$2 RETURNING 1) SELECT EXISTS(SELECT 1 FROM {schema}accounts WHERE user_id=$1), EXISTS(SELECT 1 FROM deleted);"))
+				.bind(id.0)
+				.bind(ambulance_id.0)
+				.fetch_one(&mut *self.connection().await.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?)
+				.await
+				.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?;
+
+		match (user_exists, deleted) {
+			(false, _) => Err(AmbulanceLookupError::UserNotFound),
+			(true, false) => Err(AmbulanceLookupError::AmbulanceNotFound),
+			(true, true) => Ok(())
+		}
+	}
+
+	async fn count_trackers(&self, ambulance_id: AmbulanceId) -> Result<i64, AmbulanceLookupError> {
+		// selecting from ambulances distinguishes a missing ambulance from an untracked one
+		match sqlx::query_as::<_, (i64,)>(&self.schema.qualify("SELECT (SELECT COUNT(*) FROM {schema}live_tracking_sessions WHERE ambulance_id=$1 AND arrived_at IS NULL) FROM {schema}ambulances WHERE ambulance_id=$1 AND deleted_at IS NULL;"))
+			.bind(ambulance_id.0)
+			.fetch_optional(&mut *self.connection().await.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?)
+			.await
+			.map_err(|e| AmbulanceLookupError::OtherError(e.into()))? {
+			Some((count,)) => Ok(count),
+			None => Err(AmbulanceLookupError::AmbulanceNotFound)
+		}
+	}
+
+	async fn get_trackers(&self, ambulance_id: AmbulanceId) -> Result<Vec<(AccountId, Vec<PhoneNumber>)>, AmbulanceLookupError> {
+		// selecting from ambulances gives a row of nulls for an untracked ambulance and none for a
+		// missing one
+		let mut conn = self.connection().await.map_err(|e| AmbulanceLookupError::OtherError(e.into()))?;
+		let mut tx = begin_with_timeout(&mut conn, self.statement_timeouts.read).await.map_err(query_error)?;
+		let rows = sqlx::query_as::<_, (Option<Uuid>, Option<Uuid>, Option<String>, Option<String>)>(&self.schema.qualify("SELECT l.user_id, p.phone_id, p.phone, p.label FROM {schema}ambulances a LEFT JOIN {schema}live_tracking_sessions l ON l.ambulance_id=a.ambulance_id AND l.arrived_at IS NULL LEFT JOIN {schema}eta_notifications n ON n.tracking_id=l.tracking_id LEFT JOIN {schema}phone_numbers p ON n.phone_id=p.phone_id WHERE a.ambulance_id=$1 AND a.deleted_at IS NULL ORDER BY l.inserted_at, l.user_id, p.phone_id;"))
+			.bind(ambulance_id.0)
+			.fetch_all(&mut *tx)
+			.await
+			.map_err(query_error)?;
+		tx.commit().await.map_err(query_error)?;
+		if rows.is_empty() {
+			return Err(AmbulanceLookupError::AmbulanceNotFound);
+		}
+
+		// rows of the same user are adjacent
+		let mut trackers: Vec<(AccountId, Vec<PhoneNumber>)> = Vec::new();
+		for (user_id, phone_id, phone, label) in rows {
+			let Some(user_id) = user_id else { continue };
+			if trackers.last().map(|(id, _)| id.0) != Some(user_id) {
+				trackers.push((AccountId::new(user_id), Vec::new()));
+			}
+			if let (Some(phone_id), Some(phone), Some((_, phones))) = (phone_id, phone, trackers.last_mut()) {
+				phones.push(PhoneNumber {
+					phone_id,
+					label: label.unwrap_or_else(|| format_phone(&phone)),
+					number: phone,
+				});
+			}
+		}
+		Ok(trackers)
+	}
+
+	async fn refresh_tracked_etas(&self) -> Result<usize, Box<dyn std::error::Error>> {
+		let mut conn = self.connection().await?;
+		let mut tx = begin_with_timeout(&mut conn, self.statement_timeouts.read).await.map_err(query_error)?;
+		let sessions: Vec<(Uuid, Uuid, wkb::Decode<Geometry>, wkb::Decode<Geometry>)> =
+			sqlx::query_as(&self.schema.qualify("SELECT l.tracking_id, a.ambulance_id, a.location, l.destination FROM {schema}live_tracking_sessions l JOIN {schema}ambulances a ON l.ambulance_id=a.ambulance_id WHERE l.arrived_at IS NULL AND l.destination IS NOT NULL AND a.deleted_at IS NULL;"))
+				.fetch_all(&mut *tx)
+				.await
+				.map_err(query_error)?;
+		tx.commit().await.map_err(query_error)?;
+		// the connection is returned before each update acquires its own
+		drop(conn);
+
+		let mut refreshed = 0;
+		for (tracking_id, ambulance_id, location, destination) in sessions {
+			let (location, destination) = match (decode_point(location.geometry), decode_point(destination.geometry)) {
+				(Ok(location), Ok(destination)) => (location, destination),
+				(Err(e), _) | (_, Err(e)) => {
+					tracing::warn!(error = %e, %tracking_id, %ambulance_id, "skipping tracked eta with unsupported geometry");
+					continue;
+				}
+			};
+
+			// a single failed calculation should not prevent refreshing the rest
+			let eta = match self.finder.calculate_eta(ambulance_id, location, destination).await {
+				Ok(eta) => eta,
+				Err(e) => {
+					tracing::warn!(error = %e, %tracking_id, %ambulance_id, "failed to refresh tracked eta");
+					continue;
+				}
+			};
+			let now = self.clock.now();
+
+			sqlx::query(&self.schema.qualify("UPDATE {schema}live_tracking_sessions SET eta=$2, eta_last_calculated=$3 WHERE tracking_id=$1;"))
+				.bind(tracking_id)
+				.bind(now + eta)
+				.bind(now)
+				.execute(&mut *self.connection().await?)
+				.await?;
+			refreshed += 1;
+		}
+
+		Ok(refreshed)
+	}
+}
+
+impl SQLTrackingManager {
+	/// Creates a new TrackingManager using the specified connection as the backend and finder to
+	/// calculate ETAs. It is expected that the migrations file has been executed already.
+	pub fn new(pool: PgPool, finder: Box<dyn EtaFinder + 'static + Sync + Send>) -> Self {
+		Self {
+			pool,
+			finder,
+			clock: Arc::new(SystemClock),
+			acquire_retry: None,
+			statement_timeouts: StatementTimeouts::default(),
+			schema: Schema::default()
+		}
+	}
+
+	/// Calculates ETAs from the clock's time rather than the system clock
+	pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+		self.clock = clock;
+		self
+	}
+
+	/// Retries acquiring a connection when the pool is exhausted, as
+	/// [crate::sql::sql_ambulance_tracker::SQLAmbulanceTracker::with_acquire_retry] does. By default
+	/// a lookup fails with `OtherError` as soon as acquiring times out.
+	pub fn with_acquire_retry(mut self, retry: AcquireRetry) -> Self {
+		self.acquire_retry = Some(retry);
+		self
+	}
+
+	/// Cancels the queries listing tracking sessions which run longer than the read timeout,
+	/// failing with [AmbulanceLookupError::Timeout]. These are [TrackingManager::get_trackers] and
+	/// the listing in [TrackingManager::refresh_tracked_etas]; the write timeout is unused.
+	pub fn with_statement_timeouts(mut self, timeouts: StatementTimeouts) -> Self {
+		self.statement_timeouts = timeouts;
+		self
+	}
+
+	/// Uses the tables in the given Postgres schema, as
+	/// [crate::sql::sql_account_manager::SqlAccountManager::with_schema] does. The schema's own
+	/// `urgency` type is used for the urgency of new trackings.
+	pub fn with_schema(mut self, schema: &str) -> Self {
+		self.schema = Schema::named(schema);
+		self
+	}
+
+	async fn connection(&self) -> Result<PoolConnection<Postgres>, sqlx::Error> {
+		acquire(&self.pool, self.acquire_retry).await
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::data::{AccountManager, AccountRole, AmbulanceReader, AmbulanceTracker, SettingsError, SettingsManager, UserSettings};
+	use crate::sql::sql_account_manager::SqlAccountManager;
+	use crate::sql::sql_ambulance_tracker::SQLAmbulanceTracker;
+	use crate::sql::sql_settings_manager::SQLSettingsManager;
+	use crate::eta::fixed_eta::FixedEta;
+
+	async fn setup(pool: PgPool) -> (SQLTrackingManager, AccountId, Ambulance, PhoneNumber) {
+		let acc = SqlAccountManager::new(pool.clone());
+		let (site_admin, _) = acc.create_site_admin("root").await.unwrap();
+		let (user, _) = acc.create_account(&site_admin, AccountRole::Admin, "user1").await.unwrap();
+
+		let ambulance = SQLAmbulanceTracker::new(pool.clone())
+			.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), Utc::now()).await.unwrap();
+		let phone = SQLSettingsManager::new(pool.clone())
+			.new_phone(user, "0123456789", "Home").await.unwrap();
+
+		(SQLTrackingManager::new(pool, Box::new(FixedEta::new(Duration::from_secs(600)))), user, ambulance, phone)
+	}
+
+	/// Reads back where the user's tracking of the ambulance is headed
+	async fn stored_destination(pool: &PgPool, user: AccountId, ambulance: &Ambulance) -> Point {
+		let (x, y): (f64, f64) = sqlx::query_as("SELECT ST_X(destination), ST_Y(destination) FROM live_tracking_sessions WHERE user_id=$1 AND ambulance_id=$2;")
+			.bind(user.0)
+			.bind(ambulance.id.0)
+			.fetch_one(pool)
+			.await
+			.unwrap();
+		Point::new(x, y)
+	}
+
+	#[sqlx::test]
+	async fn test_track_ambulance(pool: PgPool) {
+		let (tracker, user, ambulance, phone) = setup(pool.clone()).await;
+
+		let destination = Point::new(1.0, 1.0);
+		let tracked = tracker.track_ambulance(user, ambulance.id, "patient 1", Urgency::High, Some(destination), (phone.phone_id, Duration::from_secs(300))).await.unwrap();
+		assert_eq!(tracked.user_label, "patient 1");
+		assert_eq!(tracked.urgency, Urgency::High);
+		assert_eq!(tracked.phones_tracking.0.phone_id, phone.phone_id);
+		assert_eq!(tracked.user_eta_notify, Some(Duration::from_secs(60 * 15)));
+		assert_eq!(stored_destination(&pool, user, &ambulance).await, destination);
+
+		let tracking = tracker.get_user_tracking(user).await.unwrap();
+		assert_eq!(tracking.ambulance.id, ambulance.id);
+		assert_eq!(tracking.user_label, "patient 1");
+		assert_eq!(tracking.urgency, Urgency::High);
+		assert_eq!(tracking.phones_tracking.0.phone_id, phone.phone_id);
+		assert_eq!(tracking.phones_tracking.1, Duration::from_secs(300));
+		assert_eq!(tracking.user_eta_notify, Some(Duration::from_secs(60 * 15)));
+		assert_eq!(tracking.eta, tracked.eta);
+
+		tracker.stop_tracking_ambulance(user, ambulance.id).await.unwrap();
+		assert!(matches!(tracker.get_user_tracking(user).await, Err(UserLookupError::NotTracking)));
+	}
+
+	#[sqlx::test]
+	async fn test_urgency_round_trip(pool: PgPool) {
+		let (tracker, user, ambulance, phone) = setup(pool).await;
+
+		for urgency in Urgency::ALL {
+			let tracked = tracker.track_ambulance(user, ambulance.id, "patient 1", urgency, Some(Point::new(1.0, 1.0)), (phone.phone_id, Duration::from_secs(300))).await.unwrap();
+			assert_eq!(tracked.urgency, urgency);
+			assert_eq!(tracker.get_user_tracking(user).await.unwrap().urgency, urgency);
+		}
+	}
+
+	#[sqlx::test]
+	async fn test_track_ambulance_returns_eta(pool: PgPool) {
+		let (tracker, user, ambulance, phone) = setup(pool.clone()).await;
+		let hospital = Point::new(1.0, 1.0);
+		SQLSettingsManager::new(pool.clone()).set_settings(user, UserSettings {
+			hospital_location: Some(hospital),
+			default_eta_alert: Duration::from_secs(60 * 15)
+		}).await.unwrap();
+
+		let before = Utc::now();
+		let tracked = tracker.track_ambulance(user, ambulance.id, "patient 1", Urgency::High, None, (phone.phone_id, Duration::from_secs(300))).await.unwrap();
+		assert_eq!(tracked.ambulance.id, ambulance.id);
+		assert_eq!(stored_destination(&pool, user, &ambulance).await, hospital);
+
+		let remaining = (tracked.eta - before).num_seconds();
+		assert!((600..=605).contains(&remaining), "unexpected eta {}", remaining);
+		assert_eq!(tracker.get_user_tracking(user).await.unwrap().eta, tracked.eta);
+	}
+
+	#[sqlx::test]
+	async fn test_track_ambulance_destination_resolution(pool: PgPool) {
+		let (tracker, user, ambulance, phone) = setup(pool.clone()).await;
+		let phones = (phone.phone_id, Duration::from_secs(300));
+
+		// neither an explicit destination nor a hospital
+		let result = tracker.track_ambulance(user, ambulance.id, "patient 1", Urgency::High, None, phones).await;
+		assert!(matches!(result, Err(AmbulanceLookupError::NoDestination)));
+		assert!(matches!(tracker.get_user_tracking(user).await, Err(UserLookupError::NotTracking)));
+
+		let hospital = Point::new(1.0, 1.0);
+		SQLSettingsManager::new(pool.clone()).set_settings(user, UserSettings {
+			hospital_location: Some(hospital),
+			default_eta_alert: Duration::from_secs(60 * 15)
+		}).await.unwrap();
+
+		// an explicit destination takes priority over the hospital, and is kept when tracking again
+		let destination = Point::new(2.0, 2.0);
+		tracker.track_ambulance(user, ambulance.id, "patient 1", Urgency::High, Some(destination), phones).await.unwrap();
+		assert_eq!(stored_destination(&pool, user, &ambulance).await, destination);
+		tracker.track_ambulance(user, ambulance.id, "patient 1", Urgency::High, None, phones).await.unwrap();
+		assert_eq!(stored_destination(&pool, user, &ambulance).await, destination);
+	}
+
+	#[sqlx::test]
+	async fn test_set_destination(pool: PgPool) {
+		let (tracker, user, ambulance, phone) = setup(pool.clone()).await;
+		let dismissed = move |pool: PgPool| async move {
+			let (dismissed,): (bool,) = sqlx::query_as("SELECT self_alert_dismissed FROM live_tracking_sessions WHERE user_id=$1;")
+				.bind(user.0)
+				.fetch_one(&pool)
+				.await
+				.unwrap();
+			dismissed
+		};
+
+		tracker.track_ambulance(user, ambulance.id, "patient 1", Urgency::High, Some(Point::new(1.0, 1.0)), (phone.phone_id, Duration::from_secs(300))).await.unwrap();
+		tracker.dismiss_eta_alert(user, ambulance.id).await.unwrap();
+		assert!(dismissed(pool.clone()).await);
+
+		let destination = Point::new(2.0, 2.0);
+		let before = Utc::now();
+		tracker.set_destination(user, ambulance.id, destination).await.unwrap();
+		assert_eq!(stored_destination(&pool, user, &ambulance).await, destination);
+		assert!(!dismissed(pool.clone()).await);
+
+		let eta = tracker.get_user_tracking(user).await.unwrap().eta;
+		let remaining = (eta - before).num_seconds();
+		assert!((600..=605).contains(&remaining), "unexpected eta {}", remaining);
+	}
+
+	#[sqlx::test]
+	async fn test_count_trackers(pool: PgPool) {
+		let (tracker, user, ambulance, phone) = setup(pool.clone()).await;
+		let untracked = SQLAmbulanceTracker::new(pool.clone())
+			.add_ambulance("Ambulance 2", Point::new(2.0, 2.0), Utc::now()).await.unwrap();
+
+		let acc = SqlAccountManager::new(pool.clone());
+		let (site_admin, _) = acc.create_site_admin("root2").await.unwrap();
+		let (user2, _) = acc.create_account(&site_admin, AccountRole::Admin, "user2").await.unwrap();
+		let (user3, _) = acc.create_account(&site_admin, AccountRole::Admin, "user3").await.unwrap();
+		let settings = SQLSettingsManager::new(pool.clone());
+		let phone2 = settings.new_phone(user2, "0123456780", "Work").await.unwrap();
+		let phone3 = settings.new_phone(user3, "0123456781", "Mobile").await.unwrap();
+
+		for (id, phone_id) in [(user, phone.phone_id), (user2, phone2.phone_id), (user3, phone3.phone_id)] {
+			tracker.track_ambulance(id, ambulance.id, "patient", Urgency::Normal, Some(Point::new(1.0, 1.0)), (phone_id, Duration::from_secs(300))).await.unwrap();
+		}
+		// tracking again does not add another row
+		tracker.track_ambulance(user, ambulance.id, "patient", Urgency::High, Some(Point::new(1.0, 1.0)), (phone.phone_id, Duration::from_secs(300))).await.unwrap();
+		assert_eq!(tracker.count_trackers(ambulance.id).await.unwrap(), 3);
+		assert_eq!(tracker.count_trackers(untracked.id).await.unwrap(), 0);
+
+		// arrived trackings are no longer active
+		sqlx::query("UPDATE live_tracking_sessions SET arrived_at=now() WHERE user_id=$1;")
+			.bind(user3.0)
+			.execute(&pool)
+			.await
+			.unwrap();
+		tracker.stop_tracking_ambulance(user2, ambulance.id).await.unwrap();
+		assert_eq!(tracker.count_trackers(ambulance.id).await.unwrap(), 1);
+
+		let missing = AmbulanceId(Uuid::from_u128(1));
+		assert!(matches!(tracker.count_trackers(missing).await, Err(AmbulanceLookupError::AmbulanceNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn test_refresh_tracked_etas(pool: PgPool) {
+		let (tracker, user, ambulance, phone) = setup(pool.clone()).await;
+		let ambulance2 = SQLAmbulanceTracker::new(pool.clone())
+			.add_ambulance("Ambulance 2", Point::new(2.0, 2.0), Utc::now()).await.unwrap();
+
+		tracker.track_ambulance(user, ambulance.id, "patient 1", Urgency::High, Some(Point::new(1.0, 1.0)), (phone.phone_id, Duration::from_secs(300))).await.unwrap();
+		tracker.track_ambulance(user, ambulance2.id, "patient 2", Urgency::Low, Some(Point::new(1.0, 1.0)), (phone.phone_id, Duration::from_secs(300))).await.unwrap();
+		tracker.set_destination(user, ambulance.id, Point::new(1.0, 1.0)).await.unwrap();
+		tracker.set_destination(user, ambulance2.id, Point::new(1.0, 1.0)).await.unwrap();
+
+		let refresher = SQLTrackingManager::new(pool.clone(), Box::new(FixedEta::new(Duration::from_secs(1200))));
+		let before = Utc::now();
+		assert_eq!(refresher.refresh_tracked_etas().await.unwrap(), 2);
+
+		let etas: Vec<(DateTime<Utc>,)> = sqlx::query_as("SELECT eta FROM live_tracking_sessions WHERE user_id=$1;")
+			.bind(user.0)
+			.fetch_all(&pool)
+			.await
+			.unwrap();
+		assert_eq!(etas.len(), 2);
+		for (eta,) in etas {
+			let remaining = (eta - before).num_seconds();
+			assert!((1200..=1205).contains(&remaining), "unexpected eta {}", remaining);
+		}
+	}
+
+	#[sqlx::test]
+	async fn test_refresh_skips_soft_deleted_ambulance(pool: PgPool) {
+		let (tracker, user, ambulance, phone) = setup(pool.clone()).await;
+		let tracked = tracker.track_ambulance(user, ambulance.id, "patient 1", Urgency::High, Some(Point::new(1.0, 1.0)), (phone.phone_id, Duration::from_secs(300))).await.unwrap();
+		SQLAmbulanceTracker::new(pool.clone()).soft_delete_ambulance(ambulance.id).await.unwrap();
+
+		let refresher = SQLTrackingManager::new(pool.clone(), Box::new(FixedEta::new(Duration::from_secs(1200))));
+		assert_eq!(refresher.refresh_tracked_etas().await.unwrap(), 0);
+
+		let (eta,): (DateTime<Utc>,) = sqlx::query_as("SELECT eta FROM live_tracking_sessions WHERE user_id=$1;")
+			.bind(user.0)
+			.fetch_one(&pool)
+			.await
+			.unwrap();
+		assert_eq!(eta, tracked.eta);
+	}
+
+	#[sqlx::test]
+	async fn test_stop_tracking(pool: PgPool) {
+		let (tracker, user, ambulance, phone) = setup(pool.clone()).await;
+
+		tracker.track_ambulance(user, ambulance.id, "patient 1", Urgency::High, Some(Point::new(1.0, 1.0)), (phone.phone_id, Duration::from_secs(300))).await.unwrap();
+		tracker.stop_tracking_ambulance(user, ambulance.id).await.unwrap();
+		assert!(matches!(tracker.get_user_tracking(user).await, Err(UserLookupError::NotTracking)));
+
+		let (notifications,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM eta_notifications").fetch_one(&pool).await.unwrap();
+		assert_eq!(notifications, 0);
+
+		// already stopped
+		let result = tracker.stop_tracking_ambulance(user, ambulance.id).await;
+		assert!(matches!(result, Err(AmbulanceLookupError::AmbulanceNotFound)));
+
+		// never tracked
+		let result = tracker.stop_tracking_ambulance(user, AmbulanceId(Uuid::from_u128(1))).await;
+		assert!(matches!(result, Err(AmbulanceLookupError::AmbulanceNotFound)));
+
+		let result = tracker.stop_tracking_ambulance(AccountId::new(Uuid::from_u128(2)), ambulance.id).await;
+		assert!(matches!(result, Err(AmbulanceLookupError::UserNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn test_tracking_not_found(pool: PgPool) {
+		let (tracker, user, ambulance, phone) = setup(pool).await;
+		let phones = (phone.phone_id, Duration::from_secs(300));
+
+		let missing_ambulance = AmbulanceId(Uuid::from_u128(1));
+		let result = tracker.track_ambulance(user, missing_ambulance, "patient 1", Urgency::High, None, phones).await;
+		assert!(matches!(result, Err(AmbulanceLookupError::AmbulanceNotFound)));
+
+		let missing_user = AccountId::new(Uuid::from_u128(2));
+		let result = tracker.track_ambulance(missing_user, ambulance.id, "patient 1", Urgency::High, None, phones).await;
+		assert!(matches!(result, Err(AmbulanceLookupError::UserNotFound)));
+		assert!(matches!(tracker.get_user_tracking(missing_user).await, Err(UserLookupError::UserNotFound)));
+
+		// a phone which is not the user's leaves nothing tracked
+		let missing_phone = (Uuid::from_u128(3), Duration::from_secs(300));
+		let result = tracker.track_ambulance(user, ambulance.id, "patient 1", Urgency::High, Some(Point::new(1.0, 1.0)), missing_phone).await;
+		assert!(matches!(result, Err(AmbulanceLookupError::PhoneNotFound)));
+		assert!(matches!(tracker.get_user_tracking(user).await, Err(UserLookupError::NotTracking)));
+
+		// the ambulance exists but is not tracked by the user
+		let result = tracker.set_destination(user, ambulance.id, Point::new(1.0, 1.0)).await;
+		assert!(matches!(result, Err(AmbulanceLookupError::AmbulanceNotFound)));
+		let result = tracker.dismiss_eta_alert(user, ambulance.id).await;
+		assert!(matches!(result, Err(AmbulanceLookupError::AmbulanceNotFound)));
+		let result = tracker.stop_tracking_ambulance(user, ambulance.id).await;
+		assert!(matches!(result, Err(AmbulanceLookupError::AmbulanceNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn test_get_trackers(pool: PgPool) {
+		let (tracker, user, ambulance, phone) = setup(pool.clone()).await;
+		let untracked = SQLAmbulanceTracker::new(pool.clone())
+			.add_ambulance("Ambulance 2", Point::new(2.0, 2.0), Utc::now()).await.unwrap();
+
+		let acc = SqlAccountManager::new(pool.clone());
+		let (site_admin, _) = acc.create_site_admin("root2").await.unwrap();
+		let (user2, _) = acc.create_account(&site_admin, AccountRole::Admin, "user2").await.unwrap();
+		let (user3, _) = acc.create_account(&site_admin, AccountRole::Admin, "user3").await.unwrap();
+		let settings = SQLSettingsManager::new(pool.clone());
+		let phone2 = settings.new_phone(user2, "0123456780", "Work").await.unwrap();
+		let phone3 = settings.new_phone(user3, "0123456781", "Mobile").await.unwrap();
+
+		tracker.track_ambulance(user, ambulance.id, "patient 1", Urgency::Normal, Some(Point::new(1.0, 1.0)), (phone.phone_id, Duration::from_secs(300))).await.unwrap();
+		tracker.track_ambulance(user2, ambulance.id, "patient 2", Urgency::High, Some(Point::new(1.0, 1.0)), (phone2.phone_id, Duration::from_secs(60))).await.unwrap();
+		tracker.track_ambulance(user3, ambulance.id, "patient 3", Urgency::Low, Some(Point::new(1.0, 1.0)), (phone3.phone_id, Duration::from_secs(300))).await.unwrap();
+		// deleting a phone removes its notification but not the tracking
+		settings.delete_phone(user3, phone3.phone_id).await.unwrap();
+
+		let trackers = tracker.get_trackers(ambulance.id).await.unwrap();
+		let phone_ids = |id: AccountId| -> Vec<Uuid> {
+			let mut ids: Vec<Uuid> = trackers.iter().find(|(user_id, _)| *user_id == id).unwrap().1.iter().map(|phone| phone.phone_id).collect();
+			ids.sort();
+			ids
+		};
+		assert_eq!(trackers.len(), 3);
+		assert_eq!(phone_ids(user), vec![phone.phone_id]);
+		assert_eq!(phone_ids(user2), vec![phone2.phone_id]);
+		assert!(phone_ids(user3).is_empty());
+
+		// arrived trackings are no longer active
+		sqlx::query("UPDATE live_tracking_sessions SET arrived_at=now() WHERE user_id=$1;")
+			.bind(user2.0)
+			.execute(&pool)
+			.await
+			.unwrap();
+		let trackers = tracker.get_trackers(ambulance.id).await.unwrap();
+		assert_eq!(trackers.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![user, user3]);
+
+		assert!(tracker.get_trackers(untracked.id).await.unwrap().is_empty());
+		let missing = AmbulanceId(Uuid::from_u128(1));
+		assert!(matches!(tracker.get_trackers(missing).await, Err(AmbulanceLookupError::AmbulanceNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn test_soft_deleted_ambulance(pool: PgPool) {
+		let (tracker, user, ambulance, phone) = setup(pool.clone()).await;
+		let phones = (phone.phone_id, Duration::from_secs(300));
+		tracker.track_ambulance(user, ambulance.id, "patient", Urgency::Normal, Some(Point::new(1.0, 1.0)), phones).await.unwrap();
+		SQLAmbulanceTracker::new(pool).soft_delete_ambulance(ambulance.id).await.unwrap();
+
+		assert!(matches!(tracker.count_trackers(ambulance.id).await, Err(AmbulanceLookupError::AmbulanceNotFound)));
+		assert!(matches!(tracker.get_trackers(ambulance.id).await, Err(AmbulanceLookupError::AmbulanceNotFound)));
+		let tracked = tracker.track_ambulance(user, ambulance.id, "patient", Urgency::High, Some(Point::new(1.0, 1.0)), phones).await;
+		assert!(matches!(tracked, Err(AmbulanceLookupError::AmbulanceNotFound)));
+		assert!(matches!(tracker.get_user_tracking(user).await, Err(UserLookupError::NotTracking)));
+		assert!(matches!(tracker.set_destination(user, ambulance.id, Point::new(2.0, 2.0)).await, Err(AmbulanceLookupError::AmbulanceNotFound)));
+	}
+
+	#[sqlx::test]
+	async fn test_statement_timeouts(pool: PgPool) {
+		let (tracker, user, ambulance, phone) = setup(pool.clone()).await;
+		let tracker = tracker.with_statement_timeouts(StatementTimeouts::new(Some(Duration::from_millis(100)), None));
+		tracker.track_ambulance(user, ambulance.id, "patient", Urgency::Normal, Some(Point::new(1.0, 1.0)), (phone.phone_id, Duration::from_secs(300))).await.unwrap();
+
+		// a lock held elsewhere makes the queries wait until they are cancelled
+		let mut blocker = pool.begin().await.unwrap();
+		sqlx::query("LOCK TABLE live_tracking_sessions IN ACCESS EXCLUSIVE MODE;").execute(&mut *blocker).await.unwrap();
+
+		assert!(matches!(tracker.get_trackers(ambulance.id).await, Err(AmbulanceLookupError::Timeout)));
+		let refreshed = tracker.refresh_tracked_etas().await.unwrap_err();
+		assert!(matches!(refreshed.downcast_ref(), Some(AmbulanceLookupError::Timeout)), "{:?}", refreshed);
+
+		blocker.rollback().await.unwrap();
+		assert_eq!(tracker.get_trackers(ambulance.id).await.unwrap().len(), 1);
+		assert_eq!(tracker.refresh_tracked_etas().await.unwrap(), 1);
+	}
+
+	#[sqlx::test]
+	async fn test_schema_is_isolated_from_default(pool: PgPool) {
+		// the default schema's rows exist before the tenant is migrated, so that any of the
+		// migrations' drops reaching them would be seen
+		let (default, default_user, default_ambulance, _) = setup(pool.clone()).await;
+		crate::sql::run_migrations_in_schema(&pool, "tenant x").await.unwrap();
+
+		let accounts = SqlAccountManager::new(pool.clone()).with_schema("tenant x");
+		let (site_admin, _) = accounts.create_site_admin("root").await.unwrap();
+		let (user, _) = accounts.create_account(&site_admin, AccountRole::Admin, "user1").await.unwrap();
+		let ambulances = SQLAmbulanceTracker::new(pool.clone()).with_schema("tenant x");
+		let ambulance = ambulances.add_ambulance("Ambulance 1", Point::new(0.0, 0.0), Utc::now()).await.unwrap();
+		let settings = SQLSettingsManager::new(pool.clone()).with_schema("tenant x");
+		let phone = settings.new_phone(user, "0123456789", "Home").await.unwrap();
+		let tenant = SQLTrackingManager::new(pool.clone(), Box::new(FixedEta::new(Duration::from_secs(600)))).with_schema("tenant x");
+
+		let tracked = tenant.track_ambulance(user, ambulance.id, "patient", Urgency::High, Some(Point::new(1.0, 1.0)), (phone.phone_id, Duration::from_secs(300))).await.unwrap();
+		assert_eq!(tracked.urgency, Urgency::High);
+		assert_eq!(tenant.get_user_tracking(user).await.unwrap().urgency, Urgency::High);
+		let trackers = tenant.get_trackers(ambulance.id).await.unwrap();
+		assert_eq!(trackers.iter().map(|(id, phones)| (*id, phones.iter().map(|p| p.phone_id).collect())).collect::<Vec<(AccountId, Vec<Uuid>)>>(), vec![(user, vec![phone.phone_id])]);
+		assert_eq!(tenant.refresh_tracked_etas().await.unwrap(), 1);
+
+		// each schema only sees its own rows
+		assert!(matches!(default.get_trackers(ambulance.id).await, Err(AmbulanceLookupError::AmbulanceNotFound)));
+		assert!(matches!(tenant.get_trackers(default_ambulance.id).await, Err(AmbulanceLookupError::AmbulanceNotFound)));
+		assert!(matches!(default.get_user_tracking(user).await, Err(UserLookupError::UserNotFound)));
+		assert!(matches!(settings.get_phones(default_user).await, Err(SettingsError::UserNotFound)));
+		assert_eq!(SQLSettingsManager::new(pool.clone()).get_phones(default_user).await.unwrap().len(), 1);
+		assert_eq!(ambulances.count_ambulances().await.unwrap(), 1);
+		assert_eq!(SQLAmbulanceTracker::new(pool.clone()).count_ambulances().await.unwrap(), 1);
+		assert_eq!(default.refresh_tracked_etas().await.unwrap(), 0);
+	}
+}
\ No newline at end of file
@@ -0,0 +1,60 @@
+use sqlx::{Connection, PgConnection, Postgres, Transaction};
+use std::time::Duration;
+
+/// The longest a single statement may run before Postgres cancels it, for queries which only read
+/// and for queries which write. `None` leaves the server's `statement_timeout` in place.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StatementTimeouts {
+	pub read: Option<Duration>,
+	pub write: Option<Duration>
+}
+
+impl StatementTimeouts {
+	pub fn new(read: Option<Duration>, write: Option<Duration>) -> Self {
+		Self { read, write }
+	}
+}
+
+/// Begins a transaction on the connection in which statements are cancelled after the timeout.
+/// The timeout is set with `SET LOCAL`, so it ends with the transaction rather than remaining on
+/// the pooled connection.
+pub async fn begin_with_timeout(conn: &mut PgConnection, timeout: Option<Duration>) -> Result<Transaction<'_, Postgres>, sqlx::Error> {
+	let mut tx = conn.begin().await?;
+	if let Some(timeout) = timeout {
+		// a zero timeout would disable it instead
+		let millis = timeout.as_millis().max(1);
+		sqlx::query("SELECT set_config('statement_timeout', $1, true);")
+			.bind(millis.to_string())
+			.execute(&mut *tx)
+			.await?;
+	}
+	Ok(tx)
+}
+
+/// Whether the error is Postgres cancelling a statement, as happens when it exceeds the statement
+/// timeout
+pub fn is_statement_timeout(e: &sqlx::Error) -> bool {
+	// 57014 is query_canceled
+	matches!(e, sqlx::Error::Database(e) if e.code().as_deref() == Some("57014"))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sqlx::PgPool;
+
+	#[sqlx::test]
+	async fn slow_statement_is_cancelled(pool: PgPool) {
+		let mut conn = pool.acquire().await.unwrap();
+
+		let mut tx = begin_with_timeout(&mut conn, Some(Duration::from_millis(50))).await.unwrap();
+		let result = sqlx::query("SELECT pg_sleep(5);").execute(&mut *tx).await;
+		assert!(result.as_ref().is_err_and(is_statement_timeout), "{:?}", result);
+		tx.rollback().await.unwrap();
+
+		// the timeout does not outlive the transaction
+		let mut tx = begin_with_timeout(&mut conn, None).await.unwrap();
+		sqlx::query("SELECT pg_sleep(0.1);").execute(&mut *tx).await.unwrap();
+		tx.commit().await.unwrap();
+	}
+}
\ No newline at end of file
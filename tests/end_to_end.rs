@@ -0,0 +1,53 @@
+//! Runs a realistic flow through every manager against a single freshly migrated database
+
+use std::time::Duration;
+use ambulancetrackerbackend::data::{AccountManager, AccountRole, AmbulanceTracker, SettingsManager, TrackingManager, Urgency, UserSettings};
+use ambulancetrackerbackend::eta::fixed_eta::FixedEta;
+use ambulancetrackerbackend::sql::run_migrations;
+use ambulancetrackerbackend::sql::sql_account_manager::SqlAccountManager;
+use ambulancetrackerbackend::sql::sql_ambulance_tracker::SQLAmbulanceTracker;
+use ambulancetrackerbackend::sql::sql_settings_manager::SQLSettingsManager;
+use ambulancetrackerbackend::sql::sql_tracking_manager::SQLTrackingManager;
+use geo_types::Point;
+use sqlx::types::chrono::Utc;
+use sqlx::PgPool;
+
+#[sqlx::test(migrations = false)]
+async fn track_ambulance_to_hospital(pool: PgPool) {
+	run_migrations(&pool).await.unwrap();
+
+	let accounts = SqlAccountManager::new(pool.clone());
+	let settings = SQLSettingsManager::new(pool.clone());
+	let tracker = SQLAmbulanceTracker::new(pool.clone());
+	let tracking = SQLTrackingManager::new(pool, Box::new(FixedEta::new(Duration::from_secs(10 * 60))));
+
+	let (site_admin, _) = accounts.create_site_admin("site_admin").await.unwrap();
+	let (admin, _) = accounts.create_account(&site_admin, AccountRole::Admin, "admin").await.unwrap();
+	let (user, password) = accounts.create_account(&admin, AccountRole::User, "user").await.unwrap();
+	accounts.login("user", &password).await.unwrap();
+
+	let hospital = Point::new(-73.9857, 40.7484);
+	settings.set_settings(user, UserSettings {
+		hospital_location: Some(hospital),
+		default_eta_alert: Duration::from_secs(15 * 60)
+	}).await.unwrap();
+	let phone = settings.new_phone(user, "(555) 123-4567", "Mobile").await.unwrap();
+
+	let ambulance = tracker.add_ambulance("Ambulance 1", Point::new(-73.9680, 40.7851), Utc::now()).await.unwrap();
+
+	let before = Utc::now();
+	let tracked = tracking.track_ambulance(user, ambulance.id, "Mum", Urgency::High, None, (phone.phone_id, Duration::from_secs(5 * 60))).await.unwrap();
+	let after = Utc::now();
+
+	assert_eq!(tracked.ambulance.id, ambulance.id);
+	assert_eq!(tracked.phones_tracking.0.number, "+15551234567");
+	assert_eq!(tracked.phones_tracking.1, Duration::from_secs(5 * 60));
+
+	assert!(before + Duration::from_secs(10 * 60) <= tracked.eta + Duration::from_secs(1));
+	assert!(tracked.eta <= after + Duration::from_secs(10 * 60) + Duration::from_secs(1));
+
+	let stored = tracking.get_user_tracking(user).await.unwrap();
+	assert_eq!(stored.user_label, "Mum");
+	assert_eq!(stored.urgency, Urgency::High);
+	assert_eq!(stored.eta, tracked.eta);
+}
\ No newline at end of file